@@ -0,0 +1,114 @@
+//! Parses the kernel command line. It starts with optional `key=value`
+//! parameters, and the rest is run by the kernel shell as commands separated
+//! by `;`. For example:
+//!
+//! ```text
+//! root=2 init=/bin/hello-rust
+//! root=2 -- /bin/hello-rust
+//! mount 2; ls /bin
+//! ```
+//!
+//! Parameters:
+//!
+//! - `root=<device ID>`: Mount the ext2 filesystem on this VirtIO block device
+//!   as the root filesystem, and start init from it.
+//! - `init=<path>`: The program to start as init. Defaults to `/sbin/init`.
+//!
+//! Like in Linux, words after `--` are arguments for init. There are no shell
+//! commands after them.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{boot_info, vfs};
+
+#[derive(Debug, Clone)]
+pub(crate) struct KernelCmdline {
+    pub(crate) root: Option<usize>,
+    pub(crate) init: Option<vfs::FilePath>,
+    pub(crate) init_args: Vec<String>,
+    pub(crate) shell_commands: &'static str,
+}
+
+/// Parses the kernel command line from the bootloader.
+pub(crate) fn kernel_cmdline() -> KernelCmdline {
+    parse(boot_info::boot_info().kernel_cmdline)
+}
+
+fn parse(cmdline: &'static str) -> KernelCmdline {
+    let mut parsed = KernelCmdline {
+        root: None,
+        init: None,
+        init_args: Vec::new(),
+        shell_commands: "",
+    };
+
+    let mut rest = cmdline.trim_start();
+    while let Some(word) = rest.split_whitespace().next() {
+        if word == "--" {
+            parsed.init_args = rest[word.len()..]
+                .split_whitespace()
+                .map(String::from)
+                .collect();
+            return parsed;
+        }
+        // Shell commands never start with a `key=value` word, so the first
+        // word without an `=` starts the shell commands.
+        let Some((key, value)) = word.split_once('=') else {
+            break;
+        };
+        match key {
+            "root" => match value.parse() {
+                Ok(device_id) => parsed.root = Some(device_id),
+                Err(_) => log::warn!("invalid root device ID in kernel command line: {value:?}"),
+            },
+            "init" => match vfs::FilePath::parse(value) {
+                Some(path) if path.absolute => parsed.init = Some(path),
+                _ => log::warn!("invalid init path in kernel command line: {value:?}"),
+            },
+            _ => log::warn!("unknown kernel command line parameter: {word:?}"),
+        }
+        rest = rest[word.len()..].trim_start();
+    }
+    parsed.shell_commands = rest;
+    parsed
+}
+
+#[cfg(feature = "tests")]
+mod tests {
+    use super::*;
+
+    use crate::tests::kernel_test;
+
+    #[kernel_test]
+    fn test_parse_cmdline() {
+        let cmdline = parse("");
+        assert_eq!(cmdline.root, None);
+        assert!(cmdline.init.is_none());
+        assert_eq!(cmdline.shell_commands, "");
+
+        let cmdline = parse("mount 2; exec 1 KEY=VALUE /bin/hello");
+        assert_eq!(cmdline.root, None);
+        assert_eq!(
+            cmdline.shell_commands,
+            "mount 2; exec 1 KEY=VALUE /bin/hello"
+        );
+
+        let cmdline = parse(" root=2  init=/bin/init test");
+        assert_eq!(cmdline.root, Some(2));
+        assert_eq!(
+            cmdline
+                .init
+                .as_ref()
+                .map(vfs::FilePath::as_string)
+                .as_deref(),
+            Some("/bin/init")
+        );
+        assert_eq!(cmdline.shell_commands, "test");
+
+        let cmdline = parse("root=2 -- /bin/hello  a b");
+        assert_eq!(cmdline.root, Some(2));
+        assert_eq!(cmdline.init_args, ["/bin/hello", "a", "b"]);
+        assert_eq!(cmdline.shell_commands, "");
+    }
+}
@@ -4,55 +4,108 @@ use core::fmt;
 
 use bitflags::bitflags;
 use elf::endian::AnyEndian;
-use elf::segment::ProgramHeader;
-use elf::{ElfBytes, ParseError};
+use elf::file::{Class, FileHeader};
+use elf::segment::{ProgramHeader, SegmentTable};
+use elf::ParseError;
 use x86_64::VirtAddr;
 
 use crate::memory::PageTableEntryFlags;
 
+/// Where the bytes of an ELF file come from. Executables are parsed by reading
+/// just the parts we need, instead of reading the whole file up front.
+pub(crate) trait ElfFile {
+    /// Reads exactly `len` bytes at `offset`, or returns `None` if the file
+    /// is too short or can't be read.
+    fn read_exact_at(&self, offset: u64, len: usize) -> Option<Vec<u8>>;
+
+    /// The size of the file in bytes, or `None` if it can't be found.
+    fn size(&self) -> Option<u64>;
+}
+
+/// Size of an ELF64 file header.
+const ELF64_EHDR_SIZE: usize = 64;
+
+/// Size of an ELF64 program header.
+const ELF64_PHDR_SIZE: u16 = 56;
+
 /// Wrapper around a parsed ELF header for executables.
-pub(crate) struct ElfExecutableHeader<'a> {
-    pub(crate) parsed: ElfBytes<'a, AnyEndian>,
+pub(crate) struct ElfExecutableHeader {
+    pub(crate) ehdr: FileHeader<AnyEndian>,
+    pub(crate) program_headers: Vec<ProgramHeader>,
     pub(crate) entrypoint: VirtAddr,
     pub(crate) loadable_segments: Vec<LoadableSegment>,
+
+    /// True for `ET_DYN` (PIE) executables, which can be loaded at any base
+    /// address. Addresses in this struct are relative to the load address.
+    pub(crate) position_independent: bool,
+
+    /// Path to the dynamic linker from the `PT_INTERP` header, if the
+    /// executable needs one.
+    pub(crate) interpreter: Option<String>,
+
+    dynamic_header: Option<ProgramHeader>,
 }
 
 #[derive(Debug)]
 pub(crate) enum ElfExecutableHeaderError {
     ParseError(ParseError),
+
+    /// The file ended before something we needed to read, or reading it
+    /// failed.
+    ReadFailed {
+        offset: u64,
+        len: usize,
+    },
+
     Other(String),
 }
 
-impl<'a> ElfExecutableHeader<'a> {
-    pub(crate) fn parse(bytes: &'a [u8]) -> Result<Self, ElfExecutableHeaderError> {
-        let parsed = ElfBytes::<AnyEndian>::minimal_parse(bytes)
-            .map_err(ElfExecutableHeaderError::ParseError)?;
+impl ElfExecutableHeader {
+    pub(crate) fn parse(file: &dyn ElfFile) -> Result<Self, ElfExecutableHeaderError> {
+        let ehdr = parse_file_header(file)?;
 
-        if parsed.ehdr.e_type != elf::abi::ET_EXEC {
-            return Err(ElfExecutableHeaderError::Other(format!(
-                "expected ET_EXEC but found {:?}",
-                parsed.ehdr.e_type
-            )));
-        }
+        let position_independent = match ehdr.e_type {
+            elf::abi::ET_EXEC => false,
+            elf::abi::ET_DYN => true,
+            e_type => {
+                return Err(ElfExecutableHeaderError::Other(format!(
+                    "expected ET_EXEC or ET_DYN but found {e_type:?}"
+                )));
+            }
+        };
 
-        if parsed.ehdr.e_machine != elf::abi::EM_X86_64 {
+        if ehdr.e_machine != elf::abi::EM_X86_64 {
             return Err(ElfExecutableHeaderError::Other(format!(
                 "expected EM_X86_64 but found {:?}",
-                parsed.ehdr.e_machine
+                ehdr.e_machine
             )));
         }
 
-        let entrypoint = VirtAddr::new(parsed.ehdr.e_entry);
+        let entrypoint = VirtAddr::new(ehdr.e_entry);
 
-        let Some(segments) = parsed
-            .segments() else {
-                return Err(ElfExecutableHeaderError::Other(
-                    String::from("no segments found")
-                ));
-            };
+        let program_headers = parse_program_headers(file, &ehdr)?;
+        if program_headers.is_empty() {
+            return Err(ElfExecutableHeaderError::Other(String::from(
+                "no segments found",
+            )));
+        }
+
+        let elf_file_size = file.size().ok_or_else(|| {
+            ElfExecutableHeaderError::Other(String::from("couldn't get file size"))
+        })?;
 
         let mut loadable_segments = Vec::new();
-        for program_header in segments {
+        let mut dynamic_header = None;
+        let mut interpreter = None;
+        for &program_header in &program_headers {
+            if program_header.p_type == elf::abi::PT_DYNAMIC {
+                dynamic_header = Some(program_header);
+                continue;
+            }
+            if program_header.p_type == elf::abi::PT_INTERP {
+                interpreter = Some(parse_interpreter(file, &program_header)?);
+                continue;
+            }
             if program_header.p_type != elf::abi::PT_LOAD {
                 continue;
             }
@@ -63,7 +116,32 @@ impl<'a> ElfExecutableHeader<'a> {
                 )));
             }
 
+            let end = program_header
+                .p_vaddr
+                .checked_add(program_header.p_memsz)
+                .ok_or_else(|| {
+                    ElfExecutableHeaderError::Other(format!("invalid p_memsz: {program_header:?}"))
+                })?;
+            if program_header.p_filesz > program_header.p_memsz || VirtAddr::try_new(end).is_err() {
+                return Err(ElfExecutableHeaderError::Other(format!(
+                    "invalid segment size: {program_header:?}"
+                )));
+            }
+
+            // Segments are read from the file lazily, long after we parse
+            // the header, so make sure now that they are inside the file.
+            let in_file = program_header
+                .p_offset
+                .checked_add(program_header.p_filesz)
+                .is_some_and(|end| end <= elf_file_size);
+            if !in_file {
+                return Err(ElfExecutableHeaderError::Other(format!(
+                    "segment data is outside of the file: {program_header:?}"
+                )));
+            }
+
             let file_offset = program_header.p_offset;
+            let file_size = program_header.p_filesz;
             let vaddr = VirtAddr::new(program_header.p_vaddr);
             let mem_size = program_header.p_memsz;
             let flags =
@@ -75,6 +153,7 @@ impl<'a> ElfExecutableHeader<'a> {
             loadable_segments.push(LoadableSegment {
                 raw_header: program_header,
                 file_offset,
+                file_size,
                 vaddr,
                 mem_size,
                 flags,
@@ -83,19 +162,78 @@ impl<'a> ElfExecutableHeader<'a> {
         }
 
         Ok(Self {
-            parsed,
+            ehdr,
+            program_headers,
             entrypoint,
             loadable_segments,
+            position_independent,
+            interpreter,
+            dynamic_header,
         })
     }
 }
 
-impl fmt::Debug for ElfExecutableHeader<'_> {
+impl ElfExecutableHeader {
+    /// Reads the `R_X86_64_RELATIVE` relocations listed in the `PT_DYNAMIC`
+    /// segment. These are all a static PIE executable needs. Anything that
+    /// needs symbol lookups has to go through the dynamic linker, so those
+    /// executables are rejected. Executables with an interpreter (and the
+    /// interpreter itself) relocate themselves, so don't call this for them.
+    pub(crate) fn relative_relocations(
+        &self,
+        file: &dyn ElfFile,
+    ) -> Result<Vec<RelativeRelocation>, ElfExecutableHeaderError> {
+        match &self.dynamic_header {
+            Some(header) => parse_relative_relocations(file, header, &self.loadable_segments),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Where the program headers are once the loadable segments are mapped,
+    /// which userspace finds via `AT_PHDR` in the auxiliary vector. This is
+    /// either the address of the `PT_PHDR` segment, or wherever the program
+    /// headers land inside a loadable segment. Returns `None` if the program
+    /// headers aren't all inside a loadable segment, since userspace couldn't
+    /// read them.
+    pub(crate) fn program_headers_vaddr(&self) -> Option<VirtAddr> {
+        let table_len = u64::from(self.ehdr.e_phnum) * u64::from(self.ehdr.e_phentsize);
+        if let Some(phdr) = self
+            .program_headers
+            .iter()
+            .find(|header| header.p_type == elf::abi::PT_PHDR)
+        {
+            let end = phdr.p_vaddr.checked_add(table_len)?;
+            let loaded = self.loadable_segments.iter().any(|segment| {
+                let start = segment.vaddr.as_u64();
+                let seg_end = start.checked_add(segment.mem_size);
+                start <= phdr.p_vaddr && seg_end.is_some_and(|seg_end| end <= seg_end)
+            });
+            if !loaded {
+                return None;
+            }
+            return VirtAddr::try_new(phdr.p_vaddr).ok();
+        }
+
+        let offset = self.ehdr.e_phoff;
+        let end = offset.checked_add(table_len)?;
+        self.loadable_segments
+            .iter()
+            .find(|segment| {
+                let file_end = segment.file_offset.checked_add(segment.file_size);
+                segment.file_offset <= offset && file_end.is_some_and(|file_end| end <= file_end)
+            })
+            .map(|segment| segment.vaddr + (offset - segment.file_offset))
+    }
+}
+
+impl fmt::Debug for ElfExecutableHeader {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ElfExecutableHeader")
-            .field("header", &self.parsed.ehdr)
+            .field("header", &self.ehdr)
             .field("entrypoint", &self.entrypoint)
             .field("loadable_segments", &self.loadable_segments)
+            .field("position_independent", &self.position_independent)
+            .field("interpreter", &self.interpreter)
             .finish()
     }
 }
@@ -105,6 +243,7 @@ impl fmt::Debug for ElfExecutableHeader<'_> {
 pub(crate) struct LoadableSegment {
     pub(crate) raw_header: ProgramHeader,
     pub(crate) file_offset: u64,
+    pub(crate) file_size: u64,
     pub(crate) vaddr: VirtAddr,
     pub(crate) mem_size: u64,
     pub(crate) flags: LoadableSegmentFlags,
@@ -140,3 +279,185 @@ impl LoadableSegmentFlags {
         flags
     }
 }
+
+fn read_exact_at(
+    file: &dyn ElfFile,
+    offset: u64,
+    len: usize,
+) -> Result<Vec<u8>, ElfExecutableHeaderError> {
+    file.read_exact_at(offset, len)
+        .ok_or(ElfExecutableHeaderError::ReadFailed { offset, len })
+}
+
+fn parse_file_header(
+    file: &dyn ElfFile,
+) -> Result<FileHeader<AnyEndian>, ElfExecutableHeaderError> {
+    let bytes = read_exact_at(file, 0, ELF64_EHDR_SIZE)?;
+    let ident = elf::file::parse_ident::<AnyEndian>(&bytes[..elf::abi::EI_NIDENT])
+        .map_err(ElfExecutableHeaderError::ParseError)?;
+    if ident.1 != Class::ELF64 {
+        return Err(ElfExecutableHeaderError::Other(format!(
+            "expected a 64 bit ELF file but found {:?}",
+            ident.1
+        )));
+    }
+    FileHeader::parse_tail(ident, &bytes[elf::abi::EI_NIDENT..])
+        .map_err(ElfExecutableHeaderError::ParseError)
+}
+
+fn parse_program_headers(
+    file: &dyn ElfFile,
+    ehdr: &FileHeader<AnyEndian>,
+) -> Result<Vec<ProgramHeader>, ElfExecutableHeaderError> {
+    if ehdr.e_phnum == 0 {
+        return Ok(Vec::new());
+    }
+    if ehdr.e_phentsize != ELF64_PHDR_SIZE {
+        return Err(ElfExecutableHeaderError::Other(format!(
+            "unexpected e_phentsize {}",
+            ehdr.e_phentsize
+        )));
+    }
+    let len = usize::from(ehdr.e_phnum) * usize::from(ELF64_PHDR_SIZE);
+    let bytes = read_exact_at(file, ehdr.e_phoff, len)?;
+    Ok(SegmentTable::new(ehdr.endianness, ehdr.class, &bytes)
+        .iter()
+        .collect())
+}
+
+/// Reads the data in the file for a segment.
+fn segment_data(
+    file: &dyn ElfFile,
+    header: &ProgramHeader,
+) -> Result<Vec<u8>, ElfExecutableHeaderError> {
+    let len = usize::try_from(header.p_filesz)
+        .map_err(|_| ElfExecutableHeaderError::Other(format!("invalid p_filesz: {header:?}")))?;
+    read_exact_at(file, header.p_offset, len)
+}
+
+/// Reads the nul-terminated interpreter path from a `PT_INTERP` header.
+fn parse_interpreter(
+    file: &dyn ElfFile,
+    interp_header: &ProgramHeader,
+) -> Result<String, ElfExecutableHeaderError> {
+    let data = segment_data(file, interp_header)?;
+    let path = data.split(|b| *b == 0).next().unwrap_or_default();
+    String::from_utf8(path.to_vec())
+        .map_err(|_| ElfExecutableHeaderError::Other(format!("invalid PT_INTERP path: {data:?}")))
+}
+
+/// An `R_X86_64_RELATIVE` relocation. If the executable is loaded with load
+/// bias `B` (the difference between where it is loaded and where it was
+/// linked), then `B + addend` is written to address `B + offset`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RelativeRelocation {
+    pub(crate) offset: u64,
+    pub(crate) addend: i64,
+}
+
+/// Not in the `elf` crate's `abi` module. See "Relative relocation table" in
+/// the System V gABI.
+const DT_RELR: i64 = 36;
+
+/// Size of an `Elf64_Rela`.
+const RELA_ENTRY_SIZE: u64 = 24;
+
+fn parse_relative_relocations(
+    file: &dyn ElfFile,
+    dynamic_header: &ProgramHeader,
+    loadable_segments: &[LoadableSegment],
+) -> Result<Vec<RelativeRelocation>, ElfExecutableHeaderError> {
+    let dynamic = segment_data(file, dynamic_header)?;
+
+    let mut rela_addr = None;
+    let mut rela_size = 0;
+    let mut rela_entry_size = RELA_ENTRY_SIZE;
+    for entry in dynamic.chunks_exact(16) {
+        #[allow(clippy::cast_possible_wrap)]
+        let tag = read_u64(&entry[..8]) as i64;
+        let value = read_u64(&entry[8..]);
+        match tag {
+            elf::abi::DT_NULL => break,
+            elf::abi::DT_RELA => rela_addr = Some(value),
+            elf::abi::DT_RELASZ => rela_size = value,
+            elf::abi::DT_RELAENT => rela_entry_size = value,
+            elf::abi::DT_PLTRELSZ if value == 0 => {}
+            elf::abi::DT_NEEDED | elf::abi::DT_PLTRELSZ | elf::abi::DT_REL | DT_RELR => {
+                return Err(ElfExecutableHeaderError::Other(format!(
+                    "unsupported dynamic section tag {tag:#x} without a dynamic linker"
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    let Some(rela_addr) = rela_addr else {
+        return Ok(Vec::new());
+    };
+    if rela_entry_size != RELA_ENTRY_SIZE {
+        return Err(ElfExecutableHeaderError::Other(format!(
+            "unexpected DT_RELAENT {rela_entry_size}"
+        )));
+    }
+    let rela_data = loaded_file_data(file, loadable_segments, rela_addr, rela_size)?;
+
+    rela_data
+        .chunks_exact(RELA_ENTRY_SIZE as usize)
+        .map(|entry| {
+            let offset = read_u64(&entry[..8]);
+            let info = read_u64(&entry[8..16]);
+            #[allow(clippy::cast_possible_wrap)]
+            let addend = read_u64(&entry[16..]) as i64;
+
+            let relocation_type = info as u32;
+            if relocation_type != elf::abi::R_X86_64_RELATIVE {
+                return Err(ElfExecutableHeaderError::Other(format!(
+                    "unsupported relocation type {relocation_type} at {offset:#x}"
+                )));
+            }
+
+            // Make sure the loader only ever writes inside the executable.
+            let in_segment = loadable_segments.iter().any(|segment| {
+                let start = segment.vaddr.as_u64();
+                offset >= start && offset.saturating_add(8) <= start + segment.mem_size
+            });
+            if !in_segment {
+                return Err(ElfExecutableHeaderError::Other(format!(
+                    "relocation offset {offset:#x} is outside of the loadable segments"
+                )));
+            }
+
+            Ok(RelativeRelocation { offset, addend })
+        })
+        .collect()
+}
+
+/// Reads the file data that will be loaded at `[vaddr, vaddr + len)`.
+fn loaded_file_data(
+    file: &dyn ElfFile,
+    loadable_segments: &[LoadableSegment],
+    vaddr: u64,
+    len: u64,
+) -> Result<Vec<u8>, ElfExecutableHeaderError> {
+    for segment in loadable_segments {
+        let start = segment.vaddr.as_u64();
+        let Some(offset) = vaddr.checked_sub(start) else {
+            continue;
+        };
+        if offset.saturating_add(len) > segment.file_size {
+            continue;
+        }
+        let Some(file_offset) = segment.file_offset.checked_add(offset) else {
+            continue;
+        };
+        return read_exact_at(file, file_offset, len as usize);
+    }
+    Err(ElfExecutableHeaderError::Other(format!(
+        "no loadable segment contains {vaddr:#x}..{:#x}",
+        vaddr.saturating_add(len)
+    )))
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().expect("expected 8 bytes"))
+}
@@ -41,6 +41,13 @@ impl Inode {
         let mode = self.mode;
         mode.contains(InodeMode::IFREG)
     }
+
+    /// The access rights and user/group override bits of the mode, without
+    /// the file format.
+    pub(super) fn permissions(&self) -> u16 {
+        let mode = self.mode;
+        mode.bits() & 0o7777
+    }
 }
 
 #[derive(Debug, Copy, Clone, FromZeroes, FromBytes, AsBytes)]
@@ -16,15 +16,25 @@ use super::superblock::InodeNumber;
 #[derive(Debug)]
 pub(crate) struct VFSFileSystem<D> {
     reader: Arc<Mutex<FileSystem<D>>>,
+    id: vfs::FileSystemId,
 }
 
 unsafe impl<D: BlockDeviceDriver + Send> Send for VFSFileSystem<D> {}
 
 impl<D: BlockDeviceDriver + 'static> VFSFileSystem<D> {
     pub(crate) fn read(device: BlockDevice<D>) -> Self {
-        let reader = FileSystem::read(device).expect("couldn't read ext2 filesystem!");
+        Self::try_read(device).expect("couldn't read ext2 filesystem!")
+    }
+
+    /// Like `read`, but returns `None` if the device doesn't have an ext2
+    /// filesystem on it.
+    pub(crate) fn try_read(device: BlockDevice<D>) -> Option<Self> {
+        let reader = FileSystem::read(device)?;
         let reader = Arc::new(Mutex::new(reader));
-        Self { reader }
+        Some(Self {
+            reader,
+            id: vfs::FileSystemId::new_unique(),
+        })
     }
 }
 
@@ -34,6 +44,7 @@ impl<D: Debug + BlockDeviceDriver + 'static> vfs::FileSystem for VFSFileSystem<D
         let reader = self.reader.clone();
         let vfs_inode = Box::new(VFSInode {
             reader,
+            file_system_id: self.id,
             inode_number,
             inode,
         });
@@ -51,11 +62,47 @@ impl<D: Debug + BlockDeviceDriver + 'static> vfs::FileSystem for VFSFileSystem<D
 #[derive(Debug)]
 pub(crate) struct VFSInode<D> {
     reader: Arc<Mutex<FileSystem<D>>>,
+    file_system_id: vfs::FileSystemId,
     inode_number: InodeNumber,
     inode: Inode,
 }
 
+impl<D: BlockDeviceDriver + 'static> VFSInode<D> {
+    fn vfs_metadata(&self) -> vfs::Metadata {
+        let inode = &self.inode;
+        let file_type = if inode.is_dir() {
+            vfs::FileType::Directory
+        } else {
+            vfs::FileType::Regular
+        };
+        vfs::Metadata {
+            file_type,
+            permissions: inode.permissions(),
+            inode: u64::from(self.inode_number.0),
+            size: self.reader.lock().superblock().inode_size(inode),
+            // ext2 also counts inode blocks in 512 byte units, no matter the
+            // filesystem's block size.
+            blocks: u64::from(inode.blocks),
+            links: u32::from(inode.links_count),
+            // TODO: Add the high 16 bits of the uid and gid, which are in
+            // `osd2` on Linux.
+            uid: u32::from(inode.uid),
+            gid: u32::from(inode.gid),
+            accessed: u64::from(inode.atime),
+            modified: u64::from(inode.mtime),
+            changed: u64::from(inode.ctime),
+        }
+    }
+}
+
 impl<D: Debug + BlockDeviceDriver + 'static> vfs::FileInode for VFSInode<D> {
+    fn id(&self) -> Option<vfs::InodeId> {
+        Some(vfs::InodeId {
+            file_system: self.file_system_id,
+            inode: u64::from(self.inode_number.0),
+        })
+    }
+
     fn read(&mut self, buffer: &mut [u8], offset: usize) -> vfs::FileInodeReadResult {
         assert!(
             self.inode.is_file(),
@@ -67,6 +114,9 @@ impl<D: Debug + BlockDeviceDriver + 'static> vfs::FileInode for VFSInode<D> {
 
         let block_size = usize::from(u16::from(reader.superblock().block_size()));
         let file_size = reader.superblock().inode_size(&self.inode) as usize;
+        if offset >= file_size {
+            return vfs::FileInodeReadResult::Done { bytes_read: 0 };
+        }
         let end = file_size.min(offset + buffer.len());
 
         let start_block = offset / block_size;
@@ -81,7 +131,7 @@ impl<D: Debug + BlockDeviceDriver + 'static> vfs::FileInode for VFSInode<D> {
             let block_data = block_buffer.data();
 
             let slice_start = current_offset % block_size;
-            let slice_end = block_size.min(end - current_offset);
+            let slice_end = block_size.min(slice_start + (end - current_offset));
             let slice = &block_data[slice_start..slice_end];
 
             let buffer_start = bytes_read;
@@ -99,6 +149,14 @@ impl<D: Debug + BlockDeviceDriver + 'static> vfs::FileInode for VFSInode<D> {
         }
     }
 
+    fn size(&mut self) -> usize {
+        self.reader.lock().superblock().inode_size(&self.inode) as usize
+    }
+
+    fn metadata(&mut self) -> vfs::Metadata {
+        self.vfs_metadata()
+    }
+
     fn write(&mut self, data: &[u8]) -> bool {
         assert!(
             self.inode.is_file(),
@@ -108,6 +166,18 @@ impl<D: Debug + BlockDeviceDriver + 'static> vfs::FileInode for VFSInode<D> {
 
         let mut lock = self.reader.lock();
 
+        // TODO: Support allocating new blocks for the file. For now we can
+        // only write as much data as fits in the blocks the file already has.
+        let block_size = usize::from(u16::from(lock.superblock().block_size()));
+        let num_blocks = lock.superblock().iter_inode_blocks(&self.inode).count();
+        if data.len() > num_blocks * block_size {
+            log::warn!(
+                "ext2 write: {} bytes doesn't fit in existing {num_blocks} blocks, and allocating new blocks isn't supported",
+                data.len()
+            );
+            return false;
+        }
+
         let mut written_bytes = 0;
         lock.iter_file_blocks(&self.inode, |_, mut block_buf| {
             if written_bytes >= data.len() {
@@ -127,17 +197,58 @@ impl<D: Debug + BlockDeviceDriver + 'static> vfs::FileInode for VFSInode<D> {
             true
         });
 
-        assert!(
-            written_bytes == data.len(),
-            "couldn't write all bytes to file! implement adding new blocks"
-        );
-
         // Write inode back
         self.inode.size_low = data.len() as u32;
         lock.write_inode(self.inode.clone(), self.inode_number);
 
         true
     }
+
+    fn write_at(&mut self, data: &[u8], offset: usize) -> bool {
+        assert!(
+            self.inode.is_file(),
+            "expected file inode but found {:?}",
+            self.inode
+        );
+
+        let mut lock = self.reader.lock();
+
+        // TODO: Support allocating new blocks for the file, like in `write`.
+        let block_size = usize::from(u16::from(lock.superblock().block_size()));
+        let num_blocks = lock.superblock().iter_inode_blocks(&self.inode).count();
+        let Some(end) = offset.checked_add(data.len()) else {
+            return false;
+        };
+        if end > num_blocks * block_size {
+            log::warn!(
+                "ext2 write_at: writing up to byte {end} doesn't fit in existing {num_blocks} blocks, and allocating new blocks isn't supported"
+            );
+            return false;
+        }
+
+        // Only touch the blocks we write to. If we start past the end of the
+        // file, zero the gap too, since the blocks can have stale data.
+        let file_size = lock.superblock().inode_size(&self.inode) as usize;
+        let mut pos = offset.min(file_size);
+        while pos < end {
+            let block_start = pos - pos % block_size;
+            let chunk_end = end.min(block_start + block_size);
+            let index = BlockIndex::new((block_start / block_size) as u64);
+            let mut block_buf = lock.read_inode_block(&self.inode, index);
+            let chunk = &mut block_buf.data_mut()[pos - block_start..chunk_end - block_start];
+            for (file_pos, byte) in (pos..).zip(chunk.iter_mut()) {
+                *byte = file_pos.checked_sub(offset).map_or(0, |i| data[i]);
+            }
+            block_buf.flush();
+            pos = chunk_end;
+        }
+
+        if end > file_size {
+            self.inode.size_low = end as u32;
+            lock.write_inode(self.inode.clone(), self.inode_number);
+        }
+        true
+    }
 }
 
 impl<D: Debug + BlockDeviceDriver + 'static> vfs::DirectoryInode for VFSInode<D> {
@@ -163,6 +274,7 @@ impl<D: Debug + BlockDeviceDriver + 'static> vfs::DirectoryInode for VFSInode<D>
                     };
                     entries.push(Box::new(EXT2DirectoryEntry {
                         reader,
+                        file_system_id: self.file_system_id,
                         inode_number: entry.inode_number(),
                         name: String::from(entry.name()),
                         entry_type,
@@ -185,15 +297,21 @@ impl<D: Debug + BlockDeviceDriver + 'static> vfs::DirectoryInode for VFSInode<D>
         let reader = self.reader.clone();
         Some(Box::new(Self {
             reader,
+            file_system_id: self.file_system_id,
             inode_number,
             inode,
         }))
     }
+
+    fn metadata(&mut self) -> vfs::Metadata {
+        self.vfs_metadata()
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct EXT2DirectoryEntry<D> {
     reader: Arc<Mutex<FileSystem<D>>>,
+    file_system_id: vfs::FileSystemId,
     inode_number: InodeNumber,
     name: String,
     entry_type: vfs::DirectoryEntryType,
@@ -208,6 +326,10 @@ impl<D: Debug + BlockDeviceDriver + 'static> vfs::DirectoryEntry for EXT2Directo
         self.entry_type
     }
 
+    fn inode_number(&self) -> u64 {
+        u64::from(self.inode_number.0)
+    }
+
     fn get_inode(&mut self) -> vfs::Inode {
         let inode_number = self.inode_number;
         let Some(inode) = self.reader.lock().read_inode(inode_number) else {
@@ -216,6 +338,7 @@ impl<D: Debug + BlockDeviceDriver + 'static> vfs::DirectoryEntry for EXT2Directo
         let reader = self.reader.clone();
         let vfs_inode = Box::new(VFSInode {
             reader,
+            file_system_id: self.file_system_id,
             inode_number,
             inode,
         });
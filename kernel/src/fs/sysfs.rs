@@ -17,6 +17,19 @@ impl vfs::FileSystem for Sysfs {
     }
 }
 
+// Nothing in sysfs is stored anywhere, so inode numbers are made up from
+// where the file is. Each task gets a range of inode numbers for its
+// directory and the files in it.
+const ROOT_INODE: u64 = 1;
+const TASKS_INODE: u64 = 2;
+const TASK_DIRECTORY_INODE: u64 = 0;
+const TASK_INFO_INODE: u64 = 1;
+const TASK_STRACE_INODE: u64 = 2;
+
+fn task_inode(task_id: TaskId, file: u64) -> u64 {
+    ((u64::from(u32::from(task_id)) + 1) << 8) | file
+}
+
 #[derive(Debug)]
 struct VFSRootInode;
 
@@ -24,6 +37,10 @@ impl vfs::DirectoryInode for VFSRootInode {
     fn subdirectories(&mut self) -> alloc::vec::Vec<alloc::boxed::Box<dyn vfs::DirectoryEntry>> {
         vec![Box::new(VFSTasksDirectory)]
     }
+
+    fn metadata(&mut self) -> vfs::Metadata {
+        vfs::Metadata::generated(vfs::FileType::Directory, 0o555, ROOT_INODE, 0)
+    }
 }
 
 /// Holds a subdirectory per running task.
@@ -39,6 +56,10 @@ impl vfs::DirectoryEntry for VFSTasksDirectory {
         vfs::DirectoryEntryType::Directory
     }
 
+    fn inode_number(&self) -> u64 {
+        TASKS_INODE
+    }
+
     fn get_inode(&mut self) -> vfs::Inode {
         vfs::Inode {
             inode_type: vfs::InodeType::Directory(Box::new(Self)),
@@ -55,6 +76,10 @@ impl vfs::DirectoryInode for VFSTasksDirectory {
             .map(|task_id| Box::new(VFSTaskDirectory { task_id }) as Box<dyn vfs::DirectoryEntry>)
             .collect()
     }
+
+    fn metadata(&mut self) -> vfs::Metadata {
+        vfs::Metadata::generated(vfs::FileType::Directory, 0o555, TASKS_INODE, 0)
+    }
 }
 
 /// Subdirectory for a specific task.
@@ -72,6 +97,10 @@ impl vfs::DirectoryEntry for VFSTaskDirectory {
         vfs::DirectoryEntryType::Directory
     }
 
+    fn inode_number(&self) -> u64 {
+        task_inode(self.task_id, TASK_DIRECTORY_INODE)
+    }
+
     fn get_inode(&mut self) -> vfs::Inode {
         vfs::Inode {
             inode_type: vfs::InodeType::Directory(Box::new(self.clone())),
@@ -81,9 +110,19 @@ impl vfs::DirectoryEntry for VFSTaskDirectory {
 
 impl vfs::DirectoryInode for VFSTaskDirectory {
     fn subdirectories(&mut self) -> alloc::vec::Vec<alloc::boxed::Box<dyn vfs::DirectoryEntry>> {
-        vec![Box::new(VFSTaskInfoFile {
-            task_id: self.task_id,
-        })]
+        vec![
+            Box::new(VFSTaskInfoFile {
+                task_id: self.task_id,
+            }),
+            Box::new(VFSTaskStraceFile {
+                task_id: self.task_id,
+            }),
+        ]
+    }
+
+    fn metadata(&mut self) -> vfs::Metadata {
+        let inode = task_inode(self.task_id, TASK_DIRECTORY_INODE);
+        vfs::Metadata::generated(vfs::FileType::Directory, 0o555, inode, 0)
     }
 }
 
@@ -114,6 +153,10 @@ impl vfs::DirectoryEntry for VFSTaskInfoFile {
         vfs::DirectoryEntryType::File
     }
 
+    fn inode_number(&self) -> u64 {
+        task_inode(self.task_id, TASK_INFO_INODE)
+    }
+
     fn get_inode(&mut self) -> vfs::Inode {
         vfs::Inode {
             inode_type: vfs::InodeType::File(Box::new(Self {
@@ -127,6 +170,90 @@ impl vfs::FileInode for VFSTaskInfoFile {
     fn read(&mut self, buffer: &mut [u8], offset: usize) -> vfs::FileInodeReadResult {
         sysfs_read_file(&self.data(), buffer, offset)
     }
+
+    fn metadata(&mut self) -> vfs::Metadata {
+        let inode = task_inode(self.task_id, TASK_INFO_INODE);
+        let size = self.data().len() as u64;
+        vfs::Metadata::generated(vfs::FileType::Regular, 0o444, inode, size)
+    }
+}
+
+/// Turns syscall tracing for a task on or off. Reads `1` if it is on, and `0`
+/// if it is off. Write `1` (or `on`) to turn it on, and `0` (or `off`) to turn
+/// it off.
+#[derive(Debug)]
+struct VFSTaskStraceFile {
+    task_id: TaskId,
+}
+
+impl VFSTaskStraceFile {
+    fn data(&self) -> String {
+        sched::TASKS
+            .lock_disable_interrupts()
+            .get_task(self.task_id)
+            .map_or_else(
+                || String::from("task not found...\n"),
+                |task| format!("{}\n", u8::from(task.syscall_trace())),
+            )
+    }
+}
+
+impl vfs::DirectoryEntry for VFSTaskStraceFile {
+    fn name(&self) -> String {
+        String::from("strace")
+    }
+
+    fn entry_type(&self) -> vfs::DirectoryEntryType {
+        vfs::DirectoryEntryType::File
+    }
+
+    fn inode_number(&self) -> u64 {
+        task_inode(self.task_id, TASK_STRACE_INODE)
+    }
+
+    fn get_inode(&mut self) -> vfs::Inode {
+        vfs::Inode {
+            inode_type: vfs::InodeType::File(Box::new(Self {
+                task_id: self.task_id,
+            })),
+        }
+    }
+}
+
+impl vfs::FileInode for VFSTaskStraceFile {
+    fn read(&mut self, buffer: &mut [u8], offset: usize) -> vfs::FileInodeReadResult {
+        sysfs_read_file(&self.data(), buffer, offset)
+    }
+
+    fn write(&mut self, data: &[u8]) -> bool {
+        // Writes replace the whole file, and opening with `O_TRUNC` writes
+        // nothing first.
+        let enabled = match core::str::from_utf8(data).map(str::trim) {
+            Ok("") => return true,
+            Ok("1" | "on") => true,
+            Ok("0" | "off") => false,
+            _ => return false,
+        };
+        let Some(task) = sched::TASKS
+            .lock_disable_interrupts()
+            .get_task(self.task_id)
+        else {
+            return false;
+        };
+        task.set_syscall_trace(enabled);
+        true
+    }
+
+    fn write_at(&mut self, data: &[u8], _offset: usize) -> bool {
+        // Like sysfs in Linux, every write is a whole new value.
+        self.write(data)
+    }
+
+    fn metadata(&mut self) -> vfs::Metadata {
+        let inode = task_inode(self.task_id, TASK_STRACE_INODE);
+        let size = self.data().len() as u64;
+        vfs::Metadata::generated(vfs::FileType::Regular, 0o644, inode, size)
+    }
 }
 
 /// Generic code to implement a sysfs file read that just reads from a string.
@@ -142,7 +269,7 @@ fn sysfs_read_file(
     buffer[..copy_data.len()].copy_from_slice(copy_data);
     if end == data.len() {
         vfs::FileInodeReadResult::Done {
-            bytes_read: file_content.len(),
+            bytes_read: copy_data.len(),
         }
     } else {
         vfs::FileInodeReadResult::Success
@@ -71,7 +71,6 @@ pub(crate) const TSS_SELECTOR: SegmentSelector =
     SegmentSelector::new(5, x86_64::PrivilegeLevel::Ring0);
 
 pub(crate) const DOUBLE_FAULT_IST_INDEX: u16 = 0;
-pub(crate) const PAGE_FAULT_IST_INDEX: u16 = 1;
 
 /// How large to make the various TSS stacks.
 const TSS_STACK_SIZE_BYTES: usize = 4096 * 5; // TODO: Is this too large?
@@ -87,9 +86,6 @@ const TSS_STACK_SIZE_BYTES: usize = 4096 * 5; // TODO: Is this too large?
 static mut DOUBLE_FAULT_STACK_TABLES: [[u8; TSS_STACK_SIZE_BYTES]; percpu::MAX_CPUS as usize] =
     [[0; TSS_STACK_SIZE_BYTES]; percpu::MAX_CPUS as usize];
 
-static mut PAGE_FAULT_STACK_TABLES: [[u8; TSS_STACK_SIZE_BYTES]; percpu::MAX_CPUS as usize] =
-    [[0; TSS_STACK_SIZE_BYTES]; percpu::MAX_CPUS as usize];
-
 fn get_tss_stack_ptr(
     processor_id: ProcessorID,
     stacks: &mut [[u8; TSS_STACK_SIZE_BYTES]; percpu::MAX_CPUS as usize],
@@ -119,9 +115,6 @@ fn create_tss(processor_id: ProcessorID) -> TaskStateSegment {
     tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
         unsafe { get_tss_stack_ptr(processor_id, &mut DOUBLE_FAULT_STACK_TABLES) };
 
-    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] =
-        unsafe { get_tss_stack_ptr(processor_id, &mut PAGE_FAULT_STACK_TABLES) };
-
     tss
 }
 
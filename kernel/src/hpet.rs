@@ -54,10 +54,6 @@ impl Milliseconds {
     fn femtoseconds(self) -> u64 {
         self.0 * 1_000_000_000_000
     }
-
-    fn from_femtoseconds(femtoseconds: u64) -> Self {
-        Self(femtoseconds / 1_000_000_000_000)
-    }
 }
 
 impl From<u64> for Milliseconds {
@@ -86,11 +82,76 @@ impl Add for Milliseconds {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Nanoseconds(u64);
+
+impl Nanoseconds {
+    pub(crate) const fn new(nanoseconds: u64) -> Self {
+        Self(nanoseconds)
+    }
+
+    pub(crate) fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+
+    pub(crate) fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    /// Rounds down to whole milliseconds.
+    pub(crate) fn milliseconds(self) -> Milliseconds {
+        Milliseconds(self.0 / 1_000_000)
+    }
+
+    /// Rounds up to whole milliseconds.
+    pub(crate) fn milliseconds_rounded_up(self) -> Milliseconds {
+        Milliseconds(self.0.div_ceil(1_000_000))
+    }
+}
+
+impl From<u64> for Nanoseconds {
+    fn from(nanoseconds: u64) -> Self {
+        Self(nanoseconds)
+    }
+}
+
+impl From<Nanoseconds> for u64 {
+    fn from(nanoseconds: Nanoseconds) -> Self {
+        nanoseconds.0
+    }
+}
+
+impl From<Milliseconds> for Nanoseconds {
+    fn from(milliseconds: Milliseconds) -> Self {
+        Self(milliseconds.0 * 1_000_000)
+    }
+}
+
+impl fmt::Display for Nanoseconds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}ns", self.0)
+    }
+}
+
+impl Add for Nanoseconds {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(self.0 + rhs.0)
+    }
+}
+
 /// Returns the number of milliseconds since the HPET was initialized.
+pub(crate) fn elapsed_milliseconds() -> Milliseconds {
+    elapsed_nanoseconds().milliseconds()
+}
+
+/// Returns the number of nanoseconds since the HPET was initialized. The
+/// resolution is the HPET's counter period, which is at most 100 nanoseconds.
 ///
 /// TODO: We should probably ensure the HPET can't be reset if we are relying on
 /// this.
-pub(crate) fn elapsed_milliseconds() -> Milliseconds {
+pub(crate) fn elapsed_nanoseconds() -> Nanoseconds {
     let hpet = HPET.get().expect("HPET not initialized");
 
     let caps = hpet.registers.general_capabilities_and_id().read();
@@ -98,7 +159,10 @@ pub(crate) fn elapsed_milliseconds() -> Milliseconds {
 
     let tick = hpet.registers.main_counter_value().read();
 
-    Milliseconds::from_femtoseconds(tick * u64::from(interval_femtoseconds))
+    // Multiply in 128 bits, since the counter value in femtoseconds overflows
+    // 64 bits after about 5 hours.
+    let femtoseconds = u128::from(tick) * u128::from(interval_femtoseconds);
+    Nanoseconds((femtoseconds / 1_000_000) as u64)
 }
 
 /// High Precision Event Timer. See <https://wiki.osdev.org/HPET>
@@ -0,0 +1,69 @@
+//! Starts userspace. If the kernel command line names a root device (see
+//! `cmdline`), we mount it and run init from it as the first userspace task.
+//! The kernel shell is the fallback: it runs if there is no root device, if
+//! init can't be started, or once init exits.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::cmdline::{self, KernelCmdline};
+use crate::fs::ext2;
+use crate::vfs::FilePath;
+use crate::{block, sched, shell, vfs, virtio};
+
+/// Where we look for init if the command line doesn't say.
+const DEFAULT_INIT_PATH: &str = "/sbin/init";
+
+/// Kernel task that boots into userspace, and then runs the kernel shell.
+pub(crate) extern "C" fn boot_task(_arg: *const ()) {
+    let cmdline = cmdline::kernel_cmdline();
+    if let Some(device_id) = cmdline.root {
+        if let Err(err) = mount_root(device_id) {
+            log::error!("failed to mount root device {device_id}: {err}");
+        } else if let Err(err) = run_init(&cmdline) {
+            log::error!("failed to start init: {err}");
+        }
+    }
+    shell::run_serial_shell(cmdline.shell_commands);
+}
+
+fn mount_root(device_id: usize) -> Result<(), String> {
+    if device_id >= virtio::virtio_block_device_count() {
+        return Err(String::from("no such VirtIO block device"));
+    }
+    let device = block::virtio_block_device(device_id);
+    let filesystem =
+        ext2::VFSFileSystem::try_read(device).ok_or("device doesn't have an ext2 filesystem")?;
+    vfs::mount_root_filesystem(Box::new(filesystem));
+    log::info!("mounted ext2 filesystem from VirtIO block device {device_id} as root");
+    Ok(())
+}
+
+/// Starts init and waits for it to exit. Init adopts orphaned tasks, so it
+/// should never exit, but if it does we just log it.
+fn run_init(cmdline: &KernelCmdline) -> Result<(), String> {
+    let path = match &cmdline.init {
+        Some(path) => path.clone(),
+        None => FilePath::parse(DEFAULT_INIT_PATH).expect("invalid default init path"),
+    };
+    // Check that init exists first, since a task that fails to exec just
+    // exits like any other.
+    vfs::get_path_inode(&path)?;
+
+    let task_id = sched::new_userspace_task(sched::ExecParams {
+        path: path.clone(),
+        args: cmdline.init_args.clone(),
+        env: Vec::new(),
+    });
+    sched::set_init_task(task_id);
+    log::info!("started init {path} as task {task_id:?}");
+
+    match sched::wait_on_task(task_id) {
+        Some(exit_code) => {
+            log::error!("init exited with {exit_code:?}, starting the kernel shell");
+        }
+        None => log::error!("init exited, starting the kernel shell"),
+    }
+    Ok(())
+}
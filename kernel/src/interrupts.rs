@@ -36,7 +36,10 @@ pub(crate) struct InterruptVector(pub(crate) u8);
 /// - [Definition for `common_interrupt`](https://elixir.bootlin.com/linux/v6.3/source/arch/x86/kernel/irq.c#L240)
 ///   - [`DEFINE_IDTENTRY_IRQ` def](https://elixir.bootlin.com/linux/v6.3/source/arch/x86/include/asm/idtentry.h#L191)
 ///
-fn common_external_interrupt_handler(_stack_frame: &InterruptStackFrame, vector: InterruptVector) {
+fn common_external_interrupt_handler(
+    stack_frame: &mut InterruptStackFrame,
+    vector: InterruptVector,
+) {
     with_swapgs_accounting(|| {
         let &(interrupt_id, handler) = EXTERNAL_INTERRUPT_HANDLERS
             .lock()
@@ -48,6 +51,8 @@ fn common_external_interrupt_handler(_stack_frame: &InterruptStackFrame, vector:
         // Now that we have signaled the end of the interrupt, we are out of the
         // interrupt context. If we need to call the scheduler, do it.
         sched::run_scheduler_if_needed();
+
+        handle_pending_signals(stack_frame);
     });
 }
 
@@ -75,8 +80,8 @@ static EXTERNAL_INTERRUPT_HANDLERS: SpinLock<
 macro_rules! external_stub_interrupt_handler {
     ($idt:ident $vector:literal) => {
         paste! {
-            extern "x86-interrupt" fn [<_idt_entry_ $vector>](stack_frame: InterruptStackFrame) {
-                common_external_interrupt_handler(&stack_frame, InterruptVector($vector));
+            extern "x86-interrupt" fn [<_idt_entry_ $vector>](mut stack_frame: InterruptStackFrame) {
+                common_external_interrupt_handler(&mut stack_frame, InterruptVector($vector));
             }
 
             $idt[$vector].set_handler_fn([<_idt_entry_ $vector>]);
@@ -122,11 +127,11 @@ fn init_idt() {
         .set_handler_fn(stack_segment_fault_handler);
     idt.general_protection_fault
         .set_handler_fn(general_protection_fault_handler);
-    unsafe {
-        idt.page_fault
-            .set_handler_fn(page_fault_handler)
-            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
-    }
+    // Page faults run on the current task's kernel stack, not an IST stack,
+    // because resolving one can sleep while a file is read. A kernel stack
+    // overflow still can't push a page fault frame, so it ends up as a double
+    // fault instead.
+    idt.page_fault.set_handler_fn(page_fault_handler);
     idt.x87_floating_point
         .set_handler_fn(x87_floating_point_handler);
     idt.alignment_check.set_handler_fn(alignment_check_handler);
@@ -223,8 +228,17 @@ pub(crate) fn install_interrupt_reserved_vector(
     );
 }
 
-extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn divide_error_handler(mut stack_frame: InterruptStackFrame) {
     with_swapgs_accounting(|| {
+        if came_from_userspace(&stack_frame) {
+            sched::handle_user_fault(
+                sched::UserFault::DivideError,
+                stack_frame.instruction_pointer,
+                None,
+            );
+            handle_pending_signals(&mut stack_frame);
+            return;
+        }
         panic!("EXCEPTION: DIVIDE ERROR\nStack Frame: {stack_frame:#?}");
     });
 }
@@ -261,8 +275,17 @@ extern "x86-interrupt" fn bound_range_exceeded_handler(stack_frame: InterruptSta
     });
 }
 
-extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn invalid_opcode_handler(mut stack_frame: InterruptStackFrame) {
     with_swapgs_accounting(|| {
+        if came_from_userspace(&stack_frame) {
+            sched::handle_user_fault(
+                sched::UserFault::InvalidOpcode,
+                stack_frame.instruction_pointer,
+                None,
+            );
+            handle_pending_signals(&mut stack_frame);
+            return;
+        }
         panic!("EXCEPTION: INVALID_OPCODE\nStack Frame: {stack_frame:#?}");
     });
 }
@@ -286,7 +309,16 @@ extern "x86-interrupt" fn double_fault_handler(
         };
     }
 
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+    // A kernel stack overflow shows up as a double fault, since the CPU can't
+    // push the page fault's frame. CR2 then holds the address in the guard
+    // page that it tried to push to.
+    let kernel_guard_access_msg = if is_kernel_guard_page(Cr2::read()) {
+        "KERNEL GUARD PAGE WAS ACCESSED, LIKELY A STACK OVERFLOW!!!\n"
+    } else {
+        ""
+    };
+
+    panic!("EXCEPTION: DOUBLE FAULT\n{kernel_guard_access_msg}{stack_frame:#?}");
 }
 
 extern "x86-interrupt" fn invalid_tss_handler(
@@ -317,20 +349,78 @@ extern "x86-interrupt" fn stack_segment_fault_handler(
 }
 
 extern "x86-interrupt" fn general_protection_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
     with_swapgs_accounting(|| {
+        if came_from_userspace(&stack_frame) {
+            sched::handle_user_fault(
+                sched::UserFault::GeneralProtectionFault,
+                stack_frame.instruction_pointer,
+                None,
+            );
+            handle_pending_signals(&mut stack_frame);
+            return;
+        }
         panic!("EXCEPTION: GENERAL PROTECTION FAULT\nError code: {error_code}\nStack Frame: {stack_frame:#?}");
     });
 }
 
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     with_swapgs_accounting(|| {
         let accessed_address = Cr2::read();
+
+        // Writes to copy-on-write pages (from userspace, or from the kernel
+        // writing to user memory) are expected, and we just need to give the
+        // task its own copy of the page.
+        let is_user_address = accessed_address < VirtAddr::new(HIGHER_HALF_START);
+        let is_write_protection_fault = error_code.contains(
+            PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE,
+        );
+        if is_user_address
+            && is_write_protection_fault
+            && sched::resolve_copy_on_write_fault(accessed_address)
+        {
+            return;
+        }
+
+        // Stack pages and pages of executables are mapped the first time they
+        // are touched, either by userspace or by the kernel copying to user
+        // memory.
+        if is_user_address
+            && !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+            && sched::resolve_missing_page_fault(
+                accessed_address,
+                came_from_userspace(&stack_frame),
+            )
+        {
+            return;
+        }
+
+        // If the kernel faulted while copying to or from user memory, make
+        // the copy fail instead of panicking.
+        if let Some(fixup_ip) = sched::user_copy_fault_fixup(stack_frame.instruction_pointer) {
+            unsafe {
+                stack_frame
+                    .as_mut()
+                    .update(|frame| frame.instruction_pointer = fixup_ip);
+            };
+            return;
+        }
+
+        if came_from_userspace(&stack_frame) {
+            sched::handle_user_fault(
+                sched::UserFault::PageFault,
+                stack_frame.instruction_pointer,
+                Some(accessed_address),
+            );
+            handle_pending_signals(&mut stack_frame);
+            return;
+        }
+
         let kernel_guard_access_msg = if is_kernel_guard_page(accessed_address) {
             "KERNEL GUARD PAGE WAS ACCESSED, LIKELY A STACK OVERFLOW!!!\n"
         } else {
@@ -339,10 +429,6 @@ extern "x86-interrupt" fn page_fault_handler(
 
         panic!("EXCEPTION: PAGE FAULT\n{kernel_guard_access_msg}Accessed Address: {accessed_address:?}\nError code: {error_code:?}\n{stack_frame:#?}");
     });
-
-    loop {
-        x86_64::instructions::hlt();
-    }
 }
 
 extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: InterruptStackFrame) {
@@ -416,6 +502,33 @@ fn gsbase_is_kernel() -> bool {
     gsbase >= VirtAddr::new(HIGHER_HALF_START)
 }
 
+/// Returns true if the CPU was running userspace code when the interrupt or
+/// exception happened. The low two bits of the saved code segment selector
+/// are the privilege level.
+fn came_from_userspace(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment & 0b11 == 3
+}
+
+/// Called right before an interrupt or exception handler returns, so signals
+/// are delivered to userspace without waiting for the task's next syscall.
+fn handle_pending_signals(stack_frame: &mut InterruptStackFrame) {
+    if !came_from_userspace(stack_frame) {
+        return;
+    }
+    let redirect = sched::redirect_interrupted_task_to_signal_entry(
+        stack_frame.instruction_pointer,
+        stack_frame.stack_pointer,
+    );
+    if let Some((instruction_pointer, stack_pointer)) = redirect {
+        unsafe {
+            stack_frame.as_mut().update(|frame| {
+                frame.instruction_pointer = instruction_pointer;
+                frame.stack_pointer = stack_pointer;
+            });
+        };
+    }
+}
+
 /// Runs swapgs if necessary because we came from userspace.
 fn with_swapgs_accounting<F, R>(f: F) -> R
 where
@@ -42,6 +42,10 @@ pub(crate) enum IOAPICIRQNumber {
     /// or if that doesn't exist I think we need to parse some ACPI AML.
     Keyboard = 1,
 
+    /// The COM1 serial port. Like the keyboard, this is the same IRQ as on the
+    /// 8259 PIC.
+    Serial = 4,
+
     // Some reserved numbers in the middle. I don't trust that these aren't
     // already taken.
     Tick = 9,
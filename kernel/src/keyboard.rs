@@ -1,9 +1,9 @@
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1};
 use x86_64::instructions::port::Port;
 
 use crate::interrupts::{InterruptHandlerID, InterruptVector};
 use crate::sync::SpinLock;
-use crate::{interrupts, ioapic};
+use crate::{interrupts, ioapic, tty};
 
 static KEYBOARD: SpinLock<Option<Keyboard<layouts::Us104Key, ScancodeSet1>>> = SpinLock::new(None);
 
@@ -11,7 +11,8 @@ pub(crate) fn init_keyboard() {
     KEYBOARD.lock().replace(Keyboard::new(
         layouts::Us104Key,
         ScancodeSet1,
-        HandleControl::Ignore,
+        // Turns e.g. Ctrl-C into ^C (0x03), like a terminal.
+        HandleControl::MapLettersToUnicode,
     ));
 
     let interrupt_vector = interrupts::install_interrupt_next_vector(1, keyboard_interrupt_handler);
@@ -34,11 +35,32 @@ fn keyboard_interrupt_handler(_vector: InterruptVector, _handler_id: InterruptHa
     if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
         if let Some(key) = keyboard.process_keyevent(key_event) {
             match key {
+                // Terminals send DEL for backspace, which is what the TTY
+                // expects for erasing a character.
+                DecodedKey::Unicode('\x08') => tty::receive_input(&[127]),
                 DecodedKey::Unicode(character) => {
-                    log::info!("FOUND UNICODE CHAR {character}");
+                    let mut bytes = [0; 4];
+                    tty::receive_input(character.encode_utf8(&mut bytes).as_bytes());
+                }
+                DecodedKey::RawKey(key) => {
+                    if let Some(sequence) = ansi_escape_sequence(key) {
+                        tty::receive_input(sequence);
+                    }
                 }
-                DecodedKey::RawKey(key) => log::info!("FOUND RAW CHAR {key:?}"),
             }
         }
     }
 }
+
+/// Escape sequences a terminal would send for keys that aren't characters.
+fn ansi_escape_sequence(key: KeyCode) -> Option<&'static [u8]> {
+    match key {
+        KeyCode::ArrowUp => Some(b"\x1b[A"),
+        KeyCode::ArrowDown => Some(b"\x1b[B"),
+        KeyCode::ArrowRight => Some(b"\x1b[C"),
+        KeyCode::ArrowLeft => Some(b"\x1b[D"),
+        KeyCode::Home => Some(b"\x1b[H"),
+        KeyCode::End => Some(b"\x1b[F"),
+        _ => None,
+    }
+}
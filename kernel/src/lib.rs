@@ -45,12 +45,14 @@ pub(crate) mod apic;
 pub(crate) mod barrier;
 pub(crate) mod block;
 pub(crate) mod boot_info;
+pub(crate) mod cmdline;
 pub(crate) mod debug;
 pub(crate) mod elf;
 pub(crate) mod fs;
 pub(crate) mod gdt;
 pub(crate) mod graphics;
 pub(crate) mod hpet;
+pub(crate) mod init;
 pub(crate) mod interrupts;
 pub(crate) mod ioapic;
 pub(crate) mod keyboard;
@@ -59,8 +61,10 @@ pub(crate) mod memory;
 pub(crate) mod pci;
 pub(crate) mod percpu;
 pub(crate) mod qemu;
+pub(crate) mod random;
 #[allow(dead_code)] // This could be its own crate
 pub(crate) mod registers;
+pub(crate) mod rtc;
 pub(crate) mod sched;
 pub(crate) mod serial;
 pub(crate) mod shell;
@@ -70,6 +74,7 @@ pub(crate) mod sync;
 pub(crate) mod tests;
 pub(crate) mod tick;
 pub(crate) mod transmute;
+pub(crate) mod tty;
 pub(crate) mod vfs;
 pub(crate) mod virtio;
 
@@ -107,8 +112,14 @@ pub fn start() -> ! {
     tick::global_init();
 
     sched::new_task(
-        String::from("shell"),
-        shell::run_serial_shell,
+        String::from("entropy"),
+        random::reseed_task,
+        core::ptr::null::<()>(),
+    );
+    sched::new_task(String::from("tty"), tty::tty_task, core::ptr::null::<()>());
+    sched::new_task(
+        String::from("boot"),
+        init::boot_task,
         core::ptr::null::<()>(),
     );
     sched::start_scheduler();
@@ -120,6 +131,7 @@ static NUM_CPUS_BOOTSTRAPPED: AtomicU8 = AtomicU8::new(0);
 
 fn early_per_cpu_setup(processor_id: ProcessorID) {
     gdt::init_per_cpu_gdt(processor_id);
+    memory::per_cpu_init();
     interrupts::init_interrupts();
     percpu::init_current_cpu(processor_id);
     tick::per_cpu_init();
@@ -142,8 +154,11 @@ fn global_setup(boot_info_data: &boot_info::BootInfo) {
     unsafe {
         hpet::init(acpi_info.hpet_address());
     };
+    rtc::init();
+    random::init();
 
     keyboard::init_keyboard();
+    tty::init();
 
     // Initialize VirtIO devices
     let pci_config_region_base_address = acpi_info.pci_config_region_base_address();
@@ -13,6 +13,8 @@
 //! | 0xffff_e000_0000_0000 | -32 TB  | 0xffff_ffff_efff_ffff |  ~32 TB | (empty space) |
 //! | 0xffff_ffff_8000_0000 | -2 GB   | 0xffff_ffff_ffff_ffff |    2 GB | Kernel text and data segments |
 
+use core::alloc::AllocError;
+
 use x86_64::{PhysAddr, VirtAddr};
 
 use crate::boot_info::BootInfo;
@@ -24,7 +26,11 @@ use super::page::{Page, PageSize};
 use super::page_table::{
     Level4PageTable, MapError, MapTarget, PageTableEntryFlags, SetFlagsError, UnmapError,
 };
-use super::physical::KERNEL_PHYSICAL_ALLOCATOR;
+use super::physical::{PhysicalMemoryAllocator, KERNEL_PHYSICAL_ALLOCATOR};
+
+/// End (exclusive) of the lower half of the address space, which is used for
+/// userspace.
+pub(crate) const USER_MEMORY_END: u64 = 0x0000_8000_0000_0000;
 
 pub(crate) const HIGHER_HALF_START: u64 = 0xffff_8000_0000_0000;
 
@@ -82,7 +88,7 @@ where
     f(table)
 }
 
-pub(crate) fn clone_kernel_page_table() -> Level4PageTable {
+pub(crate) fn clone_kernel_page_table() -> Result<Level4PageTable, AllocError> {
     let mut page_table_lock = KERNEL_PAGE_TABLE.lock();
     let table = page_table_lock
         .as_mut()
@@ -91,6 +97,29 @@ pub(crate) fn clone_kernel_page_table() -> Level4PageTable {
     KERNEL_PHYSICAL_ALLOCATOR.with_lock(|allocator| table.allocate_clone(allocator))
 }
 
+/// Creates a copy-on-write clone of a page table for a forked process. See
+/// `Level4PageTable::fork`.
+pub(crate) fn fork_page_table(
+    page_table: &mut Level4PageTable,
+) -> Result<Level4PageTable, AllocError> {
+    KERNEL_PHYSICAL_ALLOCATOR.with_lock(|allocator| page_table.fork(allocator))
+}
+
+/// Called from the page fault handler when a write to `addr` faulted. Returns
+/// `true` if the fault was for a copy-on-write page and the page is now
+/// writable.
+pub(crate) fn resolve_copy_on_write_fault(
+    page_table: &mut Level4PageTable,
+    addr: VirtAddr,
+) -> bool {
+    KERNEL_PHYSICAL_ALLOCATOR
+        .with_lock(|allocator| page_table.resolve_copy_on_write(allocator, addr))
+        .unwrap_or_else(|e| {
+            log::error!("failed to allocate page for copy-on-write fault at {addr:?}: {e:?}");
+            false
+        })
+}
+
 /// Allocates a physical frame for the given virtual page of memory and maps the
 /// virtual page to the physical frame in the page table. Useful for
 /// initializing a virtual region that is known not to be backed by memory, like
@@ -109,6 +138,83 @@ pub(crate) fn allocate_and_map_pages(
     })
 }
 
+/// Like `allocate_and_map_pages`, but zeroes the new physical pages. Memory
+/// handed to userspace must be zeroed so it can't see stale data from the
+/// kernel or other tasks.
+pub(crate) fn allocate_and_map_zeroed_pages(
+    page_table: &mut Level4PageTable,
+    pages: impl Iterator<Item = Page<VirtAddr>>,
+    flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+    KERNEL_PHYSICAL_ALLOCATOR.with_lock(|allocator| {
+        for page in pages {
+            let mut phys_page =
+                page_table.map_to(allocator, page, MapTarget::NewPhysPage, flags)?;
+            phys_page.zero();
+        }
+
+        Ok(())
+    })
+}
+
+/// Allocates a zeroed physical page that isn't mapped anywhere yet. The caller
+/// owns the only reference to it, which it can hand to `map_existing_user_page`
+/// or give back with `release_physical_page`.
+pub(crate) fn allocate_zeroed_physical_page() -> Result<Page<KernPhysAddr>, AllocError> {
+    let mut page = KERNEL_PHYSICAL_ALLOCATOR.with_lock(PhysicalMemoryAllocator::allocate_page)?;
+    page.zero();
+    Ok(page)
+}
+
+/// Adds a reference to the physical page. See
+/// `PhysicalMemoryAllocator::share_page`.
+pub(crate) fn share_physical_page(page: Page<KernPhysAddr>) {
+    KERNEL_PHYSICAL_ALLOCATOR.with_lock(|allocator| allocator.share_page(page));
+}
+
+/// Drops a reference to the physical page, freeing it if that was the last
+/// one. See `PhysicalMemoryAllocator::release_page`.
+pub(crate) fn release_physical_page(page: Page<KernPhysAddr>) {
+    KERNEL_PHYSICAL_ALLOCATOR.with_lock(|allocator| allocator.release_page(page));
+}
+
+/// Maps the user page to an existing physical page. The page table takes over
+/// the caller's reference to the physical page, which is released when the
+/// page is unmapped. If mapping fails, the caller still owns the reference.
+pub(crate) fn map_existing_user_page(
+    page_table: &mut Level4PageTable,
+    page: Page<VirtAddr>,
+    phys_page: Page<KernPhysAddr>,
+    flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+    KERNEL_PHYSICAL_ALLOCATOR.with_lock(|allocator| {
+        page_table.map_to(
+            allocator,
+            page,
+            MapTarget::ExistingPhysPage(phys_page),
+            flags,
+        )
+    })?;
+    Ok(())
+}
+
+/// Unmaps the given user pages and releases their physical pages (see
+/// `PhysicalMemoryAllocator::release_page`). Pages that aren't mapped are
+/// skipped.
+pub(crate) fn unmap_and_release_user_pages(
+    page_table: &mut Level4PageTable,
+    pages: impl Iterator<Item = Page<VirtAddr>>,
+) {
+    KERNEL_PHYSICAL_ALLOCATOR.with_lock(|allocator| {
+        for page in pages {
+            match page_table.unmap(allocator, page, true) {
+                Ok(_) | Err(UnmapError::PageNotMapped) => {}
+                Err(e) => log::warn!("failed to unmap user page {page:?}: {e:?}"),
+            }
+        }
+    });
+}
+
 pub(crate) fn set_page_flags(
     page_table: &mut Level4PageTable,
     pages: impl Iterator<Item = Page<VirtAddr>>,
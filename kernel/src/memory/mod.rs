@@ -12,6 +12,7 @@ pub(crate) use page_table::*;
 pub(crate) use physical::*;
 
 use bitmap_alloc::MemoryRegion;
+use x86_64::registers::control::{Cr0, Cr0Flags};
 
 use crate::boot_info::BootInfo;
 
@@ -25,3 +26,12 @@ where
     mapping::clean_up_kernel_page_table();
     heap::init().expect("failed to initialize heap");
 }
+
+pub(crate) fn per_cpu_init() {
+    // Make the CPU respect read-only pages in the kernel too. Otherwise kernel
+    // writes to user memory (like in a `read` syscall) would silently modify
+    // copy-on-write pages that are shared with other processes.
+    unsafe {
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+}
@@ -48,6 +48,12 @@ impl<A: AsMutPtr + Copy> Page<A> {
             start_ptr.write_bytes(0, size_bytes);
         }
     }
+
+    pub(crate) fn as_byte_slice(&mut self) -> &mut [u8] {
+        let start_ptr = self.start_addr.as_mut_ptr::<u8>();
+        let size_bytes = self.size.size_bytes();
+        unsafe { core::slice::from_raw_parts_mut(start_ptr, size_bytes) }
+    }
 }
 
 impl Page<VirtAddr> {
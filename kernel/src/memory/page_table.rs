@@ -1,4 +1,5 @@
 use core::alloc::AllocError;
+use core::arch::asm;
 use core::fmt;
 
 use alloc::vec::Vec;
@@ -8,7 +9,7 @@ use x86_64::{PhysAddr, VirtAddr};
 
 use super::address::KernPhysAddr;
 use super::page::{Page, PageSize};
-use super::physical::{PhysicalMemoryAllocator, PAGE_SIZE};
+use super::physical::{PhysicalMemoryAllocator, KERNEL_PHYSICAL_ALLOCATOR, PAGE_SIZE};
 
 #[derive(Debug)]
 pub(crate) struct Level4PageTable(&'static mut PageTable);
@@ -34,19 +35,33 @@ impl Level4PageTable {
         PhysAddr::from(addr)
     }
 
+    /// Makes this the page table of the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// The page table must map the kernel (see `clone_kernel_page_table`), and
+    /// it must not be dropped while any CPU is still using it.
+    pub(crate) unsafe fn load(&self) {
+        let addr = self.physical_address().as_u64();
+        unsafe {
+            asm!("mov cr3, {}", in(reg) addr, options(nostack, preserves_flags));
+        }
+    }
+
     /// Allocates a clone of the page table into a new physical page.
-    pub(super) fn allocate_clone(&self, allocator: &mut PhysicalMemoryAllocator) -> Self {
-        let page = allocator
-            .allocate_page()
-            .expect("failed to allocate page for Level4PageTable clone");
+    pub(super) fn allocate_clone(
+        &self,
+        allocator: &mut PhysicalMemoryAllocator,
+    ) -> Result<Self, AllocError> {
+        let page = allocator.allocate_page()?;
         let new_table = unsafe { &mut *page.start_addr().as_mut_ptr::<PageTable>() };
         new_table.entries.copy_from_slice(&self.0.entries);
-        Self(new_table)
+        Ok(Self(new_table))
     }
 
     /// Translates a virtual address to a physical page mapped by the page
     /// table.
-    pub(super) fn translate_address(&self, addr: VirtAddr) -> TranslateResult {
+    pub(crate) fn translate_address(&self, addr: VirtAddr) -> TranslateResult {
         let mut current_table = &*self.0;
         let mut current_level = PageTableLevel::Level4;
 
@@ -183,7 +198,7 @@ impl Level4PageTable {
                     page.flush();
 
                     if free_physical_page {
-                        allocator.free_page(target_page);
+                        allocator.release_page(target_page);
                     }
 
                     return Ok(target_page);
@@ -196,11 +211,125 @@ impl Level4PageTable {
         }
     }
 
+    /// Creates a copy of this page table for a forked process. The kernel
+    /// (upper half) mappings are shared as-is, and every user (lower half)
+    /// page is shared between both tables. Writable user pages are marked
+    /// read-only and `COPY_ON_WRITE` in both tables, so the first write to one
+    /// of them faults and `resolve_copy_on_write` gives the writer its own
+    /// copy.
+    ///
+    /// This modifies entries in the current table and then flushes the TLB, so
+    /// it is meant to be called on the active page table.
+    pub(super) fn fork(
+        &mut self,
+        allocator: &mut PhysicalMemoryAllocator,
+    ) -> Result<Self, AllocError> {
+        let mut new_table = self.allocate_clone(allocator)?;
+        for i in 0..NUM_LOWER_HALF_ENTRIES {
+            new_table.0.entries[i].clear();
+        }
+        x86_64::instructions::tlb::flush_all();
+
+        for i in 0..NUM_LOWER_HALF_ENTRIES {
+            let result = fork_entry(
+                &mut self.0.entries[i],
+                &mut new_table.0.entries[i],
+                PageTableLevel::Level4,
+                allocator,
+            );
+            if let Err(e) = result {
+                // We already hold the allocator lock, so we can't let Drop
+                // free the table.
+                new_table.free(allocator);
+                core::mem::forget(new_table);
+                return Err(e);
+            }
+        }
+        x86_64::instructions::tlb::flush_all();
+        Ok(new_table)
+    }
+
+    /// Unmaps every page in the lower (user) half of the page table, releases
+    /// the physical pages (see `PhysicalMemoryAllocator::release_page`), and
+    /// frees the intermediate page tables.
+    fn free_lower_half(&mut self, allocator: &mut PhysicalMemoryAllocator) {
+        for i in 0..NUM_LOWER_HALF_ENTRIES {
+            free_entry(&mut self.0.entries[i], PageTableLevel::Level4, allocator);
+        }
+        x86_64::instructions::tlb::flush_all();
+    }
+
+    /// Frees the lower half of the page table as well as the level 4 table
+    /// itself. The table must not be used after this.
+    fn free(&mut self, allocator: &mut PhysicalMemoryAllocator) {
+        self.free_lower_half(allocator);
+        let table_addr = KernPhysAddr::from(self.physical_address());
+        allocator.free_page(Page::from_start_addr(table_addr, PageSize::Size4KiB));
+    }
+
+    /// Handles a write to a `COPY_ON_WRITE` page. If other page tables still
+    /// share the physical page, then the page is copied into a new physical
+    /// page first. Returns `false` if the address isn't mapped to a
+    /// copy-on-write page.
+    pub(super) fn resolve_copy_on_write(
+        &mut self,
+        allocator: &mut PhysicalMemoryAllocator,
+        addr: VirtAddr,
+    ) -> Result<bool, AllocError> {
+        let mut current_table = &mut *self.0;
+        let mut current_level = PageTableLevel::Level4;
+
+        loop {
+            let entry = current_table.address_entry_mut(current_level, addr);
+            let (entry, target) = entry.target_mut(current_level);
+            match target {
+                PageTableTarget::Unmapped => return Ok(false),
+                PageTableTarget::Page { page, flags } => {
+                    if !flags.contains(PageTableEntryFlags::COPY_ON_WRITE) {
+                        return Ok(false);
+                    }
+
+                    let new_flags = (flags - PageTableEntryFlags::COPY_ON_WRITE)
+                        | PageTableEntryFlags::WRITABLE;
+                    if allocator.is_page_shared(page) {
+                        assert!(
+                            page.size().size_bytes() == PAGE_SIZE,
+                            "copy-on-write is only supported for {PAGE_SIZE} byte pages, found {page:?}"
+                        );
+                        let new_page = allocator.allocate_page()?;
+                        unsafe {
+                            new_page
+                                .start_addr()
+                                .as_mut_ptr::<u8>()
+                                .copy_from_nonoverlapping(
+                                    page.start_addr().as_ptr::<u8>(),
+                                    PAGE_SIZE,
+                                );
+                        }
+                        allocator.release_page(page);
+                        entry.clear();
+                        entry.set_target_page(&new_page, new_flags);
+                    } else {
+                        // We are the last user of this page, so we can just
+                        // make it writable again.
+                        entry.set_flags(new_flags);
+                    }
+                    Page::containing_address(addr, page.size()).flush();
+                    return Ok(true);
+                }
+                PageTableTarget::NextTable { level, table } => {
+                    current_table = table;
+                    current_level = level;
+                }
+            }
+        }
+    }
+
     /// Unmaps the lower half of the page table. This ensures that the kernel
     /// page table doesn't touch anything in the lower half of the address
     /// space, so it can be free for userspace when cloned.
     pub(super) fn unmap_lower_half(&mut self) {
-        for i in 0..256 {
+        for i in 0..NUM_LOWER_HALF_ENTRIES {
             // TODO: Recursively free any intermediate child tables (but don't
             // try and free the leaf pages since they probably weren't actually
             // allocated).
@@ -214,7 +343,7 @@ impl Level4PageTable {
     pub(super) fn fill_top_half_entries(&mut self, allocator: &mut PhysicalMemoryAllocator) {
         let flags = PageTableEntryFlags::PRESENT | PageTableEntryFlags::WRITABLE;
 
-        for i in 256..512 {
+        for i in NUM_LOWER_HALF_ENTRIES..NUM_PAGE_TABLE_ENTRIES {
             let entry = &mut self.0.entries[i];
             let (entry, target) = entry.target_mut(PageTableLevel::Level4);
             match target {
@@ -235,6 +364,14 @@ impl Level4PageTable {
     }
 }
 
+/// Page tables for tasks are freed when the task is dropped. The kernel's
+/// page table lives forever, so this is never called for it.
+impl Drop for Level4PageTable {
+    fn drop(&mut self) {
+        KERNEL_PHYSICAL_ALLOCATOR.with_lock(|allocator| self.free(allocator));
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) enum TranslateResult {
     Unmapped,
@@ -296,6 +433,10 @@ impl MapTarget {
 /// All page table levels have 512 entries.
 const NUM_PAGE_TABLE_ENTRIES: usize = 512;
 
+/// The first half of the entries in the level 4 table are for userspace (the
+/// lower half of the address space).
+const NUM_LOWER_HALF_ENTRIES: usize = NUM_PAGE_TABLE_ENTRIES / 2;
+
 /// Underlying type for all levels of page tables.
 ///
 /// See 4.5 4-LEVEL PAGING AND 5-LEVEL PAGING
@@ -443,6 +584,65 @@ impl PageTableEntry {
     }
 }
 
+/// Copies `source` into `dest` for `Level4PageTable::fork`, recursively
+/// creating new intermediate tables and sharing leaf pages.
+fn fork_entry(
+    source: &mut PageTableEntry,
+    dest: &mut PageTableEntry,
+    level: PageTableLevel,
+    allocator: &mut PhysicalMemoryAllocator,
+) -> Result<(), AllocError> {
+    let (source, target) = source.target_mut(level);
+    match target {
+        PageTableTarget::Unmapped => {}
+        PageTableTarget::Page { page, flags } => {
+            if flags.contains(PageTableEntryFlags::WRITABLE) {
+                source.set_flags(
+                    (flags - PageTableEntryFlags::WRITABLE) | PageTableEntryFlags::COPY_ON_WRITE,
+                );
+            }
+            *dest = *source;
+            allocator.share_page(page);
+        }
+        PageTableTarget::NextTable {
+            level: next_level,
+            table,
+        } => {
+            let dest_table = dest.allocate_and_map_child_table(allocator, source.flags())?;
+            for (source_entry, dest_entry) in
+                table.entries.iter_mut().zip(dest_table.entries.iter_mut())
+            {
+                fork_entry(source_entry, dest_entry, next_level, allocator)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Recursively frees everything `entry` points to for
+/// `Level4PageTable::free_lower_half`.
+fn free_entry(
+    entry: &mut PageTableEntry,
+    level: PageTableLevel,
+    allocator: &mut PhysicalMemoryAllocator,
+) {
+    let (entry, target) = entry.target_mut(level);
+    match target {
+        PageTableTarget::Unmapped => return,
+        PageTableTarget::Page { page, .. } => allocator.release_page(page),
+        PageTableTarget::NextTable {
+            level: next_level,
+            table,
+        } => {
+            for child in &mut table.entries {
+                free_entry(child, next_level, allocator);
+            }
+            allocator.free_page(Page::from_start_addr(entry.address(), PageSize::Size4KiB));
+        }
+    }
+    entry.clear();
+}
+
 impl fmt::Debug for PageTableEntry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PageTableEntry")
@@ -518,9 +718,14 @@ bitflags! {
         /// isn't flushed from the TLB on an address space switch.
         const GLOBAL = 1 << 8;
 
+        /// (OS-defined bit) Set on user pages that used to be writable but
+        /// are now shared between multiple page tables after a fork. The
+        /// first write to one of these pages causes a page fault, and then
+        /// the writer gets its own copy of the page.
+        const COPY_ON_WRITE = 1 << 9;
+
         // Bits available to the OS to do whatever it wants. We can use these in
         // the future.
-        const OS_BIT_9 = 1 << 9;
         const OS_BIT_10 = 1 << 10;
         const OS_BIT_11 = 1 << 11;
         const OS_BIT_52 = 1 << 52;
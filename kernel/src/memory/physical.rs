@@ -1,3 +1,4 @@
+use alloc::collections::BTreeMap;
 use core::alloc::AllocError;
 
 use x86_64::PhysAddr;
@@ -56,6 +57,11 @@ impl LockedPhysicalMemoryAllocator<'_> {
 /// Wrapper around `BitmapAllocator` that knows how to deal with the kernel.
 pub(super) struct PhysicalMemoryAllocator<'a> {
     pub(super) allocator: BitmapAllocator<'a>,
+
+    /// Reference counts for pages that are mapped in more than one place, like
+    /// user pages shared between processes after a fork. Pages that aren't in
+    /// this map have a single owner.
+    shared_page_counts: BTreeMap<PhysAddr, usize>,
 }
 
 pub(crate) const PAGE_SIZE: usize = 4096; // 4 KiB
@@ -74,7 +80,10 @@ impl PhysicalMemoryAllocator<'_> {
                 let ptr = kern_phys_addr.as_mut_ptr::<u64>();
                 core::slice::from_raw_parts_mut(ptr, bitmap_len)
             });
-        Self { allocator }
+        Self {
+            allocator,
+            shared_page_counts: BTreeMap::new(),
+        }
     }
 }
 
@@ -108,6 +117,31 @@ impl PhysicalMemoryAllocator<'_> {
         self.free_pages(&PageRange::new(page, 1));
     }
 
+    /// Records that the page has another user, so `release_page` won't free
+    /// it until every user has released it.
+    pub(super) fn share_page(&mut self, page: Page<KernPhysAddr>) {
+        let addr = PhysAddr::from(page.start_addr());
+        *self.shared_page_counts.entry(addr).or_insert(1) += 1;
+    }
+
+    pub(super) fn is_page_shared(&self, page: Page<KernPhysAddr>) -> bool {
+        let addr = PhysAddr::from(page.start_addr());
+        self.shared_page_counts.contains_key(&addr)
+    }
+
+    /// Drops one reference to the page, and frees the page if that was the
+    /// last reference.
+    pub(super) fn release_page(&mut self, page: Page<KernPhysAddr>) {
+        let addr = PhysAddr::from(page.start_addr());
+        match self.shared_page_counts.get_mut(&addr) {
+            Some(count) if *count > 2 => *count -= 1,
+            Some(_) => {
+                self.shared_page_counts.remove(&addr);
+            }
+            None => self.free_page(page),
+        }
+    }
+
     pub(super) fn free_pages(&mut self, pages: &PageRange<KernPhysAddr>) {
         let start_addr = PhysAddr::from(pages.start_addr());
         let start_page = start_addr.as_u64() as usize / pages.page_size().size_bytes();
@@ -0,0 +1,295 @@
+//! Kernel random number generator. Entropy from the VirtIO RNG device and the
+//! CPU's `RDSEED`/`RDRAND` instructions is mixed into a pool, and random bytes
+//! are generated from the pool with ChaCha20.
+//!
+//! We use "fast key erasure" (see
+//! <https://blog.cr.yp.to/20170723-random.html>): every request uses the
+//! current key to generate both the output and a new key, and the old key is
+//! thrown away. That way someone who reads the kernel's memory later can't
+//! recover bytes we already handed out.
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid_count;
+
+use x86_64::instructions::random::RdRand;
+
+use crate::hpet::Milliseconds;
+use crate::sync::{Interrupted, SpinLock, WaitQueue};
+use crate::{hpet, rtc, sched, virtio};
+
+static POOL: SpinLock<EntropyPool> = SpinLock::new(EntropyPool::new());
+
+/// Tasks waiting for the pool to be seeded.
+static SEEDED_WAITERS: WaitQueue = WaitQueue::new();
+
+/// How often `reseed_task` mixes in new entropy.
+const RESEED_INTERVAL: Milliseconds = Milliseconds::new(60_000);
+
+/// Number of bytes of entropy we gather from each source when seeding.
+const SEED_LEN: usize = 32;
+
+/// ChaCha20 nonces, so mixing in entropy and generating output never use the
+/// same key stream.
+const MIX_NONCE: u64 = 0;
+const OUTPUT_NONCE: u64 = 1;
+
+#[derive(Debug)]
+struct EntropyPool {
+    key: [u32; 8],
+
+    /// True once we have mixed in entropy from a source we trust. Until then,
+    /// the output is only as unpredictable as the boot time.
+    seeded: bool,
+}
+
+impl EntropyPool {
+    const fn new() -> Self {
+        Self {
+            key: [0; 8],
+            seeded: false,
+        }
+    }
+
+    /// Mixes `data` into the key. Each 32 byte chunk is XORed into the key,
+    /// and then the key is replaced by a ChaCha20 block generated from it.
+    fn mix(&mut self, data: &[u8]) {
+        for chunk in data.chunks(SEED_LEN) {
+            for (i, byte) in chunk.iter().enumerate() {
+                self.key[i / 4] ^= u32::from(*byte) << (8 * (i % 4));
+            }
+            self.key = next_key(&self.key, MIX_NONCE);
+        }
+    }
+}
+
+/// Seeds the pool from the CPU and the clock. Must be called after the HPET
+/// and the RTC are initialized.
+pub(crate) fn init() {
+    // The time isn't very random, but it makes each boot different even
+    // without any other entropy source.
+    let now = u64::from(rtc::unix_time()) ^ u64::from(hpet::elapsed_nanoseconds());
+    add_entropy(&now.to_le_bytes(), false);
+
+    if let Some(seed) = cpu_entropy() {
+        add_entropy(&seed, true);
+    } else {
+        log::warn!("CPU doesn't support RDSEED or RDRAND, waiting for the VirtIO RNG device");
+    }
+}
+
+/// Mixes `data` into the entropy pool. `trusted` means the data came from a
+/// real entropy source, so the pool counts as seeded afterwards.
+pub(crate) fn add_entropy(data: &[u8], trusted: bool) {
+    let newly_seeded = {
+        let mut pool = POOL.lock_disable_interrupts();
+        pool.mix(data);
+        let newly_seeded = trusted && !pool.seeded;
+        pool.seeded |= trusted;
+        newly_seeded
+    };
+    if newly_seeded {
+        SEEDED_WAITERS.wake_all();
+    }
+}
+
+/// Returns true once the pool has been seeded from a real entropy source.
+pub(crate) fn is_seeded() -> bool {
+    POOL.lock_disable_interrupts().seeded
+}
+
+/// Sleeps until the pool has been seeded, or until a signal arrives.
+pub(crate) fn wait_until_seeded() -> Result<(), Interrupted> {
+    SEEDED_WAITERS.wait_until_interruptible(|| is_seeded().then_some(()))
+}
+
+/// Fills `buffer` with random bytes. This never blocks, even if the pool
+/// hasn't been seeded yet.
+pub(crate) fn fill_bytes(buffer: &mut [u8]) {
+    // Replace the key right away so we can generate the output without
+    // holding the lock.
+    let key = {
+        let mut pool = POOL.lock_disable_interrupts();
+        let key = pool.key;
+        pool.key = next_key(&key, OUTPUT_NONCE);
+        key
+    };
+
+    // Block 0 was used for the new key, so the output starts at block 1.
+    for (chunk, counter) in buffer.chunks_mut(64).zip(1..) {
+        let block = chacha20_block(&key, counter, OUTPUT_NONCE);
+        let bytes = block.iter().flat_map(|word| word.to_le_bytes());
+        for (dest, byte) in chunk.iter_mut().zip(bytes) {
+            *dest = byte;
+        }
+    }
+}
+
+/// Kernel task that periodically mixes in entropy from the VirtIO RNG device
+/// and the CPU. Exits if there is no VirtIO RNG device, since then the CPU is
+/// our only source and `init` already used it.
+pub(crate) extern "C" fn reseed_task(_arg: *const ()) {
+    loop {
+        let Some(receiver) = virtio::try_request_random_numbers(SEED_LEN as u32) else {
+            log::info!("no VirtIO RNG device, so the entropy pool won't be reseeded");
+            return;
+        };
+        // The device can fill less of the buffer than we asked for, and we
+        // only get the bytes it reports filling.
+        let bytes = receiver.wait_sleep();
+        if bytes.len() >= SEED_LEN {
+            add_entropy(&bytes, true);
+        } else {
+            log::warn!(
+                "VirtIO RNG only filled {} of {SEED_LEN} bytes, not reseeding from it",
+                bytes.len()
+            );
+        }
+
+        if let Some(seed) = cpu_entropy() {
+            add_entropy(&seed, true);
+        }
+
+        sched::sleep_timeout(RESEED_INTERVAL);
+    }
+}
+
+/// Gets `SEED_LEN` bytes from `RDSEED` if the CPU supports it, falling back to
+/// `RDRAND`.
+fn cpu_entropy() -> Option<[u8; SEED_LEN]> {
+    let rdrand = RdRand::new();
+    let rdseed_supported = rdseed_supported();
+    if !rdseed_supported && rdrand.is_none() {
+        return None;
+    }
+
+    // Both instructions can fail if the CPU's entropy source is exhausted, so
+    // retry a few times.
+    let next_u64 = || {
+        (0..10).find_map(|_| {
+            let seed = if rdseed_supported { rdseed() } else { None };
+            seed.or_else(|| rdrand.and_then(RdRand::get_u64))
+        })
+    };
+
+    let mut seed = [0; SEED_LEN];
+    for chunk in seed.chunks_mut(8) {
+        chunk.copy_from_slice(&next_u64()?.to_le_bytes());
+    }
+    Some(seed)
+}
+
+/// Checks for `RDSEED` support in CPUID leaf 7, EBX bit 18.
+fn rdseed_supported() -> bool {
+    let max_leaf = unsafe { __cpuid_count(0, 0) }.eax;
+    max_leaf >= 7 && unsafe { __cpuid_count(7, 0) }.ebx & (1 << 18) != 0
+}
+
+fn rdseed() -> Option<u64> {
+    let value: u64;
+    let success: u8;
+    unsafe {
+        asm!(
+            "rdseed {value}",
+            "setc {success}",
+            value = out(reg) value,
+            success = out(reg_byte) success,
+            options(nomem, nostack),
+        );
+    }
+    (success == 1).then_some(value)
+}
+
+/// Generates the next key from block 0 of `key`'s ChaCha20 key stream.
+fn next_key(key: &[u32; 8], nonce: u64) -> [u32; 8] {
+    let block = chacha20_block(key, 0, nonce);
+    let mut next = [0; 8];
+    next.copy_from_slice(&block[..8]);
+    next
+}
+
+/// Generates a ChaCha20 block. This is the original ChaCha20 with a 64 bit
+/// counter and a 64 bit nonce, not the RFC 8439 variant with a 32 bit counter.
+fn chacha20_block(key: &[u32; 8], counter: u64, nonce: u64) -> [u32; 16] {
+    let mut state = [0; 16];
+    // "expand 32-byte k"
+    state[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+    state[4..12].copy_from_slice(key);
+    state[12] = counter as u32;
+    state[13] = (counter >> 32) as u32;
+    state[14] = nonce as u32;
+    state[15] = (nonce >> 32) as u32;
+
+    let mut block = state;
+    for _ in 0..10 {
+        // Column rounds
+        quarter_round(&mut block, 0, 4, 8, 12);
+        quarter_round(&mut block, 1, 5, 9, 13);
+        quarter_round(&mut block, 2, 6, 10, 14);
+        quarter_round(&mut block, 3, 7, 11, 15);
+        // Diagonal rounds
+        quarter_round(&mut block, 0, 5, 10, 15);
+        quarter_round(&mut block, 1, 6, 11, 12);
+        quarter_round(&mut block, 2, 7, 8, 13);
+        quarter_round(&mut block, 3, 4, 9, 14);
+    }
+    for (word, initial) in block.iter_mut().zip(state) {
+        *word = word.wrapping_add(initial);
+    }
+    block
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+#[cfg(feature = "tests")]
+mod tests {
+    use super::*;
+
+    use crate::tests::kernel_test;
+
+    /// Test vector from section 2.3.2 of RFC 8439. The RFC's 32 bit counter
+    /// and 96 bit nonce map onto our 64 bit counter and nonce.
+    #[kernel_test]
+    fn test_chacha20_block() {
+        let key = [
+            0x0302_0100,
+            0x0706_0504,
+            0x0b0a_0908,
+            0x0f0e_0d0c,
+            0x1312_1110,
+            0x1716_1514,
+            0x1b1a_1918,
+            0x1f1e_1d1c,
+        ];
+        let block = chacha20_block(&key, 0x0900_0000_0000_0001, 0x4a00_0000);
+        assert_eq!(
+            block,
+            [
+                0xe4e7_f110,
+                0x1559_3bd1,
+                0x1fdd_0f50,
+                0xc471_20a3,
+                0xc7f4_d1c7,
+                0x0368_c033,
+                0x9aaa_2204,
+                0x4e6c_d4c3,
+                0x4664_82d2,
+                0x09aa_9f07,
+                0x05d7_c214,
+                0xa202_8bd9,
+                0xd19c_12b5,
+                0xb94e_16de,
+                0xe883_d0cb,
+                0x4e3c_50a2,
+            ]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Reads the wall clock time from the CMOS real-time clock (RTC). See
+//! <https://wiki.osdev.org/CMOS>.
+//!
+//! We only read the RTC once at boot, and then count forward from there with
+//! the HPET, which is much more precise than the RTC's one second resolution.
+
+use alloc::format;
+use alloc::string::String;
+
+use x86_64::instructions::port::Port;
+
+use crate::hpet::{self, Nanoseconds};
+use crate::sync::InitCell;
+
+/// Unix time (nanoseconds since 1970-01-01 00:00:00 UTC) when the HPET
+/// counter was 0.
+static UNIX_TIME_AT_HPET_ZERO: InitCell<Nanoseconds> = InitCell::new();
+
+const CMOS_ADDRESS_PORT: u16 = 0x70;
+const CMOS_DATA_PORT: u16 = 0x71;
+
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY_OF_MONTH: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0A;
+const RTC_STATUS_B: u8 = 0x0B;
+
+/// Set in status register A while the RTC is updating its registers.
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 0x80;
+
+/// Set in status register B if hours are 0-23 instead of 1-12 with a PM bit.
+const STATUS_B_24_HOUR: u8 = 0x02;
+
+/// Set in status register B if values are binary instead of BCD.
+const STATUS_B_BINARY: u8 = 0x04;
+
+/// Set in the hours register for PM times in 12 hour mode.
+const HOURS_PM: u8 = 0x80;
+
+/// Reads the RTC and records the current time. Must be called after the HPET
+/// is initialized.
+pub(crate) fn init() {
+    let now = read_rtc();
+    if !now.is_valid() {
+        log::warn!("RTC returned an invalid time {now:?}, so the clock starts at the Unix epoch");
+        UNIX_TIME_AT_HPET_ZERO.init(Nanoseconds::new(0));
+        return;
+    }
+    let unix_seconds = now.unix_seconds();
+    log::info!("RTC time: {now:?} ({unix_seconds} seconds since the Unix epoch)");
+
+    let unix_time = Nanoseconds::new(unix_seconds * 1_000_000_000);
+    UNIX_TIME_AT_HPET_ZERO.init(unix_time.saturating_sub(hpet::elapsed_nanoseconds()));
+}
+
+/// Returns the current Unix time in nanoseconds, or the time since boot if
+/// the RTC hasn't been read yet.
+pub(crate) fn unix_time() -> Nanoseconds {
+    let base = UNIX_TIME_AT_HPET_ZERO
+        .get()
+        .copied()
+        .unwrap_or(Nanoseconds::new(0));
+    base + hpet::elapsed_nanoseconds()
+}
+
+/// Formats a Unix time in seconds as a UTC date and time, like
+/// `2024-02-29 12:34:56`.
+pub(crate) fn format_unix_seconds(unix_seconds: u64) -> String {
+    let time = RtcTime::from_unix_seconds(unix_seconds);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        time.year, time.month, time.day, time.hours, time.minutes, time.seconds
+    )
+}
+
+/// A date and time read from the RTC, which we assume is in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RtcTime {
+    year: u64,
+    month: u64,
+    day: u64,
+    hours: u64,
+    minutes: u64,
+    seconds: u64,
+}
+
+impl RtcTime {
+    fn is_valid(self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hours < 24
+            && self.minutes < 60
+            && self.seconds < 60
+    }
+
+    /// Seconds since the Unix epoch. Uses the days-from-civil algorithm from
+    /// <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+    fn unix_seconds(self) -> u64 {
+        let (year, month) = if self.month <= 2 {
+            (self.year - 1, self.month + 9)
+        } else {
+            (self.year, self.month - 3)
+        };
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * month + 2) / 5 + self.day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        // 719_468 is the number of days from 0000-03-01 to 1970-01-01.
+        let days = era * 146_097 + day_of_era - 719_468;
+        days * 86_400 + self.hours * 3600 + self.minutes * 60 + self.seconds
+    }
+
+    /// The inverse of `unix_seconds`, using the civil-from-days algorithm
+    /// from <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+    fn from_unix_seconds(unix_seconds: u64) -> Self {
+        let seconds_of_day = unix_seconds % 86_400;
+        let days = unix_seconds / 86_400 + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        // Months start in March, like in `unix_seconds`.
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let (year, month) = if month < 10 {
+            (era * 400 + year_of_era, month + 3)
+        } else {
+            (era * 400 + year_of_era + 1, month - 9)
+        };
+        Self {
+            year,
+            month,
+            day,
+            hours: seconds_of_day / 3600,
+            minutes: seconds_of_day / 60 % 60,
+            seconds: seconds_of_day % 60,
+        }
+    }
+}
+
+/// Reads the RTC until we get the same time twice in a row, so we don't see
+/// a time that changed halfway through reading it.
+fn read_rtc() -> RtcTime {
+    let mut time = read_rtc_once();
+    loop {
+        let next = read_rtc_once();
+        if next == time {
+            return time;
+        }
+        time = next;
+    }
+}
+
+fn read_rtc_once() -> RtcTime {
+    while read_cmos_register(RTC_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+
+    let status_b = read_cmos_register(RTC_STATUS_B);
+    let decode = |value: u8| {
+        if status_b & STATUS_B_BINARY == 0 {
+            u64::from((value >> 4) * 10 + (value & 0x0F))
+        } else {
+            u64::from(value)
+        }
+    };
+
+    let raw_hours = read_cmos_register(RTC_HOURS);
+    let mut hours = decode(raw_hours & !HOURS_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is 0 and 12 PM is 12.
+        hours %= 12;
+        if raw_hours & HOURS_PM != 0 {
+            hours += 12;
+        }
+    }
+
+    // TODO: Read the century register from the ACPI FADT instead of assuming
+    // we are in the 2000s.
+    RtcTime {
+        year: 2000 + decode(read_cmos_register(RTC_YEAR)),
+        month: decode(read_cmos_register(RTC_MONTH)),
+        day: decode(read_cmos_register(RTC_DAY_OF_MONTH)),
+        hours,
+        minutes: decode(read_cmos_register(RTC_MINUTES)),
+        seconds: decode(read_cmos_register(RTC_SECONDS)),
+    }
+}
+
+fn read_cmos_register(register: u8) -> u8 {
+    let mut address_port = Port::new(CMOS_ADDRESS_PORT);
+    let mut data_port = Port::new(CMOS_DATA_PORT);
+    unsafe {
+        address_port.write(register);
+        data_port.read()
+    }
+}
+
+#[cfg(feature = "tests")]
+mod tests {
+    use super::*;
+
+    use crate::tests::kernel_test;
+
+    #[kernel_test]
+    fn test_unix_seconds_round_trip() {
+        let leap_day = RtcTime {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hours: 12,
+            minutes: 34,
+            seconds: 56,
+        };
+        assert_eq!(leap_day.unix_seconds(), 1_709_210_096);
+        assert_eq!(RtcTime::from_unix_seconds(1_709_210_096), leap_day);
+        assert_eq!(format_unix_seconds(0), "1970-01-01 00:00:00");
+        assert_eq!(format_unix_seconds(951_868_800), "2000-03-01 00:00:00");
+    }
+}
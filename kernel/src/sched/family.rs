@@ -0,0 +1,302 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::sync::SpinLock;
+
+use super::job_control::job_control;
+use super::schedcore::current_task;
+use super::signal::Signal;
+use super::task::{TaskExitCode, TaskId, TASKS};
+
+/// Parent/child relationships between tasks, along with the exit codes of
+/// children that have exited but haven't been reaped by their parent yet
+/// (zombies), and stops and continues the parent hasn't waited for yet.
+/// Everything is behind a single lock so exiting, reparenting, and reaping
+/// can't race with each other.
+///
+/// Only tasks that have a parent or children are tracked here. Tasks without a
+/// parent (like tasks started from the kernel shell) are never zombies because
+/// nobody can reap them, so their entries are removed as soon as they exit.
+static FAMILIES: SpinLock<BTreeMap<TaskId, Family>> = SpinLock::new(BTreeMap::new());
+
+/// The userspace init task, which adopts orphaned tasks so they can still be
+/// reaped. 0 means there is no init task (task IDs start at 1).
+static INIT_TASK_ID: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Default)]
+struct Family {
+    parent: Option<TaskId>,
+    children: BTreeSet<TaskId>,
+
+    /// Set when the task exits. An exited task stays here until it is reaped.
+    exit_code: Option<TaskExitCode>,
+
+    /// The task's process group when it exited, so the parent can still wait
+    /// for it by process group after the task itself is gone.
+    exit_process_group: Option<TaskId>,
+
+    /// Set when the task is stopped or continued by a signal, and cleared
+    /// when the parent waits for it. Like in Linux, only the latest change is
+    /// kept.
+    job_status: Option<ChildStatus>,
+}
+
+/// What happened to a child, as reported by `wait_for_child`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ChildStatus {
+    Exited(TaskExitCode),
+    Stopped(Signal),
+    Continued,
+}
+
+impl ChildStatus {
+    /// Encodes the status as a Linux-style wait status. See
+    /// `TaskExitCode::wait_status` for exits. Stopped tasks have 0x7f in the
+    /// low byte and the signal above it, and continued tasks are 0xffff.
+    pub(super) fn wait_status(self) -> u32 {
+        match self {
+            Self::Exited(exit_code) => exit_code.wait_status(),
+            Self::Stopped(signal) => (u32::from(signal.number()) << 8) | 0x7f,
+            Self::Continued => 0xffff,
+        }
+    }
+}
+
+/// Which children `reap_child` should consider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum WaitTarget {
+    AnyChild,
+    Child(TaskId),
+
+    /// Any child in the process group.
+    ProcessGroup(TaskId),
+}
+
+/// What `wait_for_child` waits for. Exits are always reported, and stops and
+/// continues only if asked for, like Linux's `WUNTRACED` and `WCONTINUED`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct WaitOptions {
+    /// If false, return `Ok(None)` instead of sleeping.
+    pub(super) block: bool,
+    pub(super) stopped: bool,
+    pub(super) continued: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum WaitError {
+    /// The parent has no children matching the `WaitTarget`, so waiting would
+    /// never finish.
+    NoChildren,
+
+    /// A signal arrived while we were waiting.
+    Interrupted,
+}
+
+/// Records that `child` was created by `parent`.
+pub(super) fn add_child(parent: TaskId, child: TaskId) {
+    let mut families = FAMILIES.lock_disable_interrupts();
+    families.entry(parent).or_default().children.insert(child);
+    families.entry(child).or_default().parent = Some(parent);
+}
+
+/// Returns the parent of `id`, or `None` if it doesn't have one. Orphans have
+/// the init task as their parent, or no parent if there is no init task.
+pub(super) fn parent(id: TaskId) -> Option<TaskId> {
+    FAMILIES
+        .lock_disable_interrupts()
+        .get(&id)
+        .and_then(|family| family.parent)
+}
+
+/// Makes `id` the init task, which adopts tasks whose parent exits.
+pub(crate) fn set_init_task(id: TaskId) {
+    INIT_TASK_ID.store(id.0, Ordering::Release);
+}
+
+pub(super) fn is_init_task(id: TaskId) -> bool {
+    INIT_TASK_ID.load(Ordering::Acquire) == id.0
+}
+
+/// Records that the task exited from `process_group`, turning it into a
+/// zombie until its parent reaps it. The task's children are orphaned: if there is an init task, it
+/// adopts them (including zombies, which it can then reap). Otherwise, live
+/// children lose their parent, and zombie children are discarded since nobody
+/// can reap them anymore. Wakes up the parent if it is waiting for children.
+pub(super) fn task_exited(id: TaskId, exit_code: TaskExitCode, process_group: TaskId) {
+    if is_init_task(id) {
+        INIT_TASK_ID.store(0, Ordering::Release);
+    }
+    let init = match INIT_TASK_ID.load(Ordering::Acquire) {
+        0 => None,
+        init => Some(TaskId(init)),
+    };
+
+    let (parent, adopted_zombies) = {
+        let mut families = FAMILIES.lock_disable_interrupts();
+        let Some(family) = families.get_mut(&id) else {
+            return;
+        };
+        family.exit_code = Some(exit_code);
+        family.exit_process_group = Some(process_group);
+        let parent = family.parent;
+        let children = core::mem::take(&mut family.children);
+        if parent.is_none() {
+            families.remove(&id);
+        }
+
+        let mut adopted_zombies = false;
+        for child in children {
+            let Some(child_family) = families.get_mut(&child) else {
+                continue;
+            };
+            let is_zombie = child_family.exit_code.is_some();
+            if let Some(init) = init {
+                child_family.parent = Some(init);
+                families.entry(init).or_default().children.insert(child);
+                adopted_zombies |= is_zombie;
+            } else if is_zombie {
+                families.remove(&child);
+            } else {
+                child_family.parent = None;
+            }
+        }
+        (parent, adopted_zombies)
+    };
+
+    // N.B. Don't hold the FAMILIES lock while waking the parent, since waking
+    // tasks takes other scheduler locks.
+    if let Some(parent) = parent {
+        wake_child_exit_waiters(parent);
+    }
+    if let Some(init) = init.filter(|_| adopted_zombies) {
+        wake_child_exit_waiters(init);
+    }
+}
+
+/// Records that the task was stopped by `signal`, so its parent can find out
+/// with `waitpid`.
+pub(super) fn task_stopped(id: TaskId, signal: Signal) {
+    set_job_status(id, ChildStatus::Stopped(signal));
+}
+
+/// Records that the task was continued by SIGCONT, so its parent can find
+/// out with `waitpid`.
+pub(super) fn task_continued(id: TaskId) {
+    set_job_status(id, ChildStatus::Continued);
+}
+
+fn set_job_status(id: TaskId, status: ChildStatus) {
+    let parent = {
+        let mut families = FAMILIES.lock_disable_interrupts();
+        let Some(family) = families.get_mut(&id) else {
+            return;
+        };
+        family.job_status = Some(status);
+        family.parent
+    };
+    if let Some(parent) = parent {
+        wake_child_exit_waiters(parent);
+    }
+}
+
+fn wake_child_exit_waiters(parent: TaskId) {
+    let parent_task = TASKS.lock_disable_interrupts().get_task(parent);
+    if let Some(parent_task) = parent_task {
+        parent_task.child_exit_wait_queue.wake_all();
+    }
+}
+
+/// Finds a child of `parent` matching `target` that exited, or that was
+/// stopped or continued if `options` asks for those. Exited children are
+/// reaped, and stops and continues are only reported once. Returns `Ok(None)`
+/// if matching children exist but there is nothing to report yet.
+fn reap_child(
+    parent: TaskId,
+    target: WaitTarget,
+    options: WaitOptions,
+) -> Result<Option<(TaskId, ChildStatus)>, WaitError> {
+    // N.B. Look up the process groups of live children before taking the
+    // FAMILIES lock, since that takes other scheduler locks. Children that exit
+    // in the meantime use the process group recorded when they exited.
+    let live_process_groups: BTreeMap<TaskId, TaskId> = match target {
+        WaitTarget::ProcessGroup(_) => children(parent)
+            .into_iter()
+            .filter_map(|child| Some((child, job_control(child)?.process_group)))
+            .collect(),
+        WaitTarget::AnyChild | WaitTarget::Child(_) => BTreeMap::new(),
+    };
+
+    let mut families = FAMILIES.lock_disable_interrupts();
+    let Some(parent_family) = families.get(&parent) else {
+        return Err(WaitError::NoChildren);
+    };
+
+    let candidates: Vec<TaskId> = parent_family
+        .children
+        .iter()
+        .copied()
+        .filter(|child| match target {
+            WaitTarget::AnyChild => true,
+            WaitTarget::Child(id) => *child == id,
+            WaitTarget::ProcessGroup(process_group) => {
+                let exit_process_group = families
+                    .get(child)
+                    .and_then(|family| family.exit_process_group);
+                exit_process_group.or_else(|| live_process_groups.get(child).copied())
+                    == Some(process_group)
+            }
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Err(WaitError::NoChildren);
+    }
+
+    for child in candidates {
+        let Some(family) = families.get_mut(&child) else {
+            continue;
+        };
+        if let Some(exit_code) = family.exit_code {
+            families.remove(&child);
+            if let Some(parent_family) = families.get_mut(&parent) {
+                parent_family.children.remove(&child);
+            }
+            return Ok(Some((child, ChildStatus::Exited(exit_code))));
+        }
+        let wanted = match family.job_status {
+            Some(ChildStatus::Stopped(_)) => options.stopped,
+            Some(ChildStatus::Continued) => options.continued,
+            Some(ChildStatus::Exited(_)) | None => false,
+        };
+        if wanted {
+            let status = family.job_status.take().expect("job status disappeared");
+            return Ok(Some((child, status)));
+        }
+    }
+    Ok(None)
+}
+
+fn children(parent: TaskId) -> Vec<TaskId> {
+    FAMILIES
+        .lock_disable_interrupts()
+        .get(&parent)
+        .map(|family| family.children.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+/// Waits for a child of the current task matching `target` to exit (or stop
+/// or continue, see `WaitOptions`), and reaps it if it exited. Waiting is
+/// interrupted by signals.
+pub(super) fn wait_for_child(
+    target: WaitTarget,
+    options: WaitOptions,
+) -> Result<Option<(TaskId, ChildStatus)>, WaitError> {
+    let task = current_task();
+    if !options.block {
+        return reap_child(task.id, target, options);
+    }
+    task.child_exit_wait_queue
+        .wait_until_interruptible(|| reap_child(task.id, target, options).transpose())
+        .map_err(|_| WaitError::Interrupted)?
+        .map(Some)
+}
@@ -0,0 +1,177 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use x86_64::VirtAddr;
+
+use crate::hpet::{self, Milliseconds};
+use crate::memory::{self, KernPhysAddr, TranslateResult};
+use crate::sync::SpinLock;
+use crate::tick;
+
+use super::schedcore::{
+    awaken_task, awaken_task_if_sleeping, current_task, prepare_to_sleep, run_scheduler,
+};
+use super::signal::current_task_has_deliverable_signal;
+use super::task::{TaskId, TASKS};
+use super::user_memory::{read_user, BadUserAddress};
+
+/// Tasks waiting in `futex_wait`, keyed by the physical address of the futex
+/// word. Using the physical address means tasks that share memory (threads,
+/// or processes with a shared mapping) find each other even if the word is at
+/// a different virtual address in each of them.
+static FUTEXES: SpinLock<BTreeMap<KernPhysAddr, VecDeque<TaskId>>> = SpinLock::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FutexError {
+    /// The futex word isn't mapped.
+    BadAddress,
+
+    /// The futex word isn't 4 byte aligned.
+    Misaligned,
+
+    /// The futex word didn't have the expected value, so we didn't wait.
+    WouldBlock,
+
+    TimedOut,
+
+    /// A signal arrived while we were waiting.
+    Interrupted,
+}
+
+impl From<BadUserAddress> for FutexError {
+    fn from(_: BadUserAddress) -> Self {
+        Self::BadAddress
+    }
+}
+
+/// Sleeps until another task calls `futex_wake` on the 32 bit word at `addr`,
+/// as long as the word still contains `expected`. Gives up after `timeout` if
+/// there is one, or if a signal arrives.
+pub(super) fn futex_wait(
+    addr: u64,
+    expected: u32,
+    timeout: Option<Milliseconds>,
+) -> Result<(), FutexError> {
+    let key = futex_key(addr)?;
+    let task_id = current_task().id;
+
+    // Check the word and queue ourselves under the same lock `futex_wake`
+    // takes, so we can't miss a wakeup that happens in between.
+    {
+        let mut futexes = FUTEXES.lock_disable_interrupts();
+        if load_futex_word(key) != expected {
+            return Err(FutexError::WouldBlock);
+        }
+        futexes.entry(key).or_default().push_back(task_id);
+    }
+
+    // TODO: Timers can't be cancelled, so this timer can go off after we are
+    // done waiting. Then it wakes us up from whatever we are sleeping on next,
+    // which has to check its condition again anyway.
+    let deadline = timeout.map(|timeout| hpet::elapsed_milliseconds() + timeout);
+    if let Some(deadline) = deadline {
+        tick::add_timer(deadline, move || {
+            if let Some(task) = TASKS.lock_disable_interrupts().get_task(task_id) {
+                awaken_task_if_sleeping(&task);
+            }
+        });
+    }
+
+    let result = loop {
+        // Set desired_state to sleeping before checking if we were woken up
+        // to avoid a race condition where we get woken up before we go to
+        // sleep.
+        prepare_to_sleep();
+        if !dequeue_waiter(key, task_id) {
+            // futex_wake already took us off the queue.
+            break Ok(());
+        }
+        if deadline.is_some_and(|deadline| hpet::elapsed_milliseconds() >= deadline) {
+            break Err(FutexError::TimedOut);
+        }
+        if current_task_has_deliverable_signal() {
+            break Err(FutexError::Interrupted);
+        }
+
+        // Spurious wakeup. Put ourselves back and go back to sleep.
+        FUTEXES
+            .lock_disable_interrupts()
+            .entry(key)
+            .or_default()
+            .push_back(task_id);
+        run_scheduler();
+    };
+    awaken_task(task_id);
+    result
+}
+
+/// Wakes up at most `count` tasks waiting on the 32 bit word at `addr`, and
+/// returns how many were woken up.
+pub(super) fn futex_wake(addr: u64, count: u32) -> Result<u32, FutexError> {
+    let key = futex_key(addr)?;
+    let mut woken = 0;
+    let mut futexes = FUTEXES.lock_disable_interrupts();
+    let Some(waiters) = futexes.get_mut(&key) else {
+        return Ok(0);
+    };
+    while woken < count {
+        let Some(task_id) = waiters.pop_front() else {
+            break;
+        };
+        // The task may have been killed while it was waiting.
+        if let Some(task) = TASKS.lock_disable_interrupts().get_task(task_id) {
+            awaken_task_if_sleeping(&task);
+            woken += 1;
+        }
+    }
+    if waiters.is_empty() {
+        futexes.remove(&key);
+    }
+    Ok(woken)
+}
+
+/// Removes the task from the futex's queue. Returns false if it wasn't
+/// there.
+fn dequeue_waiter(key: KernPhysAddr, task_id: TaskId) -> bool {
+    let mut futexes = FUTEXES.lock_disable_interrupts();
+    let Some(waiters) = futexes.get_mut(&key) else {
+        return false;
+    };
+    let Some(index) = waiters.iter().position(|id| *id == task_id) else {
+        return false;
+    };
+    waiters.remove(index);
+    if waiters.is_empty() {
+        futexes.remove(&key);
+    }
+    true
+}
+
+/// Finds the physical address of the futex word at the user address `addr`.
+fn futex_key(addr: u64) -> Result<KernPhysAddr, FutexError> {
+    if addr % 4 != 0 {
+        return Err(FutexError::Misaligned);
+    }
+    // Reading the word makes sure its page is mapped, since user pages can be
+    // mapped lazily.
+    let _: u32 = read_user(addr)?;
+
+    let addr = VirtAddr::new(addr);
+    let address_space = current_task().address_space();
+    let mut table = address_space.page_table.lock();
+    // A copy-on-write page gets a new physical address the first time it is
+    // written to, which would strand anyone waiting on the old address. Copy
+    // it now so the address stays put.
+    memory::resolve_copy_on_write_fault(&mut table, addr);
+    match table.translate_address(addr) {
+        TranslateResult::Mapped(mapping) => Ok(mapping.address()),
+        TranslateResult::Unmapped => Err(FutexError::BadAddress),
+    }
+}
+
+/// Reads the futex word through the kernel's mapping of physical memory, so
+/// it can't fault while we hold a spin lock.
+fn load_futex_word(key: KernPhysAddr) -> u32 {
+    let word = unsafe { &*key.as_ptr::<AtomicU32>() };
+    word.load(Ordering::SeqCst)
+}
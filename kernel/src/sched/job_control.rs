@@ -0,0 +1,155 @@
+//! Process groups and sessions, for job control.
+//!
+//! Like in Linux, every task is in a process group, and every process group
+//! is in a session. Both are named after the task ID of the task that created
+//! them (the leader). A shell puts each job in its own process group, so the
+//! terminal can send ^C and ^Z to the whole job, and only lets the foreground
+//! job read input. A session is everything started from one terminal, which
+//! is its controlling terminal. See `tty` for the terminal side.
+//!
+//! Every task starts out as the leader of its own session and process group,
+//! but forked tasks move into their parent's right away. Userspace tasks the
+//! kernel starts get the console as their controlling terminal.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::family;
+use super::schedcore::current_task;
+use super::signal::{send_signal, Signal};
+use super::task::{Task, TaskId, TASKS};
+
+/// A task's process group and session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct JobControl {
+    pub(crate) process_group: TaskId,
+    pub(crate) session: TaskId,
+
+    /// True if the console is the session's controlling terminal. The console
+    /// is the only terminal we have.
+    pub(crate) controlling_terminal: bool,
+}
+
+impl JobControl {
+    /// A new session and process group led by `id`, without a controlling
+    /// terminal.
+    pub(super) fn new(id: TaskId) -> Self {
+        Self {
+            process_group: id,
+            session: id,
+            controlling_terminal: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JobControlError {
+    /// There is no such task, or the caller isn't allowed to see it.
+    NoSuchTask,
+
+    /// The change would break the rules for process groups and sessions,
+    /// like moving a task into another session.
+    NotPermitted,
+}
+
+pub(crate) fn current_job_control() -> JobControl {
+    *current_task().job_control.lock()
+}
+
+/// Returns the process group and session of the task `id`.
+pub(crate) fn job_control(id: TaskId) -> Option<JobControl> {
+    let task = TASKS.lock_disable_interrupts().get_task(id)?;
+    let job_control = *task.job_control.lock();
+    Some(job_control)
+}
+
+/// Forked tasks start out in their parent's process group and session.
+pub(super) fn inherit_job_control(parent: &Task, child: &Task) {
+    let job_control = *parent.job_control.lock();
+    *child.job_control.lock() = job_control;
+}
+
+/// Live tasks whose process group and session match `predicate`.
+fn tasks_where(predicate: impl Fn(&JobControl) -> bool) -> Vec<Arc<Task>> {
+    TASKS
+        .lock_disable_interrupts()
+        .iter()
+        .filter(|task| task.exit_code().is_none() && predicate(&task.job_control.lock()))
+        .cloned()
+        .collect()
+}
+
+/// Implements `setpgid`. Moves `id`, which must be the current task or one of
+/// its children, into `process_group`. If `process_group` is `id`, `id`
+/// becomes the leader of a new process group. Otherwise, the group must
+/// already exist in the same session. Session leaders can't move.
+///
+/// TODO: Like Linux, don't allow this for children that have called exec.
+pub(crate) fn set_process_group(id: TaskId, process_group: TaskId) -> Result<(), JobControlError> {
+    let current = current_task();
+    let task = if id == current.id {
+        current.clone()
+    } else if family::parent(id) == Some(current.id) {
+        TASKS
+            .lock_disable_interrupts()
+            .get_task(id)
+            .ok_or(JobControlError::NoSuchTask)?
+    } else {
+        return Err(JobControlError::NoSuchTask);
+    };
+
+    let session = current.job_control.lock().session;
+    let job_control = *task.job_control.lock();
+    if job_control.session != session || job_control.session == id {
+        return Err(JobControlError::NotPermitted);
+    }
+    if process_group != id && process_group_session(process_group) != Some(session) {
+        return Err(JobControlError::NotPermitted);
+    }
+    task.job_control.lock().process_group = process_group;
+    Ok(())
+}
+
+/// Implements `setsid`. Makes the current task the leader of a new session
+/// and process group, without a controlling terminal, and returns the session
+/// ID. Process group leaders can't do this, since their group would end up
+/// split across two sessions.
+pub(crate) fn create_session() -> Result<TaskId, JobControlError> {
+    let task = current_task();
+    if !tasks_where(|other| other.process_group == task.id).is_empty() {
+        return Err(JobControlError::NotPermitted);
+    }
+    *task.job_control.lock() = JobControl::new(task.id);
+    Ok(task.id)
+}
+
+/// Returns true if any live task is in `session`.
+pub(crate) fn session_exists(session: TaskId) -> bool {
+    !tasks_where(|other| other.session == session).is_empty()
+}
+
+/// Returns the session of `process_group`, or `None` if there are no live
+/// tasks in the group.
+pub(crate) fn process_group_session(process_group: TaskId) -> Option<TaskId> {
+    tasks_where(|other| other.process_group == process_group)
+        .first()
+        .map(|task| task.job_control.lock().session)
+}
+
+/// Gives every task in `session` the console as its controlling terminal, or
+/// takes it away. See `tty::set_controlling_terminal`.
+pub(crate) fn set_controlling_terminal(session: TaskId, controlling_terminal: bool) {
+    for task in tasks_where(|other| other.session == session) {
+        task.job_control.lock().controlling_terminal = controlling_terminal;
+    }
+}
+
+/// Sends `signal` to every task in `process_group`. Returns false if there
+/// are no tasks in the group.
+pub(crate) fn signal_process_group(process_group: TaskId, signal: Signal) -> bool {
+    let tasks = tasks_where(|other| other.process_group == process_group);
+    for task in &tasks {
+        send_signal(task.id, signal);
+    }
+    !tasks.is_empty()
+}
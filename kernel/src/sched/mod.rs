@@ -1,12 +1,23 @@
+mod family;
+mod futex;
+mod job_control;
 mod preempt;
 mod schedcore;
+mod signal;
 mod stack;
 mod syscall;
 mod task;
+mod time;
+mod user_memory;
 mod userspace;
+mod vm;
 
+pub(crate) use family::*;
+pub(crate) use job_control::*;
 pub(crate) use preempt::*;
 pub(crate) use schedcore::*;
+pub(crate) use signal::*;
 pub(crate) use stack::*;
 pub(crate) use task::*;
+pub(crate) use user_memory::*;
 pub(crate) use userspace::*;
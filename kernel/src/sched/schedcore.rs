@@ -2,18 +2,23 @@ use alloc::collections::VecDeque;
 use alloc::string::String;
 use alloc::sync::Arc;
 use core::arch::asm;
-use x86_64::PhysAddr;
+use core::sync::atomic::Ordering;
+use x86_64::registers::model_specific::FsBase;
+use x86_64::{PhysAddr, VirtAddr};
 
 use crate::gdt::set_tss_rsp0;
 use crate::hpet::Milliseconds;
 use crate::sync::SpinLock;
+use crate::vfs;
 use crate::{define_per_cpu_u32, define_per_cpu_u8};
 use crate::{percpu, tick};
 
 use super::preempt::{get_preempt_count_no_guard, set_preempt_count};
 use super::syscall::set_per_cpu_TOP_OF_KERNEL_STACK;
 use super::task::{DesiredTaskState, KernelTaskStartFunction, Task, TaskExitCode, TaskId, TASKS};
-use super::{stack, syscall};
+use super::user_memory::write_user;
+use super::vm::AddressSpace;
+use super::{family, futex, job_control, stack, syscall};
 
 static RUN_QUEUE: SpinLock<RunQueue> = SpinLock::new(RunQueue::new());
 
@@ -59,8 +64,14 @@ impl RunQueue {
     }
 
     /// Finds the next task that is ready and removes it from the pending task
-    /// list.
-    fn pop_next_ready_pending_task(&mut self) -> Option<TaskId> {
+    /// list. Tasks whose address space is in use on another CPU aren't ready,
+    /// since we don't have TLB shootdowns (see `AddressSpace`). The current
+    /// task is switching out of `current_address_space`, so tasks sharing it
+    /// are fine.
+    fn pop_next_ready_pending_task(
+        &mut self,
+        current_address_space: &Arc<AddressSpace>,
+    ) -> Option<TaskId> {
         let mut non_ready_tasks = VecDeque::new();
         let next_task_id: Option<TaskId> = loop {
             let Some(next_task_id) = self.pending_tasks.pop_front() else {
@@ -71,7 +82,11 @@ impl RunQueue {
             let next_task = TASKS
                 .lock_disable_interrupts()
                 .get_task_assert(next_task_id);
-            if next_task.desired_state.load() == DesiredTaskState::ReadyToRun {
+            let address_space = next_task.address_space();
+            let address_space_free = Arc::ptr_eq(&address_space, current_address_space)
+                || !address_space.running.load(Ordering::Acquire);
+            if next_task.desired_state.load() == DesiredTaskState::ReadyToRun && address_space_free
+            {
                 // Found a ready task
                 break Some(next_task_id);
             }
@@ -140,7 +155,7 @@ pub(crate) fn start_scheduler() -> ! {
     let prev_stack_ptr = core::ptr::addr_of!(dummy_stack_ptr);
     let current_task = current_task();
     let next_stack_ptr = current_task.registers.rsp;
-    let next_page_table = current_task.page_table.lock().physical_address();
+    let next_page_table = current_task.page_table_address();
 
     // Drop to decrement reference count or else we will leak because
     // switch_to_task will never return
@@ -164,6 +179,36 @@ pub(crate) fn new_task(name: String, start_fn: KernelTaskStartFunction, arg: *co
     id
 }
 
+/// Like `new_task`, but uses the given address space and open files, and makes the
+/// new task a child of `parent` in the same process group and session. See
+/// `Tasks::new_task_with`.
+pub(super) fn new_task_with(
+    name: String,
+    start_fn: KernelTaskStartFunction,
+    arg: *const (),
+    address_space: Arc<AddressSpace>,
+    files: vfs::FileDescriptorTable,
+    parent: TaskId,
+) -> TaskId {
+    let id = {
+        let mut tasks = TASKS.lock_disable_interrupts();
+        let id = tasks.new_task_with(name, start_fn, arg, address_space, files);
+        if let Some(parent_task) = tasks.get_task(parent) {
+            job_control::inherit_job_control(&parent_task, &tasks.get_task_assert(id));
+        }
+        id
+    };
+
+    // Record the parent before the task can run, so it can't exit without its
+    // parent knowing.
+    family::add_child(parent, id);
+    RUN_QUEUE
+        .lock_disable_interrupts()
+        .pending_tasks
+        .push_back(id);
+    id
+}
+
 /// How much time a task gets to run before being preempted.
 const DEFAULT_TIME_SLICE: Milliseconds = Milliseconds::new(100);
 
@@ -241,7 +286,9 @@ fn task_swap_parameters(run_queue: &mut RunQueue) -> Option<(*const u64, u64, Ph
     let prev_task = current_task();
     let prev_task_id = prev_task.id;
     let prev_task_state = prev_task.desired_state.load();
-    let next_task_id = if let Some(id) = run_queue.pop_next_ready_pending_task() {
+    let prev_address_space = prev_task.address_space();
+    let next_task_id = if let Some(id) = run_queue.pop_next_ready_pending_task(&prev_address_space)
+    {
         id
     } else {
         // No other task to switch to. If we are on the idle task, or if the
@@ -269,7 +316,7 @@ fn task_swap_parameters(run_queue: &mut RunQueue) -> Option<(*const u64, u64, Ph
         .lock_disable_interrupts()
         .get_task_assert(next_task_id);
     let next_stack_ptr = next_task.registers.rsp;
-    let next_page_table = next_task.page_table.lock().physical_address();
+    let next_page_table = next_task.page_table_address();
 
     // Give the next task some time slice
     next_task.remaining_slice.store(DEFAULT_TIME_SLICE);
@@ -279,6 +326,14 @@ fn task_swap_parameters(run_queue: &mut RunQueue) -> Option<(*const u64, u64, Ph
         log::warn!("Tried to switch to the same task!: {prev_task_id:?} {next_task_id:?}");
         return None;
     }
+
+    // We hold the run queue lock until we have switched, so no other CPU can
+    // pick a task with the previous address space before we are out of it.
+    prev_address_space.running.store(false, Ordering::Release);
+    next_task
+        .address_space()
+        .running
+        .store(true, Ordering::Release);
     log::info!(
         "SCHEDULER: (CPU {:?}) Switching from '{}' {:?} to '{}' {:?}",
         processor_id,
@@ -292,6 +347,11 @@ fn task_swap_parameters(run_queue: &mut RunQueue) -> Option<(*const u64, u64, Ph
     set_per_cpu_TOP_OF_KERNEL_STACK(next_task.kernel_stack.top_addr().as_u64());
     set_tss_rsp0(processor_id, next_task.kernel_stack.top_addr());
 
+    // The kernel doesn't use FS, so we can swap the FS base for userspace's
+    // thread-local storage now instead of in switch_to_task.
+    prev_task.fs_base.store(FsBase::read().as_u64());
+    FsBase::write(VirtAddr::new(next_task.fs_base.load()));
+
     Some((prev_stack_ptr, next_stack_ptr, next_page_table))
 }
 
@@ -316,26 +376,66 @@ pub(crate) fn scheduler_tick(time_between_ticks: Milliseconds) {
     }
 }
 
-/// Puts the current task to sleep for the given number of milliseconds.
+/// Puts the current task to sleep for the given number of milliseconds. The
+/// task may wake up early, like when it is sent a signal. The timer only fires
+/// on a tick, so it may also sleep for up to a tick longer.
 pub(crate) fn sleep_timeout(timeout: Milliseconds) {
     let task_id = prepare_to_sleep();
     tick::add_relative_timer(timeout, move || {
-        awaken_task(task_id);
+        // The task may have exited before the timer went off.
+        if let Some(task) = TASKS.lock_disable_interrupts().get_task(task_id) {
+            awaken_task_if_sleeping(&task);
+        }
     });
     run_scheduler();
 }
 
-pub(super) fn kill_current_task(exit_code: TaskExitCode) {
+/// Sleeps until the first tick at or after `expiration` milliseconds since
+/// boot. Like `sleep_timeout`, the task may be woken up early (e.g. by a
+/// signal), so callers need to check the time again.
+pub(super) fn sleep_until(expiration: Milliseconds) {
+    let task_id = prepare_to_sleep();
+    tick::add_timer(expiration, move || {
+        // The task may have exited before the timer went off.
+        if let Some(task) = TASKS.lock_disable_interrupts().get_task(task_id) {
+            awaken_task_if_sleeping(&task);
+        }
+    });
+    run_scheduler();
+}
+
+pub(super) fn kill_current_task(exit_code: TaskExitCode) -> ! {
     let current_task = current_task();
     log::info!(
         "killing task {} {:?} with code {exit_code:?}",
         current_task.name,
         current_task.id
     );
+
+    // Close all open files now instead of whenever the last reference to the
+    // task goes away, so e.g. readers of a pipe this task was writing to see
+    // EOF right away.
+    let files = core::mem::replace(
+        &mut *current_task.files.lock(),
+        vfs::FileDescriptorTable::new(),
+    );
+    drop(files);
+
+    let clear_child_tid = current_task.clear_child_tid.load();
+    if clear_child_tid != 0 {
+        // Errors are ignored, since userspace might have unmapped the address
+        // already, and then nobody can be waiting on it.
+        if write_user(clear_child_tid, &0_u32).is_ok() {
+            let _ = futex::futex_wake(clear_child_tid, 1);
+        }
+    }
+
     current_task.desired_state.swap(DesiredTaskState::Killed);
 
     // Inform waiters that the task has exited.
     current_task.exit_wait_cell.send_all_consumers(exit_code);
+    let process_group = current_task.job_control.lock().process_group;
+    family::task_exited(current_task.id, exit_code, process_group);
 
     // Drop to decrement reference count or else we will leak because
     // run_scheduler will never return
@@ -365,6 +465,19 @@ pub(crate) fn awaken_task(task_id: TaskId) {
     }
 }
 
+/// Wakes up the given task if it is sleeping, so it can notice something
+/// changed (like a new signal). Unlike `awaken_task`, this never revives a task
+/// that was killed.
+pub(super) fn awaken_task_if_sleeping(task: &Task) {
+    let was_sleeping = task
+        .desired_state
+        .compare_exchange(DesiredTaskState::Sleeping, DesiredTaskState::ReadyToRun)
+        .is_ok();
+    if was_sleeping {
+        set_per_cpu_NEEDS_RESCHEDULE(1);
+    }
+}
+
 /// Waits until the given task is finished.
 pub(crate) fn wait_on_task(target_task_id: TaskId) -> Option<TaskExitCode> {
     let Some(target_task) = TASKS.lock_disable_interrupts().get_task(target_task_id) else { return None; };
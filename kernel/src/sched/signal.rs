@@ -0,0 +1,833 @@
+//! POSIX-style signals.
+//!
+//! Each task has a set of pending signals, a mask of blocked signals, and an
+//! action for every signal. Sending a signal only marks it pending (and wakes
+//! the task up if it is sleeping). Pending signals are delivered when the task
+//! is about to return to userspace, either at the end of a syscall or at the
+//! end of an interrupt or exception that interrupted userspace.
+//!
+//! User handlers run on the task's own stack. We push a `SignalFrame` with the
+//! interrupted registers and make the handler return into a trampoline that
+//! calls `sigreturn`, which restores them. The trampoline lives in a page that
+//! exec maps into every program at `SIGNAL_TRAMPOLINE_ADDR`.
+//!
+//! Interrupts and exceptions don't save every user register like syscalls do,
+//! so we can't build a `SignalFrame` from an interrupt handler. Instead, we
+//! point the task at a second trampoline that saves the registers `syscall`
+//! clobbers and calls back into the kernel, where we have the full register
+//! state. See `redirect_interrupted_task_to_signal_entry`.
+//!
+//! Stop signals (SIGSTOP, SIGTSTP, SIGTTIN, and SIGTTOU) put the task to sleep
+//! until it gets SIGCONT or SIGKILL, and the task's parent can find out with
+//! `waitpid`. This is what job control in a shell is built on.
+
+use core::arch::global_asm;
+use core::fmt;
+use core::str::FromStr;
+
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+use crate::memory::{Level4PageTable, PageTableEntryFlags, PAGE_SIZE, USER_MEMORY_END};
+use crate::sync::{Interrupted, WaitQueue};
+
+use super::family::{self, is_init_task};
+use super::schedcore::{awaken_task_if_sleeping, current_task, kill_current_task};
+use super::syscall::{SYS_SIGNAL_ENTRY, SYS_SIGRETURN};
+use super::task::{TaskExitCode, TaskId, TaskRegisters, TASKS};
+use super::user_memory::{read_user, write_user, BadUserAddress};
+use super::vm::{
+    map_pages_with_contents, VirtualMemoryArea, VirtualMemoryAreaKind, VirtualMemoryAreas, VmError,
+    PAGE_SIZE_U64,
+};
+
+/// Number of signals, including the real-time signals. Signal numbers go from
+/// 1 to `NUM_SIGNALS`, so a set of signals fits in a `u64` like Linux's
+/// `sigset_t`.
+const NUM_SIGNALS: usize = 64;
+
+/// Names of the standard signals, indexed by signal number.
+const SIGNAL_NAMES: [&str; 32] = [
+    "",
+    "SIGHUP",
+    "SIGINT",
+    "SIGQUIT",
+    "SIGILL",
+    "SIGTRAP",
+    "SIGABRT",
+    "SIGBUS",
+    "SIGFPE",
+    "SIGKILL",
+    "SIGUSR1",
+    "SIGSEGV",
+    "SIGUSR2",
+    "SIGPIPE",
+    "SIGALRM",
+    "SIGTERM",
+    "SIGSTKFLT",
+    "SIGCHLD",
+    "SIGCONT",
+    "SIGSTOP",
+    "SIGTSTP",
+    "SIGTTIN",
+    "SIGTTOU",
+    "SIGURG",
+    "SIGXCPU",
+    "SIGXFSZ",
+    "SIGVTALRM",
+    "SIGPROF",
+    "SIGWINCH",
+    "SIGIO",
+    "SIGPWR",
+    "SIGSYS",
+];
+
+/// A signal number. These are the same as Linux's numbers on x86_64.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Signal(u8);
+
+impl Signal {
+    pub(crate) const SIGINT: Self = Self(2);
+    pub(crate) const SIGQUIT: Self = Self(3);
+    pub(crate) const SIGILL: Self = Self(4);
+    pub(crate) const SIGBUS: Self = Self(7);
+    pub(crate) const SIGFPE: Self = Self(8);
+    pub(crate) const SIGKILL: Self = Self(9);
+    pub(crate) const SIGSEGV: Self = Self(11);
+    pub(crate) const SIGPIPE: Self = Self(13);
+    pub(crate) const SIGTERM: Self = Self(15);
+    const SIGCHLD: Self = Self(17);
+    pub(crate) const SIGCONT: Self = Self(18);
+    const SIGSTOP: Self = Self(19);
+    pub(crate) const SIGTSTP: Self = Self(20);
+    pub(crate) const SIGTTIN: Self = Self(21);
+    pub(crate) const SIGTTOU: Self = Self(22);
+    const SIGURG: Self = Self(23);
+    const SIGWINCH: Self = Self(28);
+
+    pub(crate) fn from_number(number: u64) -> Option<Self> {
+        let number = u8::try_from(number).ok()?;
+        (1..=NUM_SIGNALS as u8)
+            .contains(&number)
+            .then_some(Self(number))
+    }
+
+    pub(crate) fn number(self) -> u8 {
+        self.0
+    }
+
+    fn index(self) -> usize {
+        usize::from(self.0 - 1)
+    }
+
+    /// The bit for this signal in a signal set.
+    fn mask(self) -> u64 {
+        1 << self.index()
+    }
+
+    /// SIGKILL and SIGSTOP can't be caught, blocked, or ignored.
+    fn is_catchable(self) -> bool {
+        self != Self::SIGKILL && self != Self::SIGSTOP
+    }
+
+    fn default_action(self) -> DefaultAction {
+        match self {
+            // SIGCONT continues a stopped task even if it is ignored. That
+            // happens when it is sent, see `SignalState::resume`.
+            Self::SIGCHLD | Self::SIGCONT | Self::SIGURG | Self::SIGWINCH => DefaultAction::Ignore,
+            Self::SIGSTOP | Self::SIGTSTP | Self::SIGTTIN | Self::SIGTTOU => DefaultAction::Stop,
+            _ => DefaultAction::Terminate,
+        }
+    }
+
+    fn is_stop(self) -> bool {
+        self.default_action() == DefaultAction::Stop
+    }
+}
+
+impl fmt::Debug for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match SIGNAL_NAMES.get(usize::from(self.0)) {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "signal {}", self.0),
+        }
+    }
+}
+
+/// Parses a signal number (like "2") or name (like "SIGINT" or "INT").
+impl FromStr for Signal {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(number) = s.parse::<u64>() {
+            return Self::from_number(number).ok_or("signal number out of range");
+        }
+        let name = s.strip_prefix("SIG").unwrap_or(s);
+        SIGNAL_NAMES
+            .iter()
+            .skip(1)
+            .position(|known| known.strip_prefix("SIG") == Some(name))
+            .map(|index| Self(index as u8 + 1))
+            .ok_or("unknown signal name")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+}
+
+/// What a task does when it receives a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum SignalAction {
+    Default,
+    Ignore,
+    Handler(SignalHandler),
+}
+
+/// A user function to call when a signal is delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct SignalHandler {
+    address: u64,
+
+    /// Extra signals to block while the handler runs.
+    mask: u64,
+
+    /// `SA_*` flags.
+    flags: u64,
+
+    /// Where the handler returns to. This is our trampoline unless userspace
+    /// supplied its own with `SA_RESTORER`.
+    restorer: u64,
+}
+
+// Values for `UserSignalAction::handler`.
+const SIG_DFL: u64 = 0;
+const SIG_IGN: u64 = 1;
+
+// Flags for `UserSignalAction::flags`. We don't restart syscalls, so
+// `SA_RESTART` is accepted but interrupted syscalls always fail with EINTR.
+// Any other flag makes `sigaction` fail with EINVAL. In particular, we don't
+// pass a `siginfo_t` to handlers, so `SA_SIGINFO` handlers can't work.
+const SA_RESTORER: u64 = 0x0400_0000;
+const SA_RESTART: u64 = 0x1000_0000;
+const SA_NODEFER: u64 = 0x4000_0000;
+const SA_RESETHAND: u64 = 0x8000_0000;
+const SUPPORTED_SA_FLAGS: u64 = SA_RESTORER | SA_RESTART | SA_NODEFER | SA_RESETHAND;
+
+/// The `sigaction` struct userspace passes to and gets from the `sigaction`
+/// syscall. This has the same layout as Linux's `struct kernel_sigaction`.
+#[derive(Debug, Clone, Copy, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+pub(super) struct UserSignalAction {
+    handler: u64,
+    flags: u64,
+    restorer: u64,
+    mask: u64,
+}
+
+/// Returned when userspace asks for a signal action we don't support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct InvalidSignalAction;
+
+impl SignalAction {
+    pub(super) fn from_user(action: &UserSignalAction) -> Result<Self, InvalidSignalAction> {
+        if action.flags & !SUPPORTED_SA_FLAGS != 0 {
+            return Err(InvalidSignalAction);
+        }
+        match action.handler {
+            SIG_DFL => Ok(Self::Default),
+            SIG_IGN => Ok(Self::Ignore),
+            address if address < USER_MEMORY_END => {
+                let restorer = if action.flags & SA_RESTORER == 0 {
+                    SIGNAL_TRAMPOLINE_ADDR
+                } else {
+                    action.restorer
+                };
+                Ok(Self::Handler(SignalHandler {
+                    address,
+                    mask: action.mask,
+                    flags: action.flags,
+                    restorer,
+                }))
+            }
+            _ => Err(InvalidSignalAction),
+        }
+    }
+
+    pub(super) fn to_user(self) -> UserSignalAction {
+        match self {
+            Self::Default => UserSignalAction::new_zeroed(),
+            Self::Ignore => UserSignalAction {
+                handler: SIG_IGN,
+                ..UserSignalAction::new_zeroed()
+            },
+            Self::Handler(handler) => UserSignalAction {
+                handler: handler.address,
+                flags: handler.flags,
+                restorer: handler.restorer,
+                mask: handler.mask,
+            },
+        }
+    }
+}
+
+/// A task's signal state.
+#[derive(Debug, Clone)]
+pub(super) struct SignalState {
+    pending: u64,
+    blocked: u64,
+    actions: [SignalAction; NUM_SIGNALS],
+
+    /// Set when a stop signal is delivered, and cleared when the task gets
+    /// SIGCONT. See `stop_current_task`.
+    stopped: bool,
+}
+
+/// What to do with a signal that is being delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Disposition {
+    Terminate,
+    Stop,
+    Handle(SignalHandler),
+}
+
+impl SignalState {
+    pub(super) const fn new() -> Self {
+        Self {
+            pending: 0,
+            blocked: 0,
+            actions: [SignalAction::Default; NUM_SIGNALS],
+            stopped: false,
+        }
+    }
+
+    /// Copies the actions and blocked mask from the parent in fork. Pending
+    /// signals aren't inherited, but signals already sent to the child stay
+    /// pending.
+    pub(super) fn inherit(&mut self, parent: &Self) {
+        self.blocked = parent.blocked;
+        self.actions = parent.actions;
+    }
+
+    /// Handlers point into the old program, so exec resets them to the
+    /// default action. Ignored signals stay ignored.
+    pub(super) fn reset_for_exec(&mut self) {
+        for action in &mut self.actions {
+            if matches!(action, SignalAction::Handler(_)) {
+                *action = SignalAction::Default;
+            }
+        }
+    }
+
+    fn is_ignored(&self, signal: Signal) -> bool {
+        match self.actions[signal.index()] {
+            SignalAction::Ignore => true,
+            SignalAction::Default => signal.default_action() == DefaultAction::Ignore,
+            SignalAction::Handler(_) => false,
+        }
+    }
+
+    /// Marks the signal as pending. Returns false if the signal was discarded
+    /// because it is ignored. (Blocked signals stay pending even if they are
+    /// ignored, because the action might change before they are unblocked.)
+    fn send(&mut self, signal: Signal) -> bool {
+        // Like in Linux, SIGCONT discards pending stop signals and vice versa,
+        // even if the new signal is then discarded itself.
+        if signal == Signal::SIGCONT {
+            self.pending &= !STOP_SIGNALS;
+        } else if signal.is_stop() {
+            self.pending &= !Signal::SIGCONT.mask();
+        }
+
+        if self.blocked & signal.mask() == 0 && self.is_ignored(signal) {
+            return false;
+        }
+        self.pending |= signal.mask();
+        true
+    }
+
+    /// Returns true if there is a pending signal that isn't blocked or
+    /// ignored. Blocking waits give up when this is true so the signal can be
+    /// delivered.
+    fn has_deliverable(&mut self) -> bool {
+        self.next_deliverable().is_some()
+    }
+
+    /// Finds the lowest numbered pending signal that isn't blocked, and
+    /// returns what to do with it. Ignored signals are discarded along the
+    /// way. The returned signal is left pending.
+    fn next_deliverable(&mut self) -> Option<(Signal, Disposition)> {
+        loop {
+            let deliverable = self.pending & !self.blocked;
+            if deliverable == 0 {
+                return None;
+            }
+            let signal = Signal(deliverable.trailing_zeros() as u8 + 1);
+            match self.actions[signal.index()] {
+                SignalAction::Handler(handler) => {
+                    return Some((signal, Disposition::Handle(handler)));
+                }
+                _ if self.is_ignored(signal) => self.pending &= !signal.mask(),
+                _ if signal.is_stop() => return Some((signal, Disposition::Stop)),
+                _ => return Some((signal, Disposition::Terminate)),
+            }
+        }
+    }
+
+    /// Like `next_deliverable`, but removes the signal from the pending set.
+    /// If a handler is going to run, this also blocks the handler's signals
+    /// and returns the blocked mask to restore after the handler returns. If
+    /// the signal stops the task, the task is marked as stopped.
+    fn take_deliverable(&mut self) -> Option<(Signal, Disposition, u64)> {
+        let (signal, disposition) = self.next_deliverable()?;
+        self.pending &= !signal.mask();
+        let old_blocked = self.blocked;
+        if disposition == Disposition::Stop {
+            self.stopped = true;
+        }
+        if let Disposition::Handle(handler) = disposition {
+            let mut mask = handler.mask;
+            if handler.flags & SA_NODEFER == 0 {
+                mask |= signal.mask();
+            }
+            self.set_blocked(self.blocked | mask);
+            if handler.flags & SA_RESETHAND != 0 {
+                self.actions[signal.index()] = SignalAction::Default;
+            }
+        }
+        Some((signal, disposition, old_blocked))
+    }
+
+    /// Clears the stopped flag for SIGCONT, and returns true if the task was
+    /// stopped.
+    fn resume(&mut self) -> bool {
+        core::mem::replace(&mut self.stopped, false)
+    }
+
+    /// A stopped task stays asleep until it is continued or killed.
+    fn can_run(&self) -> bool {
+        !self.stopped || self.pending & Signal::SIGKILL.mask() != 0
+    }
+
+    /// Returns true if `signal` is blocked or ignored, so sending it would
+    /// have no effect right away.
+    fn is_blocked_or_ignored(&self, signal: Signal) -> bool {
+        self.blocked & signal.mask() != 0 || self.is_ignored(signal)
+    }
+
+    /// Returns true if `signal` will run a user handler, rather than being
+    /// blocked, ignored, or killing the task.
+    fn will_run_handler(&self, signal: Signal) -> bool {
+        self.blocked & signal.mask() == 0
+            && matches!(self.actions[signal.index()], SignalAction::Handler(_))
+    }
+
+    pub(super) fn action(&self, signal: Signal) -> SignalAction {
+        self.actions[signal.index()]
+    }
+
+    /// Changes the action for `signal`, and returns the old one.
+    pub(super) fn set_action(
+        &mut self,
+        signal: Signal,
+        action: SignalAction,
+    ) -> Result<SignalAction, InvalidSignalAction> {
+        if !signal.is_catchable() {
+            return Err(InvalidSignalAction);
+        }
+        let old_action = core::mem::replace(&mut self.actions[signal.index()], action);
+
+        // POSIX says setting a pending signal to be ignored discards it.
+        if self.is_ignored(signal) {
+            self.pending &= !signal.mask();
+        }
+        Ok(old_action)
+    }
+
+    pub(super) fn blocked(&self) -> u64 {
+        self.blocked
+    }
+
+    /// Sets the blocked mask. Attempts to block SIGKILL or SIGSTOP are
+    /// silently ignored, like on Linux.
+    pub(super) fn set_blocked(&mut self, blocked: u64) {
+        self.blocked = blocked & !(Signal::SIGKILL.mask() | Signal::SIGSTOP.mask());
+    }
+}
+
+/// Bits for all of the stop signals in a signal set.
+const STOP_SIGNALS: u64 = (1 << (Signal::SIGSTOP.0 - 1))
+    | (1 << (Signal::SIGTSTP.0 - 1))
+    | (1 << (Signal::SIGTTIN.0 - 1))
+    | (1 << (Signal::SIGTTOU.0 - 1));
+
+/// Stopped tasks sleep here until they are continued.
+static STOPPED_TASKS: WaitQueue = WaitQueue::new();
+
+/// Sends `signal` to the task with the given ID. If the signal isn't ignored,
+/// this also wakes the task up if it is sleeping so it can handle the signal.
+/// SIGCONT continues the task if it is stopped. Returns false if there is no
+/// such task.
+///
+/// Kernel tasks never return to userspace, so signals sent to them stay
+/// pending forever. Like in Linux, the init task only gets signals it has a
+/// handler for, so it can't be killed (or stopped) by accident.
+pub(crate) fn send_signal(id: TaskId, signal: Signal) -> bool {
+    let Some(task) = TASKS.lock_disable_interrupts().get_task(id) else {
+        return false;
+    };
+    let (pending, continued) = {
+        let mut signals = task.signals.lock();
+        if is_init_task(id) && !matches!(signals.action(signal), SignalAction::Handler(_)) {
+            return true;
+        }
+        let continued = signal == Signal::SIGCONT && signals.resume();
+        (signals.send(signal), continued)
+    };
+    if continued {
+        family::task_continued(id);
+        STOPPED_TASKS.wake_all();
+    }
+    if pending {
+        awaken_task_if_sleeping(&task);
+    }
+    true
+}
+
+/// Returns true if the current task has a signal to handle. Used to interrupt
+/// blocking syscalls.
+pub(crate) fn current_task_has_deliverable_signal() -> bool {
+    current_task().signals.lock().has_deliverable()
+}
+
+/// Called from CPU exception handlers. Returns true if the current task has
+/// an unblocked user handler for `signal`, in which case the signal has been
+/// marked pending and will be delivered when the handler returns to
+/// userspace. Otherwise, the caller should kill the task.
+pub(crate) fn raise_fault_signal(signal: Signal) -> bool {
+    let task = current_task();
+    let mut signals = task.signals.lock();
+    if signals.will_run_handler(signal) {
+        signals.pending |= signal.mask();
+        true
+    } else {
+        false
+    }
+}
+
+/// The x86_64 System V ABI lets functions use 128 bytes below the stack
+/// pointer without moving it, so we can't put anything there.
+const RED_ZONE_SIZE: u64 = 128;
+
+/// Pushed onto the user stack when a signal handler is called. The handler
+/// sees `return_address` as its return address, and `sigreturn` uses the rest
+/// to restore the task.
+#[derive(FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+#[allow(dead_code)] // Some fields are only read by userspace
+struct SignalFrame {
+    return_address: u64,
+    signal: u64,
+    blocked: u64,
+    registers: TaskRegisters,
+}
+
+/// Delivers the current task's next pending signal, if there is one. If the
+/// signal has a user handler, `registers` are modified so returning to
+/// userspace calls the handler. If the signal stops the task, this returns
+/// once the task is continued, after delivering whatever signal is next.
+/// Called right before returning to userspace from a syscall.
+pub(super) fn deliver_pending_signal(registers: &mut TaskRegisters) {
+    loop {
+        let task = current_task();
+        let delivery = task.signals.lock().take_deliverable();
+        drop(task);
+
+        let Some((signal, disposition, old_blocked)) = delivery else {
+            return;
+        };
+        match disposition {
+            Disposition::Terminate => kill_current_task(TaskExitCode::KilledBySignal(signal)),
+            Disposition::Stop => stop_current_task(signal),
+            Disposition::Handle(handler) => {
+                if push_signal_frame(registers, signal, handler, old_blocked).is_err() {
+                    kill_for_bad_signal_stack(signal);
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Sleeps until the current task is continued by SIGCONT or killed by
+/// SIGKILL, after a stop signal was taken by `take_deliverable`. The parent
+/// can find out about both with `waitpid`.
+///
+/// TODO: Stop every thread of a process, not just the one that got the
+/// signal.
+fn stop_current_task(signal: Signal) {
+    let task = current_task();
+    // SIGCONT might have arrived already, in which case there is nothing to
+    // tell the parent.
+    if !task.signals.lock().can_run() {
+        family::task_stopped(task.id, signal);
+    }
+    STOPPED_TASKS.wait_until(|| task.signals.lock().can_run().then_some(()));
+}
+
+/// Stops the current task if its next signal is a stop signal, like it would
+/// be when returning to userspace, so blocking syscalls can keep going once
+/// the task is continued instead of failing with `EINTR`. Returns false if
+/// there is no signal to deliver, and `Interrupted` if the next signal has to
+/// be delivered in userspace.
+pub(crate) fn stop_for_pending_signal() -> Result<bool, Interrupted> {
+    let task = current_task();
+    let mut signals = task.signals.lock();
+    match signals.next_deliverable() {
+        None => Ok(false),
+        Some((_, Disposition::Stop)) => {
+            let (signal, _, _) = signals
+                .take_deliverable()
+                .expect("deliverable signal disappeared");
+            drop(signals);
+            drop(task);
+            stop_current_task(signal);
+            Ok(true)
+        }
+        Some(_) => Err(Interrupted),
+    }
+}
+
+/// Returns true if `signal` is blocked or ignored by the current task.
+pub(crate) fn current_task_ignores_signal(signal: Signal) -> bool {
+    current_task().signals.lock().is_blocked_or_ignored(signal)
+}
+
+fn push_signal_frame(
+    registers: &mut TaskRegisters,
+    signal: Signal,
+    handler: SignalHandler,
+    old_blocked: u64,
+) -> Result<(), BadUserAddress> {
+    let frame = SignalFrame {
+        return_address: handler.restorer,
+        signal: u64::from(signal.0),
+        blocked: old_blocked,
+        registers: registers.clone(),
+    };
+
+    // Make the stack look like the handler was just called: the stack pointer
+    // is 8 bytes past a 16 byte boundary because of the return address.
+    let frame_size = core::mem::size_of::<SignalFrame>() as u64;
+    let frame_addr =
+        (registers.rsp.wrapping_sub(RED_ZONE_SIZE + frame_size) & !0xf).wrapping_sub(8);
+    write_user(frame_addr, &frame)?;
+
+    registers.rip = handler.address;
+    registers.rsp = frame_addr;
+
+    // Linux also passes pointers to a siginfo_t and a ucontext_t in rsi and
+    // rdx. We don't have those, so the handler only gets the signal number.
+    registers.rdi = u64::from(signal.0);
+    registers.rsi = 0;
+    registers.rdx = 0;
+
+    // The ABI requires the direction flag to be clear when calling functions.
+    registers.rflags &= !RFlags::DIRECTION_FLAG.bits();
+    Ok(())
+}
+
+/// Implements `sigreturn`. The signal trampoline calls this after the handler
+/// returns, and we restore the registers and blocked mask from the
+/// `SignalFrame` the handler was called with.
+pub(super) fn return_from_signal_handler(registers: &mut TaskRegisters) {
+    // The handler's `ret` popped the return address, so the rest of the frame
+    // starts at the stack pointer.
+    let frame_addr = registers.rsp.wrapping_sub(8);
+    let Ok(frame) = read_user::<SignalFrame>(frame_addr) else {
+        kill_for_bad_signal_stack(Signal::SIGSEGV);
+    };
+    if !restore_user_registers(registers, &frame.registers) {
+        kill_for_bad_signal_stack(Signal::SIGSEGV);
+    }
+    current_task().signals.lock().set_blocked(frame.blocked);
+}
+
+/// Copies registers that userspace saved on its stack into `registers`, making
+/// sure userspace can't use them to get into ring 0 or to make returning to
+/// userspace fault in the kernel. Returns false if the registers are invalid.
+fn restore_user_registers(registers: &mut TaskRegisters, saved: &TaskRegisters) -> bool {
+    let rip = saved.rip;
+    let rsp = saved.rsp;
+    if rip >= USER_MEMORY_END || rsp >= USER_MEMORY_END {
+        return false;
+    }
+
+    // Userspace can only change the arithmetic flags, the direction flag, and
+    // the trap and alignment check flags. Interrupts must stay enabled.
+    let user_flags = RFlags::CARRY_FLAG
+        | RFlags::PARITY_FLAG
+        | RFlags::AUXILIARY_CARRY_FLAG
+        | RFlags::ZERO_FLAG
+        | RFlags::SIGN_FLAG
+        | RFlags::TRAP_FLAG
+        | RFlags::DIRECTION_FLAG
+        | RFlags::OVERFLOW_FLAG
+        | RFlags::ALIGNMENT_CHECK;
+    let rflags = (saved.rflags & user_flags.bits()) | RFlags::INTERRUPT_FLAG.bits();
+
+    *registers = TaskRegisters {
+        rflags,
+        cs: u64::from(USER_CODE_SELECTOR.0),
+        ss: u64::from(USER_DATA_SELECTOR.0),
+        ..saved.clone()
+    };
+    true
+}
+
+/// If the current task has a signal to deliver, returns the instruction and
+/// stack pointers to use when the current interrupt or exception returns to
+/// userspace. This sends the task to the signal entry trampoline, which calls
+/// `resume_from_signal_entry` so we can deliver the signal with the full set
+/// of user registers. Signals that terminate the task are handled right here.
+/// Stop signals go through the trampoline too, since it isn't safe to sleep
+/// until the task is continued in the middle of an interrupt handler.
+pub(crate) fn redirect_interrupted_task_to_signal_entry(
+    instruction_pointer: VirtAddr,
+    stack_pointer: VirtAddr,
+) -> Option<(VirtAddr, VirtAddr)> {
+    let task = current_task();
+    let next = task.signals.lock().next_deliverable();
+    drop(task);
+
+    let (signal, disposition) = next?;
+    if disposition == Disposition::Terminate {
+        kill_current_task(TaskExitCode::KilledBySignal(signal));
+    }
+
+    // Save the interrupted instruction pointer below the red zone. The
+    // trampoline pushes the registers that `syscall` clobbers below it.
+    let saved_rip_addr = stack_pointer.as_u64().wrapping_sub(RED_ZONE_SIZE + 8);
+    if write_user(saved_rip_addr, &instruction_pointer.as_u64()).is_err() {
+        kill_for_bad_signal_stack(signal);
+    }
+    Some((
+        signal_entry_trampoline_addr(),
+        VirtAddr::new(saved_rip_addr),
+    ))
+}
+
+/// Called via the signal entry trampoline. Restores the registers the
+/// trampoline saved, which makes `registers` exactly what they were when the
+/// task was interrupted. The syscall handler then delivers the signal as if
+/// the task had made a syscall at that point.
+pub(super) fn resume_from_signal_entry(registers: &mut TaskRegisters) {
+    // The trampoline pushed rdi, rcx, and r11 below the saved rip.
+    let saved_addr = registers.rsp;
+    let Ok([r11, rcx, rdi, rip]) = read_user::<[u64; 4]>(saved_addr) else {
+        kill_for_bad_signal_stack(Signal::SIGSEGV);
+    };
+    if rip >= USER_MEMORY_END {
+        kill_for_bad_signal_stack(Signal::SIGSEGV);
+    }
+    registers.r11 = r11;
+    registers.rcx = rcx;
+    registers.rdi = rdi;
+    registers.rip = rip;
+    registers.rsp = saved_addr + 4 * 8 + RED_ZONE_SIZE;
+}
+
+/// Kills the current task when we can't read or write a signal frame on its
+/// stack. Linux does the same thing, with SIGSEGV.
+fn kill_for_bad_signal_stack(signal: Signal) -> ! {
+    let task = current_task();
+    log::warn!(
+        "task {} {:?} has a bad stack while handling {signal}, killing it",
+        task.name,
+        task.id,
+    );
+    drop(task);
+    kill_current_task(TaskExitCode::KilledBySignal(Signal::SIGSEGV));
+}
+
+/// Where the signal trampoline page is mapped in every user program. This is
+/// the last page of user memory, so it is out of the way of everything else.
+pub(super) const SIGNAL_TRAMPOLINE_ADDR: u64 = USER_MEMORY_END - PAGE_SIZE as u64;
+
+global_asm!(
+    // The code for the signal trampoline page. This is only ever copied into
+    // user memory, so it lives in .rodata.
+    ".pushsection .rodata",
+    ".global SIGNAL_TRAMPOLINE_START",
+    "SIGNAL_TRAMPOLINE_START:",
+    // Signal handlers return here.
+    "mov edi, {sigreturn}",
+    "syscall",
+    "ud2",
+    ".balign 16, 0xcc",
+    // Interrupts and exceptions send tasks here when a signal handler needs
+    // to run. The interrupted rip is already on the stack.
+    ".global SIGNAL_ENTRY_TRAMPOLINE",
+    "SIGNAL_ENTRY_TRAMPOLINE:",
+    "push rdi",
+    "push rcx",
+    "push r11",
+    "mov edi, {signal_entry}",
+    "syscall",
+    "ud2",
+    ".global SIGNAL_TRAMPOLINE_END",
+    "SIGNAL_TRAMPOLINE_END:",
+    ".popsection",
+    sigreturn = const SYS_SIGRETURN,
+    signal_entry = const SYS_SIGNAL_ENTRY,
+);
+
+extern "C" {
+    static SIGNAL_TRAMPOLINE_START: u8;
+    static SIGNAL_ENTRY_TRAMPOLINE: u8;
+    static SIGNAL_TRAMPOLINE_END: u8;
+}
+
+fn signal_trampoline_code() -> &'static [u8] {
+    unsafe {
+        let start = core::ptr::addr_of!(SIGNAL_TRAMPOLINE_START);
+        let end = core::ptr::addr_of!(SIGNAL_TRAMPOLINE_END);
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+fn signal_entry_trampoline_addr() -> VirtAddr {
+    let offset = unsafe {
+        core::ptr::addr_of!(SIGNAL_ENTRY_TRAMPOLINE) as u64
+            - core::ptr::addr_of!(SIGNAL_TRAMPOLINE_START) as u64
+    };
+    VirtAddr::new(SIGNAL_TRAMPOLINE_ADDR + offset)
+}
+
+/// Maps the signal trampoline page into a new program.
+pub(super) fn map_signal_trampoline(
+    table: &mut Level4PageTable,
+    vm_areas: &mut VirtualMemoryAreas,
+) -> Result<(), VmError> {
+    let start = VirtAddr::new(SIGNAL_TRAMPOLINE_ADDR);
+    let area = VirtualMemoryArea::new(
+        start,
+        start + PAGE_SIZE_U64,
+        PageTableEntryFlags::PRESENT,
+        VirtualMemoryAreaKind::SignalTrampoline,
+    );
+    map_pages_with_contents(table, &area, signal_trampoline_code())?;
+    vm_areas.insert(area);
+    Ok(())
+}
@@ -0,0 +1,768 @@
+//! Linux's x86_64 syscall ABI, so programs built for Linux (like static musl
+//! binaries) can run without porting them. Tasks use this ABI if they were
+//! started with `EXEC_LINUX_ABI`, see `SyscallAbi`.
+//!
+//! Where a Linux syscall takes the same arguments as ours, we use our handler
+//! directly. Everything else gets a small adapter here. Unimplemented syscalls
+//! return `ENOSYS`, which most of libc handles gracefully.
+
+use alloc::vec::Vec;
+
+use x86_64::registers::model_specific::FsBase;
+use zerocopy::{FromBytes, FromZeroes};
+
+use crate::sched::family;
+use crate::sched::job_control::current_job_control;
+use crate::sched::schedcore::{current_task, current_task_id};
+use crate::sched::user_memory::{copy_to_user, read_user, read_user_bytes, write_user};
+use crate::sched::userspace::{clone_current_task, fork_current_task};
+use crate::vfs;
+
+use super::{
+    create_pipe, exec_path, get_open_file, kill_task, open_path, read_file_to_user, stat_fd,
+    stat_path, syscall_arch_prctl, syscall_args, syscall_brk, syscall_clock_gettime, syscall_close,
+    syscall_exit, syscall_fork, syscall_fstat, syscall_futex, syscall_getdents, syscall_getpgid,
+    syscall_getrandom, syscall_getsid, syscall_ioctl, syscall_kill, syscall_lseek, syscall_mmap,
+    syscall_munmap, syscall_nanosleep, syscall_pipe, syscall_read, syscall_setpgid, syscall_setsid,
+    syscall_sigaction, syscall_sigprocmask, syscall_sigreturn, syscall_write, user_c_str,
+    user_virt_addr, wait_for_pid, write_file, SyscallAbi, SyscallError, SyscallHandler,
+    SyscallResult, TaskRegisters, MAX_IO_LEN,
+};
+
+/// Returns the handler for a Linux syscall number, or `None` if we don't
+/// implement it.
+pub(super) fn syscall_handler(number: u64) -> Option<SyscallHandler> {
+    let handler: SyscallHandler = match number {
+        0 => syscall_read,
+        1 => syscall_write,
+        2 => linux_open,
+        3 => syscall_close,
+        // We don't have symlinks, so `lstat` is the same as `stat`.
+        4 | 6 => linux_stat,
+        5 => syscall_fstat,
+        8 => syscall_lseek,
+        9 => syscall_mmap,
+        11 => syscall_munmap,
+        12 => syscall_brk,
+        13 => linux_rt_sigaction,
+        14 => linux_rt_sigprocmask,
+        // Our sigreturn expects the same stack layout as Linux's, where the
+        // handler's return address (our trampoline, or the libc restorer) has
+        // just been popped.
+        15 => syscall_sigreturn,
+        16 => syscall_ioctl,
+        19 => linux_readv,
+        20 => linux_writev,
+        22 => syscall_pipe,
+        24 => linux_sched_yield,
+        35 => syscall_nanosleep,
+        39 | 186 => linux_gettid,
+        56 => linux_clone,
+        57 | 58 => syscall_fork,
+        59 => linux_execve,
+        // There are no thread groups (see `linux_clone`), so `exit_group`
+        // only has the calling task to exit.
+        60 | 231 => syscall_exit,
+        61 => linux_wait4,
+        62 => syscall_kill,
+        63 => linux_uname,
+        // Everything runs as root.
+        102 | 104 | 107 | 108 => linux_getuid,
+        109 => syscall_setpgid,
+        110 => linux_getppid,
+        111 => linux_getpgrp,
+        112 => syscall_setsid,
+        121 => syscall_getpgid,
+        124 => syscall_getsid,
+        158 => syscall_arch_prctl,
+        200 => linux_tkill,
+        202 => syscall_futex,
+        217 => syscall_getdents,
+        218 => linux_set_tid_address,
+        228 => syscall_clock_gettime,
+        234 => linux_tgkill,
+        257 => linux_openat,
+        262 => linux_newfstatat,
+        293 => linux_pipe2,
+        318 => syscall_getrandom,
+        _ => return None,
+    };
+    Some(handler)
+}
+
+/// Returns the name of a Linux syscall, for logging.
+pub(super) fn syscall_name(number: u64) -> Option<&'static str> {
+    let number = usize::try_from(number).ok()?;
+    LINUX_SYSCALL_NAMES.get(number).copied().or_else(|| {
+        let index = number.checked_sub(424)?;
+        LINUX_SYSCALL_NAMES_FROM_424.get(index).copied()
+    })
+}
+
+fn linux_open(registers: &mut TaskRegisters) -> SyscallResult {
+    let [path_ptr, flags, _mode, ..] = syscall_args(registers);
+    let path = user_c_path(path_ptr)?;
+    // Our open flags have the same values as Linux's. Flags we don't support,
+    // like O_LARGEFILE, are ignored.
+    open_path(&path, flags)
+}
+
+/// We don't have a current directory or directory file descriptors, and paths
+/// have to be absolute anyway, so `dirfd` is ignored.
+fn linux_openat(registers: &mut TaskRegisters) -> SyscallResult {
+    let [_dirfd, path_ptr, flags, _mode, ..] = syscall_args(registers);
+    let path = user_c_path(path_ptr)?;
+    open_path(&path, flags)
+}
+
+fn linux_stat(registers: &mut TaskRegisters) -> SyscallResult {
+    let [path_ptr, stat_ptr, ..] = syscall_args(registers);
+    let path = user_c_path(path_ptr)?;
+    stat_path(&path, stat_ptr)
+}
+
+/// `newfstatat` flag to stat `dirfd` itself when the path is empty.
+const AT_EMPTY_PATH: u64 = 0x1000;
+
+/// Like in `linux_openat`, `dirfd` is ignored, unless the path is empty and
+/// `AT_EMPTY_PATH` is set (which is how libc implements `fstat`). We don't
+/// have symlinks, so `AT_SYMLINK_NOFOLLOW` doesn't change anything.
+fn linux_newfstatat(registers: &mut TaskRegisters) -> SyscallResult {
+    let [dirfd, path_ptr, stat_ptr, flags, ..] = syscall_args(registers);
+    if flags & AT_EMPTY_PATH != 0 && user_c_str(path_ptr)?.is_empty() {
+        return stat_fd(dirfd, stat_ptr);
+    }
+    let path = user_c_path(path_ptr)?;
+    stat_path(&path, stat_ptr)
+}
+
+/// Size of the signal sets userspace passes to `rt_sigaction` and
+/// `rt_sigprocmask`. Our signal sets are a single `u64`, like Linux's.
+const SIGSET_SIZE: u64 = 8;
+
+fn linux_rt_sigaction(registers: &mut TaskRegisters) -> SyscallResult {
+    let [_signal, _action_ptr, _old_action_ptr, sigset_size, ..] = syscall_args(registers);
+    if sigset_size != SIGSET_SIZE {
+        return Err(SyscallError::InvalidArgument);
+    }
+    syscall_sigaction(registers)
+}
+
+fn linux_rt_sigprocmask(registers: &mut TaskRegisters) -> SyscallResult {
+    let [_how, _set_ptr, _old_set_ptr, sigset_size, ..] = syscall_args(registers);
+    if sigset_size != SIGSET_SIZE {
+        return Err(SyscallError::InvalidArgument);
+    }
+    syscall_sigprocmask(registers)
+}
+
+/// Linux's `struct iovec`.
+#[derive(Debug, Clone, Copy, FromZeroes, FromBytes)]
+#[repr(C)]
+struct UserIovec {
+    base: u64,
+    len: u64,
+}
+
+/// Maximum number of `iovec`s in a `readv` or `writev`. Same as Linux.
+const IOV_MAX: u64 = 1024;
+
+fn user_iovecs(iov_ptr: u64, iov_count: u64) -> Result<Vec<UserIovec>, SyscallError> {
+    if iov_count > IOV_MAX {
+        return Err(SyscallError::InvalidArgument);
+    }
+    (0..iov_count)
+        .map(|i| {
+            let addr = iov_ptr
+                .checked_add(i * core::mem::size_of::<UserIovec>() as u64)
+                .ok_or(SyscallError::BadAddress)?;
+            read_user(addr).map_err(Into::into)
+        })
+        .collect()
+}
+
+/// Reads into each buffer in turn with a single read from the file, so like
+/// `read`, at most `MAX_IO_LEN` bytes are read.
+fn linux_readv(registers: &mut TaskRegisters) -> SyscallResult {
+    let [fd, iov_ptr, iov_count, ..] = syscall_args(registers);
+    let file = get_open_file(fd)?;
+    let user_buffers: Vec<(u64, u64)> = user_iovecs(iov_ptr, iov_count)?
+        .into_iter()
+        .map(|iovec| (iovec.base, iovec.len))
+        .collect();
+    let bytes_read = read_file_to_user(&file, &user_buffers)?;
+    Ok(bytes_read as u64)
+}
+
+/// Gathers the buffers and writes them with a single write to the file, so
+/// like `write`, at most `MAX_IO_LEN` bytes are written.
+fn linux_writev(registers: &mut TaskRegisters) -> SyscallResult {
+    let [fd, iov_ptr, iov_count, ..] = syscall_args(registers);
+    let file = get_open_file(fd)?;
+    let mut data = Vec::new();
+    for iovec in user_iovecs(iov_ptr, iov_count)? {
+        let len = (iovec.len as usize).min(MAX_IO_LEN - data.len());
+        data.extend(read_user_bytes(iovec.base, len)?);
+        if data.len() == MAX_IO_LEN {
+            break;
+        }
+    }
+    write_file(&file, &data)
+}
+
+/// We always run the scheduler before returning from a syscall, so there is
+/// nothing else to do.
+fn linux_sched_yield(_registers: &mut TaskRegisters) -> SyscallResult {
+    Ok(0)
+}
+
+/// Used for both `getpid` and `gettid`. We don't support `CLONE_THREAD`, so
+/// every task is alone in its thread group and the two IDs are the same.
+fn linux_gettid(_registers: &mut TaskRegisters) -> SyscallResult {
+    Ok(u64::from(u32::from(current_task_id())))
+}
+
+fn linux_getppid(_registers: &mut TaskRegisters) -> SyscallResult {
+    let parent = family::parent(current_task_id());
+    Ok(parent.map_or(0, |parent| u64::from(u32::from(parent))))
+}
+
+fn linux_getpgrp(_registers: &mut TaskRegisters) -> SyscallResult {
+    Ok(u64::from(u32::from(current_job_control().process_group)))
+}
+
+fn linux_getuid(_registers: &mut TaskRegisters) -> SyscallResult {
+    Ok(0)
+}
+
+/// `clone` flags.
+const CLONE_EXIT_SIGNAL_MASK: u64 = 0xff;
+const CLONE_VM: u64 = 0x100;
+const CLONE_FS: u64 = 0x200;
+const CLONE_FILES: u64 = 0x400;
+const CLONE_SIGHAND: u64 = 0x800;
+const CLONE_VFORK: u64 = 0x4000;
+const CLONE_THREAD: u64 = 0x1_0000;
+const CLONE_SYSVSEM: u64 = 0x4_0000;
+const CLONE_SETTLS: u64 = 0x8_0000;
+const CLONE_PARENT_SETTID: u64 = 0x10_0000;
+const CLONE_CHILD_CLEARTID: u64 = 0x20_0000;
+const CLONE_DETACHED: u64 = 0x40_0000;
+const CLONE_CHILD_SETTID: u64 = 0x100_0000;
+
+/// Flags we ignore because we either always behave that way or they don't
+/// matter to us.
+const CLONE_IGNORED_FLAGS: u64 = CLONE_FS | CLONE_FILES | CLONE_SYSVSEM | CLONE_DETACHED;
+
+/// Flags that must all be set to create a task that shares our memory, and
+/// the other flags allowed with them.
+const CLONE_SHARED_VM_FLAGS: u64 = CLONE_VM | CLONE_SIGHAND;
+const CLONE_SHARED_VM_OPTIONAL_FLAGS: u64 =
+    CLONE_SETTLS | CLONE_PARENT_SETTID | CLONE_CHILD_SETTID | CLONE_CHILD_CLEARTID;
+
+/// Signal number of `SIGCHLD`, the usual exit signal for `clone`.
+const SIGCHLD: u64 = 17;
+
+/// Supports creating a process like `fork` or `vfork`, and creating a task
+/// that shares our memory (`CLONE_VM` and `CLONE_SIGHAND` together). Since we
+/// don't share memory with vfork children, a vfork is just a fork that
+/// doesn't make the parent wait.
+///
+/// TODO: Support `CLONE_THREAD`, which libc uses to create threads. That needs
+/// thread groups, so `getpid` returns the same ID in every thread and
+/// `exit_group` exits all of them. Until then, tasks that share memory are
+/// separate processes.
+fn linux_clone(registers: &mut TaskRegisters) -> SyscallResult {
+    let [flags, stack_ptr, parent_tid_ptr, child_tid_ptr, tls, ..] = syscall_args(registers);
+    let exit_signal = flags & CLONE_EXIT_SIGNAL_MASK;
+    let flags = flags & !(CLONE_EXIT_SIGNAL_MASK | CLONE_IGNORED_FLAGS);
+    if flags & CLONE_THREAD != 0 {
+        log::warn!("clone: CLONE_THREAD isn't supported without thread groups");
+        return Err(SyscallError::InvalidArgument);
+    }
+    let child_id = if flags & CLONE_SHARED_VM_FLAGS == CLONE_SHARED_VM_FLAGS
+        && flags & !(CLONE_SHARED_VM_FLAGS | CLONE_SHARED_VM_OPTIONAL_FLAGS) == 0
+    {
+        let stack_ptr = user_virt_addr(stack_ptr)?;
+        let fs_base = if flags & CLONE_SETTLS == 0 {
+            FsBase::read()
+        } else {
+            user_virt_addr(tls)?
+        };
+        let clear_child_tid = if flags & CLONE_CHILD_CLEARTID == 0 {
+            0
+        } else {
+            child_tid_ptr
+        };
+        // The child shares our memory, so we can write its TID before it
+        // starts running.
+        let child_id = clone_current_task(registers, stack_ptr, fs_base, clear_child_tid);
+        let tid = u32::from(child_id);
+        if flags & CLONE_PARENT_SETTID != 0 {
+            write_user(parent_tid_ptr, &tid)?;
+        }
+        if flags & CLONE_CHILD_SETTID != 0 {
+            write_user(child_tid_ptr, &tid)?;
+        }
+        child_id
+    } else if (flags == 0 || flags == CLONE_VM | CLONE_VFORK)
+        && (exit_signal == 0 || exit_signal == SIGCHLD)
+    {
+        // TODO: We never send SIGCHLD, so there is no difference between the
+        // exit signals.
+        let mut child_registers = registers.clone();
+        if stack_ptr != 0 {
+            child_registers.rsp = user_virt_addr(stack_ptr)?.as_u64();
+        }
+        fork_current_task(&child_registers).map_err(|_| SyscallError::OutOfMemory)?
+    } else {
+        log::warn!("unsupported clone flags {flags:#x} with exit signal {exit_signal}");
+        return Err(SyscallError::InvalidArgument);
+    };
+    Ok(u64::from(u32::from(child_id)))
+}
+
+fn linux_execve(registers: &mut TaskRegisters) -> SyscallResult {
+    let [path_ptr, argv_ptr, envp_ptr, ..] = syscall_args(registers);
+    let path = user_c_path(path_ptr)?;
+    exec_path(registers, &path, argv_ptr, envp_ptr, SyscallAbi::Linux)
+}
+
+/// Size of Linux's `struct rusage`.
+const RUSAGE_SIZE: usize = 144;
+
+/// Like our `waitpid`, which takes the same options, but the resource usage is
+/// all zeros.
+fn linux_wait4(registers: &mut TaskRegisters) -> SyscallResult {
+    let [pid, status_ptr, options, rusage_ptr, ..] = syscall_args(registers);
+    let child_id = wait_for_pid(pid, status_ptr, options)?;
+    if rusage_ptr != 0 {
+        copy_to_user(rusage_ptr, &[0; RUSAGE_SIZE])?;
+    }
+    Ok(child_id)
+}
+
+/// Length of each field in Linux's `struct utsname`, including the nul.
+const UTSNAME_FIELD_LEN: usize = 65;
+
+fn linux_uname(registers: &mut TaskRegisters) -> SyscallResult {
+    let [buf_ptr, ..] = syscall_args(registers);
+    let fields = [
+        "rust-os",                 // sysname
+        "rust-os",                 // nodename
+        env!("CARGO_PKG_VERSION"), // release
+        "rust-os",                 // version
+        "x86_64",                  // machine
+        "(none)",                  // domainname
+    ];
+    let mut buffer = [0; UTSNAME_FIELD_LEN * 6];
+    for (field, dest) in fields.iter().zip(buffer.chunks_mut(UTSNAME_FIELD_LEN)) {
+        dest[..field.len()].copy_from_slice(field.as_bytes());
+    }
+    copy_to_user(buf_ptr, &buffer)?;
+    Ok(0)
+}
+
+/// Sets the current task's `clear_child_tid` address (see `Task`) and
+/// returns its TID.
+fn linux_set_tid_address(registers: &mut TaskRegisters) -> SyscallResult {
+    let [tid_ptr, ..] = syscall_args(registers);
+    let task = current_task();
+    task.clear_child_tid.store(tid_ptr);
+    Ok(u64::from(u32::from(task.id)))
+}
+
+fn linux_tkill(registers: &mut TaskRegisters) -> SyscallResult {
+    let [tid, signal, ..] = syscall_args(registers);
+    kill_task(tid, signal)
+}
+
+/// We don't have thread groups, so the thread group ID is ignored.
+fn linux_tgkill(registers: &mut TaskRegisters) -> SyscallResult {
+    let [_tgid, tid, signal, ..] = syscall_args(registers);
+    kill_task(tid, signal)
+}
+
+fn linux_pipe2(registers: &mut TaskRegisters) -> SyscallResult {
+    let [fds_ptr, flags, ..] = syscall_args(registers);
+    let close_on_exec = u64::from(vfs::OpenFlags::CLOSE_ON_EXEC.bits());
+    if flags & !close_on_exec != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    create_pipe(fds_ptr, flags & close_on_exec != 0)
+}
+
+/// Reads a nul-terminated path from userspace.
+fn user_c_path(ptr: u64) -> Result<vfs::FilePath, SyscallError> {
+    let path = user_c_str(ptr)?;
+    vfs::FilePath::parse(&path).ok_or(SyscallError::InvalidArgument)
+}
+
+/// Linux syscall names from `asm/unistd_64.h`. Numbers 335 to 423 are unused.
+static LINUX_SYSCALL_NAMES: [&str; 335] = [
+    "read",
+    "write",
+    "open",
+    "close",
+    "stat",
+    "fstat",
+    "lstat",
+    "poll",
+    "lseek",
+    "mmap",
+    "mprotect",
+    "munmap",
+    "brk",
+    "rt_sigaction",
+    "rt_sigprocmask",
+    "rt_sigreturn",
+    "ioctl",
+    "pread64",
+    "pwrite64",
+    "readv",
+    "writev",
+    "access",
+    "pipe",
+    "select",
+    "sched_yield",
+    "mremap",
+    "msync",
+    "mincore",
+    "madvise",
+    "shmget",
+    "shmat",
+    "shmctl",
+    "dup",
+    "dup2",
+    "pause",
+    "nanosleep",
+    "getitimer",
+    "alarm",
+    "setitimer",
+    "getpid",
+    "sendfile",
+    "socket",
+    "connect",
+    "accept",
+    "sendto",
+    "recvfrom",
+    "sendmsg",
+    "recvmsg",
+    "shutdown",
+    "bind",
+    "listen",
+    "getsockname",
+    "getpeername",
+    "socketpair",
+    "setsockopt",
+    "getsockopt",
+    "clone",
+    "fork",
+    "vfork",
+    "execve",
+    "exit",
+    "wait4",
+    "kill",
+    "uname",
+    "semget",
+    "semop",
+    "semctl",
+    "shmdt",
+    "msgget",
+    "msgsnd",
+    "msgrcv",
+    "msgctl",
+    "fcntl",
+    "flock",
+    "fsync",
+    "fdatasync",
+    "truncate",
+    "ftruncate",
+    "getdents",
+    "getcwd",
+    "chdir",
+    "fchdir",
+    "rename",
+    "mkdir",
+    "rmdir",
+    "creat",
+    "link",
+    "unlink",
+    "symlink",
+    "readlink",
+    "chmod",
+    "fchmod",
+    "chown",
+    "fchown",
+    "lchown",
+    "umask",
+    "gettimeofday",
+    "getrlimit",
+    "getrusage",
+    "sysinfo",
+    "times",
+    "ptrace",
+    "getuid",
+    "syslog",
+    "getgid",
+    "setuid",
+    "setgid",
+    "geteuid",
+    "getegid",
+    "setpgid",
+    "getppid",
+    "getpgrp",
+    "setsid",
+    "setreuid",
+    "setregid",
+    "getgroups",
+    "setgroups",
+    "setresuid",
+    "getresuid",
+    "setresgid",
+    "getresgid",
+    "getpgid",
+    "setfsuid",
+    "setfsgid",
+    "getsid",
+    "capget",
+    "capset",
+    "rt_sigpending",
+    "rt_sigtimedwait",
+    "rt_sigqueueinfo",
+    "rt_sigsuspend",
+    "sigaltstack",
+    "utime",
+    "mknod",
+    "uselib",
+    "personality",
+    "ustat",
+    "statfs",
+    "fstatfs",
+    "sysfs",
+    "getpriority",
+    "setpriority",
+    "sched_setparam",
+    "sched_getparam",
+    "sched_setscheduler",
+    "sched_getscheduler",
+    "sched_get_priority_max",
+    "sched_get_priority_min",
+    "sched_rr_get_interval",
+    "mlock",
+    "munlock",
+    "mlockall",
+    "munlockall",
+    "vhangup",
+    "modify_ldt",
+    "pivot_root",
+    "_sysctl",
+    "prctl",
+    "arch_prctl",
+    "adjtimex",
+    "setrlimit",
+    "chroot",
+    "sync",
+    "acct",
+    "settimeofday",
+    "mount",
+    "umount2",
+    "swapon",
+    "swapoff",
+    "reboot",
+    "sethostname",
+    "setdomainname",
+    "iopl",
+    "ioperm",
+    "create_module",
+    "init_module",
+    "delete_module",
+    "get_kernel_syms",
+    "query_module",
+    "quotactl",
+    "nfsservctl",
+    "getpmsg",
+    "putpmsg",
+    "afs_syscall",
+    "tuxcall",
+    "security",
+    "gettid",
+    "readahead",
+    "setxattr",
+    "lsetxattr",
+    "fsetxattr",
+    "getxattr",
+    "lgetxattr",
+    "fgetxattr",
+    "listxattr",
+    "llistxattr",
+    "flistxattr",
+    "removexattr",
+    "lremovexattr",
+    "fremovexattr",
+    "tkill",
+    "time",
+    "futex",
+    "sched_setaffinity",
+    "sched_getaffinity",
+    "set_thread_area",
+    "io_setup",
+    "io_destroy",
+    "io_getevents",
+    "io_submit",
+    "io_cancel",
+    "get_thread_area",
+    "lookup_dcookie",
+    "epoll_create",
+    "epoll_ctl_old",
+    "epoll_wait_old",
+    "remap_file_pages",
+    "getdents64",
+    "set_tid_address",
+    "restart_syscall",
+    "semtimedop",
+    "fadvise64",
+    "timer_create",
+    "timer_settime",
+    "timer_gettime",
+    "timer_getoverrun",
+    "timer_delete",
+    "clock_settime",
+    "clock_gettime",
+    "clock_getres",
+    "clock_nanosleep",
+    "exit_group",
+    "epoll_wait",
+    "epoll_ctl",
+    "tgkill",
+    "utimes",
+    "vserver",
+    "mbind",
+    "set_mempolicy",
+    "get_mempolicy",
+    "mq_open",
+    "mq_unlink",
+    "mq_timedsend",
+    "mq_timedreceive",
+    "mq_notify",
+    "mq_getsetattr",
+    "kexec_load",
+    "waitid",
+    "add_key",
+    "request_key",
+    "keyctl",
+    "ioprio_set",
+    "ioprio_get",
+    "inotify_init",
+    "inotify_add_watch",
+    "inotify_rm_watch",
+    "migrate_pages",
+    "openat",
+    "mkdirat",
+    "mknodat",
+    "fchownat",
+    "futimesat",
+    "newfstatat",
+    "unlinkat",
+    "renameat",
+    "linkat",
+    "symlinkat",
+    "readlinkat",
+    "fchmodat",
+    "faccessat",
+    "pselect6",
+    "ppoll",
+    "unshare",
+    "set_robust_list",
+    "get_robust_list",
+    "splice",
+    "tee",
+    "sync_file_range",
+    "vmsplice",
+    "move_pages",
+    "utimensat",
+    "epoll_pwait",
+    "signalfd",
+    "timerfd_create",
+    "eventfd",
+    "fallocate",
+    "timerfd_settime",
+    "timerfd_gettime",
+    "accept4",
+    "signalfd4",
+    "eventfd2",
+    "epoll_create1",
+    "dup3",
+    "pipe2",
+    "inotify_init1",
+    "preadv",
+    "pwritev",
+    "rt_tgsigqueueinfo",
+    "perf_event_open",
+    "recvmmsg",
+    "fanotify_init",
+    "fanotify_mark",
+    "prlimit64",
+    "name_to_handle_at",
+    "open_by_handle_at",
+    "clock_adjtime",
+    "syncfs",
+    "sendmmsg",
+    "setns",
+    "getcpu",
+    "process_vm_readv",
+    "process_vm_writev",
+    "kcmp",
+    "finit_module",
+    "sched_setattr",
+    "sched_getattr",
+    "renameat2",
+    "seccomp",
+    "getrandom",
+    "memfd_create",
+    "kexec_file_load",
+    "bpf",
+    "execveat",
+    "userfaultfd",
+    "membarrier",
+    "mlock2",
+    "copy_file_range",
+    "preadv2",
+    "pwritev2",
+    "pkey_mprotect",
+    "pkey_alloc",
+    "pkey_free",
+    "statx",
+    "io_pgetevents",
+    "rseq",
+];
+
+static LINUX_SYSCALL_NAMES_FROM_424: [&str; 27] = [
+    "pidfd_send_signal",
+    "io_uring_setup",
+    "io_uring_enter",
+    "io_uring_register",
+    "open_tree",
+    "move_mount",
+    "fsopen",
+    "fsconfig",
+    "fsmount",
+    "fspick",
+    "pidfd_open",
+    "clone3",
+    "close_range",
+    "openat2",
+    "pidfd_getfd",
+    "faccessat2",
+    "process_madvise",
+    "epoll_pwait2",
+    "mount_setattr",
+    "quotactl_fd",
+    "landlock_create_ruleset",
+    "landlock_add_rule",
+    "landlock_restrict_self",
+    "memfd_secret",
+    "process_mrelease",
+    "futex_waitv",
+    "set_mempolicy_home_node",
+];
@@ -0,0 +1,1473 @@
+//! Syscall entry and our syscall handlers. Flags, options, and other
+//! constants passed to syscalls have the same values as Linux's, both here
+//! and in the Linux ABI (see `linux`), so userspace can use the usual C
+//! constants.
+
+mod linux;
+mod trace;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::asm;
+
+use x86_64::registers::model_specific::FsBase;
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::define_per_cpu_u64;
+use crate::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+use crate::hpet::{Milliseconds, Nanoseconds};
+use crate::memory::{PageTableEntryFlags, PAGE_SIZE, USER_MEMORY_END};
+use crate::sync::Mutex;
+use crate::{random, tty, vfs};
+
+use super::family::{wait_for_child, WaitError, WaitOptions, WaitTarget};
+use super::futex::{futex_wait, futex_wake, FutexError};
+use super::job_control::{
+    create_session, current_job_control, job_control, process_group_session, set_process_group,
+    signal_process_group, JobControlError,
+};
+use super::schedcore::{current_task, current_task_id, kill_current_task, run_scheduler};
+use super::signal::{
+    deliver_pending_signal, resume_from_signal_entry, return_from_signal_handler, send_signal,
+    InvalidSignalAction, Signal, SignalAction, SIGNAL_TRAMPOLINE_ADDR,
+};
+use super::task::{TaskExitCode, TaskId, TaskRegisters, TASKS};
+use super::time::{self, Clock, SleepInterrupted};
+use super::user_memory::{
+    check_user_writable, copy_to_user, read_user, read_user_bytes, read_user_c_str,
+    read_user_string, write_user, BadUserAddress,
+};
+use super::userspace::{clone_current_task, exec_current_task, fork_current_task, ExecError};
+use super::vm::{MappedFile, VmError};
+
+pub(super) fn syscall_init() {
+    // N.B. There is some other initialization done when setting up the GDT for
+    // the STAR register to set user and kernel mode segments. See gdt.rs for
+    // more details.
+
+    // Enable System Call Extensions (SCE) to allow userspace to use the syscall
+    // instruction.
+    unsafe {
+        x86_64::registers::model_specific::Efer::update(|efer| {
+            *efer |= x86_64::registers::model_specific::EferFlags::SYSTEM_CALL_EXTENSIONS;
+        });
+    }
+
+    // Use SFMASK register to disable interrupts when executing a syscall. This
+    // is important because we use swapgs and we mess with the stack.
+    x86_64::registers::model_specific::SFMask::write(RFlags::INTERRUPT_FLAG);
+
+    // Set syscall handler address via LSTAR register
+    let syscall_handler_addr = VirtAddr::new(syscall_handler as usize as u64);
+    x86_64::registers::model_specific::LStar::write(syscall_handler_addr);
+}
+
+define_per_cpu_u64!(
+    /// Used during syscalls to store and restore the top of the kernel stack.
+    pub(super) TOP_OF_KERNEL_STACK
+);
+
+define_per_cpu_u64!(
+    /// Used during syscalls to store and restore the user stack
+    pub(super) USER_STACK_SCRATCH
+);
+
+#[naked]
+pub(super) unsafe extern "C" fn syscall_handler() {
+    unsafe {
+        asm!(
+            // Swap out the user GS base for the kernel GS base and restore the
+            // kernel stack.
+            "swapgs",
+            "mov gs:{user_stack_scratch}, rsp",
+            "mov rsp, gs:{kernel_stack}",
+
+            // Construct a pointer to the syscall arguments on the stack. Must
+            // match TaskRegisters struct order (in reverse).
+            "push {user_data_selector}",    // ss
+            "push gs:{user_stack_scratch}", // rsp
+            "push r11",                     // rflags, part of syscall convention
+            "push {user_code_selector}",    // cs
+            "push rcx",                     // rip, part of syscall convention
+            "push rdi",                     // syscall number
+            // Callee-clobbered
+            "push rdi",
+            "push rsi",
+            "push rdx",
+            "push rcx",
+            "push rax",
+            "push r8",
+            "push r9",
+            "push r10",
+            "push r11",
+            // Callee-saved
+            "push rbx",
+            "push rbp",
+            "push r12",
+            "push r13",
+            "push r14",
+            "push r15",
+
+            // First arg is pointer to syscall arguments on the stack
+            "mov rdi, rsp",
+
+            // Call the actual syscall handler
+            "call {syscall_handler_inner}",
+
+            // Restore registers and run iretq to get back to userland.
+            "mov rdi, rsp",
+            "jmp {return_to_userspace}",
+            user_data_selector = const USER_DATA_SELECTOR.0,
+            user_code_selector = const USER_CODE_SELECTOR.0,
+            user_stack_scratch = sym USER_STACK_SCRATCH,
+            kernel_stack = sym TOP_OF_KERNEL_STACK,
+            syscall_handler_inner = sym syscall_handler_inner,
+            return_to_userspace = sym return_to_userspace,
+            options(noreturn),
+        )
+    }
+}
+
+/// Restores all of the given registers and returns to userspace. This is the
+/// second half of `syscall_handler`, and it is also used to start tasks that
+/// were created from another task's registers, like in fork.
+///
+/// We use iretq instead of sysretq because sysretq clobbers rcx and r11, and
+/// returning from a signal handler has to restore every register.
+/// `TaskRegisters` ends with an iretq frame, so this is just as easy.
+///
+/// N.B. This doesn't store anything in `TOP_OF_KERNEL_STACK`. The scheduler
+/// sets it to the top of the task's kernel stack whenever it switches tasks,
+/// so the next syscall starts with a fresh kernel stack.
+#[naked]
+pub(super) unsafe extern "C" fn return_to_userspace(registers: *const TaskRegisters) -> ! {
+    unsafe {
+        asm!(
+            // Interrupts are enabled again by iretq when it restores rflags.
+            // Until then, an interrupt would see the user GS base after
+            // swapgs.
+            "cli",
+            // Pop registers off of the TaskRegisters struct.
+            "mov rsp, rdi",
+            // Callee-saved
+            "pop r15",
+            "pop r14",
+            "pop r13",
+            "pop r12",
+            "pop rbp",
+            "pop rbx",
+            // Callee-clobbered
+            "pop r11",
+            "pop r10",
+            "pop r9",
+            "pop r8",
+            "pop rax",
+            "pop rcx",
+            "pop rdx",
+            "pop rsi",
+            "pop rdi",
+            // Syscall number
+            "add rsp, 8",
+            // The rest is the iretq frame: rip, cs, rflags, rsp, and ss.
+            "swapgs",
+            // Return to userspace
+            "iretq",
+            options(noreturn),
+        )
+    }
+}
+
+extern "C" fn syscall_handler_inner(registers: &mut TaskRegisters) {
+    let abi = syscall_abi(registers);
+    let (syscall_num, handler) = match abi {
+        SyscallAbi::Native => {
+            let syscall_num = registers.syscall_number_or_irq_or_error_code;
+            let handler = SYSCALL_HANDLERS
+                .get(syscall_num as usize)
+                .copied()
+                .flatten();
+            (syscall_num, handler)
+        }
+        SyscallAbi::Linux => (registers.rax, linux::syscall_handler(registers.rax)),
+    };
+    let trace = trace::SyscallTrace::start(abi, syscall_num, syscall_args(registers));
+    let result = handler.map_or_else(
+        || {
+            let args = syscall_args(registers);
+            match abi {
+                SyscallAbi::Native => {
+                    log::warn!("Unknown syscall {syscall_num} called with args {args:?}");
+                }
+                SyscallAbi::Linux => {
+                    let name = linux::syscall_name(syscall_num).unwrap_or("unknown");
+                    log::warn!(
+                        "Unimplemented Linux syscall {name} ({syscall_num}) called with args {args:?}"
+                    );
+                }
+            }
+            Err(SyscallError::NoSuchSyscall)
+        },
+        |handler| handler(registers),
+    );
+    if let Some(trace) = trace {
+        trace.finish(&result);
+    }
+    registers.rax = match result {
+        Ok(value) => value,
+        Err(err) => err.to_return_value(),
+    };
+
+    // Run scheduler after syscalls
+    run_scheduler();
+
+    // Signals might have been sent while we were in the syscall or while
+    // another task was running.
+    deliver_pending_signal(registers);
+}
+
+/// Which registers a task passes the syscall number and arguments in, and
+/// which syscall numbers it uses. This is set at exec and inherited by forked
+/// and cloned tasks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(super) enum SyscallAbi {
+    /// Our own syscalls. See `SYSCALL_HANDLERS`.
+    Native,
+
+    /// Linux's x86_64 syscalls, so we can run programs built for Linux (like
+    /// static musl binaries) without porting them. See `linux.rs`.
+    Linux,
+}
+
+impl TryFrom<u8> for SyscallAbi {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            value if value == Self::Native as u8 => Ok(Self::Native),
+            value if value == Self::Linux as u8 => Ok(Self::Linux),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<SyscallAbi> for u8 {
+    fn from(value: SyscallAbi) -> Self {
+        value as Self
+    }
+}
+
+/// The ABI of the syscall the current task is making.
+fn syscall_abi(registers: &TaskRegisters) -> SyscallAbi {
+    // The signal trampolines are shared by every program, so they always use
+    // the native ABI.
+    if (SIGNAL_TRAMPOLINE_ADDR..USER_MEMORY_END).contains(&registers.rip) {
+        return SyscallAbi::Native;
+    }
+    current_task().syscall_abi.load()
+}
+
+/// With the native ABI, syscall arguments are passed in rsi, rdx, r10, r8, and
+/// r9. (rdi holds the syscall number.) The rare syscall with a sixth argument,
+/// like `mmap`, gets it in rax. The Linux ABI passes the syscall number in rax
+/// and the arguments in rdi, rsi, rdx, r10, r8, and r9.
+fn syscall_args(registers: &TaskRegisters) -> [u64; 6] {
+    match syscall_abi(registers) {
+        SyscallAbi::Native => [
+            registers.rsi,
+            registers.rdx,
+            registers.r10,
+            registers.r8,
+            registers.r9,
+            registers.rax,
+        ],
+        SyscallAbi::Linux => [
+            registers.rdi,
+            registers.rsi,
+            registers.rdx,
+            registers.r10,
+            registers.r8,
+            registers.r9,
+        ],
+    }
+}
+
+/// Errors returned from syscalls. Userspace sees these as negative return
+/// values, and they use the same numbers as Linux's errno values so userspace
+/// can use conventional error handling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub(super) enum SyscallError {
+    NotPermitted = 1,
+    NoSuchFileOrDirectory = 2,
+    NoSuchProcess = 3,
+    Interrupted = 4,
+    InputOutput = 5,
+    ArgumentListTooLong = 7,
+    ExecFormatError = 8,
+    BadFileDescriptor = 9,
+    NoChildProcesses = 10,
+    TryAgain = 11,
+    OutOfMemory = 12,
+    PermissionDenied = 13,
+    BadAddress = 14,
+    NotADirectory = 20,
+    IsADirectory = 21,
+    InvalidArgument = 22,
+    TooManyOpenFiles = 24,
+    NotATerminal = 25,
+    FileTooLarge = 27,
+    NoSpaceLeft = 28,
+    IllegalSeek = 29,
+    BrokenPipe = 32,
+    NoSuchSyscall = 38,
+    TimedOut = 110,
+}
+
+impl SyscallError {
+    fn to_return_value(self) -> u64 {
+        0u64.wrapping_sub(self as u64)
+    }
+
+    /// The error's name in Linux, like `ENOENT`.
+    fn name(self) -> &'static str {
+        match self {
+            Self::NotPermitted => "EPERM",
+            Self::NoSuchFileOrDirectory => "ENOENT",
+            Self::NoSuchProcess => "ESRCH",
+            Self::Interrupted => "EINTR",
+            Self::InputOutput => "EIO",
+            Self::ArgumentListTooLong => "E2BIG",
+            Self::ExecFormatError => "ENOEXEC",
+            Self::BadFileDescriptor => "EBADF",
+            Self::NoChildProcesses => "ECHILD",
+            Self::TryAgain => "EAGAIN",
+            Self::OutOfMemory => "ENOMEM",
+            Self::PermissionDenied => "EACCES",
+            Self::BadAddress => "EFAULT",
+            Self::NotADirectory => "ENOTDIR",
+            Self::IsADirectory => "EISDIR",
+            Self::InvalidArgument => "EINVAL",
+            Self::TooManyOpenFiles => "EMFILE",
+            Self::NotATerminal => "ENOTTY",
+            Self::FileTooLarge => "EFBIG",
+            Self::NoSpaceLeft => "ENOSPC",
+            Self::IllegalSeek => "ESPIPE",
+            Self::BrokenPipe => "EPIPE",
+            Self::NoSuchSyscall => "ENOSYS",
+            Self::TimedOut => "ETIMEDOUT",
+        }
+    }
+}
+
+impl From<vfs::FileError> for SyscallError {
+    fn from(err: vfs::FileError) -> Self {
+        match err {
+            vfs::FileError::NotFound => Self::NoSuchFileOrDirectory,
+            vfs::FileError::IsDirectory => Self::IsADirectory,
+            vfs::FileError::NotDirectory => Self::NotADirectory,
+            vfs::FileError::NotReadable | vfs::FileError::NotWritable => Self::BadFileDescriptor,
+            vfs::FileError::InvalidSeek => Self::InvalidArgument,
+            vfs::FileError::WriteFailed => Self::NoSpaceLeft,
+            vfs::FileError::TooManyOpenFiles => Self::TooManyOpenFiles,
+            vfs::FileError::TooLarge => Self::FileTooLarge,
+            vfs::FileError::NotSeekable => Self::IllegalSeek,
+            vfs::FileError::BrokenPipe => Self::BrokenPipe,
+            vfs::FileError::Interrupted => Self::Interrupted,
+            vfs::FileError::InputOutput => Self::InputOutput,
+        }
+    }
+}
+
+impl From<ExecError> for SyscallError {
+    fn from(err: ExecError) -> Self {
+        match err {
+            ExecError::NotFound(_) => Self::NoSuchFileOrDirectory,
+            ExecError::NotAFile => Self::PermissionDenied,
+            ExecError::InvalidElf(_) => Self::ExecFormatError,
+            ExecError::ArgumentsTooLarge => Self::ArgumentListTooLong,
+            ExecError::OutOfMemory => Self::OutOfMemory,
+        }
+    }
+}
+
+impl From<VmError> for SyscallError {
+    fn from(err: VmError) -> Self {
+        match err {
+            VmError::InvalidRange => Self::InvalidArgument,
+            VmError::NoSpace | VmError::OutOfMemory => Self::OutOfMemory,
+        }
+    }
+}
+
+impl From<BadUserAddress> for SyscallError {
+    fn from(_: BadUserAddress) -> Self {
+        Self::BadAddress
+    }
+}
+
+impl From<WaitError> for SyscallError {
+    fn from(err: WaitError) -> Self {
+        match err {
+            WaitError::NoChildren => Self::NoChildProcesses,
+            WaitError::Interrupted => Self::Interrupted,
+        }
+    }
+}
+
+impl From<FutexError> for SyscallError {
+    fn from(err: FutexError) -> Self {
+        match err {
+            FutexError::BadAddress => Self::BadAddress,
+            FutexError::Misaligned => Self::InvalidArgument,
+            FutexError::WouldBlock => Self::TryAgain,
+            FutexError::TimedOut => Self::TimedOut,
+            FutexError::Interrupted => Self::Interrupted,
+        }
+    }
+}
+
+impl From<JobControlError> for SyscallError {
+    fn from(err: JobControlError) -> Self {
+        match err {
+            JobControlError::NoSuchTask => Self::NoSuchProcess,
+            JobControlError::NotPermitted => Self::NotPermitted,
+        }
+    }
+}
+
+impl From<InvalidSignalAction> for SyscallError {
+    fn from(_: InvalidSignalAction) -> Self {
+        Self::InvalidArgument
+    }
+}
+
+type SyscallResult = Result<u64, SyscallError>;
+
+/// Syscall handlers get the userspace registers that were saved on syscall
+/// entry. Most handlers only need the arguments from `syscall_args`, but some
+/// (like fork) need everything.
+type SyscallHandler = fn(&mut TaskRegisters) -> SyscallResult;
+
+/// Number of our own syscalls. See `SYSCALL_HANDLERS`.
+const NUM_NATIVE_SYSCALLS: usize = 33;
+
+static SYSCALL_HANDLERS: [Option<SyscallHandler>; NUM_NATIVE_SYSCALLS] = [
+    Some(syscall_exit), // 0
+    Some(syscall_print),
+    Some(syscall_open),
+    Some(syscall_read),
+    Some(syscall_write),
+    Some(syscall_close), // 5
+    Some(syscall_lseek),
+    Some(syscall_fork),
+    Some(syscall_exec),
+    Some(syscall_waitpid),
+    Some(syscall_brk), // 10
+    Some(syscall_mmap),
+    Some(syscall_munmap),
+    Some(syscall_kill),
+    Some(syscall_sigaction),
+    Some(syscall_sigprocmask), // 15
+    Some(syscall_sigreturn),
+    Some(syscall_signal_entry),
+    Some(syscall_pipe),
+    Some(syscall_clone),
+    Some(syscall_arch_prctl), // 20
+    Some(syscall_futex),
+    Some(syscall_clock_gettime),
+    Some(syscall_nanosleep),
+    Some(syscall_getrandom),
+    Some(syscall_ioctl), // 25
+    Some(syscall_setpgid),
+    Some(syscall_getpgid),
+    Some(syscall_setsid),
+    Some(syscall_getsid),
+    Some(syscall_stat), // 30
+    Some(syscall_fstat),
+    Some(syscall_getdents),
+];
+
+/// Syscall numbers the kernel itself needs to know, for the signal
+/// trampolines. These must match `SYSCALL_HANDLERS`.
+pub(super) const SYS_SIGRETURN: u64 = 16;
+pub(super) const SYS_SIGNAL_ENTRY: u64 = 17;
+
+fn syscall_exit(registers: &mut TaskRegisters) -> SyscallResult {
+    let [exit_code, ..] = syscall_args(registers);
+    kill_current_task(TaskExitCode::from(exit_code));
+}
+
+fn syscall_print(registers: &mut TaskRegisters) -> SyscallResult {
+    let [data_ptr, data_len, ..] = syscall_args(registers);
+    let s = user_string(data_ptr, data_len, MAX_IO_LEN)?;
+    log::info!("PRINT SYSCALL: {}", s);
+    Ok(data_len)
+}
+
+fn syscall_open(registers: &mut TaskRegisters) -> SyscallResult {
+    let [path_ptr, path_len, flags, ..] = syscall_args(registers);
+    let path = user_path(path_ptr, path_len)?;
+    open_path(&path, flags)
+}
+
+/// Opens the file at `path` and returns its new file descriptor.
+fn open_path(path: &vfs::FilePath, flags: u64) -> SyscallResult {
+    let flags = vfs::OpenFlags::from_bits_truncate(flags as u32);
+    let file = vfs::OpenFile::open(path, flags)?;
+    let task = current_task();
+    let mut files = task.files.lock();
+    let fd = files.insert(Arc::new(Mutex::new(file)))?;
+    if flags.contains(vfs::OpenFlags::CLOSE_ON_EXEC) {
+        files.set_close_on_exec(fd);
+    }
+    Ok(u64::from(fd.0))
+}
+
+fn syscall_read(registers: &mut TaskRegisters) -> SyscallResult {
+    let [fd, buf_ptr, buf_len, ..] = syscall_args(registers);
+    let file = get_open_file(fd)?;
+    let bytes_read = read_file_to_user(&file, &[(buf_ptr, buf_len)])?;
+    Ok(bytes_read as u64)
+}
+
+/// Reads at most `MAX_IO_LEN` bytes from an open file with a single read, and
+/// copies them into `user_buffers` (address and length pairs) in turn.
+/// Returns how many bytes were read.
+///
+/// Data read from a device or pipe can't be put back, so we check that the
+/// user buffers are writable before reading. If the copy fails anyway (like
+/// if another thread unmapped the memory in the meantime), the file offset is
+/// moved back so regular files don't lose the data.
+fn read_file_to_user(
+    file: &Mutex<vfs::OpenFile>,
+    user_buffers: &[(u64, u64)],
+) -> Result<usize, SyscallError> {
+    let total_len = user_buffers
+        .iter()
+        .fold(0_u64, |total, (_, len)| total.saturating_add(*len));
+    let mut buffer = vec![0; (total_len as usize).min(MAX_IO_LEN)];
+
+    let mut remaining = buffer.len();
+    for &(addr, len) in user_buffers {
+        let len = (len as usize).min(remaining);
+        check_user_writable(addr, len)?;
+        remaining -= len;
+    }
+
+    let bytes_read = read_file(file, &mut buffer)?;
+
+    let mut remaining = &buffer[..bytes_read];
+    for &(addr, len) in user_buffers {
+        if remaining.is_empty() {
+            break;
+        }
+        let len = (len as usize).min(remaining.len());
+        if let Err(err) = copy_to_user(addr, &remaining[..len]) {
+            // Streams aren't seekable, so this only matters for regular files.
+            #[allow(clippy::cast_possible_wrap)]
+            let _ = file
+                .lock()
+                .seek(-(bytes_read as i64), vfs::SeekWhence::Current);
+            return Err(err.into());
+        }
+        remaining = &remaining[len..];
+    }
+    Ok(bytes_read)
+}
+
+/// Reads from an open file into `buffer` and returns how many bytes were read.
+fn read_file(file: &Mutex<vfs::OpenFile>, buffer: &mut [u8]) -> Result<usize, SyscallError> {
+    // Don't hold the lock while reading from a device or pipe, since that can
+    // sleep forever. See `vfs::Stream`.
+    let stream = file.lock().readable_stream()?;
+    let bytes_read = match stream {
+        Some(stream) => stream.read(buffer)?,
+        None => file.lock().read(buffer)?,
+    };
+    Ok(bytes_read)
+}
+
+fn syscall_write(registers: &mut TaskRegisters) -> SyscallResult {
+    let [fd, buf_ptr, buf_len, ..] = syscall_args(registers);
+    let file = get_open_file(fd)?;
+    let data = read_user_bytes(buf_ptr, (buf_len as usize).min(MAX_IO_LEN))?;
+    write_file(&file, &data)
+}
+
+/// Writes `data` to an open file and returns how many bytes were written.
+fn write_file(file: &Mutex<vfs::OpenFile>, data: &[u8]) -> SyscallResult {
+    // Like `read_file`, don't hold the lock while writing to a pipe or device.
+    let stream = file.lock().writable_stream()?;
+    let result = match stream {
+        Some(stream) => stream.write(data),
+        None => file.lock().write(data),
+    };
+    if result == Err(vfs::FileError::BrokenPipe) {
+        // Like Linux, writing to a pipe with no readers also raises SIGPIPE,
+        // which kills the task unless it is handled or ignored.
+        send_signal(current_task_id(), Signal::SIGPIPE);
+    }
+    Ok(result? as u64)
+}
+
+fn syscall_close(registers: &mut TaskRegisters) -> SyscallResult {
+    let [fd, ..] = syscall_args(registers);
+    let fd = file_descriptor(fd)?;
+    let file = current_task().files.lock().remove(fd);
+    file.map_or(Err(SyscallError::BadFileDescriptor), |_| Ok(0))
+}
+
+fn syscall_lseek(registers: &mut TaskRegisters) -> SyscallResult {
+    let [fd, offset, whence, ..] = syscall_args(registers);
+    let file = get_open_file(fd)?;
+    let whence = match whence {
+        0 => vfs::SeekWhence::Start,
+        1 => vfs::SeekWhence::Current,
+        2 => vfs::SeekWhence::End,
+        _ => return Err(SyscallError::InvalidArgument),
+    };
+    #[allow(clippy::cast_possible_wrap)]
+    let offset = offset as i64;
+    let new_offset = file.lock().seek(offset, whence)?;
+    Ok(new_offset as u64)
+}
+
+fn syscall_fork(registers: &mut TaskRegisters) -> SyscallResult {
+    let child_id = fork_current_task(registers).map_err(|_| SyscallError::OutOfMemory)?;
+    Ok(u64::from(u32::from(child_id)))
+}
+
+/// `exec` flag to run the new program with the Linux syscall ABI.
+const EXEC_LINUX_ABI: u64 = 1;
+
+fn syscall_exec(registers: &mut TaskRegisters) -> SyscallResult {
+    let [path_ptr, path_len, argv_ptr, envp_ptr, flags, ..] = syscall_args(registers);
+    if flags & !EXEC_LINUX_ABI != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let abi = if flags & EXEC_LINUX_ABI == 0 {
+        SyscallAbi::Native
+    } else {
+        SyscallAbi::Linux
+    };
+    let path = user_path(path_ptr, path_len)?;
+    exec_path(registers, &path, argv_ptr, envp_ptr, abi)
+}
+
+/// Replaces the current task's program with the one at `path`, reading argv
+/// and envp from userspace. Only returns if the exec fails.
+fn exec_path(
+    registers: &mut TaskRegisters,
+    path: &vfs::FilePath,
+    argv_ptr: u64,
+    envp_ptr: u64,
+    abi: SyscallAbi,
+) -> SyscallResult {
+    let argv = user_c_str_array(argv_ptr)?;
+    // Like Linux, treat a NULL envp as an empty environment.
+    let envp = if envp_ptr == 0 {
+        Vec::new()
+    } else {
+        user_c_str_array(envp_ptr)?
+    };
+    exec_current_task(registers, path, &argv, &envp, abi)?;
+    Ok(0)
+}
+
+/// `waitpid` options. `WAIT_NO_HANG` returns 0
+/// instead of blocking if no child has exited. `WAIT_UNTRACED` and
+/// `WAIT_CONTINUED` also report children that were stopped or continued by a
+/// signal.
+const WAIT_NO_HANG: u64 = 0x1;
+const WAIT_UNTRACED: u64 = 0x2;
+const WAIT_CONTINUED: u64 = 0x8;
+
+fn syscall_waitpid(registers: &mut TaskRegisters) -> SyscallResult {
+    let [pid, status_ptr, options, ..] = syscall_args(registers);
+    wait_for_pid(pid, status_ptr, options)
+}
+
+/// Waits for the child `pid` to exit, or for any child if `pid` is -1. Like in
+/// Linux, a `pid` of 0 waits for any child in the current task's process
+/// group, and a `pid` less than -1 waits for any child in the process group
+/// `-pid`. Writes the child's wait status to `status_ptr` if it isn't NULL,
+/// and returns its task ID. See the `WAIT_*` options.
+fn wait_for_pid(pid: u64, status_ptr: u64, options: u64) -> SyscallResult {
+    if options & !(WAIT_NO_HANG | WAIT_UNTRACED | WAIT_CONTINUED) != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let options = WaitOptions {
+        block: options & WAIT_NO_HANG == 0,
+        stopped: options & WAIT_UNTRACED != 0,
+        continued: options & WAIT_CONTINUED != 0,
+    };
+
+    #[allow(clippy::cast_possible_wrap)]
+    let target = match pid as i64 {
+        0 => WaitTarget::ProcessGroup(current_job_control().process_group),
+        -1 => WaitTarget::AnyChild,
+        group if group < 0 => {
+            let process_group =
+                u32::try_from(group.unsigned_abs()).map_err(|_| SyscallError::NoChildProcesses)?;
+            WaitTarget::ProcessGroup(TaskId(process_group))
+        }
+        pid => {
+            let pid = u32::try_from(pid).map_err(|_| SyscallError::InvalidArgument)?;
+            WaitTarget::Child(TaskId(pid))
+        }
+    };
+
+    let Some((child_id, status)) = wait_for_child(target, options)? else {
+        return Ok(0);
+    };
+    if status_ptr != 0 {
+        write_user(status_ptr, &status.wait_status())?;
+    }
+    Ok(u64::from(u32::from(child_id)))
+}
+
+fn syscall_brk(registers: &mut TaskRegisters) -> SyscallResult {
+    let [addr, ..] = syscall_args(registers);
+    let address_space = current_task().address_space();
+    let mut vm_areas = address_space.vm_areas.lock();
+    let brk = vm_areas.brk(&mut address_space.page_table.lock(), addr);
+    Ok(brk.as_u64())
+}
+
+const PROT_READ: u64 = 0x1;
+const PROT_WRITE: u64 = 0x2;
+const PROT_EXEC: u64 = 0x4;
+
+const MAP_SHARED: u64 = 0x01;
+const MAP_FIXED: u64 = 0x10;
+const MAP_ANONYMOUS: u64 = 0x20;
+
+fn syscall_mmap(registers: &mut TaskRegisters) -> SyscallResult {
+    let [addr, len, prot, flags, fd, offset] = syscall_args(registers);
+    let page_flags = prot_page_flags(prot)?;
+    let fixed = flags & MAP_FIXED != 0;
+
+    if flags & MAP_ANONYMOUS != 0 {
+        // TODO: Support shared anonymous mappings.
+        if flags & MAP_SHARED != 0 {
+            return Err(SyscallError::InvalidArgument);
+        }
+        let address_space = current_task().address_space();
+        let mut vm_areas = address_space.vm_areas.lock();
+        let addr = vm_areas.mmap_anonymous(
+            &mut address_space.page_table.lock(),
+            addr,
+            len,
+            page_flags,
+            fixed,
+        )?;
+        return Ok(addr.as_u64());
+    }
+
+    // File mappings are private copies of the file. That is indistinguishable
+    // from a shared mapping as long as nobody writes to it.
+    // TODO: Support writable shared file mappings.
+    if flags & MAP_SHARED != 0 && prot & PROT_WRITE != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    if offset % PAGE_SIZE as u64 != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let file = get_open_file(fd)?;
+    let file_size = file.lock().readable_size()?;
+
+    let address_space = current_task().address_space();
+    let mut vm_areas = address_space.vm_areas.lock();
+    let addr = vm_areas.mmap_file(
+        &mut address_space.page_table.lock(),
+        addr,
+        len,
+        page_flags,
+        fixed,
+        MappedFile {
+            file,
+            offset,
+            size: file_size as u64,
+        },
+    )?;
+    Ok(addr.as_u64())
+}
+
+/// Converts `mmap` `PROT_*` flags to page table flags. x86_64 can't express
+/// write-only or execute-only pages, so any access implies read access.
+fn prot_page_flags(prot: u64) -> Result<PageTableEntryFlags, SyscallError> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let mut flags = PageTableEntryFlags::empty();
+    if prot != 0 {
+        flags |= PageTableEntryFlags::PRESENT;
+    }
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableEntryFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableEntryFlags::NO_EXECUTE;
+    }
+    Ok(flags)
+}
+
+fn syscall_munmap(registers: &mut TaskRegisters) -> SyscallResult {
+    let [addr, len, ..] = syscall_args(registers);
+    let address_space = current_task().address_space();
+    let mut vm_areas = address_space.vm_areas.lock();
+    vm_areas.munmap(&mut address_space.page_table.lock(), addr, len)?;
+    Ok(0)
+}
+
+/// Sends `signal` to the task `pid`. Like in Linux, a `pid` of 0 sends it to
+/// the current task's process group, and a `pid` less than -1 sends it to the
+/// process group `-pid`.
+fn syscall_kill(registers: &mut TaskRegisters) -> SyscallResult {
+    let [pid, signal, ..] = syscall_args(registers);
+    #[allow(clippy::cast_possible_wrap)]
+    match pid as i64 {
+        0 => kill_process_group(current_job_control().process_group, signal),
+        // TODO: Support sending signals to every task with a `pid` of -1.
+        -1 => Err(SyscallError::InvalidArgument),
+        group if group < 0 => {
+            let process_group =
+                u32::try_from(group.unsigned_abs()).map_err(|_| SyscallError::NoSuchProcess)?;
+            kill_process_group(TaskId(process_group), signal)
+        }
+        _ => kill_task(pid, signal),
+    }
+}
+
+/// Sends `signal` to every task in the process group. Signal 0 only checks
+/// that the group exists.
+fn kill_process_group(process_group: TaskId, signal: u64) -> SyscallResult {
+    let found = if signal == 0 {
+        process_group_session(process_group).is_some()
+    } else {
+        let signal = Signal::from_number(signal).ok_or(SyscallError::InvalidArgument)?;
+        signal_process_group(process_group, signal)
+    };
+    if found {
+        Ok(0)
+    } else {
+        Err(SyscallError::NoSuchProcess)
+    }
+}
+
+/// Sends `signal` to the task `pid`. Signal 0 only checks that the task
+/// exists.
+fn kill_task(pid: u64, signal: u64) -> SyscallResult {
+    let task_id = u32::try_from(pid)
+        .ok()
+        .filter(|pid| *pid > 0)
+        .map(TaskId::from)
+        .ok_or(SyscallError::InvalidArgument)?;
+
+    if signal == 0 {
+        let task = TASKS.lock_disable_interrupts().get_task(task_id);
+        return task.map_or(Err(SyscallError::NoSuchProcess), |_| Ok(0));
+    }
+    let signal = Signal::from_number(signal).ok_or(SyscallError::InvalidArgument)?;
+    if send_signal(task_id, signal) {
+        Ok(0)
+    } else {
+        Err(SyscallError::NoSuchProcess)
+    }
+}
+
+fn syscall_sigaction(registers: &mut TaskRegisters) -> SyscallResult {
+    let [signal, action_ptr, old_action_ptr, ..] = syscall_args(registers);
+    let signal = Signal::from_number(signal).ok_or(SyscallError::InvalidArgument)?;
+    let new_action = if action_ptr == 0 {
+        None
+    } else {
+        Some(SignalAction::from_user(&read_user(action_ptr)?)?)
+    };
+
+    let old_action = {
+        let task = current_task();
+        let mut signals = task.signals.lock();
+        match new_action {
+            Some(action) => signals.set_action(signal, action)?,
+            None => signals.action(signal),
+        }
+    };
+    if old_action_ptr != 0 {
+        write_user(old_action_ptr, &old_action.to_user())?;
+    }
+    Ok(0)
+}
+
+/// `sigprocmask` values for `how`.
+const SIG_BLOCK: u64 = 0;
+const SIG_UNBLOCK: u64 = 1;
+const SIG_SETMASK: u64 = 2;
+
+fn syscall_sigprocmask(registers: &mut TaskRegisters) -> SyscallResult {
+    let [how, set_ptr, old_set_ptr, ..] = syscall_args(registers);
+    let set: Option<u64> = if set_ptr == 0 {
+        None
+    } else {
+        Some(read_user(set_ptr)?)
+    };
+
+    let old_blocked = {
+        let task = current_task();
+        let mut signals = task.signals.lock();
+        let old_blocked = signals.blocked();
+        if let Some(set) = set {
+            let blocked = match how {
+                SIG_BLOCK => old_blocked | set,
+                SIG_UNBLOCK => old_blocked & !set,
+                SIG_SETMASK => set,
+                _ => return Err(SyscallError::InvalidArgument),
+            };
+            signals.set_blocked(blocked);
+        }
+        old_blocked
+    };
+    if old_set_ptr != 0 {
+        write_user(old_set_ptr, &old_blocked)?;
+    }
+    Ok(0)
+}
+
+fn syscall_sigreturn(registers: &mut TaskRegisters) -> SyscallResult {
+    return_from_signal_handler(registers);
+    // Return the restored rax so we don't clobber it.
+    Ok(registers.rax)
+}
+
+/// Only called by the signal entry trampoline. See
+/// `signal::redirect_interrupted_task_to_signal_entry`.
+fn syscall_signal_entry(registers: &mut TaskRegisters) -> SyscallResult {
+    resume_from_signal_entry(registers);
+    Ok(registers.rax)
+}
+
+/// Creates a pipe and writes its read and write file descriptors to
+/// `fds_ptr`, which points to two `u32`s.
+fn syscall_pipe(registers: &mut TaskRegisters) -> SyscallResult {
+    let [fds_ptr, ..] = syscall_args(registers);
+    create_pipe(fds_ptr, false)
+}
+
+/// Shared by `pipe` and Linux's `pipe2`. If `close_on_exec` is set, both file
+/// descriptors are closed when the task execs.
+fn create_pipe(fds_ptr: u64, close_on_exec: bool) -> SyscallResult {
+    let (reader, writer) = vfs::OpenFile::pipe();
+
+    let task = current_task();
+    let mut files = task.files.lock();
+    let read_fd = files.insert(Arc::new(Mutex::new(reader)))?;
+    let write_fd = match files.insert(Arc::new(Mutex::new(writer))) {
+        Ok(fd) => fd,
+        Err(err) => {
+            files.remove(read_fd);
+            return Err(err.into());
+        }
+    };
+    if close_on_exec {
+        files.set_close_on_exec(read_fd);
+        files.set_close_on_exec(write_fd);
+    }
+    drop(files);
+
+    if let Err(err) = write_user(fds_ptr, &[read_fd.0, write_fd.0]) {
+        let mut files = task.files.lock();
+        files.remove(read_fd);
+        files.remove(write_fd);
+        return Err(err.into());
+    }
+    Ok(0)
+}
+
+/// Creates a thread that shares the current task's memory. The thread starts
+/// on the stack at `stack_ptr` with its FS base (for thread-local storage) set
+/// to `fs_base`, and otherwise returns from the syscall like a forked child.
+/// Returns the thread's task ID, which the caller can `waitpid` on.
+fn syscall_clone(registers: &mut TaskRegisters) -> SyscallResult {
+    let [stack_ptr, fs_base, ..] = syscall_args(registers);
+    let stack_ptr = user_virt_addr(stack_ptr)?;
+    let fs_base = user_virt_addr(fs_base)?;
+    let child_id = clone_current_task(registers, stack_ptr, fs_base, 0);
+    Ok(u64::from(u32::from(child_id)))
+}
+
+/// `arch_prctl` codes.
+const ARCH_SET_FS: u64 = 0x1002;
+const ARCH_GET_FS: u64 = 0x1003;
+
+fn syscall_arch_prctl(registers: &mut TaskRegisters) -> SyscallResult {
+    let [code, addr, ..] = syscall_args(registers);
+    let task = current_task();
+    match code {
+        ARCH_SET_FS => {
+            let fs_base = user_virt_addr(addr)?;
+            task.fs_base.store(fs_base.as_u64());
+            FsBase::write(fs_base);
+        }
+        ARCH_GET_FS => write_user(addr, &task.fs_base.load())?,
+        _ => return Err(SyscallError::InvalidArgument),
+    }
+    Ok(0)
+}
+
+/// Checks that an address we are going to load into a register for
+/// userspace, like a stack pointer, is in user memory. It doesn't have to be
+/// mapped. Non-canonical addresses would fault in the kernel instead of in
+/// userspace.
+fn user_virt_addr(addr: u64) -> Result<VirtAddr, SyscallError> {
+    if addr < USER_MEMORY_END {
+        Ok(VirtAddr::new(addr))
+    } else {
+        Err(SyscallError::InvalidArgument)
+    }
+}
+
+/// `futex` operations and flags.
+const FUTEX_WAIT: u64 = 0;
+const FUTEX_WAKE: u64 = 1;
+const FUTEX_PRIVATE_FLAG: u64 = 128;
+
+/// `FUTEX_WAIT` sleeps while the `u32` at `addr` is `value`, for at most the
+/// time in the `timespec` at `timeout_ptr` if it isn't NULL. `FUTEX_WAKE`
+/// wakes up at most `value` waiters and returns how many it woke up.
+///
+/// `FUTEX_PRIVATE_FLAG` is accepted and ignored, since futexes are keyed by
+/// physical address either way.
+fn syscall_futex(registers: &mut TaskRegisters) -> SyscallResult {
+    let [addr, op, value, timeout_ptr, ..] = syscall_args(registers);
+    // Like Linux, only use the low 32 bits, since C callers pass an int that
+    // may have been sign extended.
+    let value = value as u32;
+    match op & !FUTEX_PRIVATE_FLAG {
+        FUTEX_WAIT => {
+            let timeout = if timeout_ptr == 0 {
+                None
+            } else {
+                Some(read_user::<UserTimespec>(timeout_ptr)?.to_milliseconds()?)
+            };
+            futex_wait(addr, value, timeout)?;
+            Ok(0)
+        }
+        FUTEX_WAKE => Ok(u64::from(futex_wake(addr, value)?)),
+        _ => Err(SyscallError::InvalidArgument),
+    }
+}
+
+/// Writes the current time of the clock with the given ID to the `timespec`
+/// at `timespec_ptr`. See `Clock` for the supported clocks.
+fn syscall_clock_gettime(registers: &mut TaskRegisters) -> SyscallResult {
+    let [clock_id, timespec_ptr, ..] = syscall_args(registers);
+    let clock = Clock::from_id(clock_id).ok_or(SyscallError::InvalidArgument)?;
+    write_user(timespec_ptr, &UserTimespec::from_nanoseconds(clock.now()))?;
+    Ok(0)
+}
+
+/// Sleeps for the time in the `timespec` at `request_ptr`. If a signal
+/// interrupts the sleep, returns `EINTR` and writes the time left to the
+/// `timespec` at `remaining_ptr` if it isn't NULL.
+fn syscall_nanosleep(registers: &mut TaskRegisters) -> SyscallResult {
+    let [request_ptr, remaining_ptr, ..] = syscall_args(registers);
+    let duration = read_user::<UserTimespec>(request_ptr)?.to_nanoseconds()?;
+    match time::sleep(duration) {
+        Ok(()) => Ok(0),
+        Err(SleepInterrupted { remaining }) => {
+            if remaining_ptr != 0 {
+                write_user(remaining_ptr, &UserTimespec::from_nanoseconds(remaining))?;
+            }
+            Err(SyscallError::Interrupted)
+        }
+    }
+}
+
+/// `getrandom` flags.
+const GRND_NONBLOCK: u64 = 0x1;
+const GRND_RANDOM: u64 = 0x2;
+const GRND_INSECURE: u64 = 0x4;
+
+/// Fills the buffer with random bytes and returns how many it wrote, which
+/// may be less than `buf_len` for large requests. Sleeps until the entropy
+/// pool is seeded, unless `GRND_NONBLOCK` or `GRND_INSECURE` is set.
+/// `GRND_RANDOM` is accepted but doesn't change anything, like in Linux.
+fn syscall_getrandom(registers: &mut TaskRegisters) -> SyscallResult {
+    let [buf_ptr, buf_len, flags, ..] = syscall_args(registers);
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM | GRND_INSECURE) != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    if flags & GRND_INSECURE == 0 && !random::is_seeded() {
+        if flags & GRND_NONBLOCK != 0 {
+            return Err(SyscallError::TryAgain);
+        }
+        random::wait_until_seeded().map_err(|_| SyscallError::Interrupted)?;
+    }
+
+    let mut buffer = vec![0; (buf_len as usize).min(MAX_IO_LEN)];
+    random::fill_bytes(&mut buffer);
+    copy_to_user(buf_ptr, &buffer)?;
+    Ok(buffer.len() as u64)
+}
+
+// `ioctl` requests.
+const TCGETS: u64 = 0x5401;
+const TCSETS: u64 = 0x5402;
+const TCSETSW: u64 = 0x5403;
+const TCSETSF: u64 = 0x5404;
+const TIOCSCTTY: u64 = 0x540e;
+const TIOCGPGRP: u64 = 0x540f;
+const TIOCSPGRP: u64 = 0x5410;
+const TIOCGWINSZ: u64 = 0x5413;
+const TIOCGSID: u64 = 0x5429;
+
+/// Device-specific requests. The only device that supports any is the
+/// console, which supports getting and setting the terminal settings, getting
+/// the window size, and job control (see `tty`). Everything else fails with
+/// `ENOTTY`, which is how programs check whether a file is a terminal.
+fn syscall_ioctl(registers: &mut TaskRegisters) -> SyscallResult {
+    let [fd, request, arg, ..] = syscall_args(registers);
+    let file = get_open_file(fd)?;
+    if file.lock().device() != Some(vfs::Device::Console) {
+        return Err(SyscallError::NotATerminal);
+    }
+    match request {
+        TCGETS => write_user(arg, &tty::termios())?,
+        // Output is written synchronously, so there is never any output to
+        // wait for in TCSETSW.
+        TCSETS | TCSETSW | TCSETSF => {
+            let termios = read_user::<tty::Termios>(arg)?;
+            tty::stop_if_background(Signal::SIGTTOU)?;
+            tty::set_termios(termios, request == TCSETSF);
+        }
+        TIOCGWINSZ => write_user(arg, &tty::window_size())?,
+        // The argument says whether to steal the console from another
+        // session.
+        TIOCSCTTY => tty::set_controlling_terminal(arg == 1)?,
+        TIOCGPGRP => {
+            let process_group =
+                tty::foreground_process_group().ok_or(SyscallError::NotATerminal)?;
+            write_user(arg, &u32::from(process_group))?;
+        }
+        TIOCSPGRP => set_foreground_process_group(read_user::<i32>(arg)?)?,
+        TIOCGSID => {
+            let session = tty::session().ok_or(SyscallError::NotATerminal)?;
+            write_user(arg, &u32::from(session))?;
+        }
+        _ => return Err(SyscallError::NotATerminal),
+    }
+    Ok(0)
+}
+
+/// Implements `TIOCSPGRP`, which shells use to move jobs into the foreground.
+/// The process group must be in the current task's session, and the console
+/// must be its controlling terminal.
+fn set_foreground_process_group(process_group: i32) -> Result<(), SyscallError> {
+    let job_control = current_job_control();
+    if !job_control.controlling_terminal {
+        return Err(SyscallError::NotATerminal);
+    }
+    tty::stop_if_background(Signal::SIGTTOU)?;
+    let process_group = u32::try_from(process_group)
+        .map(TaskId)
+        .map_err(|_| SyscallError::InvalidArgument)?;
+    match process_group_session(process_group) {
+        None => Err(SyscallError::NoSuchProcess),
+        Some(session) if session != job_control.session => Err(SyscallError::NotPermitted),
+        Some(_) => {
+            tty::set_foreground_process_group(process_group);
+            Ok(())
+        }
+    }
+}
+
+/// Converts a `pid` argument to a task ID, where 0 means the current task.
+fn task_id_or_current(pid: u64) -> Result<TaskId, SyscallError> {
+    match u32::try_from(pid) {
+        Ok(0) => Ok(current_task_id()),
+        Ok(pid) => Ok(TaskId(pid)),
+        Err(_) => Err(SyscallError::InvalidArgument),
+    }
+}
+
+/// Moves the task `pid` into the process group `pgid`. A `pid` of 0 means the
+/// current task, and a `pgid` of 0 makes `pid` the leader of a new process
+/// group. See `job_control::set_process_group`.
+fn syscall_setpgid(registers: &mut TaskRegisters) -> SyscallResult {
+    let [pid, pgid, ..] = syscall_args(registers);
+    let task_id = task_id_or_current(pid)?;
+    let process_group = if pgid == 0 {
+        task_id
+    } else {
+        task_id_or_current(pgid)?
+    };
+    set_process_group(task_id, process_group)?;
+    Ok(0)
+}
+
+/// Returns the process group of the task `pid`, or of the current task if
+/// `pid` is 0.
+fn syscall_getpgid(registers: &mut TaskRegisters) -> SyscallResult {
+    let [pid, ..] = syscall_args(registers);
+    let job_control = job_control(task_id_or_current(pid)?).ok_or(SyscallError::NoSuchProcess)?;
+    Ok(u64::from(u32::from(job_control.process_group)))
+}
+
+/// Makes the current task the leader of a new session, and returns the
+/// session ID. See `job_control::create_session`.
+fn syscall_setsid(_registers: &mut TaskRegisters) -> SyscallResult {
+    let session = create_session()?;
+    Ok(u64::from(u32::from(session)))
+}
+
+/// Returns the session of the task `pid`, or of the current task if `pid` is
+/// 0.
+fn syscall_getsid(registers: &mut TaskRegisters) -> SyscallResult {
+    let [pid, ..] = syscall_args(registers);
+    let job_control = job_control(task_id_or_current(pid)?).ok_or(SyscallError::NoSuchProcess)?;
+    Ok(u64::from(u32::from(job_control.session)))
+}
+
+fn syscall_stat(registers: &mut TaskRegisters) -> SyscallResult {
+    let [path_ptr, path_len, stat_ptr, ..] = syscall_args(registers);
+    let path = user_path(path_ptr, path_len)?;
+    stat_path(&path, stat_ptr)
+}
+
+/// Writes the metadata of the file at `path` to the `stat` at `stat_ptr`.
+fn stat_path(path: &vfs::FilePath, stat_ptr: u64) -> SyscallResult {
+    let metadata = vfs::path_metadata(path)?;
+    write_user(stat_ptr, &UserStat::from_metadata(&metadata))?;
+    Ok(0)
+}
+
+fn syscall_fstat(registers: &mut TaskRegisters) -> SyscallResult {
+    let [fd, stat_ptr, ..] = syscall_args(registers);
+    stat_fd(fd, stat_ptr)
+}
+
+/// Writes the metadata of an open file to the `stat` at `stat_ptr`.
+fn stat_fd(fd: u64, stat_ptr: u64) -> SyscallResult {
+    let metadata = get_open_file(fd)?.lock().metadata();
+    write_user(stat_ptr, &UserStat::from_metadata(&metadata))?;
+    Ok(0)
+}
+
+/// Size of Linux's `struct linux_dirent64` without the name: the inode
+/// number, the offset of the next entry, the record length, and the type.
+const DIRENT_HEADER_LEN: usize = 19;
+
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+
+/// Fills the buffer with a Linux `struct linux_dirent64` for each entry of
+/// the open directory `fd`, starting at the directory's offset, and returns
+/// how many bytes were written. Returns 0 once every entry has been read, and
+/// fails with `EINVAL` if the next entry doesn't fit in the buffer.
+fn syscall_getdents(registers: &mut TaskRegisters) -> SyscallResult {
+    let [fd, buf_ptr, buf_len, ..] = syscall_args(registers);
+    let file = get_open_file(fd)?;
+    let buf_len = (buf_len as usize).min(MAX_IO_LEN);
+
+    let mut buffer = Vec::new();
+    let mut buffer_full = false;
+    let mut next_offset = None;
+    // Only hold the lock while collecting the entries, not while copying them
+    // to userspace, which can page fault and sleep.
+    file.lock().read_directory(|index, entry| {
+        let name = entry.name();
+        // Each record is padded so the next one is 8 byte aligned. The name
+        // is nul-terminated.
+        let record_len = (DIRENT_HEADER_LEN + name.len() + 1).next_multiple_of(8);
+        if buffer.len() + record_len > buf_len {
+            buffer_full = true;
+            return false;
+        }
+        let entry_type = match entry.entry_type() {
+            vfs::DirectoryEntryType::File => DT_REG,
+            vfs::DirectoryEntryType::Directory => DT_DIR,
+        };
+        let start = buffer.len();
+        buffer.extend_from_slice(&entry.inode_number().to_ne_bytes());
+        // Seeking to this offset continues after this entry.
+        buffer.extend_from_slice(&(index as u64 + 1).to_ne_bytes());
+        buffer.extend_from_slice(&(record_len as u16).to_ne_bytes());
+        buffer.push(entry_type);
+        buffer.extend_from_slice(name.as_bytes());
+        buffer.resize(start + record_len, 0);
+        next_offset = Some(index + 1);
+        true
+    })?;
+
+    if buffer.is_empty() && buffer_full {
+        return Err(SyscallError::InvalidArgument);
+    }
+    copy_to_user(buf_ptr, &buffer)?;
+    // Only skip the entries once userspace has them.
+    if let Some(offset) = next_offset {
+        file.lock().seek(offset as i64, vfs::SeekWhence::Start)?;
+    }
+    Ok(buffer.len() as u64)
+}
+
+/// Linux's `struct timespec`.
+#[derive(Debug, Clone, Copy, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+struct UserTimespec {
+    seconds: u64,
+    nanoseconds: u64,
+}
+
+impl UserTimespec {
+    fn from_nanoseconds(nanoseconds: Nanoseconds) -> Self {
+        let nanoseconds = u64::from(nanoseconds);
+        Self {
+            seconds: nanoseconds / 1_000_000_000,
+            nanoseconds: nanoseconds % 1_000_000_000,
+        }
+    }
+
+    fn to_nanoseconds(self) -> Result<Nanoseconds, SyscallError> {
+        if self.nanoseconds >= 1_000_000_000 {
+            return Err(SyscallError::InvalidArgument);
+        }
+        self.seconds
+            .checked_mul(1_000_000_000)
+            .and_then(|nanos| nanos.checked_add(self.nanoseconds))
+            .map(Nanoseconds::new)
+            .ok_or(SyscallError::InvalidArgument)
+    }
+
+    /// Converts a duration to milliseconds, rounding up so we never sleep for
+    /// less than the requested time.
+    fn to_milliseconds(self) -> Result<Milliseconds, SyscallError> {
+        if self.nanoseconds >= 1_000_000_000 {
+            return Err(SyscallError::InvalidArgument);
+        }
+        self.seconds
+            .checked_mul(1000)
+            .and_then(|millis| millis.checked_add(self.nanoseconds.div_ceil(1_000_000)))
+            .map(Milliseconds::new)
+            .ok_or(SyscallError::InvalidArgument)
+    }
+}
+
+/// Linux's `struct stat`.
+#[derive(Debug, Clone, Copy, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+struct UserStat {
+    device: u64,
+    inode: u64,
+    links: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    _padding: u32,
+    special_device: u64,
+    size: u64,
+    block_size: u64,
+    blocks: u64,
+    accessed: UserTimespec,
+    modified: UserTimespec,
+    changed: UserTimespec,
+    _reserved: [u64; 3],
+}
+
+/// Block size we suggest for efficient I/O in `stat`.
+const STAT_BLOCK_SIZE: u64 = 4096;
+
+impl UserStat {
+    // TODO: Fill in `device` once we can mount more than one filesystem, so
+    // inode numbers from different filesystems can be told apart.
+    fn from_metadata(metadata: &vfs::Metadata) -> Self {
+        let time = |seconds| UserTimespec {
+            seconds,
+            nanoseconds: 0,
+        };
+        Self {
+            device: 0,
+            inode: metadata.inode,
+            links: u64::from(metadata.links),
+            mode: metadata.mode(),
+            uid: metadata.uid,
+            gid: metadata.gid,
+            _padding: 0,
+            special_device: 0,
+            size: metadata.size,
+            block_size: STAT_BLOCK_SIZE,
+            blocks: metadata.blocks,
+            accessed: time(metadata.accessed),
+            modified: time(metadata.modified),
+            changed: time(metadata.changed),
+            _reserved: [0; 3],
+        }
+    }
+}
+
+fn file_descriptor(fd: u64) -> Result<vfs::FileDescriptor, SyscallError> {
+    let fd = u32::try_from(fd).map_err(|_| SyscallError::BadFileDescriptor)?;
+    Ok(vfs::FileDescriptor(fd))
+}
+
+fn get_open_file(fd: u64) -> Result<Arc<Mutex<vfs::OpenFile>>, SyscallError> {
+    let fd = file_descriptor(fd)?;
+    let file = current_task().files.lock().get(fd);
+    file.ok_or(SyscallError::BadFileDescriptor)
+}
+
+/// Maximum length of a string (like a path) read from userspace.
+const MAX_USER_STRING_LEN: usize = 4096;
+
+/// Maximum number of elements in a NULL-terminated array of pointers (like
+/// argv) read from userspace.
+const MAX_USER_ARRAY_LEN: usize = 256;
+
+/// Maximum number of bytes a single `read` or `write` transfers. Larger
+/// requests are cut short, which callers have to handle anyway.
+const MAX_IO_LEN: usize = 64 * 1024;
+
+/// Reads a NULL-terminated array of pointers to nul-terminated strings from
+/// userspace, like argv in exec.
+fn user_c_str_array(ptr: u64) -> Result<Vec<String>, SyscallError> {
+    let mut strings = Vec::new();
+    loop {
+        if strings.len() >= MAX_USER_ARRAY_LEN {
+            return Err(SyscallError::ArgumentListTooLong);
+        }
+        let element_ptr = ptr
+            .checked_add(strings.len() as u64 * 8)
+            .ok_or(SyscallError::BadAddress)?;
+        let str_ptr: u64 = read_user(element_ptr)?;
+        if str_ptr == 0 {
+            return Ok(strings);
+        }
+        strings.push(user_c_str(str_ptr)?);
+    }
+}
+
+/// Reads a nul-terminated UTF-8 string from userspace.
+fn user_c_str(ptr: u64) -> Result<String, SyscallError> {
+    let bytes =
+        read_user_c_str(ptr, MAX_USER_STRING_LEN)?.ok_or(SyscallError::ArgumentListTooLong)?;
+    String::from_utf8(bytes).map_err(|_| SyscallError::InvalidArgument)
+}
+
+/// Reads a UTF-8 string from userspace given its pointer and length.
+fn user_string(ptr: u64, len: u64, max_len: usize) -> Result<String, SyscallError> {
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|len| *len <= max_len)
+        .ok_or(SyscallError::InvalidArgument)?;
+    read_user_string(ptr, len)?.ok_or(SyscallError::InvalidArgument)
+}
+
+/// Reads a path from userspace given its pointer and length.
+fn user_path(ptr: u64, len: u64) -> Result<vfs::FilePath, SyscallError> {
+    let path = user_string(ptr, len, MAX_USER_STRING_LEN)?;
+    vfs::FilePath::parse(&path).ok_or(SyscallError::InvalidArgument)
+}
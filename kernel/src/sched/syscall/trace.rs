@@ -0,0 +1,284 @@
+//! strace-style syscall tracing. Every syscall made by a task with tracing
+//! turned on (with the kernel shell's `strace` command, `/tasks/<id>/strace`
+//! in sysfs, or `Task::set_syscall_trace`) is logged as one line with the
+//! syscall's name, its decoded arguments, its return value, and how long it
+//! took:
+//!
+//! ```text
+//! task 5: open("/etc/motd", 0x0) = 3 <0.000012s>
+//! task 5: read(3, 0x7fffffffe000, 256) = 42 <0.000103s>
+//! task 5: kill(7, SIGTERM) = -3 ESRCH <0.000002s>
+//! ```
+//!
+//! Arguments are decoded when the syscall starts, so buffers the syscall
+//! fills in (like the one passed to `read`) are only shown as addresses.
+//! Syscalls we don't have a signature for show all six argument registers in
+//! hex.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::hpet::{self, Nanoseconds};
+use crate::sched::schedcore::current_task;
+use crate::sched::signal::Signal;
+use crate::sched::task::TaskId;
+use crate::sched::user_memory::{read_user_bytes, read_user_c_str};
+
+use super::{linux, SyscallAbi, SyscallResult, NUM_NATIVE_SYSCALLS};
+use Arg::{CStr, Hex, Int, Str};
+
+/// How to show a syscall argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arg {
+    /// Signed decimal, like file descriptors, PIDs, and lengths.
+    Int,
+
+    /// Hex, like flags and addresses.
+    Hex,
+
+    /// A signal number, shown by name.
+    Signal,
+
+    /// A string in user memory whose length is the argument at this index.
+    Str(usize),
+
+    /// A nul-terminated string in user memory.
+    CStr,
+}
+
+/// How to show a syscall's return value. Errors are always shown by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Return {
+    Int,
+    Hex,
+
+    /// The syscall never returns, like `exit`.
+    Never,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Signature {
+    name: &'static str,
+    args: &'static [Arg],
+    returns: Return,
+}
+
+impl Signature {
+    const fn new(name: &'static str, args: &'static [Arg]) -> Self {
+        Self {
+            name,
+            args,
+            returns: Return::Int,
+        }
+    }
+
+    const fn returns(self, returns: Return) -> Self {
+        Self { returns, ..self }
+    }
+}
+
+/// Signatures of our own syscalls, indexed by syscall number like
+/// `SYSCALL_HANDLERS`.
+static NATIVE_SIGNATURES: [Signature; NUM_NATIVE_SYSCALLS] = [
+    Signature::new("exit", &[Int]).returns(Return::Never), // 0
+    Signature::new("print", &[Str(1), Int]),
+    Signature::new("open", &[Str(1), Int, Hex]),
+    Signature::new("read", &[Int, Hex, Int]),
+    Signature::new("write", &[Int, Str(2), Int]),
+    Signature::new("close", &[Int]), // 5
+    Signature::new("lseek", &[Int, Int, Int]),
+    Signature::new("fork", &[]),
+    Signature::new("exec", &[Str(1), Int, Hex, Hex, Hex]),
+    Signature::new("waitpid", &[Int, Hex, Hex]),
+    Signature::new("brk", &[Hex]).returns(Return::Hex), // 10
+    Signature::new("mmap", &[Hex, Int, Hex, Hex, Int, Hex]).returns(Return::Hex),
+    Signature::new("munmap", &[Hex, Int]),
+    Signature::new("kill", &[Int, Arg::Signal]),
+    Signature::new("sigaction", &[Arg::Signal, Hex, Hex]),
+    Signature::new("sigprocmask", &[Int, Hex, Hex]), // 15
+    Signature::new("sigreturn", &[]),
+    Signature::new("signal_entry", &[]),
+    Signature::new("pipe", &[Hex]),
+    Signature::new("clone", &[Hex, Hex]),
+    Signature::new("arch_prctl", &[Hex, Hex]), // 20
+    Signature::new("futex", &[Hex, Int, Int, Hex]),
+    Signature::new("clock_gettime", &[Int, Hex]),
+    Signature::new("nanosleep", &[Hex, Hex]),
+    Signature::new("getrandom", &[Hex, Int, Hex]),
+    Signature::new("ioctl", &[Int, Hex, Hex]), // 25
+    Signature::new("setpgid", &[Int, Int]),
+    Signature::new("getpgid", &[Int]),
+    Signature::new("setsid", &[]),
+    Signature::new("getsid", &[Int]),
+    Signature::new("stat", &[Str(1), Int, Hex]), // 30
+    Signature::new("fstat", &[Int, Hex]),
+    Signature::new("getdents", &[Int, Hex, Int]),
+];
+
+/// Signatures of the Linux syscalls we implement. See `linux::syscall_handler`.
+fn linux_signature(number: u64) -> Option<Signature> {
+    let name = linux::syscall_name(number)?;
+    let args: &[Arg] = match number {
+        0 | 19 | 20 | 217 => &[Int, Hex, Int],
+        1 => &[Int, Str(2), Int],
+        2 => &[CStr, Hex, Hex],
+        3 | 60 | 121 | 124 | 231 => &[Int],
+        4 | 6 => &[CStr, Hex],
+        5 => &[Int, Hex],
+        8 => &[Int, Int, Int],
+        9 => &[Hex, Int, Hex, Hex, Int, Hex],
+        11 => &[Hex, Int],
+        12 | 22 | 63 | 218 => &[Hex],
+        13 => &[Arg::Signal, Hex, Hex, Int],
+        14 => &[Int, Hex, Hex, Int],
+        16 => &[Int, Hex, Hex],
+        15 | 24 | 39 | 57 | 58 | 102 | 104 | 107 | 108 | 110 | 111 | 112 | 186 => &[],
+        35 | 158 | 228 | 293 => &[Hex, Hex],
+        56 => &[Hex, Hex, Hex, Hex, Hex],
+        59 => &[CStr, Hex, Hex],
+        61 => &[Int, Hex, Hex, Hex],
+        62 | 200 => &[Int, Arg::Signal],
+        109 => &[Int, Int],
+        202 => &[Hex, Int, Int, Hex, Hex, Int],
+        234 => &[Int, Int, Arg::Signal],
+        257 | 262 => &[Int, CStr, Hex, Hex],
+        318 => &[Hex, Int, Hex],
+        _ => return None,
+    };
+    let returns = match number {
+        9 | 12 => Return::Hex,
+        60 | 231 => Return::Never,
+        _ => Return::Int,
+    };
+    Some(Signature {
+        name,
+        args,
+        returns,
+    })
+}
+
+/// Maximum number of bytes of a string argument to show.
+const MAX_STRING_LEN: usize = 32;
+
+/// A syscall being traced, from when it starts until it returns.
+pub(super) struct SyscallTrace {
+    task_id: TaskId,
+    call: String,
+    returns: Return,
+    start: Nanoseconds,
+}
+
+impl SyscallTrace {
+    /// Starts tracing a syscall if the current task has tracing turned on.
+    /// Syscalls that never return are logged right away, and return `None`.
+    pub(super) fn start(abi: SyscallAbi, number: u64, args: [u64; 6]) -> Option<Self> {
+        let task = current_task();
+        if !task.syscall_trace() {
+            return None;
+        }
+        let task_id = task.id;
+        drop(task);
+
+        let signature = match abi {
+            SyscallAbi::Native => usize::try_from(number)
+                .ok()
+                .and_then(|number| NATIVE_SIGNATURES.get(number))
+                .copied(),
+            SyscallAbi::Linux => linux_signature(number),
+        };
+        let call = signature.map_or_else(
+            || {
+                let name = match abi {
+                    SyscallAbi::Native => None,
+                    SyscallAbi::Linux => linux::syscall_name(number),
+                };
+                let name = name.map_or_else(|| format!("syscall_{number}"), String::from);
+                let args: Vec<String> = args.iter().map(|arg| format!("{arg:#x}")).collect();
+                format!("{name}({})", args.join(", "))
+            },
+            |signature| format_call(&signature, args),
+        );
+
+        let returns = signature.map_or(Return::Int, |signature| signature.returns);
+        if returns == Return::Never {
+            log::info!("task {}: {call} = ?", u32::from(task_id));
+            return None;
+        }
+        Some(Self {
+            task_id,
+            call,
+            returns,
+            start: hpet::elapsed_nanoseconds(),
+        })
+    }
+
+    /// Logs the syscall with its result.
+    pub(super) fn finish(self, result: &SyscallResult) {
+        let elapsed = u64::from(hpet::elapsed_nanoseconds().saturating_sub(self.start));
+        let result = match result {
+            Ok(value) if self.returns == Return::Hex => format!("{value:#x}"),
+            #[allow(clippy::cast_possible_wrap)]
+            Ok(value) => format!("{}", *value as i64),
+            Err(err) => format!("-{} {}", *err as u16, err.name()),
+        };
+        log::info!(
+            "task {}: {} = {result} <{}.{:06}s>",
+            u32::from(self.task_id),
+            self.call,
+            elapsed / 1_000_000_000,
+            elapsed % 1_000_000_000 / 1000,
+        );
+    }
+}
+
+fn format_call(signature: &Signature, args: [u64; 6]) -> String {
+    let mut call = format!("{}(", signature.name);
+    for (i, arg) in signature.args.iter().enumerate() {
+        if i > 0 {
+            call.push_str(", ");
+        }
+        let value = args[i];
+        let _ = match *arg {
+            #[allow(clippy::cast_possible_wrap)]
+            Int => write!(call, "{}", value as i64),
+            Hex => write!(call, "{value:#x}"),
+            Arg::Signal => match Signal::from_number(value) {
+                Some(signal) => write!(call, "{signal}"),
+                None => write!(call, "{value}"),
+            },
+            Str(len_index) => {
+                let len = usize::try_from(args[len_index]).unwrap_or(usize::MAX);
+                write_user_string(&mut call, value, Some(len))
+            }
+            CStr => write_user_string(&mut call, value, None),
+        };
+    }
+    call.push(')');
+    call
+}
+
+/// Writes the string at `addr` in quotes, cut off at `MAX_STRING_LEN` bytes.
+/// Strings without a `len` end at a nul byte. If the string can't be read, its
+/// address is written instead.
+fn write_user_string(out: &mut String, addr: u64, len: Option<usize>) -> core::fmt::Result {
+    if addr == 0 {
+        return write!(out, "NULL");
+    }
+    let bytes = match len {
+        Some(len) => read_user_bytes(addr, len.min(MAX_STRING_LEN)).map(|bytes| (bytes, len)),
+        None => read_user_c_str(addr, MAX_STRING_LEN).and_then(|bytes| match bytes {
+            Some(bytes) => {
+                let len = bytes.len();
+                Ok((bytes, len))
+            }
+            None => read_user_bytes(addr, MAX_STRING_LEN).map(|bytes| (bytes, usize::MAX)),
+        }),
+    };
+    let Ok((bytes, len)) = bytes else {
+        return write!(out, "{addr:#x}");
+    };
+    let ellipsis = if len > bytes.len() { "..." } else { "" };
+    write!(out, "\"{}\"{ellipsis}", bytes.escape_ascii())
+}
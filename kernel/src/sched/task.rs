@@ -2,14 +2,21 @@ use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::PhysAddr;
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
 use crate::hpet::Milliseconds;
-use crate::memory;
-use crate::memory::Level4PageTable;
-use crate::sync::{AtomicEnum, AtomicInt, SpinLock, WaitCell};
+use crate::sync::{AtomicEnum, AtomicInt, SpinLock, WaitCell, WaitQueue};
+use crate::vfs;
 
+use super::job_control::JobControl;
 use super::schedcore::{force_unlock_scheduler, kill_current_task};
+use super::signal::{Signal, SignalState};
 use super::stack;
+use super::syscall::SyscallAbi;
+use super::vm::AddressSpace;
 
 /// All tasks in the system.
 pub(crate) static TASKS: SpinLock<Tasks> = SpinLock::new(Tasks::new());
@@ -34,6 +41,24 @@ impl Tasks {
         name: String,
         start_fn: KernelTaskStartFunction,
         arg: *const (),
+    ) -> TaskId {
+        let address_space =
+            Arc::new(AddressSpace::new().expect("failed to allocate page table for new task"));
+        let files = vfs::FileDescriptorTable::new();
+        self.new_task_with(name, start_fn, arg, address_space, files)
+    }
+
+    /// Like `new_task`, but uses the given address space and open files instead
+    /// of fresh ones. Used when creating a task from another task, like in
+    /// fork. The address space may be shared with other tasks, like for
+    /// threads.
+    pub(super) fn new_task_with(
+        &mut self,
+        name: String,
+        start_fn: KernelTaskStartFunction,
+        arg: *const (),
+        address_space: Arc<AddressSpace>,
+        files: vfs::FileDescriptorTable,
     ) -> TaskId {
         let id = self.next_task_id;
         self.next_task_id.0 += 1;
@@ -43,7 +68,7 @@ impl Tasks {
             "task ID {id:?} already exists"
         );
 
-        let task = Task::new(id, name, start_fn, arg);
+        let task = Task::new(id, name, start_fn, arg, address_space, files);
         self.tasks.insert(id, Arc::new(task));
         id
     }
@@ -59,6 +84,10 @@ impl Tasks {
         )
     }
 
+    pub(super) fn iter(&self) -> impl Iterator<Item = &Arc<Task>> {
+        self.tasks.values()
+    }
+
     pub(crate) fn task_ids(&self) -> Vec<TaskId> {
         let mut ids: Vec<TaskId> = self.tasks.keys().copied().collect();
         ids.sort();
@@ -81,7 +110,43 @@ pub(crate) struct Task {
     pub(super) registers: TaskRegisters,
     pub(super) desired_state: AtomicEnum<u8, DesiredTaskState>,
     pub(super) exit_wait_cell: WaitCell<TaskExitCode>,
-    pub(super) page_table: SpinLock<Level4PageTable>,
+
+    /// Woken up whenever one of the task's children exits. See
+    /// `family::wait_for_child`.
+    pub(super) child_exit_wait_queue: WaitQueue,
+
+    /// User memory and the page table, which threads share. Use
+    /// `address_space()` to get it. Only exec replaces it, see
+    /// `replace_address_space`.
+    address_space: SpinLock<Arc<AddressSpace>>,
+
+    /// The FS base register, which userspace uses for thread-local storage.
+    /// The scheduler saves and restores it on every context switch.
+    pub(super) fs_base: AtomicInt<u64, u64>,
+
+    /// How the task makes syscalls. Exec sets it, and forked and cloned tasks
+    /// inherit it.
+    pub(super) syscall_abi: AtomicEnum<u8, SyscallAbi>,
+
+    /// If true, every syscall the task makes is logged. Forked and cloned
+    /// tasks inherit it. See `syscall::trace`.
+    syscall_trace: AtomicBool,
+
+    /// User address of a `u32` that is set to 0 when the task exits, followed
+    /// by a futex wake, so threads can wait for each other to exit. This is
+    /// Linux's `CLONE_CHILD_CLEARTID` and `set_tid_address`. 0 means none.
+    pub(super) clear_child_tid: AtomicInt<u64, u64>,
+
+    /// Open files for the task. The lock should only be held long enough to
+    /// look up or modify a descriptor. Each `OpenFile` has its own lock.
+    pub(super) files: SpinLock<vfs::FileDescriptorTable>,
+
+    /// Pending and blocked signals, and what to do when they are delivered.
+    pub(super) signals: SpinLock<SignalState>,
+
+    /// The task's process group and session, and whether it has a
+    /// controlling terminal. See `job_control`.
+    pub(super) job_control: SpinLock<JobControl>,
 
     /// How much longer the task can run before it is preempted.
     pub(super) remaining_slice: AtomicInt<u64, Milliseconds>,
@@ -97,9 +162,15 @@ impl From<TaskId> for u32 {
     }
 }
 
+impl From<u32> for TaskId {
+    fn from(id: u32) -> Self {
+        Self(id)
+    }
+}
+
 /// Used to store kernel stack context in the task so we know where to resume
 /// execution.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, FromZeroes, FromBytes, AsBytes)]
 #[repr(packed)]
 #[allow(dead_code)]
 pub(super) struct TaskRegisters {
@@ -152,6 +223,8 @@ impl Task {
         name: String,
         start_fn: KernelTaskStartFunction,
         arg: *const (),
+        address_space: Arc<AddressSpace>,
+        files: vfs::FileDescriptorTable,
     ) -> Self {
         // Allocate a kernel stack
         let kernel_stack = stack::allocate_stack();
@@ -194,19 +267,70 @@ impl Task {
             ..Default::default()
         };
 
-        let page_table = memory::clone_kernel_page_table();
-
         Self {
             id,
             name,
             registers,
             desired_state: AtomicEnum::new(DesiredTaskState::ReadyToRun),
             exit_wait_cell: WaitCell::new(),
-            page_table: SpinLock::new(page_table),
+            child_exit_wait_queue: WaitQueue::new(),
+            address_space: SpinLock::new(address_space),
+            fs_base: AtomicInt::new(0),
+            syscall_abi: AtomicEnum::new(SyscallAbi::Native),
+            syscall_trace: AtomicBool::new(false),
+            clear_child_tid: AtomicInt::new(0),
+            files: SpinLock::new(files),
+            signals: SpinLock::new(SignalState::new()),
+            job_control: SpinLock::new(JobControl::new(id)),
             remaining_slice: AtomicInt::new(Milliseconds::new(0)),
             kernel_stack,
         }
     }
+
+    pub(super) fn address_space(&self) -> Arc<AddressSpace> {
+        self.address_space.lock().clone()
+    }
+
+    /// Physical address of the task's page table, for loading into CR3.
+    pub(super) fn page_table_address(&self) -> PhysAddr {
+        self.address_space.lock().page_table_address
+    }
+
+    /// Gives the task a new address space and switches to its page table,
+    /// returning the old address space. This must only be called on the
+    /// current task.
+    pub(super) fn replace_address_space(
+        &self,
+        address_space: Arc<AddressSpace>,
+    ) -> Arc<AddressSpace> {
+        let mut current = self.address_space.lock();
+        // Holding the lock keeps us from being preempted, so the scheduler
+        // never switches back to us with the old page table.
+        unsafe {
+            address_space.page_table.lock().load();
+        }
+        // Loading CR3 flushed the old address space out of this CPU's TLB,
+        // so other threads still using it can run elsewhere now.
+        address_space.running.store(true, Ordering::Release);
+        current.running.store(false, Ordering::Release);
+        core::mem::replace(&mut *current, address_space)
+    }
+
+    /// Returns the task's exit code if it has exited, without waiting.
+    pub(crate) fn exit_code(&self) -> Option<TaskExitCode> {
+        self.exit_wait_cell.try_get()
+    }
+
+    /// Returns true if the task's syscalls are being traced.
+    pub(crate) fn syscall_trace(&self) -> bool {
+        self.syscall_trace.load(Ordering::Relaxed)
+    }
+
+    /// Turns syscall tracing on or off for the task. It takes effect at the
+    /// task's next syscall.
+    pub(crate) fn set_syscall_trace(&self, enabled: bool) {
+        self.syscall_trace.store(enabled, Ordering::Relaxed);
+    }
 }
 
 /// `DesiredTaskState` is the _desired_ state for a task (duh). For example, if
@@ -243,7 +367,48 @@ impl From<DesiredTaskState> for u8 {
 pub(crate) enum TaskExitCode {
     ExitSuccess,
     ExitFailure(u64),
-    // TODO: Add failure codes here
+
+    /// The task was killed because it caused a CPU exception in userspace.
+    UserFault(UserFault),
+
+    /// The task was killed by a signal it didn't handle.
+    KilledBySignal(Signal),
+}
+
+impl TaskExitCode {
+    /// Encodes the exit code as a Linux-style wait status, which is what
+    /// `waitpid` gives to userspace. For normal exits, the low 8 bits of the
+    /// exit code go in bits 8 through 15. For tasks killed by a signal, the
+    /// status is the signal number. Tasks killed by a fault look like they
+    /// were killed by the signal Linux would have sent.
+    pub(super) fn wait_status(self) -> u32 {
+        match self {
+            Self::ExitSuccess => 0,
+            Self::ExitFailure(code) => ((code & 0xff) as u32) << 8,
+            Self::UserFault(fault) => u32::from(fault.signal().number()),
+            Self::KilledBySignal(signal) => u32::from(signal.number()),
+        }
+    }
+}
+
+/// CPU exceptions that kill a task when they happen in userspace.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum UserFault {
+    PageFault,
+    GeneralProtectionFault,
+    InvalidOpcode,
+    DivideError,
+}
+
+impl UserFault {
+    /// The signal Linux sends for this exception.
+    pub(crate) fn signal(self) -> Signal {
+        match self {
+            Self::PageFault | Self::GeneralProtectionFault => Signal::SIGSEGV,
+            Self::InvalidOpcode => Signal::SIGILL,
+            Self::DivideError => Signal::SIGFPE,
+        }
+    }
 }
 
 impl From<u64> for TaskExitCode {
@@ -268,7 +433,7 @@ impl From<u64> for TaskExitCode {
 /// `extern "C"` is important here. We get to this function via a `ret` in
 /// `switch_to_task`, and we need to pass in arguments via the known C calling
 /// convention registers.
-pub(super) extern "C" fn task_setup(task_fn: KernelTaskStartFunction, arg: *const ()) {
+pub(super) extern "C" fn task_setup(task_fn: KernelTaskStartFunction, arg: *const ()) -> ! {
     // Release the scheduler lock. Normally, when we switch to a task, the task
     // exits `run_scheduler` and the lock would be released. However, the first
     // time we switch to a task we won't be exiting from `run_scheduler`, so we
@@ -280,8 +445,6 @@ pub(super) extern "C" fn task_setup(task_fn: KernelTaskStartFunction, arg: *cons
     task_fn(arg);
 
     kill_current_task(TaskExitCode::ExitSuccess);
-
-    panic!("somehow returned to task_setup for dead task after running scheduler");
 }
 
 /// Macro to generate task start function with type safety, so user doesn't have
@@ -0,0 +1,70 @@
+use crate::hpet::{self, Nanoseconds};
+use crate::rtc;
+
+use super::schedcore::sleep_until;
+use super::signal::{current_task_has_deliverable_signal, stop_for_pending_signal};
+
+/// Clocks userspace can read with `clock_gettime`. The numbers are the same as
+/// Linux's clock IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Clock {
+    /// Wall clock time since the Unix epoch.
+    Realtime,
+
+    /// Time since boot. Never goes backwards.
+    Monotonic,
+
+    /// Time since boot, including time spent suspended. We never suspend, so
+    /// this is the same as `Monotonic`.
+    Boottime,
+}
+
+impl Clock {
+    pub(super) fn from_id(id: u64) -> Option<Self> {
+        match id {
+            0 => Some(Self::Realtime),
+            1 => Some(Self::Monotonic),
+            7 => Some(Self::Boottime),
+            _ => None,
+        }
+    }
+
+    pub(super) fn now(self) -> Nanoseconds {
+        match self {
+            Self::Realtime => rtc::unix_time(),
+            Self::Monotonic | Self::Boottime => hpet::elapsed_nanoseconds(),
+        }
+    }
+}
+
+/// Returned from `sleep` when a signal arrives before the deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct SleepInterrupted {
+    pub(super) remaining: Nanoseconds,
+}
+
+/// Sleeps for `duration`, or until a signal arrives. If a stop signal arrives,
+/// the task stops, and goes back to sleep until the same deadline once it is
+/// continued.
+///
+/// Timers only fire on a tick, so we sleep until the first tick after the
+/// deadline.
+pub(super) fn sleep(duration: Nanoseconds) -> Result<(), SleepInterrupted> {
+    let deadline = hpet::elapsed_nanoseconds()
+        .checked_add(duration)
+        .unwrap_or(Nanoseconds::new(u64::MAX));
+    loop {
+        let now = hpet::elapsed_nanoseconds();
+        if now >= deadline {
+            return Ok(());
+        }
+        if current_task_has_deliverable_signal() {
+            if stop_for_pending_signal().is_err() {
+                let remaining = deadline.saturating_sub(now);
+                return Err(SleepInterrupted { remaining });
+            }
+            continue;
+        }
+        sleep_until(deadline.milliseconds_rounded_up());
+    }
+}
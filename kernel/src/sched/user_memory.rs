@@ -0,0 +1,160 @@
+//! Safe access to user memory from syscalls.
+//!
+//! Every pointer that comes from userspace goes through here. We first check
+//! that the range is inside one of the task's `VirtualMemoryAreas` (so
+//! userspace can't trick us into reading or writing kernel memory), and then
+//! we copy with `copy_user_bytes`. If the copy still page faults (e.g. another
+//! thread unmapped the memory after we checked it), the page fault handler
+//! calls `user_copy_fault_fixup` and resumes the copy at a fixup address so we
+//! return an error instead of panicking.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+
+use x86_64::VirtAddr;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::memory::{PAGE_SIZE, USER_MEMORY_END};
+
+use super::schedcore::current_task;
+
+/// Returned when a user pointer isn't valid for the requested access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct BadUserAddress;
+
+global_asm!(
+    // Copies rdx bytes from rsi to rdi, and returns the number of bytes that
+    // were _not_ copied. That is 0 on success, and nonzero if we faulted and
+    // the page fault handler jumped to the fixup address. `rep movsb` updates
+    // rcx as it goes, so rcx holds the remaining bytes either way.
+    ".global copy_user_bytes",
+    "copy_user_bytes:",
+    "mov rcx, rdx",
+    ".global COPY_USER_BYTES_FAULT_IP",
+    "COPY_USER_BYTES_FAULT_IP:",
+    "rep movsb",
+    ".global COPY_USER_BYTES_FIXUP_IP",
+    "COPY_USER_BYTES_FIXUP_IP:",
+    "mov rax, rcx",
+    "ret",
+);
+
+extern "C" {
+    fn copy_user_bytes(dest: *mut u8, src: *const u8, len: usize) -> usize;
+
+    /// Address of the instruction in `copy_user_bytes` that touches user
+    /// memory. Only the address is meaningful.
+    static COPY_USER_BYTES_FAULT_IP: u8;
+
+    /// Where to resume if `COPY_USER_BYTES_FAULT_IP` faults.
+    static COPY_USER_BYTES_FIXUP_IP: u8;
+}
+
+/// Called from the page fault handler for faults it couldn't resolve. If the
+/// fault came from copying user memory, returns the instruction pointer the
+/// handler should resume at so the copy fails gracefully.
+pub(crate) fn user_copy_fault_fixup(instruction_pointer: VirtAddr) -> Option<VirtAddr> {
+    let fault_ip = unsafe { core::ptr::addr_of!(COPY_USER_BYTES_FAULT_IP) };
+    let fixup_ip = unsafe { core::ptr::addr_of!(COPY_USER_BYTES_FIXUP_IP) };
+    (instruction_pointer.as_ptr::<u8>() == fault_ip).then(|| VirtAddr::from_ptr(fixup_ip))
+}
+
+/// Checks that `[addr, addr + len)` is entirely in the current task's user
+/// memory, and that it is writable if `write` is true.
+fn check_user_range(addr: u64, len: usize, write: bool) -> Result<(), BadUserAddress> {
+    let end = addr
+        .checked_add(len as u64)
+        .filter(|end| *end <= USER_MEMORY_END)
+        .ok_or(BadUserAddress)?;
+    if len == 0 {
+        return Ok(());
+    }
+
+    let address_space = current_task().address_space();
+    let vm_areas = address_space.vm_areas.lock();
+    if vm_areas.contains_range(VirtAddr::new(addr), VirtAddr::new(end), write) {
+        Ok(())
+    } else {
+        Err(BadUserAddress)
+    }
+}
+
+/// Checks that `len` bytes at user address `addr` can be written, for callers
+/// that need to know before doing something they can't undo.
+pub(super) fn check_user_writable(addr: u64, len: usize) -> Result<(), BadUserAddress> {
+    check_user_range(addr, len, true)
+}
+
+/// Copies `dest.len()` bytes from user address `src` into `dest`.
+pub(super) fn copy_from_user(dest: &mut [u8], src: u64) -> Result<(), BadUserAddress> {
+    check_user_range(src, dest.len(), false)?;
+    let not_copied = unsafe { copy_user_bytes(dest.as_mut_ptr(), src as *const u8, dest.len()) };
+    if not_copied == 0 {
+        Ok(())
+    } else {
+        Err(BadUserAddress)
+    }
+}
+
+/// Copies `src` to user address `dest`.
+pub(super) fn copy_to_user(dest: u64, src: &[u8]) -> Result<(), BadUserAddress> {
+    check_user_range(dest, src.len(), true)?;
+    let not_copied = unsafe { copy_user_bytes(dest as *mut u8, src.as_ptr(), src.len()) };
+    if not_copied == 0 {
+        Ok(())
+    } else {
+        Err(BadUserAddress)
+    }
+}
+
+/// Reads a `T` from user address `addr`. There are no alignment requirements.
+pub(super) fn read_user<T: FromBytes>(addr: u64) -> Result<T, BadUserAddress> {
+    let mut bytes = vec![0; core::mem::size_of::<T>()];
+    copy_from_user(&mut bytes, addr)?;
+    Ok(T::read_from(bytes.as_slice()).expect("read_user buffer has the wrong size"))
+}
+
+/// Writes `value` to user address `addr`. There are no alignment requirements.
+pub(super) fn write_user<T: AsBytes>(addr: u64, value: &T) -> Result<(), BadUserAddress> {
+    copy_to_user(addr, value.as_bytes())
+}
+
+/// Reads `len` bytes from user address `addr`.
+pub(super) fn read_user_bytes(addr: u64, len: usize) -> Result<Vec<u8>, BadUserAddress> {
+    let mut bytes = vec![0; len];
+    copy_from_user(&mut bytes, addr)?;
+    Ok(bytes)
+}
+
+/// Reads a nul-terminated string from user address `addr`, not including the
+/// nul byte. Returns `Ok(None)` if there is no nul byte in the first `max_len`
+/// bytes.
+pub(super) fn read_user_c_str(
+    addr: u64,
+    max_len: usize,
+) -> Result<Option<Vec<u8>>, BadUserAddress> {
+    let mut bytes = Vec::new();
+    let mut current = addr;
+    while bytes.len() <= max_len {
+        // Read up to the end of the current page at a time. We don't know
+        // where the string ends, and the next page might not be mapped.
+        let page_remaining = PAGE_SIZE - (current as usize % PAGE_SIZE);
+        let chunk = read_user_bytes(current, page_remaining)?;
+        if let Some(nul_index) = chunk.iter().position(|b| *b == 0) {
+            bytes.extend_from_slice(&chunk[..nul_index]);
+            return Ok((bytes.len() <= max_len).then_some(bytes));
+        }
+        bytes.extend_from_slice(&chunk);
+        current += page_remaining as u64;
+    }
+    Ok(None)
+}
+
+/// Reads `len` bytes from user address `addr` as a UTF-8 string. Returns
+/// `Ok(None)` if the bytes aren't valid UTF-8.
+pub(super) fn read_user_string(addr: u64, len: usize) -> Result<Option<String>, BadUserAddress> {
+    let bytes = read_user_bytes(addr, len)?;
+    Ok(String::from_utf8(bytes).ok())
+}
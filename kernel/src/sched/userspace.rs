@@ -1,24 +1,40 @@
 use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::alloc::AllocError;
 use core::arch::asm;
+use core::ops::Range;
 
+use x86_64::registers::model_specific::FsBase;
 use x86_64::registers::rflags::RFlags;
 use x86_64::VirtAddr;
 
-use crate::memory::{
-    allocate_and_map_pages, set_page_flags, Page, PageRange, PageSize, PageTableEntryFlags,
-};
-use crate::{elf, task_creator_box, vfs};
+use crate::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+use crate::memory::{self, Level4PageTable, PageTableEntryFlags, PAGE_SIZE, USER_MEMORY_END};
+use crate::sync::Mutex;
+use crate::{elf, random, task_creator_box, tty, vfs};
 
-use super::schedcore::current_task;
-use super::syscall::TOP_OF_KERNEL_STACK;
-use super::task::TaskId;
+use super::preempt::get_preempt_count_no_guard;
+use super::schedcore::{current_task, kill_current_task, new_task_with};
+use super::signal::{
+    map_signal_trampoline, raise_fault_signal, Signal, SignalState, SIGNAL_TRAMPOLINE_ADDR,
+};
+use super::syscall::{return_to_userspace, SyscallAbi};
+use super::task::{Task, TaskExitCode, TaskId, TaskRegisters, UserFault};
+use super::vm::{
+    map_pages_with_contents, AddressSpace, FileBacking, VirtualMemoryArea, VirtualMemoryAreaKind,
+    VirtualMemoryAreas, PAGE_SIZE_U64,
+};
 
 /// Parameters to create a new process.
 pub(crate) struct ExecParams {
     pub(crate) path: vfs::FilePath,
     pub(crate) args: Vec<String>,
+
+    /// Environment variables, as `KEY=VALUE` strings.
+    pub(crate) env: Vec<String>,
 }
 
 pub(crate) fn new_userspace_task(params: ExecParams) -> TaskId {
@@ -31,141 +47,1004 @@ task_creator_box!(create_userspace_task, ExecParams, task_userspace_setup);
 /// is the "entrypoint" to a userspace task, and performs some setup before
 /// actually jumping to userspace.
 extern "C" fn task_userspace_setup(params: Box<ExecParams>) {
-    let path = &params.path;
-    let inode = match vfs::get_path_inode(path) {
-        Ok(inode) => inode,
-        Err(e) => {
-            log::warn!("Failed to get inode for path: {e:?}");
-            return;
-        }
-    };
+    open_console_stdio();
 
-    let vfs::InodeType::File(mut file) = inode.inode_type else {
-        log::warn!("Path {path} not a file");
-        return;
-    };
+    // The first arg is the program name
+    let first_arg = params
+        .path
+        .components
+        .last()
+        .map(|s| String::from(s.as_str()))
+        .unwrap_or_default();
+    let argv = core::iter::once(first_arg)
+        .chain(params.args.iter().cloned())
+        .collect::<Vec<String>>();
 
-    let bytes = file.read_all();
-    let elf_exe = match elf::ElfExecutableHeader::parse(&bytes) {
-        Ok(exe) => exe,
+    let (instruction_ptr, stack_ptr) = match load_executable(&params.path, &argv, &params.env) {
+        Ok(pointers) => pointers,
         Err(e) => {
-            log::warn!("Failed to parse ELF: {e:?}");
+            log::warn!("Failed to load executable {}: {e:?}", params.path);
             return;
         }
     };
 
-    let instruction_ptr = elf_exe.entrypoint;
-    let stack_ptr = set_up_elf_segments(&elf_exe, &params);
-
     // N.B. It is important that jump_to_userspace is marked as returning !,
     // which means it never returns, because I _think_ that the compiler will
     // properly clean up all the other stuff in this function. Before I had `!`
     // I was getting some intermittent page faults.
     drop(params);
-    drop(file);
-    drop(elf_exe);
-    drop(bytes);
+    drop(argv);
 
     unsafe {
         jump_to_userspace(instruction_ptr, stack_ptr);
     };
 }
 
-// Separate function so we can clean up before jump_to_userspace, which never returns
-fn set_up_elf_segments(elf_exe: &elf::ElfExecutableHeader, params: &ExecParams) -> VirtAddr {
+/// Gives the current task the console as stdin, stdout, and stderr, like
+/// Linux does for init. They share one `OpenFile`, as if they were `dup`ed.
+/// The task leads its own session, and the console becomes its controlling
+/// terminal, with the task in the foreground.
+fn open_console_stdio() {
+    tty::set_controlling_terminal(true).expect("new tasks should lead their own session");
+
+    let task = current_task();
+
+    let path = vfs::FilePath::parse("/dev/console").expect("invalid console path");
+    let console =
+        vfs::OpenFile::open(&path, vfs::OpenFlags::READ_WRITE).expect("failed to open the console");
+    let console = Arc::new(Mutex::new(console));
+    let mut files = task.files.lock();
+    for _ in 0..3 {
+        files
+            .insert(console.clone())
+            .expect("failed to add the console to the open files");
+    }
+}
+
+#[derive(Debug)]
+pub(super) enum ExecError {
+    NotFound(String),
+    NotAFile,
+    InvalidElf(elf::ElfExecutableHeaderError),
+
+    /// The arguments and environment don't fit on the initial stack.
+    ArgumentsTooLarge,
+
+    /// We couldn't allocate memory for the new program.
+    OutOfMemory,
+}
+
+/// Replaces the current task's program with the one from the ELF file at
+/// `path`, and sets up `registers` so returning to userspace from the syscall
+/// jumps to the new program's entrypoint. The current task gets a new address
+/// space and its signal handlers are reset, but open files are kept. From
+/// then on, the task makes syscalls with `syscall_abi`.
+pub(super) fn exec_current_task(
+    registers: &mut TaskRegisters,
+    path: &vfs::FilePath,
+    argv: &[String],
+    envp: &[String],
+    syscall_abi: SyscallAbi,
+) -> Result<(), ExecError> {
+    let (instruction_ptr, stack_ptr) = load_executable(path, argv, envp)?;
     let task = current_task();
-    let mut table = task.page_table.lock();
+    let close_on_exec = task.files.lock().remove_close_on_exec();
+    drop(close_on_exec);
+    task.signals.lock().reset_for_exec();
+    task.fs_base.store(0);
+    FsBase::write(VirtAddr::zero());
+    task.syscall_abi.swap(syscall_abi);
+    task.clear_child_tid.store(0);
+    *registers = TaskRegisters {
+        rip: instruction_ptr.as_u64(),
+        cs: u64::from(USER_CODE_SELECTOR.0),
+        rflags: RFlags::INTERRUPT_FLAG.bits(),
+        rsp: stack_ptr.as_u64(),
+        ss: u64::from(USER_DATA_SELECTOR.0),
+        ..Default::default()
+    };
+    Ok(())
+}
+
+/// Loads the ELF executable at `path` into a new address space for the
+/// current task, replacing its old one. Returns the entrypoint and the
+/// initial stack pointer.
+///
+/// If this returns an error, the task's address space has not been replaced.
+fn load_executable(
+    path: &vfs::FilePath,
+    argv: &[String],
+    envp: &[String],
+) -> Result<(VirtAddr, VirtAddr), ExecError> {
+    let execfn = path.as_string();
+    if initial_stack_size(&execfn, argv, envp) > USER_STACK_INITIAL_SIZE {
+        return Err(ExecError::ArgumentsTooLarge);
+    }
+
+    let file = open_executable_file(path)?;
+    let elf_exe = elf::ElfExecutableHeader::parse(&*file).map_err(ExecError::InvalidElf)?;
+    let load_bias = elf_load_bias(&elf_exe, PIE_LOAD_BASE)?;
+
+    // If there is an interpreter (dynamic linker), it is responsible for
+    // relocating the executable.
+    let relocations = if elf_exe.interpreter.is_some() {
+        Vec::new()
+    } else {
+        elf_exe
+            .relative_relocations(&*file)
+            .map_err(ExecError::InvalidElf)?
+    };
+
+    let interpreter = elf_exe
+        .interpreter
+        .as_ref()
+        .map(|interpreter| {
+            let interpreter_path = vfs::FilePath::parse(interpreter).ok_or_else(|| {
+                ExecError::NotFound(format!("invalid interpreter path {interpreter:?}"))
+            })?;
+            let interpreter_file = open_executable_file(&interpreter_path)?;
+            let (interpreter, interpreter_load_bias) =
+                parse_interpreter(&interpreter_file, &elf_exe, load_bias)?;
+            let pages =
+                plan_elf_pages(&interpreter_file, &interpreter, interpreter_load_bias, &[])?;
+            Ok::<_, ExecError>((interpreter, interpreter_load_bias, pages))
+        })
+        .transpose()?;
+
+    // Do anything that reads files or might sleep before locking anything.
+    let pages = plan_elf_pages(&file, &elf_exe, load_bias, &relocations)?;
+    let mut random_bytes = [0; AT_RANDOM_LEN];
+    random::fill_bytes(&mut random_bytes);
+
+    // Build the whole new address space before switching to it, so if we run
+    // out of memory the task still has its old program and exec just fails.
+    let address_space = AddressSpace::new().map_err(|_| ExecError::OutOfMemory)?;
+    let (entrypoint, stack_ptr) = {
+        let mut vm_areas = address_space.vm_areas.lock();
+        let mut table = address_space.page_table.lock();
+        let executable =
+            set_up_elf_segments(&mut table, &mut vm_areas, &elf_exe, load_bias, pages)?;
+        // The heap goes after the executable, not the interpreter.
+        vm_areas.set_heap_start(executable.end);
+        let interpreter = interpreter
+            .map(|(interpreter, interpreter_load_bias, pages)| {
+                set_up_elf_segments(
+                    &mut table,
+                    &mut vm_areas,
+                    &interpreter,
+                    interpreter_load_bias,
+                    pages,
+                )
+            })
+            .transpose()?;
+        let program = LoadedProgram {
+            executable,
+            interpreter,
+        };
+        let stack_ptr = set_up_stack(
+            &mut table,
+            &mut vm_areas,
+            &program,
+            &execfn,
+            argv,
+            envp,
+            &random_bytes,
+        )?;
+        map_signal_trampoline(&mut table, &mut vm_areas).map_err(|_| ExecError::OutOfMemory)?;
+        (program.entrypoint(), stack_ptr)
+    };
+
+    // Other threads keep running in the old address space, which is freed
+    // once the last of them exits.
+    //
+    // TODO: Kill the other threads of the task, like Linux does.
+    drop(current_task().replace_address_space(Arc::new(address_space)));
+    Ok((entrypoint, stack_ptr))
+}
+
+/// Opens an executable for reading. The file is only read as needed, so the
+/// file stays open for as long as any task has it mapped.
+fn open_executable_file(path: &vfs::FilePath) -> Result<Arc<Mutex<vfs::OpenFile>>, ExecError> {
+    let inode = vfs::get_path_inode(path).map_err(ExecError::NotFound)?;
+    let vfs::InodeType::File(file) = inode.inode_type else {
+        return Err(ExecError::NotAFile);
+    };
+    let file = vfs::OpenFile::from_file_inode(file, vfs::OpenFlags::empty());
+    Ok(Arc::new(Mutex::new(file)))
+}
+
+impl elf::ElfFile for Mutex<vfs::OpenFile> {
+    fn read_exact_at(&self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        let mut file = self.lock();
+        let end = offset.checked_add(len as u64)?;
+        // Check the size first so a bogus header can't make us allocate a
+        // huge buffer.
+        if end > file.size().ok()? as u64 {
+            return None;
+        }
+        let mut buffer = vec![0; len];
+        let bytes_read = file.read_at(&mut buffer, offset as usize).ok()?;
+        (bytes_read == len).then_some(buffer)
+    }
+
+    fn size(&self) -> Option<u64> {
+        self.lock().size().ok().map(|size| size as u64)
+    }
+}
+
+/// Parses the interpreter named by the executable's `PT_INTERP` header, and
+/// returns it along with its load bias.
+fn parse_interpreter(
+    file: &Mutex<vfs::OpenFile>,
+    elf_exe: &elf::ElfExecutableHeader,
+    exe_load_bias: u64,
+) -> Result<(elf::ElfExecutableHeader, u64), ExecError> {
+    let interpreter = elf::ElfExecutableHeader::parse(file).map_err(ExecError::InvalidElf)?;
+    if !interpreter.position_independent || interpreter.interpreter.is_some() {
+        return Err(ExecError::InvalidElf(elf::ElfExecutableHeaderError::Other(
+            String::from("interpreter must be a PIE without its own interpreter"),
+        )));
+    }
+    let load_bias = elf_load_bias(&interpreter, INTERPRETER_LOAD_BASE)?;
+
+    let exe_range = elf_image_range(elf_exe, exe_load_bias);
+    let interpreter_range = elf_image_range(&interpreter, load_bias);
+    if exe_range.start < interpreter_range.end && interpreter_range.start < exe_range.end {
+        return Err(ExecError::InvalidElf(elf::ElfExecutableHeaderError::Other(
+            String::from("executable overlaps with its interpreter"),
+        )));
+    }
+    Ok((interpreter, load_bias))
+}
+
+/// What a forked or cloned task needs to start running. See
+/// `forked_task_start`.
+struct ForkedTask {
+    registers: TaskRegisters,
+    signals: SignalState,
+    fs_base: u64,
+    syscall_abi: SyscallAbi,
+    syscall_trace: bool,
+    clear_child_tid: u64,
+}
+
+/// Creates a copy of the current task with a copy-on-write clone of its
+/// address space, a copy of its file descriptor table, and its signal actions.
+/// The new task resumes in userspace with the given registers, except it sees
+/// 0 as the return value of the syscall.
+pub(super) fn fork_current_task(registers: &TaskRegisters) -> Result<TaskId, AllocError> {
+    let parent = current_task();
+    let address_space = Arc::new(parent.address_space().fork()?);
+    let fs_base = parent.fs_base.load();
+    Ok(new_child_task(
+        parent,
+        registers.clone(),
+        address_space,
+        fs_base,
+        0,
+    ))
+}
+
+/// Creates a new thread of the current task, which shares its address space.
+/// Otherwise, the thread is set up like a forked child: it gets a copy of the
+/// file descriptor table and signal actions, it is a child of the current
+/// task, and it returns 0 from the syscall. The thread starts on the stack at
+/// `stack_ptr` with its FS base set to `fs_base`. If `clear_child_tid` isn't
+/// 0, it is the thread's `clear_child_tid` address (see `Task`).
+///
+/// TODO: Share the file descriptor table and signal actions between threads,
+/// like Linux's `CLONE_FILES` and `CLONE_SIGHAND`.
+pub(super) fn clone_current_task(
+    registers: &TaskRegisters,
+    stack_ptr: VirtAddr,
+    fs_base: VirtAddr,
+    clear_child_tid: u64,
+) -> TaskId {
+    let parent = current_task();
+    let address_space = parent.address_space();
+    let mut child_registers = registers.clone();
+    child_registers.rsp = stack_ptr.as_u64();
+    new_child_task(
+        parent,
+        child_registers,
+        address_space,
+        fs_base.as_u64(),
+        clear_child_tid,
+    )
+}
+
+/// Starts a child of `parent` that resumes in userspace with `registers`, but
+/// sees 0 as the return value of the syscall.
+fn new_child_task(
+    parent: Arc<Task>,
+    mut registers: TaskRegisters,
+    address_space: Arc<AddressSpace>,
+    fs_base: u64,
+    clear_child_tid: u64,
+) -> TaskId {
+    let files = parent.files.lock().clone();
+    let signals = parent.signals.lock().clone();
+    let syscall_abi = parent.syscall_abi.load();
+    let syscall_trace = parent.syscall_trace();
+    let name = parent.name.clone();
+    let parent_id = parent.id;
+    drop(parent);
+
+    registers.rax = 0;
+    let child = Box::new(ForkedTask {
+        registers,
+        signals,
+        fs_base,
+        syscall_abi,
+        syscall_trace,
+        clear_child_tid,
+    });
+    let arg = Box::into_raw(child).cast_const().cast::<()>();
+    new_task_with(
+        name,
+        forked_task_start,
+        arg,
+        address_space,
+        files,
+        parent_id,
+    )
+}
+
+/// Called from the page fault handler when a write to a user address faults.
+/// Returns `true` if the address was in a copy-on-write page, which is now
+/// writable.
+pub(crate) fn resolve_copy_on_write_fault(addr: VirtAddr) -> bool {
+    let address_space = current_task().address_space();
+    let mut table = address_space.page_table.lock();
+    memory::resolve_copy_on_write_fault(&mut table, addr)
+}
+
+/// Called from the page fault handler when a user address that isn't mapped
+/// is accessed. Returns `true` if the address is in an area whose pages are
+/// mapped as they are touched, like the stack or an executable's segments,
+/// and the access should be retried.
+///
+/// If the page belongs to the task but we can't fill it in, a fault from
+/// userspace kills the task: with `SIGBUS` if the page's file data couldn't
+/// be read, like Linux, and with `SIGKILL` if we are out of memory. Raising a
+/// signal the task can handle would be pointless, since a handler would just
+/// fault again.
+pub(crate) fn resolve_missing_page_fault(addr: VirtAddr, from_userspace: bool) -> bool {
+    let address_space = current_task().address_space();
+    let Some(missing) = address_space.vm_areas.lock().missing_page(addr) else {
+        return false;
+    };
+
+    // Loading the page might read from a file, which can sleep. That's fine
+    // if we faulted in userspace or in a syscall, since the page fault
+    // handler runs on the task's kernel stack. If the kernel faulted while
+    // holding a spinlock, give up, and let the user memory copy fail.
+    let may_sleep = missing.may_sleep();
+    if may_sleep && get_preempt_count_no_guard() > 0 {
+        return false;
+    }
+    // Userspace runs with interrupts enabled, and the page fault handler
+    // disabled them, so turn them back on while we wait for the disk.
+    let enable_interrupts = may_sleep && from_userspace;
+    if enable_interrupts {
+        x86_64::instructions::interrupts::enable();
+    }
+    let phys_page = missing.load();
+    if enable_interrupts {
+        x86_64::instructions::interrupts::disable();
+    }
+
+    let mapped = phys_page.is_some_and(|phys_page| {
+        let vm_areas = address_space.vm_areas.lock();
+        let mut table = address_space.page_table.lock();
+        vm_areas.map_missing_page(&mut table, &missing, phys_page)
+    });
+    if !mapped && from_userspace {
+        drop(address_space);
+        let task = current_task();
+        log::warn!(
+            "task {} {:?} couldn't get a page for {addr:?}, killing it",
+            task.name,
+            task.id,
+        );
+        drop(task);
+        let signal = if may_sleep {
+            Signal::SIGBUS
+        } else {
+            Signal::SIGKILL
+        };
+        kill_current_task(TaskExitCode::KilledBySignal(signal));
+    }
+    mapped
+}
+
+/// Called from CPU exception handlers when userspace causes an exception. If
+/// the task has a handler for the corresponding signal, the signal is raised
+/// and this returns, and the exception handler should deliver it before
+/// returning to userspace. Otherwise, the task is killed.
+///
+/// Stack overflows always kill the task, since there is no stack left to run
+/// a signal handler on.
+pub(crate) fn handle_user_fault(
+    fault: UserFault,
+    instruction_pointer: VirtAddr,
+    accessed_address: Option<VirtAddr>,
+) {
+    if is_stack_overflow(accessed_address) || !raise_fault_signal(fault.signal()) {
+        kill_current_task_for_user_fault(fault, instruction_pointer, accessed_address);
+    }
+}
+
+fn is_stack_overflow(accessed_address: Option<VirtAddr>) -> bool {
+    accessed_address.is_some_and(|addr| {
+        current_task()
+            .address_space()
+            .vm_areas
+            .lock()
+            .is_stack_guard_page(addr)
+    })
+}
+
+/// Logs the fault and kills the current task, leaving the rest of the system
+/// running.
+fn kill_current_task_for_user_fault(
+    fault: UserFault,
+    instruction_pointer: VirtAddr,
+    accessed_address: Option<VirtAddr>,
+) -> ! {
+    let stack_overflow_msg = if is_stack_overflow(accessed_address) {
+        " (user stack overflow)"
+    } else {
+        ""
+    };
+    let task = current_task();
+    let address_msg =
+        accessed_address.map_or_else(String::new, |addr| format!(" accessing {addr:?}"));
+    log::warn!(
+        "task {} {:?} caused a {fault:?} at {instruction_pointer:?}{address_msg}{stack_overflow_msg}, killing it",
+        task.name,
+        task.id,
+    );
+    drop(task);
+    kill_current_task(TaskExitCode::UserFault(fault));
+}
+
+extern "C" fn forked_task_start(arg: *const ()) {
+    // Move everything out of the Box in one statement so the Box is freed
+    // before we jump to userspace and never return.
+    let ForkedTask {
+        registers,
+        signals,
+        fs_base,
+        syscall_abi,
+        syscall_trace,
+        clear_child_tid,
+    } = unsafe { *Box::<ForkedTask>::from_raw(arg.cast_mut().cast()) };
+
+    // The task was created with the default signal state, so we copy the
+    // parent's here. Anything sent to us before now stays pending.
+    let task = current_task();
+    task.signals.lock().inherit(&signals);
+
+    // The scheduler already loaded our FS base when it switched to us, so we
+    // have to load it ourselves after changing it.
+    task.fs_base.store(fs_base);
+    FsBase::write(VirtAddr::new(fs_base));
+    task.syscall_abi.swap(syscall_abi);
+    task.set_syscall_trace(syscall_trace);
+    task.clear_child_tid.store(clear_child_tid);
+    drop(task);
+
+    unsafe {
+        return_to_userspace(core::ptr::addr_of!(registers));
+    }
+}
 
-    // Map ELF segments to userspace addresses
+/// Where PIE executables are loaded. This is the same address Linux uses
+/// when address space randomization is off.
+const PIE_LOAD_BASE: u64 = 0x5555_5555_4000;
+
+/// Where the interpreter (dynamic linker) is loaded, which is well above the
+/// executable, the heap, and the start of the `mmap` region.
+const INTERPRETER_LOAD_BASE: u64 = 0x7f00_0000_0000;
+
+/// Returns how far the executable's segments are shifted from the addresses
+/// they were linked at, if it is a PIE loaded at `base`. This is 0 for non-PIE
+/// executables. Fails if any segment would land somewhere exec needs for
+/// something else.
+fn elf_load_bias(elf_exe: &elf::ElfExecutableHeader, base: u64) -> Result<u64, ExecError> {
+    let load_bias = if elf_exe.position_independent {
+        let lowest_vaddr = elf_exe
+            .loadable_segments
+            .iter()
+            .map(|segment| segment.vaddr.align_down(PAGE_SIZE_U64).as_u64())
+            .min()
+            .unwrap_or(0);
+        // If the executable was linked above the base address, it can just
+        // stay where it is.
+        base.saturating_sub(lowest_vaddr)
+    } else {
+        0
+    };
+
+    // Segments can't overlap the stack (with its guard page) or the signal
+    // trampoline page, since exec maps those in after the segments.
+    let stack_reservation = USER_STACK_TOP - USER_STACK_MAX_SIZE - PAGE_SIZE_U64..USER_STACK_TOP;
     for segment in &elf_exe.loadable_segments {
-        assert!(segment.alignment as usize == PageSize::Size4KiB.size_bytes());
-
-        let segment_data = elf_exe
-            .parsed
-            .segment_data(&segment.raw_header)
-            .expect("failed to get segment data");
-        let start_page = Page::from_start_addr(segment.vaddr, PageSize::Size4KiB);
-        let mut user_pages = PageRange::from_num_bytes(start_page, segment.mem_size as usize);
-
-        let initial_flags = PageTableEntryFlags::PRESENT
-            | PageTableEntryFlags::WRITABLE
-            | PageTableEntryFlags::USER_ACCESSIBLE;
-
-        allocate_and_map_pages(&mut table, user_pages.iter(), initial_flags)
-            .expect("failed to map segment pages");
-        user_pages.as_byte_slice()[..segment_data.len()].copy_from_slice(segment_data);
-
-        let user_flags =
-            segment.flags.page_table_entry_flags() | PageTableEntryFlags::USER_ACCESSIBLE;
-        set_page_flags(&mut table, user_pages.iter(), user_flags)
-            .expect("failed to set segment flags");
-    }
-
-    // Allocate a stack
-    let stack_start = VirtAddr::new(0x2_1000_0000);
-    let stack_page = Page::from_start_addr(stack_start, PageSize::Size4KiB);
-    let stack_pages = PageRange::new(stack_page, 4);
-    let stack_flags = PageTableEntryFlags::PRESENT
-        | PageTableEntryFlags::WRITABLE
-        | PageTableEntryFlags::USER_ACCESSIBLE;
-    allocate_and_map_pages(&mut table, stack_pages.iter(), stack_flags)
-        .expect("failed to map stack pages");
+        let range = segment
+            .vaddr
+            .as_u64()
+            .checked_add(load_bias)
+            .and_then(|start| Some(start..start.checked_add(segment.mem_size)?));
+        let Some(range) = range.filter(|range| range.end <= SIGNAL_TRAMPOLINE_ADDR) else {
+            return Err(ExecError::InvalidElf(elf::ElfExecutableHeaderError::Other(
+                format!(
+                    "segment at {:?} is outside of user memory or overlaps the signal trampoline",
+                    segment.vaddr
+                ),
+            )));
+        };
+        if range.start < stack_reservation.end && stack_reservation.start < range.end {
+            return Err(ExecError::InvalidElf(elf::ElfExecutableHeaderError::Other(
+                format!("segment at {:?} overlaps the stack", segment.vaddr),
+            )));
+        }
+    }
+    let in_user_memory = |addr: VirtAddr| {
+        addr.as_u64()
+            .checked_add(load_bias)
+            .is_some_and(|addr| addr < SIGNAL_TRAMPOLINE_ADDR)
+    };
+    if !in_user_memory(elf_exe.entrypoint) {
+        return Err(ExecError::InvalidElf(elf::ElfExecutableHeaderError::Other(
+            format!(
+                "entrypoint {:?} is outside of user memory",
+                elf_exe.entrypoint
+            ),
+        )));
+    }
+    Ok(load_bias)
+}
 
-    // Initialize stack. See "3.4 Process Initialization" in the System V AMD64
-    // ABI spec, and https://lwn.net/Articles/631631/ for a good explanation.
+/// The page-aligned range of addresses the executable's segments cover once
+/// it is loaded.
+fn elf_image_range(elf_exe: &elf::ElfExecutableHeader, load_bias: u64) -> Range<u64> {
+    let start = elf_exe
+        .loadable_segments
+        .iter()
+        .map(|segment| segment.vaddr.align_down(PAGE_SIZE_U64).as_u64())
+        .min()
+        .unwrap_or(0);
+    let end = elf_exe
+        .loadable_segments
+        .iter()
+        .map(|segment| {
+            (segment.vaddr + segment.mem_size)
+                .align_up(PAGE_SIZE_U64)
+                .as_u64()
+        })
+        .max()
+        .unwrap_or(0);
+    start + load_bias..end + load_bias
+}
 
-    let stack_ptr = stack_start + stack_pages.num_bytes();
-    let mut stack_ptr = stack_ptr.as_mut_ptr::<u8>();
+/// Addresses in a newly loaded ELF file that userspace finds via the
+/// auxiliary vector.
+struct LoadedExecutable {
+    entrypoint: VirtAddr,
+    load_bias: u64,
+    program_headers: Option<VirtAddr>,
+    program_header_size: u16,
+    num_program_headers: u16,
 
-    // TODO: Add environment variables and auxiliary vector onto stack
+    /// End of the last page of the loaded segments.
+    end: VirtAddr,
+}
 
-    // Push args onto stack as nul-terminated strings (remember first arg is the program path)
-    let first_arg = params
-        .path
-        .components
-        .last()
-        .map(|s| String::from(s.as_str()))
-        .unwrap_or_default();
-    let all_args = core::iter::once(&first_arg).chain(params.args.iter());
-    let arg_locations = all_args
-        .map(|arg| {
-            // Write as nul-terminated string
-            let arg_ptr = stack_ptr.wrapping_sub(arg.len() + 1);
-            unsafe {
-                arg_ptr.copy_from_nonoverlapping(arg.as_ptr(), arg.len());
-                arg_ptr.add(arg.len()).write(0);
-            }
-            stack_ptr = arg_ptr;
-            arg_ptr as usize
+/// An executable, plus the interpreter (dynamic linker) that gets control
+/// first if the executable has one.
+struct LoadedProgram {
+    executable: LoadedExecutable,
+    interpreter: Option<LoadedExecutable>,
+}
+
+impl LoadedProgram {
+    fn entrypoint(&self) -> VirtAddr {
+        self.interpreter
+            .as_ref()
+            .unwrap_or(&self.executable)
+            .entrypoint
+    }
+}
+
+/// How a page of an ELF file gets its contents. See `plan_elf_pages`.
+enum ElfPageContents {
+    /// Read from the file the first time the page is touched.
+    Lazy(FileBacking),
+
+    /// Built when the file is loaded.
+    Eager(Vec<u8>),
+}
+
+struct ElfPage {
+    flags: PageTableEntryFlags,
+    contents: ElfPageContents,
+}
+
+/// Decides how each page of the ELF file's loadable segments, shifted by
+/// `load_bias` (see `elf_load_bias`), gets its contents. Most pages are read
+/// from the file the first time they are touched, and read-only pages come
+/// from the page cache so every task running the same file shares them.
+/// Pages with data from more than one segment, or with relocations to apply,
+/// are read here.
+fn plan_elf_pages(
+    file: &Arc<Mutex<vfs::OpenFile>>,
+    elf_exe: &elf::ElfExecutableHeader,
+    load_bias: u64,
+    relocations: &[elf::RelativeRelocation],
+) -> Result<BTreeMap<VirtAddr, ElfPage>, ExecError> {
+    // Segments don't have to be page aligned, so neighboring segments can
+    // share a page. Figure out the flags and segments for every page first.
+    let mut page_segments =
+        BTreeMap::<VirtAddr, (PageTableEntryFlags, Vec<&elf::LoadableSegment>)>::new();
+    for segment in &elf_exe.loadable_segments {
+        let start = segment.vaddr + load_bias;
+        let end = (start + segment.mem_size).align_up(PAGE_SIZE_U64);
+        let flags = segment.flags.page_table_entry_flags();
+        let mut page = start.align_down(PAGE_SIZE_U64);
+        while page < end {
+            let (page_flags, segments) = page_segments
+                .entry(page)
+                .or_insert_with(|| (flags, Vec::new()));
+            *page_flags = combine_segment_flags(*page_flags, flags);
+            segments.push(segment);
+            page += PAGE_SIZE_U64;
+        }
+    }
+
+    // A relocation can straddle two pages.
+    let relocated_pages = relocations
+        .iter()
+        .flat_map(|relocation| {
+            let addr = VirtAddr::new(relocation.offset.wrapping_add(load_bias));
+            [addr, addr + 7u64].map(|addr| addr.align_down(PAGE_SIZE_U64))
         })
-        .collect::<Vec<usize>>();
-
-    // Align stack pointer _down_ to usize alignment (stack grows down)
-    let mut stack_ptr: *mut usize = unsafe {
-        #[allow(clippy::cast_ptr_alignment)]
-        stack_ptr
-            .sub(8)
-            .add(stack_ptr.align_offset(8))
-            .cast::<usize>()
+        .collect::<BTreeSet<VirtAddr>>();
+
+    let mut pages = BTreeMap::new();
+    for (addr, (flags, segments)) in page_segments {
+        let contents = match segments.as_slice() {
+            [segment] if !relocated_pages.contains(&addr) => {
+                ElfPageContents::Lazy(segment_file_backing(file, segment, load_bias, addr, flags))
+            }
+            _ => ElfPageContents::Eager(read_elf_page(
+                &**file,
+                &segments,
+                load_bias,
+                addr,
+                relocations,
+            )?),
+        };
+        pages.insert(addr, ElfPage { flags, contents });
+    }
+    Ok(pages)
+}
+
+/// Where the page at `addr`, which only has data from `segment`, gets its
+/// contents from.
+fn segment_file_backing(
+    file: &Arc<Mutex<vfs::OpenFile>>,
+    segment: &elf::LoadableSegment,
+    load_bias: u64,
+    addr: VirtAddr,
+    flags: PageTableEntryFlags,
+) -> FileBacking {
+    let data_start = segment.vaddr + load_bias;
+    let data_end = data_start + segment.file_size;
+    let zeroed_end = data_start + segment.mem_size;
+    let page_end = addr + PAGE_SIZE_U64;
+
+    // We can map the file's page from the page cache as long as the page
+    // lines up with it, nobody can write to it, and no part of it needs to be
+    // zeroed (like .bss). Like in Linux, this means the page can also have
+    // parts of the file just outside of the segment.
+    let lines_up = data_start.as_u64() % PAGE_SIZE_U64 == segment.file_offset % PAGE_SIZE_U64;
+    let needs_zeroing = addr.max(data_end) < page_end.min(zeroed_end);
+    let shared = lines_up && !needs_zeroing && !flags.contains(PageTableEntryFlags::WRITABLE);
+
+    FileBacking {
+        file: file.clone(),
+        data_start,
+        data_end,
+        data_offset: segment.file_offset,
+        shared,
+    }
+}
+
+/// Builds the contents of the page at `addr` from the segments that have data
+/// in it, and applies any relocations that land in it.
+fn read_elf_page(
+    file: &dyn elf::ElfFile,
+    segments: &[&elf::LoadableSegment],
+    load_bias: u64,
+    addr: VirtAddr,
+    relocations: &[elf::RelativeRelocation],
+) -> Result<Vec<u8>, ExecError> {
+    let mut contents = vec![0; PAGE_SIZE];
+    let page_end = addr + PAGE_SIZE_U64;
+    for segment in segments {
+        let data_start = segment.vaddr + load_bias;
+        let start = addr.max(data_start);
+        let end = page_end.min(data_start + segment.file_size);
+        if start >= end {
+            continue;
+        }
+        let len = (end - start) as usize;
+        let offset = segment
+            .file_offset
+            .checked_add(start - data_start)
+            .ok_or_else(|| {
+                ExecError::InvalidElf(elf::ElfExecutableHeaderError::Other(format!(
+                    "segment file offset overflows: {:#x}",
+                    segment.file_offset
+                )))
+            })?;
+        let data = file
+            .read_exact_at(offset, len)
+            .ok_or(ExecError::InvalidElf(
+                elf::ElfExecutableHeaderError::ReadFailed { offset, len },
+            ))?;
+        contents[(start - addr) as usize..(end - addr) as usize].copy_from_slice(&data);
+    }
+
+    for relocation in relocations {
+        let value = load_bias.wrapping_add_signed(relocation.addend);
+        let reloc_addr = relocation.offset.wrapping_add(load_bias);
+        for (byte_addr, byte) in (reloc_addr..).zip(value.to_ne_bytes()) {
+            if (addr.as_u64()..page_end.as_u64()).contains(&byte_addr) {
+                contents[(byte_addr - addr.as_u64()) as usize] = byte;
+            }
+        }
+    }
+    Ok(contents)
+}
+
+/// Maps the pages from `plan_elf_pages`. Pages that are read from the file
+/// when they are touched are only recorded in `vm_areas` for now.
+fn set_up_elf_segments(
+    table: &mut Level4PageTable,
+    vm_areas: &mut VirtualMemoryAreas,
+    elf_exe: &elf::ElfExecutableHeader,
+    load_bias: u64,
+    pages: BTreeMap<VirtAddr, ElfPage>,
+) -> Result<LoadedExecutable, ExecError> {
+    let end = pages
+        .last_key_value()
+        .map_or(VirtAddr::zero(), |(addr, _)| *addr + PAGE_SIZE_U64);
+
+    for (addr, page) in pages {
+        let area = VirtualMemoryArea::new(
+            addr,
+            addr + PAGE_SIZE_U64,
+            page.flags,
+            VirtualMemoryAreaKind::ElfSegment,
+        );
+        match page.contents {
+            ElfPageContents::Lazy(file) => vm_areas.insert(area.with_file(file)),
+            ElfPageContents::Eager(contents) => {
+                map_pages_with_contents(table, &area, &contents)
+                    .map_err(|_| ExecError::OutOfMemory)?;
+                vm_areas.insert(area);
+            }
+        }
+    }
+
+    Ok(LoadedExecutable {
+        entrypoint: elf_exe.entrypoint + load_bias,
+        load_bias,
+        program_headers: elf_exe
+            .program_headers_vaddr()
+            .and_then(|addr| addr.as_u64().checked_add(load_bias))
+            .and_then(|addr| VirtAddr::try_new(addr).ok()),
+        program_header_size: elf_exe.ehdr.e_phentsize,
+        num_program_headers: elf_exe.ehdr.e_phnum,
+        end,
+    })
+}
+
+/// Combines the flags of two segments that share a page. The page is
+/// readable, writable, or executable if either segment is.
+fn combine_segment_flags(a: PageTableEntryFlags, b: PageTableEntryFlags) -> PageTableEntryFlags {
+    let no_execute = a & b & PageTableEntryFlags::NO_EXECUTE;
+    ((a | b) - PageTableEntryFlags::NO_EXECUTE) | no_execute
+}
+
+/// The user stack grows down from here.
+const USER_STACK_TOP: u64 = 0x2_1000_0000;
+
+/// How big the user stack can grow. This is Linux's default `RLIMIT_STACK`.
+const USER_STACK_MAX_SIZE: u64 = 8 * 1024 * 1024;
+
+/// How much of the user stack is mapped up front. The rest is mapped as the
+/// stack grows. The initial contents of the stack must fit in here.
+const USER_STACK_INITIAL_SIZE: usize = 4 * PAGE_SIZE;
+
+/// Size of the random data `AT_RANDOM` points to, which libc uses for things
+/// like stack protector canaries.
+const AT_RANDOM_LEN: usize = 16;
+
+// Auxiliary vector entry types. See "3.4.3 Auxiliary Vector" in the System V
+// AMD64 ABI spec, and include/uapi/linux/auxvec.h in Linux.
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_BASE: u64 = 7;
+const AT_FLAGS: u64 = 8;
+const AT_ENTRY: u64 = 9;
+const AT_UID: u64 = 11;
+const AT_EUID: u64 = 12;
+const AT_GID: u64 = 13;
+const AT_EGID: u64 = 14;
+const AT_CLKTCK: u64 = 17;
+const AT_SECURE: u64 = 23;
+const AT_RANDOM: u64 = 25;
+const AT_EXECFN: u64 = 31;
+
+/// Number of entries `set_up_stack` puts in the auxiliary vector, including
+/// `AT_NULL`.
+const NUM_AUXV_ENTRIES: usize = 16;
+
+/// How many bytes `set_up_stack` needs, rounded up generously for alignment.
+fn initial_stack_size(execfn: &str, argv: &[String], envp: &[String]) -> usize {
+    let strings_size = AT_RANDOM_LEN
+        + execfn.len()
+        + 1
+        + argv.iter().chain(envp).map(|s| s.len() + 1).sum::<usize>();
+    let num_words = 1 + (argv.len() + 1) + (envp.len() + 1) + NUM_AUXV_ENTRIES * 2;
+    strings_size + num_words * 8 + 32
+}
+
+/// Maps the user stack and initializes it with the arguments, environment,
+/// and auxiliary vector. Returns the initial stack pointer.
+fn set_up_stack(
+    table: &mut Level4PageTable,
+    vm_areas: &mut VirtualMemoryAreas,
+    program: &LoadedProgram,
+    execfn: &str,
+    argv: &[String],
+    envp: &[String],
+    random_bytes: &[u8; AT_RANDOM_LEN],
+) -> Result<VirtAddr, ExecError> {
+    let stack_top = VirtAddr::new(USER_STACK_TOP);
+
+    // Initialize stack. See "3.4 Process Initialization" in the System V AMD64
+    // ABI spec, and https://lwn.net/Articles/631631/ for a good explanation.
+    // From the top of the stack down, we push:
+    //
+    // - The AT_RANDOM bytes, and the execfn, argument, and environment strings
+    // - Padding so the final stack pointer is 16 byte aligned
+    // - The auxiliary vector, terminated by AT_NULL
+    // - envp, terminated by a NULL pointer
+    // - argv, terminated by a NULL pointer
+    // - argc
+    let mut stack = UserStackWriter {
+        contents: vec![0; USER_STACK_INITIAL_SIZE],
+        top: stack_top.as_u64(),
+        ptr: stack_top.as_u64(),
     };
+
+    let random_ptr = stack.push_bytes(random_bytes);
+    let execfn_ptr = stack.push_c_str(execfn);
+    let arg_ptrs = argv
+        .iter()
+        .map(|arg| stack.push_c_str(arg))
+        .collect::<Vec<u64>>();
+    let env_ptrs = envp
+        .iter()
+        .map(|var| stack.push_c_str(var))
+        .collect::<Vec<u64>>();
+
+    // If there is an interpreter, these tell it where the executable is and
+    // where the interpreter itself was loaded.
+    let executable = &program.executable;
+    let interpreter_base = program
+        .interpreter
+        .as_ref()
+        .map_or(0, |interpreter| interpreter.load_bias);
+    let auxv: [(u64, u64); NUM_AUXV_ENTRIES] = [
+        (
+            AT_PHDR,
+            executable.program_headers.map_or(0, VirtAddr::as_u64),
+        ),
+        (AT_PHENT, u64::from(executable.program_header_size)),
+        (AT_PHNUM, u64::from(executable.num_program_headers)),
+        (AT_PAGESZ, PAGE_SIZE as u64),
+        (AT_BASE, interpreter_base),
+        (AT_FLAGS, 0),
+        (AT_ENTRY, executable.entrypoint.as_u64()),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_CLKTCK, 100),
+        (AT_SECURE, 0),
+        (AT_RANDOM, random_ptr),
+        (AT_EXECFN, execfn_ptr),
+        (AT_NULL, 0),
+    ];
+
+    // Everything from here down is 8 byte words, so add one word of padding
+    // if needed to make argc end up 16 byte aligned.
+    stack.align_down(16);
+    let num_words = auxv.len() * 2 + (env_ptrs.len() + 1) + (arg_ptrs.len() + 1) + 1;
+    if num_words % 2 == 1 {
+        stack.push_u64(0);
+    }
+
+    for (key, value) in auxv.iter().rev() {
+        stack.push_u64(*value);
+        stack.push_u64(*key);
+    }
+    stack.push_u64(0);
+    for ptr in env_ptrs.iter().rev() {
+        stack.push_u64(*ptr);
+    }
+    stack.push_u64(0);
+    for ptr in arg_ptrs.iter().rev() {
+        stack.push_u64(*ptr);
+    }
+    stack.push_u64(arg_ptrs.len() as u64);
+
     assert!(
-        stack_ptr as usize % core::mem::align_of::<usize>() == 0,
-        "stack_ptr {stack_ptr:p} not aligned!"
+        stack.ptr % 16 == 0,
+        "initial stack pointer {:#x} not aligned!",
+        stack.ptr
     );
 
-    // Push argv onto stack
-    arg_locations.iter().rev().for_each(|arg_ptr| unsafe {
-        stack_ptr = stack_ptr.sub(1);
-        stack_ptr.write(*arg_ptr);
-    });
+    vm_areas
+        .map_stack(table, stack_top, &stack.contents, USER_STACK_MAX_SIZE)
+        .map_err(|_| ExecError::OutOfMemory)?;
+    Ok(VirtAddr::new(stack.ptr))
+}
 
-    // Push argc onto stack
-    unsafe {
-        stack_ptr = stack_ptr.sub(1);
-        stack_ptr.cast::<usize>().write(arg_locations.len());
+/// Builds the initial contents of a user stack, which are copied into the
+/// stack's pages once it is mapped. `initial_stack_size` makes sure
+/// everything fits.
+struct UserStackWriter {
+    /// The top `USER_STACK_INITIAL_SIZE` bytes of the stack.
+    contents: Vec<u8>,
+
+    /// The user address just past the end of `contents`.
+    top: u64,
+
+    /// The user address of the current top of the stack.
+    ptr: u64,
+}
+
+impl UserStackWriter {
+    /// Pushes `bytes` and returns their user address.
+    fn push_bytes(&mut self, bytes: &[u8]) -> u64 {
+        self.ptr -= bytes.len() as u64;
+        let start = self.contents.len() - (self.top - self.ptr) as usize;
+        self.contents[start..start + bytes.len()].copy_from_slice(bytes);
+        self.ptr
     }
 
-    VirtAddr::new(stack_ptr as u64)
+    /// Pushes `s` as a nul-terminated string and returns its address.
+    fn push_c_str(&mut self, s: &str) -> u64 {
+        self.push_bytes(&[0]);
+        self.push_bytes(s.as_bytes())
+    }
+
+    fn push_u64(&mut self, value: u64) {
+        self.push_bytes(&value.to_ne_bytes());
+    }
+
+    fn align_down(&mut self, align: u64) {
+        self.ptr &= !(align - 1);
+    }
 }
 
 #[naked]
@@ -175,8 +1054,9 @@ pub(super) unsafe extern "C" fn jump_to_userspace(
 ) {
     unsafe {
         asm!(
-            // Store the kernel stack and switch to the user stack
-            "mov gs:{kernel_stack}, rsp",
+            // Switch to the user stack. We don't need to store the kernel
+            // stack pointer because the scheduler sets `TOP_OF_KERNEL_STACK`
+            // when it switches to this task, and we never return here.
             "mov rsp, rsi",      // Second argument, new stack pointer
             // Set up sysretq arguments
             "mov rcx, rdi",      // First argument, new instruction pointer
@@ -186,7 +1066,6 @@ pub(super) unsafe extern "C" fn jump_to_userspace(
             "swapgs",
             // Jump to userspace
             "sysretq",
-            kernel_stack = sym TOP_OF_KERNEL_STACK,
             rflags = const RFlags::INTERRUPT_FLAG.bits(),
             options(noreturn),
         )
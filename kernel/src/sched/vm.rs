@@ -0,0 +1,844 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::alloc::AllocError;
+use core::sync::atomic::AtomicBool;
+
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory::{
+    self, KernPhysAddr, Level4PageTable, MapError, Page, PageRange, PageSize, PageTableEntryFlags,
+    PAGE_SIZE, USER_MEMORY_END,
+};
+use crate::sync::{Mutex, SpinLock};
+use crate::vfs;
+
+/// Where `mmap` starts looking for free space when the caller doesn't ask for
+/// a specific address. This is far above where ELF executables and the stack
+/// are loaded, so the heap has plenty of room to grow.
+const MMAP_REGION_START: u64 = 0x1000_0000_0000;
+
+pub(super) const PAGE_SIZE_U64: u64 = PAGE_SIZE as u64;
+
+/// Largest `mmap` mapping, and the largest the heap can grow to. Pages are
+/// only mapped when they are touched, but unmapping an area still walks every
+/// page in it, so this keeps a single call from taking forever.
+const MAX_MAPPING_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// What a `VirtualMemoryArea` is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum VirtualMemoryAreaKind {
+    /// Part of an executable or its interpreter. Most of these areas have a
+    /// `FileBacking` and are filled in from the file as they are touched.
+    ElfSegment,
+
+    /// The user stack. Only the top of it is mapped up front, and the rest is
+    /// mapped as the stack grows into it. See `missing_page`.
+    Stack,
+
+    /// The page just below the stack. It is never mapped, and it keeps
+    /// anything else from being mapped right below the stack, so touching it
+    /// means the stack overflowed.
+    StackGuard,
+
+    /// The heap managed by `brk`, and zeroed memory from `mmap`. Like the
+    /// stack, their pages are only mapped when they are touched.
+    Heap,
+    Anonymous,
+
+    /// A private copy of part of a file, from `mmap` with a file descriptor.
+    File,
+
+    /// The page with the signal trampoline code. See `signal.rs`.
+    SignalTrampoline,
+}
+
+/// A page-aligned range of user virtual memory that a task is allowed to use.
+#[derive(Debug, Clone)]
+pub(super) struct VirtualMemoryArea {
+    pub(super) start: VirtAddr,
+
+    /// Exclusive end of the area.
+    pub(super) end: VirtAddr,
+
+    /// Page table flags for pages in this area. `USER_ACCESSIBLE` is implied.
+    pub(super) flags: PageTableEntryFlags,
+
+    pub(super) kind: VirtualMemoryAreaKind,
+
+    /// If set, pages in this area aren't mapped up front. They are read from
+    /// the file the first time they are touched.
+    pub(super) file: Option<FileBacking>,
+}
+
+impl VirtualMemoryArea {
+    pub(super) fn new(
+        start: VirtAddr,
+        end: VirtAddr,
+        flags: PageTableEntryFlags,
+        kind: VirtualMemoryAreaKind,
+    ) -> Self {
+        assert!(
+            start.is_aligned(PAGE_SIZE_U64) && end.is_aligned(PAGE_SIZE_U64),
+            "virtual memory area {start:?}..{end:?} is not page aligned"
+        );
+        assert!(
+            start < end,
+            "virtual memory area {start:?}..{end:?} is empty"
+        );
+        Self {
+            start,
+            end,
+            flags,
+            kind,
+            file: None,
+        }
+    }
+
+    pub(super) fn with_file(self, file: FileBacking) -> Self {
+        Self {
+            file: Some(file),
+            ..self
+        }
+    }
+
+    /// True if both areas are the same kind of memory with the same flags
+    /// and backing, so they could be merged if they were adjacent.
+    fn is_same_mapping(&self, other: &Self) -> bool {
+        let same_file = match (&self.file, &other.file) {
+            (None, None) => true,
+            (Some(a), Some(b)) => a.is_same_mapping(b),
+            _ => false,
+        };
+        self.kind == other.kind && self.flags.bits() == other.flags.bits() && same_file
+    }
+}
+
+/// Where the contents of a lazily loaded area come from. Addresses in
+/// `[data_start, data_end)` hold the file's data starting at `data_offset`,
+/// and the rest of the area is zeroed. The addresses are absolute instead of
+/// relative to the area so splitting the area doesn't change them.
+#[derive(Debug, Clone)]
+pub(super) struct FileBacking {
+    pub(super) file: Arc<Mutex<vfs::OpenFile>>,
+    pub(super) data_start: VirtAddr,
+    pub(super) data_end: VirtAddr,
+    pub(super) data_offset: u64,
+
+    /// If true, pages are mapped straight from the page cache (see
+    /// `vfs::cached_file_page`), so every task mapping the same file shares
+    /// them. This only works for read-only pages that line up with pages of
+    /// the file and don't need any zeroing.
+    pub(super) shared: bool,
+}
+
+impl FileBacking {
+    fn is_same_mapping(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.file, &other.file)
+            && self.data_start == other.data_start
+            && self.data_end == other.data_end
+            && self.data_offset == other.data_offset
+            && self.shared == other.shared
+    }
+
+    /// Reads the contents of the page starting at `addr`. This can sleep
+    /// reading the file, so don't hold any spinlocks.
+    fn load_page(&self, addr: VirtAddr) -> Option<Page<KernPhysAddr>> {
+        if self.shared {
+            // The first page can start before `data_start`, but shared areas
+            // line up with the file's pages, so this can't underflow.
+            let file_offset = self
+                .data_offset
+                .checked_add(addr.as_u64())?
+                .checked_sub(self.data_start.as_u64())?;
+            return vfs::cached_file_page(&self.file, file_offset / PAGE_SIZE_U64);
+        }
+
+        let mut page = match memory::allocate_zeroed_physical_page() {
+            Ok(page) => page,
+            Err(e) => {
+                log::warn!("failed to allocate page for file data at {addr:?}: {e:?}");
+                return None;
+            }
+        };
+        let start = addr.max(self.data_start);
+        let end = (addr + PAGE_SIZE_U64).min(self.data_end);
+        if start < end {
+            let Some(file_offset) = self.data_offset.checked_add(start - self.data_start) else {
+                log::warn!("file offset for {addr:?} overflows");
+                memory::release_physical_page(page);
+                return None;
+            };
+            let dest = &mut page.as_byte_slice()[(start - addr) as usize..(end - addr) as usize];
+            if let Err(e) = self.file.lock().read_at(dest, file_offset as usize) {
+                log::warn!("failed to read file data for {addr:?}: {e:?}");
+                memory::release_physical_page(page);
+                return None;
+            }
+        }
+        Some(page)
+    }
+}
+
+/// The file to map with `VirtualMemoryAreas::mmap_file`, starting at byte
+/// `offset`.
+#[derive(Debug)]
+pub(super) struct MappedFile {
+    pub(super) file: Arc<Mutex<vfs::OpenFile>>,
+    pub(super) offset: u64,
+
+    /// The size of the file when it was mapped.
+    pub(super) size: u64,
+}
+
+/// A page in a `VirtualMemoryArea` that isn't mapped yet, but should be mapped
+/// when it is touched. See `VirtualMemoryAreas::missing_page`.
+#[derive(Debug)]
+pub(super) struct MissingPage {
+    page: Page<VirtAddr>,
+    area: VirtualMemoryArea,
+}
+
+impl MissingPage {
+    /// Gets a physical page with the page's initial contents. This might
+    /// sleep reading from a file, so it must be called without holding any
+    /// locks.
+    pub(super) fn load(&self) -> Option<Page<KernPhysAddr>> {
+        match &self.area.file {
+            Some(file) => file.load_page(self.page.start_addr()),
+            None => memory::allocate_zeroed_physical_page()
+                .map_err(|e| log::warn!("failed to allocate page for {:?}: {e:?}", self.page))
+                .ok(),
+        }
+    }
+
+    /// True if `load` reads from a file, and so might sleep.
+    pub(super) fn may_sleep(&self) -> bool {
+        self.area.file.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum VmError {
+    /// The range was empty, not page aligned, or not in user memory.
+    InvalidRange,
+
+    /// There is no free virtual address range big enough.
+    NoSpace,
+
+    /// We couldn't allocate physical memory.
+    OutOfMemory,
+}
+
+/// A task's user memory: which areas it may use, and the page table that maps
+/// them. Threads of the same program share one `AddressSpace`.
+///
+/// Nothing flushes the TLBs of other CPUs when pages are unmapped or their
+/// flags change, so the scheduler never runs two tasks with the same address
+/// space at once (see `running`). Loading CR3 flushes the TLB, so each thread
+/// starts with no stale mappings when it is switched in.
+///
+/// TODO: Implement TLB shootdowns so threads can run on several CPUs at once.
+#[derive(Debug)]
+pub(super) struct AddressSpace {
+    /// Lock this before `page_table` if both are needed.
+    pub(super) vm_areas: SpinLock<VirtualMemoryAreas>,
+    pub(super) page_table: SpinLock<Level4PageTable>,
+
+    /// Where `page_table` is in physical memory, so the scheduler can load it
+    /// into CR3 without locking it.
+    pub(super) page_table_address: PhysAddr,
+
+    /// True while a task using this address space is running on some CPU.
+    /// The scheduler only changes this while holding the run queue lock.
+    pub(super) running: AtomicBool,
+}
+
+impl AddressSpace {
+    /// Creates an address space with no user memory.
+    pub(super) fn new() -> Result<Self, AllocError> {
+        let page_table = memory::clone_kernel_page_table()?;
+        Ok(Self::with_page_table(VirtualMemoryAreas::new(), page_table))
+    }
+
+    /// Creates a copy-on-write clone of the address space, like for fork.
+    pub(super) fn fork(&self) -> Result<Self, AllocError> {
+        let vm_areas = self.vm_areas.lock();
+        let page_table = memory::fork_page_table(&mut self.page_table.lock())?;
+        Ok(Self::with_page_table(vm_areas.clone(), page_table))
+    }
+
+    fn with_page_table(vm_areas: VirtualMemoryAreas, page_table: Level4PageTable) -> Self {
+        Self {
+            vm_areas: SpinLock::new(vm_areas),
+            page_table_address: page_table.physical_address(),
+            page_table: SpinLock::new(page_table),
+            running: AtomicBool::new(false),
+        }
+    }
+}
+
+/// The areas of user memory a task is allowed to use, along with the heap
+/// managed by `brk`. This is the source of truth for which user addresses are
+/// valid. The page table says what is actually mapped right now.
+///
+/// If both this and the page table need to be locked, lock this first.
+#[derive(Debug, Clone)]
+pub(super) struct VirtualMemoryAreas {
+    /// Areas keyed by their start address. Areas never overlap.
+    areas: BTreeMap<VirtAddr, VirtualMemoryArea>,
+
+    /// Start of the heap, which is just past the end of the ELF segments.
+    heap_start: VirtAddr,
+
+    /// The current program break (end of the heap). This doesn't have to be
+    /// page aligned, but the heap area always ends on a page boundary.
+    brk: VirtAddr,
+}
+
+impl VirtualMemoryAreas {
+    pub(super) const fn new() -> Self {
+        Self {
+            areas: BTreeMap::new(),
+            heap_start: VirtAddr::zero(),
+            brk: VirtAddr::zero(),
+        }
+    }
+
+    /// Finds the area containing the given address.
+    pub(super) fn find(&self, addr: VirtAddr) -> Option<&VirtualMemoryArea> {
+        self.areas
+            .range(..=addr)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| addr < area.end)
+    }
+
+    /// Returns true if every address in `[start, end)` is in an area that is
+    /// readable, and writable too if `write` is true.
+    pub(super) fn contains_range(&self, start: VirtAddr, end: VirtAddr, write: bool) -> bool {
+        let mut addr = start;
+        while addr < end {
+            let Some(area) = self.find(addr) else {
+                return false;
+            };
+            let readable = area.flags.contains(PageTableEntryFlags::PRESENT);
+            let writable = area.flags.contains(PageTableEntryFlags::WRITABLE);
+            if !readable || (write && !writable) {
+                return false;
+            }
+            addr = area.end;
+        }
+        true
+    }
+
+    /// Records an area that was mapped outside of this module, like ELF
+    /// segments and the stack in exec.
+    pub(super) fn insert(&mut self, area: VirtualMemoryArea) {
+        assert!(
+            self.is_free(area.start, area.end),
+            "tried to insert overlapping virtual memory area {area:?}"
+        );
+
+        // Merge with the previous area if it is adjacent and identical, so
+        // growing the heap doesn't create lots of tiny areas.
+        if let Some((_, prev)) = self.areas.range_mut(..area.start).next_back() {
+            if prev.end == area.start && prev.is_same_mapping(&area) {
+                prev.end = area.end;
+                return;
+            }
+        }
+        self.areas.insert(area.start, area);
+    }
+
+    /// Reserves a stack that can grow down to `max_size` bytes below `top`,
+    /// along with its guard page, and maps `initial_contents` (a whole number
+    /// of pages) at the top.
+    pub(super) fn map_stack(
+        &mut self,
+        table: &mut Level4PageTable,
+        top: VirtAddr,
+        initial_contents: &[u8],
+        max_size: u64,
+    ) -> Result<(), VmError> {
+        let bottom = top - max_size;
+        let initial_area = VirtualMemoryArea::new(
+            top - initial_contents.len() as u64,
+            top,
+            PageTableEntryFlags::PRESENT | PageTableEntryFlags::WRITABLE,
+            VirtualMemoryAreaKind::Stack,
+        );
+        map_pages_with_contents(table, &initial_area, initial_contents)?;
+        self.insert(VirtualMemoryArea::new(
+            bottom - PAGE_SIZE_U64,
+            bottom,
+            PageTableEntryFlags::empty(),
+            VirtualMemoryAreaKind::StackGuard,
+        ));
+        self.insert(VirtualMemoryArea {
+            start: bottom,
+            ..initial_area
+        });
+        Ok(())
+    }
+
+    /// Called when `addr` isn't mapped. If it is in an area where pages are
+    /// mapped as they are touched (the stack, the heap, anonymous mappings,
+    /// and areas backed by a file), returns what should be mapped there. Use
+    /// `MissingPage::load` to get the page's contents and then
+    /// `map_missing_page` to map it.
+    pub(super) fn missing_page(&self, addr: VirtAddr) -> Option<MissingPage> {
+        let area = self.find(addr)?;
+        let demand_paged = matches!(
+            area.kind,
+            VirtualMemoryAreaKind::Stack
+                | VirtualMemoryAreaKind::Heap
+                | VirtualMemoryAreaKind::Anonymous
+        ) || area.file.is_some();
+        if !demand_paged || !area.flags.contains(PageTableEntryFlags::PRESENT) {
+            return None;
+        }
+        Some(MissingPage {
+            page: Page::containing_address(addr, PageSize::Size4KiB),
+            area: area.clone(),
+        })
+    }
+
+    /// Maps a page from `missing_page`, with contents from
+    /// `MissingPage::load`. This takes over the reference to `phys_page`.
+    /// Returns true if the faulting access should be retried, and false if
+    /// the page couldn't be mapped.
+    pub(super) fn map_missing_page(
+        &self,
+        table: &mut Level4PageTable,
+        missing: &MissingPage,
+        phys_page: Page<KernPhysAddr>,
+    ) -> bool {
+        // The area might have changed while we were loading the page. If it
+        // did, drop the page and let the access fault again so we look at
+        // the new area.
+        let unchanged = self
+            .find(missing.page.start_addr())
+            .is_some_and(|area| area.is_same_mapping(&missing.area));
+        if !unchanged {
+            memory::release_physical_page(phys_page);
+            return true;
+        }
+
+        let flags = missing.area.flags | PageTableEntryFlags::USER_ACCESSIBLE;
+        match memory::map_existing_user_page(table, missing.page, phys_page, flags) {
+            Ok(()) => true,
+            // Someone else might have mapped the page since we faulted.
+            Err(MapError::PageAlreadyMapped { .. }) => {
+                memory::release_physical_page(phys_page);
+                true
+            }
+            Err(MapError::PhysicalPageAllocationFailed(e)) => {
+                log::warn!("failed to map page at {:?}: {e:?}", missing.page);
+                memory::release_physical_page(phys_page);
+                false
+            }
+        }
+    }
+
+    /// Returns true if `addr` is in the guard page below the stack.
+    pub(super) fn is_stack_guard_page(&self, addr: VirtAddr) -> bool {
+        self.find(addr)
+            .is_some_and(|area| area.kind == VirtualMemoryAreaKind::StackGuard)
+    }
+
+    /// Sets where the heap starts (and ends, since it is initially empty).
+    pub(super) fn set_heap_start(&mut self, addr: VirtAddr) {
+        self.heap_start = addr.align_up(PAGE_SIZE_U64);
+        self.brk = self.heap_start;
+    }
+
+    fn is_free(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        // Areas don't overlap, so the last area starting before `end` is the
+        // one that ends last.
+        self.areas
+            .range(..end)
+            .next_back()
+            .map_or(true, |(_, area)| area.end <= start)
+    }
+
+    /// Finds the lowest free range of `len` bytes at or above
+    /// `MMAP_REGION_START`.
+    fn find_free(&self, len: u64) -> Option<VirtAddr> {
+        let mut candidate = MMAP_REGION_START;
+        for area in self.areas.values() {
+            if area.end.as_u64() <= candidate {
+                continue;
+            }
+            if area.start.as_u64() >= candidate.checked_add(len)? {
+                break;
+            }
+            candidate = area.end.as_u64();
+        }
+        let end = candidate.checked_add(len)?;
+        (end <= USER_MEMORY_END).then(|| VirtAddr::new(candidate))
+    }
+
+    /// Changes the program break, growing the heap area or unmapping heap
+    /// pages as needed. Returns the new break, or the old break if the
+    /// request was invalid or the heap couldn't grow. This matches Linux's
+    /// `brk`, which is what libc's `sbrk` expects.
+    pub(super) fn brk(&mut self, table: &mut Level4PageTable, requested: u64) -> VirtAddr {
+        let heap_start = self.heap_start.as_u64();
+        if requested < heap_start
+            || requested >= USER_MEMORY_END
+            || requested - heap_start > MAX_MAPPING_SIZE
+        {
+            return self.brk;
+        }
+        let requested = VirtAddr::new(requested);
+        let old_end = self.brk.align_up(PAGE_SIZE_U64);
+        let new_end = requested.align_up(PAGE_SIZE_U64);
+
+        if new_end > old_end {
+            if !self.is_free(old_end, new_end) {
+                log::warn!(
+                    "brk: heap can't grow to {requested:?} without overlapping another area"
+                );
+                return self.brk;
+            }
+            let flags = PageTableEntryFlags::PRESENT
+                | PageTableEntryFlags::WRITABLE
+                | PageTableEntryFlags::NO_EXECUTE;
+            self.insert(VirtualMemoryArea::new(
+                old_end,
+                new_end,
+                flags,
+                VirtualMemoryAreaKind::Heap,
+            ));
+        } else if new_end < old_end {
+            self.remove_range(table, new_end, old_end);
+        }
+
+        self.brk = requested;
+        self.brk
+    }
+
+    /// Reserves `len` bytes of new zeroed memory and returns its address.
+    /// Pages are mapped as they are touched. If `fixed` is false, `addr` is
+    /// just a hint. If `fixed` is true, the memory is placed exactly at
+    /// `addr`, replacing anything that was there.
+    pub(super) fn mmap_anonymous(
+        &mut self,
+        table: &mut Level4PageTable,
+        addr: u64,
+        len: u64,
+        flags: PageTableEntryFlags,
+        fixed: bool,
+    ) -> Result<VirtAddr, VmError> {
+        let (start, end) = self.place_mapping(table, addr, len, fixed)?;
+        self.insert(VirtualMemoryArea::new(
+            start,
+            end,
+            flags,
+            VirtualMemoryAreaKind::Anonymous,
+        ));
+        Ok(start)
+    }
+
+    /// Like `mmap_anonymous`, but the memory starts with a copy of part of a
+    /// file, and anything past the end of the file is zeroed. Pages are read
+    /// from the file as they are touched. Writes aren't shared with the file
+    /// or with other tasks.
+    pub(super) fn mmap_file(
+        &mut self,
+        table: &mut Level4PageTable,
+        addr: u64,
+        len: u64,
+        flags: PageTableEntryFlags,
+        fixed: bool,
+        file: MappedFile,
+    ) -> Result<VirtAddr, VmError> {
+        let (start, end) = self.place_mapping(table, addr, len, fixed)?;
+        let data_len = file.size.saturating_sub(file.offset).min(end - start);
+        let backing = FileBacking {
+            file: file.file,
+            data_start: start,
+            data_end: start + data_len,
+            data_offset: file.offset,
+            shared: false,
+        };
+        self.insert(
+            VirtualMemoryArea::new(start, end, flags, VirtualMemoryAreaKind::File)
+                .with_file(backing),
+        );
+        Ok(start)
+    }
+
+    /// Picks where a new `mmap` mapping of `len` bytes goes, and returns its
+    /// page-aligned range. See `mmap_anonymous` for what `addr` and `fixed`
+    /// mean.
+    fn place_mapping(
+        &mut self,
+        table: &mut Level4PageTable,
+        addr: u64,
+        len: u64,
+        fixed: bool,
+    ) -> Result<(VirtAddr, VirtAddr), VmError> {
+        let len = len
+            .checked_next_multiple_of(PAGE_SIZE_U64)
+            .filter(|len| *len != 0 && *len <= MAX_MAPPING_SIZE)
+            .ok_or(VmError::InvalidRange)?;
+        let start = if fixed {
+            let (start, end) = user_page_range(addr, len)?;
+            self.remove_range(table, start, end);
+            start
+        } else {
+            match user_page_range(addr, len) {
+                Ok((start, end)) if addr != 0 && self.is_free(start, end) => start,
+                _ => self.find_free(len).ok_or(VmError::NoSpace)?,
+            }
+        };
+        Ok((start, start + len))
+    }
+
+    /// Unmaps every page in `[addr, addr + len)`, splitting or shrinking any
+    /// areas that only partially overlap the range.
+    pub(super) fn munmap(
+        &mut self,
+        table: &mut Level4PageTable,
+        addr: u64,
+        len: u64,
+    ) -> Result<(), VmError> {
+        let (start, end) = user_page_range(addr, len)?;
+        self.remove_range(table, start, end);
+        Ok(())
+    }
+
+    fn remove_range(&mut self, table: &mut Level4PageTable, start: VirtAddr, end: VirtAddr) {
+        let overlapping = self
+            .areas
+            .range(..end)
+            .rev()
+            .take_while(|(_, area)| area.end > start)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+
+        for key in overlapping {
+            let area = self
+                .areas
+                .remove(&key)
+                .expect("overlapping area disappeared");
+            let unmap_start = area.start.max(start);
+            let unmap_end = area.end.min(end);
+            memory::unmap_and_release_user_pages(table, page_range(unmap_start, unmap_end).iter());
+
+            if area.start < start {
+                let before = VirtualMemoryArea {
+                    end: start,
+                    ..area.clone()
+                };
+                self.areas.insert(before.start, before);
+            }
+            if area.end > end {
+                let after = VirtualMemoryArea { start: end, ..area };
+                self.areas.insert(after.start, after);
+            }
+        }
+    }
+}
+
+/// Validates a user-supplied address and length, and returns the range of
+/// whole pages it covers. The address must be page aligned, but the length
+/// doesn't have to be.
+fn user_page_range(addr: u64, len: u64) -> Result<(VirtAddr, VirtAddr), VmError> {
+    if len == 0 || addr % PAGE_SIZE_U64 != 0 {
+        return Err(VmError::InvalidRange);
+    }
+    let end = addr
+        .checked_add(len)
+        .filter(|end| *end <= USER_MEMORY_END)
+        .ok_or(VmError::InvalidRange)?;
+    let end = end.next_multiple_of(PAGE_SIZE_U64);
+    Ok((VirtAddr::new(addr), VirtAddr::new(end)))
+}
+
+fn page_range(start: VirtAddr, end: VirtAddr) -> PageRange<VirtAddr> {
+    let start_page = Page::from_start_addr(start, PageSize::Size4KiB);
+    PageRange::from_num_bytes(start_page, (end - start) as usize)
+}
+
+/// Maps new pages for `area` that start out with a copy of `contents`, and
+/// are zeroed past the end of it. The pages are filled in through the
+/// kernel's mapping of physical memory, so `table` doesn't have to be the
+/// current page table.
+pub(super) fn map_pages_with_contents(
+    table: &mut Level4PageTable,
+    area: &VirtualMemoryArea,
+    contents: &[u8],
+) -> Result<(), VmError> {
+    let pages = page_range(area.start, area.end);
+    let flags = area.flags | PageTableEntryFlags::USER_ACCESSIBLE;
+    for (i, page) in pages.iter().enumerate() {
+        let start = (i * PAGE_SIZE).min(contents.len());
+        let end = (start + PAGE_SIZE).min(contents.len());
+        if let Err(e) = map_page_with_contents(table, page, flags, &contents[start..end]) {
+            log::warn!("failed to map pages for {area:?}: {e:?}");
+            memory::unmap_and_release_user_pages(table, pages.iter().take(i));
+            return Err(VmError::OutOfMemory);
+        }
+    }
+    Ok(())
+}
+
+fn map_page_with_contents(
+    table: &mut Level4PageTable,
+    page: Page<VirtAddr>,
+    flags: PageTableEntryFlags,
+    contents: &[u8],
+) -> Result<(), MapError> {
+    let mut phys_page = memory::allocate_zeroed_physical_page()?;
+    phys_page.as_byte_slice()[..contents.len()].copy_from_slice(contents);
+    memory::map_existing_user_page(table, page, phys_page, flags).map_err(|e| {
+        memory::release_physical_page(phys_page);
+        e
+    })
+}
+
+#[cfg(feature = "tests")]
+mod tests {
+    use super::*;
+
+    use crate::memory::TranslateResult;
+    use crate::tests::kernel_test;
+
+    const P: u64 = PAGE_SIZE_U64;
+
+    fn read_write() -> PageTableEntryFlags {
+        PageTableEntryFlags::PRESENT | PageTableEntryFlags::WRITABLE
+    }
+
+    fn anonymous_area(start: u64, num_pages: u64) -> VirtualMemoryArea {
+        let start = VirtAddr::new(start);
+        VirtualMemoryArea::new(
+            start,
+            start + num_pages * P,
+            read_write(),
+            VirtualMemoryAreaKind::Anonymous,
+        )
+    }
+
+    fn area_ranges(vm_areas: &VirtualMemoryAreas) -> Vec<(u64, u64)> {
+        vm_areas
+            .areas
+            .values()
+            .map(|area| (area.start.as_u64(), area.end.as_u64()))
+            .collect()
+    }
+
+    #[kernel_test]
+    fn test_insert_merges_adjacent_areas() {
+        let mut vm_areas = VirtualMemoryAreas::new();
+        vm_areas.insert(anonymous_area(0x10_0000, 2));
+        vm_areas.insert(anonymous_area(0x10_0000 + 2 * P, 1));
+        assert_eq!(area_ranges(&vm_areas), [(0x10_0000, 0x10_0000 + 3 * P)]);
+
+        // Areas with a gap between them, or with different flags, stay apart.
+        vm_areas.insert(anonymous_area(0x20_0000, 1));
+        vm_areas.insert(VirtualMemoryArea {
+            flags: PageTableEntryFlags::PRESENT,
+            ..anonymous_area(0x20_0000 + P, 1)
+        });
+        assert_eq!(
+            area_ranges(&vm_areas),
+            [
+                (0x10_0000, 0x10_0000 + 3 * P),
+                (0x20_0000, 0x20_0000 + P),
+                (0x20_0000 + P, 0x20_0000 + 2 * P),
+            ]
+        );
+    }
+
+    #[kernel_test]
+    fn test_remove_range_splits_areas() {
+        let address_space = AddressSpace::new().expect("failed to allocate address space");
+        let mut table = address_space.page_table.lock();
+        let mut vm_areas = VirtualMemoryAreas::new();
+        vm_areas.insert(anonymous_area(0x10_0000, 4));
+        vm_areas.insert(anonymous_area(0x20_0000, 2));
+        let touched = anonymous_area(0x10_0000 + P, 1);
+        map_pages_with_contents(&mut table, &touched, &[1, 2, 3]).expect("failed to map page");
+
+        // Removing the middle of an area leaves the pieces on either side,
+        // and unmaps any pages that were mapped.
+        vm_areas.remove_range(
+            &mut table,
+            VirtAddr::new(0x10_0000 + P),
+            VirtAddr::new(0x10_0000 + 3 * P),
+        );
+        assert_eq!(
+            area_ranges(&vm_areas),
+            [
+                (0x10_0000, 0x10_0000 + P),
+                (0x10_0000 + 3 * P, 0x10_0000 + 4 * P),
+                (0x20_0000, 0x20_0000 + 2 * P),
+            ]
+        );
+        assert!(matches!(
+            table.translate_address(touched.start),
+            TranslateResult::Unmapped
+        ));
+
+        // A range spanning several areas removes the ones inside it and
+        // shrinks the ones it only partly covers.
+        vm_areas.remove_range(
+            &mut table,
+            VirtAddr::new(0x10_0000),
+            VirtAddr::new(0x20_0000 + P),
+        );
+        assert_eq!(area_ranges(&vm_areas), [(0x20_0000 + P, 0x20_0000 + 2 * P)]);
+    }
+
+    #[kernel_test]
+    fn test_place_mapping() {
+        let address_space = AddressSpace::new().expect("failed to allocate address space");
+        let mut table = address_space.page_table.lock();
+        let mut vm_areas = VirtualMemoryAreas::new();
+        let read_only = PageTableEntryFlags::PRESENT;
+        let start = MMAP_REGION_START;
+
+        // Without a hint, mappings go in the first free space in the mmap
+        // region. Lengths are rounded up to whole pages.
+        let addr = vm_areas.mmap_anonymous(&mut table, 0, 2 * P, read_write(), false);
+        assert_eq!(addr, Ok(VirtAddr::new(start)));
+        let addr = vm_areas.mmap_anonymous(&mut table, 0, 1, read_only, false);
+        assert_eq!(addr, Ok(VirtAddr::new(start + 2 * P)));
+
+        // A hint is used if it is free, and ignored if it isn't.
+        let addr = vm_areas.mmap_anonymous(&mut table, 0x40_0000, P, read_write(), false);
+        assert_eq!(addr, Ok(VirtAddr::new(0x40_0000)));
+        let addr = vm_areas.mmap_anonymous(&mut table, start, P, read_write(), false);
+        assert_eq!(addr, Ok(VirtAddr::new(start + 3 * P)));
+
+        // A fixed mapping replaces whatever was there.
+        let addr = vm_areas.mmap_anonymous(&mut table, start + P, 2 * P, read_only, true);
+        assert_eq!(addr, Ok(VirtAddr::new(start + P)));
+        assert_eq!(
+            area_ranges(&vm_areas),
+            [
+                (0x40_0000, 0x40_0000 + P),
+                (start, start + P),
+                (start + P, start + 3 * P),
+                (start + 3 * P, start + 4 * P),
+            ]
+        );
+
+        // Empty, huge, and misplaced fixed mappings are rejected.
+        for (addr, len, fixed) in [
+            (0, 0, false),
+            (0, MAX_MAPPING_SIZE + 1, false),
+            (0x1234, P, true),
+            (USER_MEMORY_END - P, 2 * P, true),
+        ] {
+            assert_eq!(
+                vm_areas.place_mapping(&mut table, addr, len, fixed),
+                Err(VmError::InvalidRange)
+            );
+        }
+    }
+}
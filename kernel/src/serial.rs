@@ -62,9 +62,8 @@ impl SerialPort {
             // and enable auxilliary output #2 (used as interrupt line for CPU)
             u8::write_to_port(self.modem_ctrl, 0x0B);
 
-            // Enable interrupts
-            //
-            // TODO: Do we even need interrupts?
+            // Enable interrupts when data is received. The TTY uses these for
+            // input.
             u8::write_to_port(self.int_en, 0x01);
         }
     }
@@ -101,6 +100,11 @@ impl SerialPort {
 
         unsafe { u8::read_from_port(self.data) }
     }
+
+    fn try_read(&self) -> Option<u8> {
+        self.is_data_ready()
+            .then(|| unsafe { u8::read_from_port(self.data) })
+    }
 }
 
 impl Write for SerialPort {
@@ -227,3 +231,9 @@ pub(crate) fn serial1_write_byte(byte: u8) {
 pub(crate) fn serial1_read_byte() -> u8 {
     SERIAL1.get().expect("SERIAL1 not initialized").read()
 }
+
+/// Read the next byte from the serial port if one is available, without
+/// waiting.
+pub(crate) fn serial1_try_read_byte() -> Option<u8> {
+    SERIAL1.get().expect("SERIAL1 not initialized").try_read()
+}
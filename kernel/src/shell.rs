@@ -10,8 +10,8 @@ use crate::qemu::{exit_qemu, QEMUExitCode};
 use crate::sync::SpinLock;
 use crate::vfs::FilePath;
 use crate::{
-    acpi, ansiterm, boot_info, debug, graphics, memory, pci, sched, serial, serial_print,
-    serial_println, task_creator_cast, tick, vfs, virtio,
+    acpi, ansiterm, boot_info, debug, graphics, memory, pci, rtc, sched, serial, serial_print,
+    serial_println, task_creator_cast, tick, tty, vfs, virtio,
 };
 
 static NEXT_COMMAND_BUFFER: SpinLock<ShellBuffer> = SpinLock::new(ShellBuffer::new());
@@ -58,14 +58,14 @@ impl ShellBuffer {
     }
 }
 
-pub(crate) extern "C" fn run_serial_shell(_arg: *const ()) {
+/// Runs the shell on the serial port, starting with `commands` (separated by
+/// `;`) from the kernel command line.
+pub(crate) fn run_serial_shell(commands: &str) {
     serial_println!("Welcome to Rust OS! Here is a shell for you to use.");
 
-    // If there was a kernel command line, run it
-    let cmdline = boot_info::boot_info().kernel_cmdline;
-    if !cmdline.is_empty() {
-        serial_println!("Running kernel command line: {}", cmdline);
-        for command_str in cmdline.split(';') {
+    if !commands.is_empty() {
+        serial_println!("Running kernel command line: {}", commands);
+        for command_str in commands.split(';') {
             let command = parse_command(command_str.as_bytes());
             if let Some(command) = command {
                 run_command(&command);
@@ -76,11 +76,16 @@ pub(crate) extern "C" fn run_serial_shell(_arg: *const ()) {
     shell_loop();
 }
 
+/// The shell does its own line editing, so it reads the console in raw mode.
+/// Commands (and the programs they start) get the console's usual settings
+/// back, so e.g. Ctrl-C interrupts programs.
 fn shell_loop() {
+    let console_termios = tty::termios();
     loop {
+        tty::set_termios(console_termios.raw(), false);
         NEXT_COMMAND_BUFFER.lock().redraw_buffer();
         loop {
-            let c = serial::serial1_read_byte();
+            let c = tty::read_byte();
             match c {
                 b'\n' | b'\r' => {
                     serial_println!();
@@ -89,6 +94,7 @@ fn shell_loop() {
                         parse_command(&buffer.buffer)
                     };
                     if let Some(command) = command {
+                        tty::set_termios(console_termios, false);
                         run_command(&command);
                     }
                     NEXT_COMMAND_BUFFER.lock().buffer.clear();
@@ -137,12 +143,12 @@ fn reset_terminal_line() {
 /// Handle ANSI escape sequences we care about. This isn't intended to be
 /// exhaustive.
 fn handle_ansi_escape_sequence() {
-    let left_bracket = serial::serial1_read_byte();
+    let left_bracket = tty::read_byte();
     if left_bracket != b'[' {
         serial_println!("invalid escape sequence: {}", left_bracket);
         return;
     }
-    let escaped_char = serial::serial1_read_byte();
+    let escaped_char = tty::read_byte();
     serial_println!("\ngot ANSI escape char: {}", escaped_char);
 }
 
@@ -160,9 +166,11 @@ enum Command {
     VirtIOBlock(VirtIOBlockCommand),
     Mount(MountTarget),
     Unmount,
-    Ls(FilePath),
+    Ls { path: FilePath, long: bool },
     Cat(FilePath),
     Exec(ExecCommand),
+    Kill { task_id: u32, signal: sched::Signal },
+    Strace { task_id: u32, enabled: bool },
     WriteFramebuffer(String),
     WriteToFile { path: FilePath, content: String },
     FATBIOS { device_id: usize },
@@ -198,6 +206,7 @@ enum MountTarget {
 struct ExecCommand {
     path: FilePath,
     args: Vec<String>,
+    env: Vec<String>,
     num_processes: usize,
 }
 
@@ -283,24 +292,67 @@ fn parse_command(buffer: &[u8]) -> Option<Command> {
         }
         "umount" => Some(Command::Unmount),
         "ls" => {
-            let path = parse_next_word(&mut words, "path", "ls <path>")?;
-            Some(Command::Ls(path))
+            let mut word = words.next();
+            let long = word == Some("-l");
+            if long {
+                word = words.next();
+            }
+            let path = parse_next_word(&mut word.into_iter(), "path", "ls [-l] <path>")?;
+            Some(Command::Ls { path, long })
         }
         "cat" => {
             let path = parse_next_word(&mut words, "path", "cat <path>")?;
             Some(Command::Cat(path))
         }
         "exec" => {
-            let usage = "exec <nproc> <path> [args]...";
+            let usage = "exec <nproc> [KEY=VALUE]... <path> [args]...";
             let num_processes = parse_next_word(&mut words, "num processes", usage)?;
-            let path = parse_next_word(&mut words, "path", usage)?;
+
+            // Like in a normal shell, leading KEY=VALUE words are environment
+            // variables. Paths are absolute, so they can't be mistaken for
+            // one.
+            let mut env = Vec::new();
+            let path = loop {
+                let Some(word) = words.next() else {
+                    serial_println!("Usage: {usage}");
+                    return None;
+                };
+                if word.contains('=') && !word.starts_with('/') {
+                    env.push(String::from(word));
+                } else {
+                    break parse_word(word, "path")?;
+                }
+            };
             let args = words.by_ref().map(String::from).collect();
             Some(Command::Exec(ExecCommand {
                 path,
                 args,
+                env,
                 num_processes,
             }))
         }
+        "kill" => {
+            let usage = "kill <task_id> [signal]";
+            let task_id = parse_next_word(&mut words, "task ID", usage)?;
+            let signal = match words.next() {
+                Some(signal) => parse_word(signal, "signal")?,
+                None => sched::Signal::SIGTERM,
+            };
+            Some(Command::Kill { task_id, signal })
+        }
+        "strace" => {
+            let usage = "strace <task_id> [on|off]";
+            let task_id = parse_next_word(&mut words, "task ID", usage)?;
+            let enabled = match words.next() {
+                None | Some("on") => true,
+                Some("off") => false,
+                Some(_) => {
+                    serial_println!("Usage: {usage}");
+                    return None;
+                }
+            };
+            Some(Command::Strace { task_id, enabled })
+        }
         "write-framebuffer" => {
             let mut content = String::new();
             for word in words.by_ref() {
@@ -519,7 +571,7 @@ fn run_command(command: &Command) {
             vfs::unmount_root_filesystem();
             serial_println!("Unmounted filesystem");
         }
-        Command::Ls(path) => {
+        Command::Ls { path, long } => {
             serial_println!("ls: {path:?}");
             let inode = match vfs::get_path_inode(path) {
                 Ok(inode) => inode,
@@ -534,14 +586,29 @@ fn run_command(command: &Command) {
                 return;
             };
 
-            dir.subdirectories().iter().for_each(|entry| {
+            for mut entry in dir.subdirectories() {
                 let trailing_slash = if entry.entry_type() == vfs::DirectoryEntryType::Directory {
                     "/"
                 } else {
                     ""
                 };
-                serial_println!("{}{}", entry.name(), trailing_slash);
-            });
+                if !*long {
+                    serial_println!("{}{}", entry.name(), trailing_slash);
+                    continue;
+                }
+                let metadata = entry.get_inode().metadata();
+                serial_println!(
+                    "{} {:>3} {:>4} {:>4} {:>8} {} {}{}",
+                    mode_string(&metadata),
+                    metadata.links,
+                    metadata.uid,
+                    metadata.gid,
+                    metadata.size,
+                    rtc::format_unix_seconds(metadata.modified),
+                    entry.name(),
+                    trailing_slash
+                );
+            }
         }
         Command::Cat(path) => {
             serial_println!("cat: {path:?}");
@@ -564,28 +631,67 @@ fn run_command(command: &Command) {
         Command::Exec(ExecCommand {
             path,
             args,
+            env,
             num_processes,
         }) => {
-            serial_println!("Executing {path} with num processes {num_processes}, args {args:?}");
+            serial_println!(
+                "Executing {path} with num processes {num_processes}, args {args:?}, env {env:?}"
+            );
             let task_ids = (0..*num_processes)
                 .map(|_| {
                     sched::new_userspace_task(sched::ExecParams {
                         path: path.clone(),
                         args: args.clone(),
+                        env: env.clone(),
                     })
                 })
                 .collect::<Vec<_>>();
 
+            // Hold on to the tasks so we can get their exit codes even after
+            // they are cleaned up.
+            let mut tasks = task_ids
+                .iter()
+                .filter_map(|id| {
+                    let task = sched::TASKS.lock_disable_interrupts().get_task(*id)?;
+                    Some((*id, task))
+                })
+                .collect::<Vec<_>>();
+
             sched::run_scheduler();
 
-            for task_id in task_ids {
-                serial_println!("Waiting for userspace task {task_id:?} to finish...");
-                // TODO: Tasks usually get killed before we get their exit code,
-                // except perhaps the last task. `wait_on_task` should return
-                // something we can wait on instead of internally sleeping and
-                // getting the exit code. Then we won't lose the exit code.
-                let exit_code = sched::wait_on_task(task_id);
-                serial_println!("Task {task_id:?} finished! Exit code: {exit_code:?}");
+            // Poll so we can print exit codes as soon as each task exits. The
+            // tasks were started on the console, so Ctrl-C interrupts them.
+            serial_println!("Waiting for userspace tasks {task_ids:?} to finish...");
+            while !tasks.is_empty() {
+                tasks.retain(|(task_id, task)| {
+                    let Some(exit_code) = task.exit_code() else {
+                        return true;
+                    };
+                    serial_println!("Task {task_id:?} finished! Exit code: {exit_code:?}");
+                    false
+                });
+                sched::sleep_timeout(Milliseconds::new(10));
+            }
+        }
+        Command::Kill { task_id, signal } => {
+            let task_id = sched::TaskId::from(*task_id);
+            if sched::send_signal(task_id, *signal) {
+                serial_println!("Sent {signal} to task {task_id:?}");
+            } else {
+                serial_println!("No task with ID {task_id:?}");
+            }
+        }
+        Command::Strace { task_id, enabled } => {
+            let task_id = sched::TaskId::from(*task_id);
+            let Some(task) = sched::TASKS.lock_disable_interrupts().get_task(task_id) else {
+                serial_println!("No task with ID {task_id:?}");
+                return;
+            };
+            task.set_syscall_trace(*enabled);
+            if *enabled {
+                serial_println!("Tracing syscalls of task {task_id:?} and its new children");
+            } else {
+                serial_println!("Stopped tracing syscalls of task {task_id:?}");
             }
         }
         Command::WriteFramebuffer(content) => {
@@ -627,6 +733,9 @@ fn run_command(command: &Command) {
             };
 
             file.write(content.as_bytes());
+            if let Some(id) = file.id() {
+                vfs::invalidate_cached_file_pages(id);
+            }
         }
         Command::FATBIOS { device_id } => {
             let response = virtio::virtio_block_read(*device_id, 0, 1).wait_sleep();
@@ -670,6 +779,23 @@ fn run_command(command: &Command) {
     }
 }
 
+/// Formats a file's type and permissions the way `ls -l` does, like
+/// `drwxr-xr-x`.
+fn mode_string(metadata: &vfs::Metadata) -> String {
+    let mut mode = String::with_capacity(10);
+    mode.push(match metadata.file_type {
+        vfs::FileType::Regular => '-',
+        vfs::FileType::Directory => 'd',
+        vfs::FileType::CharacterDevice => 'c',
+        vfs::FileType::Fifo => 'p',
+    });
+    for (i, c) in "rwxrwxrwx".chars().enumerate() {
+        let allowed = metadata.permissions & (1 << (8 - i)) != 0;
+        mode.push(if allowed { c } else { '-' });
+    }
+    mode
+}
+
 task_creator_cast!(calculate_prime_task, usize, naive_nth_prime);
 
 fn naive_nth_prime(n: usize) {
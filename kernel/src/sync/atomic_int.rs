@@ -37,6 +37,20 @@ where
         let old_val = <I as AtomicIntTrait>::swap(&self.atom, val.into(), Ordering::Acquire);
         T::from(old_val)
     }
+
+    /// Stores `new` if the current value is `current`. Returns the previous
+    /// value, which is `current` if the store happened.
+    pub(crate) fn compare_exchange(&self, current: T, new: T) -> Result<T, T> {
+        <I as AtomicIntTrait>::compare_exchange(
+            &self.atom,
+            current.into(),
+            new.into(),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .map(T::from)
+        .map_err(T::from)
+    }
 }
 
 impl<I, T> fmt::Debug for AtomicInt<I, T>
@@ -56,6 +70,15 @@ pub(crate) trait AtomicIntTrait {
     fn load(atom: &Self::Atomic, order: Ordering) -> Self;
     fn store(atom: &Self::Atomic, val: Self, order: Ordering);
     fn swap(atom: &Self::Atomic, val: Self, order: Ordering) -> Self;
+    fn compare_exchange(
+        atom: &Self::Atomic,
+        current: Self,
+        new: Self,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Self, Self>
+    where
+        Self: Sized;
 }
 
 macro_rules! atomic_int_trait_impl {
@@ -78,6 +101,16 @@ macro_rules! atomic_int_trait_impl {
             fn swap(atom: &Self::Atomic, val: Self, order: Ordering) -> Self {
                 atom.swap(val, order)
             }
+
+            fn compare_exchange(
+                atom: &Self::Atomic,
+                current: Self,
+                new: Self,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<Self, Self> {
+                atom.compare_exchange(current, new, success, failure)
+            }
         }
     };
 }
@@ -129,6 +162,15 @@ where
         let old_val = self.int.swap(val.into());
         Self::convert_from_integer(old_val)
     }
+
+    /// Stores `new` if the current value is `current`. Returns the previous
+    /// value, which is `current` if the store happened.
+    pub(crate) fn compare_exchange(&self, current: T, new: T) -> Result<T, T> {
+        self.int
+            .compare_exchange(current.into(), new.into())
+            .map(Self::convert_from_integer)
+            .map_err(Self::convert_from_integer)
+    }
 }
 
 impl<I, T> fmt::Debug for AtomicEnum<I, T>
@@ -5,6 +5,7 @@ pub(crate) mod once_cell;
 pub(crate) mod once_channel;
 pub(crate) mod spin_lock;
 pub(crate) mod wait_cell;
+pub(crate) mod wait_queue;
 
 pub(crate) use atomic_int::*;
 pub(crate) use init_cell::*;
@@ -12,3 +13,4 @@ pub(crate) use mutex::*;
 pub(crate) use once_channel::*;
 pub(crate) use spin_lock::*;
 pub(crate) use wait_cell::*;
+pub(crate) use wait_queue::*;
@@ -33,6 +33,11 @@ impl<T: Clone> WaitCell<T> {
         }
     }
 
+    /// Returns the value if it has been sent, without waiting.
+    pub(crate) fn try_get(&self) -> Option<T> {
+        self.cell.get_clone()
+    }
+
     /// Waits until the value is initialized, sleeping if necessary.
     pub(crate) fn wait_sleep(&self) -> T {
         loop {
@@ -0,0 +1,81 @@
+use alloc::collections::VecDeque;
+
+use crate::sched;
+use crate::sched::TaskId;
+
+use super::spin_lock::SpinLock;
+
+/// A queue of tasks waiting for some condition to become true. Unlike
+/// `WaitCell`, a `WaitQueue` can be waited on and woken up many times. The
+/// condition itself is stored elsewhere, and waking up tasks is the
+/// responsibility of whoever changes the condition.
+#[derive(Debug)]
+pub(crate) struct WaitQueue {
+    waiting_tasks: SpinLock<VecDeque<TaskId>>,
+}
+
+impl WaitQueue {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waiting_tasks: SpinLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Wakes up all tasks waiting on the queue. They will re-check their
+    /// condition and go back to sleep if it still isn't true.
+    pub(crate) fn wake_all(&self) {
+        let mut waiting_tasks = self.waiting_tasks.lock_disable_interrupts();
+        while let Some(task_id) = waiting_tasks.pop_front() {
+            sched::awaken_task(task_id);
+        }
+    }
+
+    /// Sleeps until `condition` returns `Some`, and returns the value.
+    pub(crate) fn wait_until<T>(&self, mut condition: impl FnMut() -> Option<T>) -> T {
+        loop {
+            // Set desired_state to sleeping before checking the condition to
+            // avoid race condition where we get woken up before we go to sleep.
+            let task_id = sched::prepare_to_sleep();
+            self.waiting_tasks
+                .lock_disable_interrupts()
+                .push_back(task_id);
+
+            if let Some(value) = condition() {
+                // Remove ourselves from the queue so we don't get woken up
+                // later after we've moved on (or after we've exited).
+                self.waiting_tasks
+                    .lock_disable_interrupts()
+                    .retain(|id| *id != task_id);
+                sched::awaken_task(task_id);
+                return value;
+            }
+            sched::run_scheduler();
+        }
+    }
+
+    /// Like `wait_until`, but gives up if the current task gets a signal it
+    /// needs to handle. Stop signals don't count: the task stops right here,
+    /// and keeps waiting once it is continued.
+    pub(crate) fn wait_until_interruptible<T>(
+        &self,
+        mut condition: impl FnMut() -> Option<T>,
+    ) -> Result<T, Interrupted> {
+        loop {
+            let result = self.wait_until(|| {
+                if let Some(value) = condition() {
+                    return Some(Ok(value));
+                }
+                sched::current_task_has_deliverable_signal().then_some(Err(Interrupted))
+            });
+            if result.is_ok() {
+                return result;
+            }
+            sched::stop_for_pending_signal()?;
+        }
+    }
+}
+
+/// Returned from `WaitQueue::wait_until_interruptible` when a signal
+/// interrupted the wait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Interrupted;
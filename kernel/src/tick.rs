@@ -11,7 +11,7 @@ use crate::{apic, hpet, interrupts, ioapic, sched};
 /// Frequency of the global tick system.
 const TICK_HZ: u64 = 20;
 
-const TICK_MILLIS: Milliseconds = Milliseconds::new(1000 / TICK_HZ);
+pub(crate) const TICK_MILLIS: Milliseconds = Milliseconds::new(1000 / TICK_HZ);
 
 /// Global list of timers
 static TIMERS: SpinLock<VecDeque<Timer>> = SpinLock::new(VecDeque::new());
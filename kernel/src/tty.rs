@@ -0,0 +1,651 @@
+//! The console terminal. Input comes from the serial port and the PS/2
+//! keyboard, and output goes to the serial port. Userspace reads and writes it
+//! through `/dev/console`, which tasks started by the kernel get as stdin,
+//! stdout, and stderr.
+//!
+//! Input goes through a line discipline modeled on Linux's. In canonical mode
+//! (the default), input is collected into a line that can be edited with
+//! backspace and ^U, and reads return at most one line. ^D finishes a line
+//! without a newline, so ^D at the start of a line makes the next read return
+//! 0 (end of file). In raw mode, reads return input as soon as it arrives. In
+//! both modes, input is echoed if `ECHO` is set, and if `ISIG` is set ^C, ^\,
+//! and ^Z send SIGINT, SIGQUIT, and SIGTSTP to the foreground process group.
+//! Userspace switches modes with the `TCGETS` and `TCSETS` ioctls, using
+//! Linux's `struct termios`.
+//!
+//! For job control, the console is the controlling terminal of one session
+//! (see `sched::job_control`), and one of the session's process groups is in
+//! the foreground. Only the foreground process group can read input. Other
+//! process groups in the session are stopped with SIGTTIN when they try, and
+//! with SIGTTOU when they try to change the terminal settings or the
+//! foreground process group. Like in Linux without `TOSTOP`, anyone can
+//! write.
+//!
+//! Interrupt handlers only queue up raw input bytes. The line discipline runs
+//! in `tty_task`, because sending signals takes locks that interrupt handlers
+//! can't take.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::interrupts::{InterruptHandlerID, InterruptVector};
+use crate::sched::{JobControlError, Signal, TaskId};
+use crate::sync::{SpinLock, WaitQueue};
+use crate::vfs::FileError;
+use crate::{interrupts, ioapic, sched, serial};
+
+/// Raw input from interrupt handlers, waiting for `tty_task`.
+static RAW_INPUT: SpinLock<RawInputQueue> = SpinLock::new(RawInputQueue::new());
+
+/// Woken up when there is new raw input.
+static RAW_INPUT_WAITERS: WaitQueue = WaitQueue::new();
+
+static TTY: SpinLock<Tty> = SpinLock::new(Tty::new());
+
+/// Woken up when there is new input to read, or the mode changes.
+static READERS: WaitQueue = WaitQueue::new();
+
+/// Maximum number of bytes of input waiting to be read, including the line
+/// being edited. Further input is dropped, like in Linux.
+const MAX_INPUT: usize = 4096;
+
+/// Size of `RawInputQueue`. The line discipline should keep up with anyone
+/// typing, but this leaves room for pasting a line or two.
+const RAW_INPUT_CAPACITY: usize = 256;
+
+/// Length of the `c_cc` array in Linux's `struct termios`.
+const NCCS: usize = 19;
+
+// Indexes into `Termios::cc`.
+const VINTR: usize = 0;
+const VQUIT: usize = 1;
+const VERASE: usize = 2;
+const VKILL: usize = 3;
+const VEOF: usize = 4;
+const VTIME: usize = 5;
+const VMIN: usize = 6;
+const VSUSP: usize = 10;
+const VEOL: usize = 11;
+
+// `Termios::iflag` bits.
+const INLCR: u32 = 0o100;
+const IGNCR: u32 = 0o200;
+const ICRNL: u32 = 0o400;
+const IXON: u32 = 0o2000;
+
+// `Termios::oflag` bits.
+const OPOST: u32 = 0o1;
+const ONLCR: u32 = 0o4;
+
+// `Termios::cflag` bits. We don't have a real serial line to configure, so
+// these are only reported back to userspace.
+const B38400: u32 = 0o17;
+const CS8: u32 = 0o60;
+const CREAD: u32 = 0o200;
+
+// `Termios::lflag` bits.
+const ISIG: u32 = 0o1;
+const ICANON: u32 = 0o2;
+const ECHO: u32 = 0o10;
+const ECHOE: u32 = 0o20;
+const ECHOK: u32 = 0o40;
+const ECHONL: u32 = 0o100;
+const NOFLSH: u32 = 0o200;
+const ECHOCTL: u32 = 0o1000;
+const IEXTEN: u32 = 0o100_000;
+
+/// Terminal settings. This has the same layout as Linux's `struct termios`
+/// (the kernel's, not glibc's, which has extra fields), so userspace can use
+/// the usual flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+#[allow(dead_code)] // Some fields are only read by userspace
+pub(crate) struct Termios {
+    iflag: u32,
+    oflag: u32,
+    cflag: u32,
+    lflag: u32,
+    line: u8,
+    cc: [u8; NCCS],
+}
+
+impl Termios {
+    /// Linux's defaults: canonical mode with echo and signals.
+    const fn new() -> Self {
+        Self {
+            iflag: ICRNL | IXON,
+            oflag: OPOST | ONLCR,
+            cflag: B38400 | CS8 | CREAD,
+            lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | IEXTEN,
+            line: 0,
+            // ^C, ^\, DEL, ^U, ^D, 0, 1, 0, ^Q, ^S, ^Z, 0, ^R, ^O, ^W, ^V
+            cc: [
+                3, 28, 127, 21, 4, 0, 1, 0, 17, 19, 26, 0, 18, 15, 23, 22, 0, 0, 0,
+            ],
+        }
+    }
+
+    /// Returns these settings in raw mode, like `cfmakeraw`: no line editing,
+    /// echo, signals, or newline translation, and reads return as soon as a
+    /// byte is available.
+    pub(crate) fn raw(mut self) -> Self {
+        self.iflag &= !(INLCR | IGNCR | ICRNL | IXON);
+        self.oflag &= !OPOST;
+        self.lflag &= !(ISIG | ICANON | ECHO | ECHONL | IEXTEN);
+        self.cc[VMIN] = 1;
+        self.cc[VTIME] = 0;
+        self
+    }
+
+    fn canonical(&self) -> bool {
+        self.lflag & ICANON != 0
+    }
+
+    /// Returns true if `byte` is the special character at `index` in `cc`. A
+    /// special character of 0 is disabled.
+    fn is_special(&self, byte: u8, index: usize) -> bool {
+        self.cc[index] != 0 && self.cc[index] == byte
+    }
+}
+
+/// Linux's `struct winsize`.
+#[derive(Debug, Clone, Copy, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+#[allow(dead_code)] // Only read by userspace
+pub(crate) struct WindowSize {
+    rows: u16,
+    columns: u16,
+    x_pixels: u16,
+    y_pixels: u16,
+}
+
+/// Fixed size queue for raw input, so interrupt handlers don't need to
+/// allocate.
+#[derive(Debug)]
+struct RawInputQueue {
+    bytes: [u8; RAW_INPUT_CAPACITY],
+    start: usize,
+    len: usize,
+}
+
+impl RawInputQueue {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; RAW_INPUT_CAPACITY],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Adds a byte to the end of the queue, dropping it if the queue is full.
+    fn push(&mut self, byte: u8) {
+        if self.len < self.bytes.len() {
+            self.bytes[(self.start + self.len) % self.bytes.len()] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.bytes[self.start];
+        self.start = (self.start + 1) % self.bytes.len();
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+#[derive(Debug)]
+struct Tty {
+    termios: Termios,
+
+    /// In canonical mode, the line that is being edited.
+    line: Vec<u8>,
+
+    /// Input that is ready to be read.
+    ready: VecDeque<u8>,
+
+    /// In canonical mode, the length of each line in `ready`, so reads can
+    /// stop at the end of a line. A length of 0 is an end of file from ^D.
+    line_lengths: VecDeque<usize>,
+
+    /// The session the console is the controlling terminal of.
+    session: Option<TaskId>,
+
+    /// The process group in `session` that gets input and signals.
+    foreground: Option<TaskId>,
+}
+
+impl Tty {
+    const fn new() -> Self {
+        Self {
+            termios: Termios::new(),
+            line: Vec::new(),
+            ready: VecDeque::new(),
+            line_lengths: VecDeque::new(),
+            session: None,
+            foreground: None,
+        }
+    }
+
+    /// Runs a byte of input through the line discipline. Returns the signal
+    /// to send if the byte was a signal character.
+    fn receive(&mut self, byte: u8) -> Option<Signal> {
+        let termios = self.termios;
+        let byte = match byte {
+            b'\r' if termios.iflag & IGNCR != 0 => return None,
+            b'\r' if termios.iflag & ICRNL != 0 => b'\n',
+            b'\n' if termios.iflag & INLCR != 0 => b'\r',
+            _ => byte,
+        };
+
+        if termios.lflag & ISIG != 0 {
+            let signal = if termios.is_special(byte, VINTR) {
+                Some(Signal::SIGINT)
+            } else if termios.is_special(byte, VQUIT) {
+                Some(Signal::SIGQUIT)
+            } else if termios.is_special(byte, VSUSP) {
+                Some(Signal::SIGTSTP)
+            } else {
+                None
+            };
+            if signal.is_some() {
+                if termios.lflag & NOFLSH == 0 {
+                    self.flush_input();
+                }
+                self.echo(byte);
+                return signal;
+            }
+        }
+
+        if !termios.canonical() {
+            if self.ready.len() < MAX_INPUT {
+                self.ready.push_back(byte);
+                self.echo(byte);
+            }
+            return None;
+        }
+
+        if termios.is_special(byte, VERASE) {
+            if let Some(erased) = self.line.pop() {
+                self.echo_erase(erased);
+            }
+        } else if termios.is_special(byte, VKILL) {
+            // This erases the whole line on the screen, like Linux's ECHOKE.
+            while let Some(erased) = self.line.pop() {
+                self.echo_erase(erased);
+            }
+        } else if termios.is_special(byte, VEOF) {
+            self.finish_line();
+        } else if byte == b'\n' || termios.is_special(byte, VEOL) {
+            self.line.push(byte);
+            if termios.lflag & ECHO == 0 && termios.lflag & ECHONL != 0 && byte == b'\n' {
+                output(termios, b"\n");
+            } else {
+                self.echo(byte);
+            }
+            self.finish_line();
+        } else if self.ready.len() + self.line.len() < MAX_INPUT - 1 {
+            // Leave room for a newline, so the line can always be finished.
+            self.line.push(byte);
+            self.echo(byte);
+        }
+        None
+    }
+
+    /// Moves the line being edited to the input that's ready to be read.
+    fn finish_line(&mut self) {
+        self.line_lengths.push_back(self.line.len());
+        self.ready.extend(self.line.drain(..));
+    }
+
+    fn flush_input(&mut self) {
+        self.line.clear();
+        self.ready.clear();
+        self.line_lengths.clear();
+    }
+
+    /// Echoes a byte of input if `ECHO` is set. With `ECHOCTL`, control
+    /// characters (other than tab and newline) are echoed like `^C`.
+    fn echo(&self, byte: u8) {
+        if self.termios.lflag & ECHO == 0 {
+            return;
+        }
+        if self.termios.lflag & ECHOCTL != 0 && is_echoed_as_caret(byte) {
+            output(self.termios, &[b'^', byte ^ 0x40]);
+        } else {
+            output(self.termios, &[byte]);
+        }
+    }
+
+    /// Erases an echoed byte from the screen if `ECHO` and `ECHOE` are set.
+    fn echo_erase(&self, byte: u8) {
+        let flags = ECHO | ECHOE;
+        if self.termios.lflag & flags != flags {
+            return;
+        }
+        let width = if self.termios.lflag & ECHOCTL != 0 && is_echoed_as_caret(byte) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..width {
+            output(self.termios, b"\x08 \x08");
+        }
+    }
+
+    /// Copies input into `buffer`, returning the number of bytes read, or
+    /// `None` if the reader needs to wait for more input. In canonical mode,
+    /// this reads at most one line.
+    fn try_read(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        let len = if self.termios.canonical() {
+            let line_length = self.line_lengths.front_mut()?;
+            let len = (*line_length).min(buffer.len());
+            *line_length -= len;
+            if *line_length == 0 {
+                self.line_lengths.pop_front();
+            }
+            len
+        } else if self.ready.is_empty() {
+            // TODO: Support VTIME and VMIN > 1. For now we return as soon as
+            // there is any input, like VMIN = 1, unless VMIN is 0, in which
+            // case reads don't wait.
+            return (self.termios.cc[VMIN] == 0).then_some(0);
+        } else {
+            self.ready.len().min(buffer.len())
+        };
+        for (dest, byte) in buffer.iter_mut().zip(self.ready.drain(..len)) {
+            *dest = byte;
+        }
+        Some(len)
+    }
+
+    fn set_termios(&mut self, termios: Termios, flush: bool) {
+        let was_canonical = self.termios.canonical();
+        self.termios = termios;
+        if flush {
+            self.flush_input();
+        }
+        match (was_canonical, termios.canonical()) {
+            // Anything typed so far can be read right away.
+            (true, false) => {
+                self.ready.extend(self.line.drain(..));
+                self.line_lengths.clear();
+            }
+            // Input that was already ready to be read stays ready, as a line
+            // of its own.
+            (false, true) if !self.ready.is_empty() => {
+                self.line_lengths.push_back(self.ready.len());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Control characters are echoed as `^` followed by a letter, except for tab
+/// and newline, which move the cursor like usual.
+fn is_echoed_as_caret(byte: u8) -> bool {
+    (byte < b' ' && byte != b'\t' && byte != b'\n') || byte == 127
+}
+
+/// Writes `data` to the serial port, translating "\n" to "\r\n" if `OPOST`
+/// and `ONLCR` are set.
+fn output(termios: Termios, data: &[u8]) {
+    let flags = OPOST | ONLCR;
+    let translate_newlines = termios.oflag & flags == flags;
+    for &byte in data {
+        if byte == b'\n' && translate_newlines {
+            serial::serial1_write_byte(b'\r');
+        }
+        serial::serial1_write_byte(byte);
+    }
+}
+
+/// Sets up the serial port interrupt. Keyboard input comes from the keyboard
+/// driver (see `keyboard.rs`).
+pub(crate) fn init() {
+    let interrupt_vector = interrupts::install_interrupt_next_vector(4, serial_interrupt_handler);
+    ioapic::install_irq(interrupt_vector, ioapic::IOAPICIRQNumber::Serial);
+
+    // Pick up anything that arrived before the interrupt was set up. The
+    // serial port won't interrupt again until we read it.
+    while let Some(byte) = serial::serial1_try_read_byte() {
+        receive_input(&[byte]);
+    }
+}
+
+fn serial_interrupt_handler(_vector: InterruptVector, _handler_id: InterruptHandlerID) {
+    while let Some(byte) = serial::serial1_try_read_byte() {
+        receive_input(&[byte]);
+    }
+}
+
+/// Queues up input for the line discipline. Safe to call from interrupt
+/// handlers.
+pub(crate) fn receive_input(bytes: &[u8]) {
+    {
+        let mut raw_input = RAW_INPUT.lock_disable_interrupts();
+        for &byte in bytes {
+            raw_input.push(byte);
+        }
+    }
+    RAW_INPUT_WAITERS.wake_all();
+}
+
+/// Kernel task that runs raw input through the line discipline.
+pub(crate) extern "C" fn tty_task(_arg: *const ()) {
+    loop {
+        let byte = RAW_INPUT_WAITERS.wait_until(|| RAW_INPUT.lock_disable_interrupts().pop());
+        let (signal, foreground) = {
+            let mut tty = TTY.lock_disable_interrupts();
+            (tty.receive(byte), tty.foreground)
+        };
+        READERS.wake_all();
+        if let (Some(signal), Some(foreground)) = (signal, foreground) {
+            sched::signal_process_group(foreground, signal);
+        }
+    }
+}
+
+/// Makes the console the controlling terminal of the current task's session,
+/// with the task's process group in the foreground. Only session leaders can
+/// do this. If another session still has the console, this fails unless
+/// `steal` is true, which takes the console away from it. (Linux only lets
+/// root steal a terminal, but everything runs as root here.)
+///
+/// TODO: When a session leader exits, send SIGHUP to the foreground process
+/// group and free the console right away, like Linux does. For now, the
+/// console is only freed once every task in the session has exited.
+pub(crate) fn set_controlling_terminal(steal: bool) -> Result<(), JobControlError> {
+    let id = sched::current_task_id();
+    let job_control = sched::current_job_control();
+    if job_control.session != id {
+        return Err(JobControlError::NotPermitted);
+    }
+    let old_session = {
+        let mut tty = TTY.lock_disable_interrupts();
+        let old_session = tty
+            .session
+            .filter(|session| *session != job_control.session);
+        if old_session.is_some_and(sched::session_exists) && !steal {
+            return Err(JobControlError::NotPermitted);
+        }
+        tty.session = Some(job_control.session);
+        tty.foreground = Some(job_control.process_group);
+        old_session
+    };
+    if let Some(old_session) = old_session {
+        sched::set_controlling_terminal(old_session, false);
+    }
+    sched::set_controlling_terminal(job_control.session, true);
+    Ok(())
+}
+
+/// Returns the console's session, or `None` if the console isn't the current
+/// task's controlling terminal.
+pub(crate) fn session() -> Option<TaskId> {
+    if !sched::current_job_control().controlling_terminal {
+        return None;
+    }
+    TTY.lock_disable_interrupts().session
+}
+
+/// Returns the console's foreground process group, or `None` if the console
+/// isn't the current task's controlling terminal.
+pub(crate) fn foreground_process_group() -> Option<TaskId> {
+    if !sched::current_job_control().controlling_terminal {
+        return None;
+    }
+    TTY.lock_disable_interrupts().foreground
+}
+
+/// Puts `process_group` in the foreground. The caller has to check that the
+/// group is in the console's session.
+pub(crate) fn set_foreground_process_group(process_group: TaskId) {
+    TTY.lock_disable_interrupts().foreground = Some(process_group);
+}
+
+/// Job control for tasks that use the console from the background. Like in
+/// Linux, this sends `signal` (SIGTTIN for reads, SIGTTOU for changing
+/// settings) to the task's process group, which stops it until a shell
+/// continues it in the foreground. If the task blocks or ignores the signal,
+/// reads fail with `EIO` and everything else is allowed. Tasks in the
+/// foreground, or that don't have the console as their controlling terminal,
+/// can always go ahead.
+///
+/// TODO: Linux also fails with `EIO` for orphaned process groups, since
+/// nobody would ever continue them.
+pub(crate) fn stop_if_background(signal: Signal) -> Result<(), FileError> {
+    loop {
+        let job_control = sched::current_job_control();
+        let foreground = TTY.lock_disable_interrupts().foreground;
+        if !job_control.controlling_terminal || foreground == Some(job_control.process_group) {
+            return Ok(());
+        }
+        if sched::current_task_ignores_signal(signal) {
+            return if signal == Signal::SIGTTIN {
+                Err(FileError::InputOutput)
+            } else {
+                Ok(())
+            };
+        }
+        sched::signal_process_group(job_control.process_group, signal);
+        // The signal is discarded if the task is init, so it never stops.
+        let stopped = sched::stop_for_pending_signal().map_err(|_| FileError::Interrupted)?;
+        if !stopped {
+            return Err(FileError::InputOutput);
+        }
+    }
+}
+
+/// Reads input into `buffer`, sleeping until there is some. Returns 0 at the
+/// end of file (^D at the start of a line in canonical mode).
+pub(crate) fn read(buffer: &mut [u8]) -> Result<usize, FileError> {
+    stop_if_background(Signal::SIGTTIN)?;
+    if buffer.is_empty() {
+        return Ok(0);
+    }
+    READERS
+        .wait_until_interruptible(|| TTY.lock_disable_interrupts().try_read(buffer))
+        .map_err(|_| FileError::Interrupted)
+}
+
+/// Reads a single byte, sleeping until there is one. This is for the kernel
+/// shell, which doesn't get signals, so unlike `read` it can't be interrupted.
+pub(crate) fn read_byte() -> u8 {
+    let mut byte = [0];
+    READERS.wait_until(|| {
+        let len = TTY.lock_disable_interrupts().try_read(&mut byte)?;
+        (len == 1).then_some(byte[0])
+    })
+}
+
+/// Writes `data` to the terminal, returning the number of bytes written.
+pub(crate) fn write(data: &[u8]) -> usize {
+    let termios = TTY.lock_disable_interrupts().termios;
+    output(termios, data);
+    data.len()
+}
+
+pub(crate) fn termios() -> Termios {
+    TTY.lock_disable_interrupts().termios
+}
+
+/// Changes the terminal settings. If `flush` is true, input that hasn't been
+/// read yet is discarded.
+pub(crate) fn set_termios(termios: Termios, flush: bool) {
+    TTY.lock_disable_interrupts().set_termios(termios, flush);
+    READERS.wake_all();
+}
+
+/// We don't know the size of the terminal on the other end of the serial
+/// port, so we report the classic 80x24.
+pub(crate) fn window_size() -> WindowSize {
+    WindowSize {
+        rows: 24,
+        columns: 80,
+        x_pixels: 0,
+        y_pixels: 0,
+    }
+}
+
+#[cfg(feature = "tests")]
+mod tests {
+    use super::*;
+
+    use crate::tests::kernel_test;
+
+    /// Runs `input` through the line discipline without echoing anything.
+    fn tty_with_input(termios: Termios, input: &[u8]) -> Tty {
+        let mut tty = Tty::new();
+        tty.set_termios(
+            Termios {
+                lflag: termios.lflag & !ECHO,
+                ..termios
+            },
+            false,
+        );
+        for &byte in input {
+            tty.receive(byte);
+        }
+        tty
+    }
+
+    #[kernel_test]
+    fn test_canonical_mode() {
+        let mut tty = tty_with_input(Termios::new(), b"ab\x7fc\rxyz\x15hi\x04\x04");
+        let mut buffer = [0; 16];
+        assert_eq!(tty.try_read(&mut buffer), Some(3));
+        assert_eq!(&buffer[..3], b"ac\n");
+        assert_eq!(tty.try_read(&mut buffer[..1]), Some(1));
+        assert_eq!(&buffer[..1], b"h");
+        assert_eq!(tty.try_read(&mut buffer), Some(1));
+        assert_eq!(&buffer[..1], b"i");
+        // ^D at the start of a line is an end of file.
+        assert_eq!(tty.try_read(&mut buffer), Some(0));
+        assert_eq!(tty.try_read(&mut buffer), None);
+    }
+
+    #[kernel_test]
+    fn test_raw_mode() {
+        let mut tty = tty_with_input(Termios::new().raw(), b"a\x7f\x03\r");
+        let mut buffer = [0; 16];
+        assert_eq!(tty.try_read(&mut buffer), Some(4));
+        assert_eq!(&buffer[..4], b"a\x7f\x03\r");
+        assert_eq!(tty.try_read(&mut buffer), None);
+    }
+
+    #[kernel_test]
+    fn test_signal_characters() {
+        let mut tty = tty_with_input(Termios::new(), b"abc");
+        assert_eq!(tty.receive(3), Some(Signal::SIGINT));
+        assert_eq!(tty.receive(26), Some(Signal::SIGTSTP));
+        assert_eq!(tty.receive(b'\n'), None);
+        let mut buffer = [0; 16];
+        assert_eq!(tty.try_read(&mut buffer), Some(1));
+        assert_eq!(&buffer[..1], b"\n");
+    }
+}
@@ -0,0 +1,66 @@
+use crate::{random, tty};
+
+use super::{FileError, FilePath, FileType, Metadata};
+
+/// Character devices at fixed paths under `/dev`. These don't live in any
+/// filesystem, so they are there no matter what is mounted at the root.
+///
+/// TODO: Replace this with a real devfs once we can mount more than one
+/// filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Device {
+    /// `/dev/random`, which reads from the kernel's random number generator
+    /// and blocks until it has been seeded.
+    Random,
+
+    /// `/dev/urandom`, which is like `/dev/random` but never blocks.
+    Urandom,
+
+    /// `/dev/console` (also `/dev/tty`), the console terminal. See `tty`.
+    Console,
+}
+
+impl Device {
+    pub(crate) fn from_path(path: &FilePath) -> Option<Self> {
+        if !path.absolute {
+            return None;
+        }
+        match path.as_string().as_str() {
+            "/dev/random" => Some(Self::Random),
+            "/dev/urandom" => Some(Self::Urandom),
+            // TODO: `/dev/tty` should be the task's controlling terminal, but
+            // the console is the only terminal we have.
+            "/dev/console" | "/dev/tty" => Some(Self::Console),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn read(self, buffer: &mut [u8]) -> Result<usize, FileError> {
+        if self == Self::Console {
+            return tty::read(buffer);
+        }
+        if self == Self::Random {
+            random::wait_until_seeded().map_err(|_| FileError::Interrupted)?;
+        }
+        random::fill_bytes(buffer);
+        Ok(buffer.len())
+    }
+
+    /// Writing to the random devices mixes the data into the entropy pool,
+    /// but doesn't count as seeding it, since anyone can write to them.
+    pub(crate) fn write(self, data: &[u8]) -> usize {
+        match self {
+            Self::Random | Self::Urandom => {
+                random::add_entropy(data, false);
+                data.len()
+            }
+            Self::Console => tty::write(data),
+        }
+    }
+
+    /// Devices don't live in a filesystem, so their metadata is made up.
+    /// Anyone can read and write them.
+    pub(crate) fn metadata(self) -> Metadata {
+        Metadata::generated(FileType::CharacterDevice, 0o666, 0, 0)
+    }
+}
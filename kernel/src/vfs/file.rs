@@ -0,0 +1,523 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use bitflags::bitflags;
+
+use crate::sync::Mutex;
+
+use super::{
+    get_path_inode, invalidate_cached_file_pages, pipe, Device, DirectoryEntry, DirectoryInode,
+    FileInode, FileInodeReadResult, FilePath, FileType, InodeId, InodeType, Metadata, PipeReader,
+    PipeWriter,
+};
+
+/// Maximum number of open files a single task can have.
+const MAX_OPEN_FILES: usize = 256;
+
+/// Writes can't go past this offset. `seek` allows any offset, so this keeps
+/// a bogus one from making a filesystem try to grow a file to terabytes. ext2
+/// only stores the low 32 bits of file sizes for us anyway.
+const MAX_FILE_SIZE: usize = u32::MAX as usize;
+
+/// Index into a task's `FileDescriptorTable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct FileDescriptor(pub(crate) u32);
+
+bitflags! {
+    /// Flags passed to `open`. Values match Linux so userspace code can use
+    /// the usual `O_*` constants.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) struct OpenFlags: u32 {
+        const WRITE_ONLY = 0o1;
+        const READ_WRITE = 0o2;
+        const CREATE = 0o100;
+        const TRUNCATE = 0o1000;
+        const APPEND = 0o2000;
+        const DIRECTORY = 0o200_000;
+
+        /// Applies to the file descriptor rather than the open file. See
+        /// `FileDescriptorTable::set_close_on_exec`.
+        const CLOSE_ON_EXEC = 0o2_000_000;
+    }
+}
+
+impl OpenFlags {
+    fn readable(self) -> bool {
+        !self.contains(Self::WRITE_ONLY)
+    }
+
+    fn writable(self) -> bool {
+        self.intersects(Self::WRITE_ONLY | Self::READ_WRITE)
+    }
+}
+
+/// Where `seek` should start from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SeekWhence {
+    Start,
+    Current,
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileError {
+    NotFound,
+    IsDirectory,
+    NotDirectory,
+    NotReadable,
+    NotWritable,
+    InvalidSeek,
+    WriteFailed,
+    TooManyOpenFiles,
+
+    /// A write would make the file bigger than `MAX_FILE_SIZE`.
+    TooLarge,
+
+    /// The file is a pipe or a device, which doesn't have an offset.
+    NotSeekable,
+
+    /// Tried to write to a pipe with no readers.
+    BrokenPipe,
+
+    /// A signal arrived while we were waiting to read or write.
+    Interrupted,
+
+    /// A background task tried to read from the terminal. See `tty`.
+    InputOutput,
+}
+
+/// An open file, which is what a `FileDescriptor` points to. Multiple file
+/// descriptors (and eventually multiple tasks) can share the same `OpenFile`,
+/// which means they share the file offset.
+#[derive(Debug)]
+pub(crate) struct OpenFile {
+    kind: OpenFileKind,
+
+    /// Byte offset into a file, or the index of the next entry to read from a
+    /// directory.
+    offset: usize,
+
+    flags: OpenFlags,
+}
+
+#[derive(Debug)]
+enum OpenFileKind {
+    File(Box<dyn FileInode>),
+    Directory(OpenDirectory),
+    PipeReader(Arc<PipeReader>),
+    PipeWriter(Arc<PipeWriter>),
+    Device(Device),
+}
+
+/// An open directory. Its entries are only read the first time they are
+/// needed, so reading a big directory a few entries at a time doesn't read the
+/// whole directory every time. Seeking back to the start reads them again,
+/// like `rewinddir`.
+#[derive(Debug)]
+struct OpenDirectory {
+    inode: Box<dyn DirectoryInode>,
+    entries: Option<Vec<Box<dyn DirectoryEntry>>>,
+}
+
+/// The device or pipe end behind an `OpenFile`. These don't have an offset,
+/// and reading or writing them can sleep for a long time, so callers take a
+/// `Stream` out of the `OpenFile` and use it without holding the `OpenFile`'s
+/// lock. The same `OpenFile` is often shared, like the console being stdin,
+/// stdout, and stderr, and holding the lock would block everyone else.
+#[derive(Debug, Clone)]
+pub(crate) enum Stream {
+    Device(Device),
+    PipeReader(Arc<PipeReader>),
+    PipeWriter(Arc<PipeWriter>),
+}
+
+impl Stream {
+    pub(crate) fn read(&self, buffer: &mut [u8]) -> Result<usize, FileError> {
+        match self {
+            Self::Device(device) => device.read(buffer),
+            Self::PipeReader(reader) => reader.read(buffer),
+            Self::PipeWriter(_) => Err(FileError::NotReadable),
+        }
+    }
+
+    pub(crate) fn write(&self, data: &[u8]) -> Result<usize, FileError> {
+        match self {
+            Self::Device(device) => Ok(device.write(data)),
+            Self::PipeWriter(writer) => writer.write(data),
+            Self::PipeReader(_) => Err(FileError::NotWritable),
+        }
+    }
+}
+
+// The underlying inodes are all accessed through Mutexes (see e.g. the ext2
+// VFSFileSystem), and an OpenFile itself is always wrapped in a Mutex, so it
+// is safe to send between tasks.
+unsafe impl Send for OpenFile {}
+
+impl OpenFile {
+    pub(crate) fn open(path: &FilePath, flags: OpenFlags) -> Result<Self, FileError> {
+        if let Some(device) = Device::from_path(path) {
+            if flags.contains(OpenFlags::DIRECTORY) {
+                return Err(FileError::NotDirectory);
+            }
+            return Ok(Self {
+                kind: OpenFileKind::Device(device),
+                offset: 0,
+                flags,
+            });
+        }
+
+        let inode = match get_path_inode(path) {
+            Ok(inode) => inode,
+            Err(err) if flags.contains(OpenFlags::CREATE) => {
+                log::debug!("open: creating {path} after lookup failed: {err}");
+                return Self::create(path, flags);
+            }
+            Err(err) => {
+                log::debug!("open: failed to find {path}: {err}");
+                return Err(FileError::NotFound);
+            }
+        };
+
+        let kind = match inode.inode_type {
+            InodeType::File(file) => {
+                if flags.contains(OpenFlags::DIRECTORY) {
+                    return Err(FileError::NotDirectory);
+                }
+                OpenFileKind::File(file)
+            }
+            InodeType::Directory(dir) => {
+                if flags.writable() {
+                    return Err(FileError::IsDirectory);
+                }
+                OpenFileKind::Directory(OpenDirectory {
+                    inode: dir,
+                    entries: None,
+                })
+            }
+        };
+
+        let mut file = Self {
+            kind,
+            offset: 0,
+            flags,
+        };
+        if flags.contains(OpenFlags::TRUNCATE) && flags.writable() {
+            file.truncate()?;
+        }
+        Ok(file)
+    }
+
+    /// Opens a file we already found with `get_path_inode`.
+    pub(crate) fn from_file_inode(file: Box<dyn FileInode>, flags: OpenFlags) -> Self {
+        Self {
+            kind: OpenFileKind::File(file),
+            offset: 0,
+            flags,
+        }
+    }
+
+    /// Creates a new pipe, returning its read end and write end.
+    pub(crate) fn pipe() -> (Self, Self) {
+        let (reader, writer) = pipe();
+        let reader = Self {
+            kind: OpenFileKind::PipeReader(Arc::new(reader)),
+            offset: 0,
+            flags: OpenFlags::empty(),
+        };
+        let writer = Self {
+            kind: OpenFileKind::PipeWriter(Arc::new(writer)),
+            offset: 0,
+            flags: OpenFlags::WRITE_ONLY,
+        };
+        (reader, writer)
+    }
+
+    fn create(path: &FilePath, flags: OpenFlags) -> Result<Self, FileError> {
+        let Some((parent_path, filename)) = path.split_dirname_filename() else {
+            return Err(FileError::IsDirectory);
+        };
+        let parent = get_path_inode(&parent_path).map_err(|_| FileError::NotFound)?;
+        let InodeType::Directory(mut parent) = parent.inode_type else {
+            return Err(FileError::NotDirectory);
+        };
+        let file = parent
+            .create_file(filename.as_str())
+            .ok_or(FileError::WriteFailed)?;
+        Ok(Self {
+            kind: OpenFileKind::File(file),
+            offset: 0,
+            flags,
+        })
+    }
+
+    fn file_inode(&mut self) -> Result<&mut Box<dyn FileInode>, FileError> {
+        match &mut self.kind {
+            OpenFileKind::File(file) => Ok(file),
+            OpenFileKind::Directory(_) => Err(FileError::IsDirectory),
+            OpenFileKind::PipeReader(_) | OpenFileKind::PipeWriter(_) | OpenFileKind::Device(_) => {
+                Err(FileError::NotSeekable)
+            }
+        }
+    }
+
+    /// Reads from the current offset into `buffer`, returning the number of
+    /// bytes read. Returns 0 at the end of the file. Reading from a pipe
+    /// sleeps until there is data.
+    pub(crate) fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FileError> {
+        if !self.flags.readable() {
+            return Err(FileError::NotReadable);
+        }
+        match &self.kind {
+            OpenFileKind::PipeReader(reader) => return reader.read(buffer),
+            OpenFileKind::Device(device) => return device.read(buffer),
+            _ => {}
+        }
+        let offset = self.offset;
+        let file = self.file_inode()?;
+        let bytes_read = match file.read(buffer, offset) {
+            FileInodeReadResult::Success => buffer.len(),
+            FileInodeReadResult::Done { bytes_read } => bytes_read,
+        };
+        self.offset += bytes_read;
+        Ok(bytes_read)
+    }
+
+    /// Returns the device or pipe behind this file if it is opened for
+    /// reading. See `Stream`.
+    pub(crate) fn readable_stream(&self) -> Result<Option<Stream>, FileError> {
+        if !self.flags.readable() {
+            return Err(FileError::NotReadable);
+        }
+        Ok(match &self.kind {
+            OpenFileKind::Device(device) => Some(Stream::Device(*device)),
+            OpenFileKind::PipeReader(reader) => Some(Stream::PipeReader(reader.clone())),
+            _ => None,
+        })
+    }
+
+    /// Like `readable_stream`, but for writing.
+    pub(crate) fn writable_stream(&self) -> Result<Option<Stream>, FileError> {
+        if !self.flags.writable() {
+            return Err(FileError::NotWritable);
+        }
+        Ok(match &self.kind {
+            OpenFileKind::Device(device) => Some(Stream::Device(*device)),
+            OpenFileKind::PipeWriter(writer) => Some(Stream::PipeWriter(writer.clone())),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn device(&self) -> Option<Device> {
+        match self.kind {
+            OpenFileKind::Device(device) => Some(device),
+            _ => None,
+        }
+    }
+
+    /// Reads from `offset` into `buffer` without using or changing the file
+    /// offset, returning the number of bytes read.
+    pub(crate) fn read_at(&mut self, buffer: &mut [u8], offset: usize) -> Result<usize, FileError> {
+        if !self.flags.readable() {
+            return Err(FileError::NotReadable);
+        }
+        let file = self.file_inode()?;
+        match file.read(buffer, offset) {
+            FileInodeReadResult::Success => Ok(buffer.len()),
+            FileInodeReadResult::Done { bytes_read } => Ok(bytes_read),
+        }
+    }
+
+    /// Identifies the underlying file for the page cache. See `FileInode::id`.
+    pub(crate) fn inode_id(&self) -> Option<InodeId> {
+        match &self.kind {
+            OpenFileKind::File(file) => file.id(),
+            _ => None,
+        }
+    }
+
+    /// Returns the size of the file in bytes.
+    pub(crate) fn size(&mut self) -> Result<usize, FileError> {
+        Ok(self.file_inode()?.size())
+    }
+
+    /// Like `size`, but fails if the file isn't open for reading. This is for
+    /// callers that will read the file later, like `mmap`.
+    pub(crate) fn readable_size(&mut self) -> Result<usize, FileError> {
+        if !self.flags.readable() {
+            return Err(FileError::NotReadable);
+        }
+        self.size()
+    }
+
+    /// Pipes don't live in a filesystem, so their metadata is made up like
+    /// devices' is.
+    pub(crate) fn metadata(&mut self) -> Metadata {
+        match &mut self.kind {
+            OpenFileKind::File(file) => file.metadata(),
+            OpenFileKind::Directory(dir) => dir.inode.metadata(),
+            OpenFileKind::PipeReader(_) | OpenFileKind::PipeWriter(_) => {
+                Metadata::generated(FileType::Fifo, 0o600, 0, 0)
+            }
+            OpenFileKind::Device(device) => device.metadata(),
+        }
+    }
+
+    /// Calls `f` with each directory entry from the current offset on, along
+    /// with the entry's index, until `f` returns false. This doesn't move the
+    /// offset. Callers `seek` past the entries they used, once they are sure
+    /// they used them.
+    ///
+    /// The entries are the ones the directory had when they were first read
+    /// (see `OpenDirectory`), so files created or deleted in the meantime
+    /// can't make us skip or repeat entries.
+    pub(crate) fn read_directory(
+        &mut self,
+        mut f: impl FnMut(usize, &dyn DirectoryEntry) -> bool,
+    ) -> Result<(), FileError> {
+        let OpenFileKind::Directory(dir) = &mut self.kind else {
+            return Err(FileError::NotDirectory);
+        };
+        let entries = dir
+            .entries
+            .get_or_insert_with(|| dir.inode.subdirectories());
+        for (index, entry) in entries.iter().enumerate().skip(self.offset) {
+            if !f(index, entry.as_ref()) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `data` at the current offset (or at the end of the file in
+    /// append mode), returning the number of bytes written. Writing to a pipe
+    /// sleeps while the pipe is full.
+    pub(crate) fn write(&mut self, data: &[u8]) -> Result<usize, FileError> {
+        if !self.flags.writable() {
+            return Err(FileError::NotWritable);
+        }
+        match &self.kind {
+            OpenFileKind::PipeWriter(writer) => return writer.write(data),
+            OpenFileKind::Device(device) => return Ok(device.write(data)),
+            _ => {}
+        }
+        let append = self.flags.contains(OpenFlags::APPEND);
+        let offset = self.offset;
+        let file = self.file_inode()?;
+        let offset = if append { file.size() } else { offset };
+        let end = offset
+            .checked_add(data.len())
+            .filter(|end| *end <= MAX_FILE_SIZE)
+            .ok_or(FileError::TooLarge)?;
+        if !file.write_at(data, offset) {
+            return Err(FileError::WriteFailed);
+        }
+        if let Some(id) = file.id() {
+            invalidate_cached_file_pages(id);
+        }
+
+        self.offset = end;
+        Ok(data.len())
+    }
+
+    /// Changes the file offset, returning the new offset.
+    pub(crate) fn seek(&mut self, offset: i64, whence: SeekWhence) -> Result<usize, FileError> {
+        if matches!(
+            self.kind,
+            OpenFileKind::PipeReader(_) | OpenFileKind::PipeWriter(_) | OpenFileKind::Device(_)
+        ) {
+            return Err(FileError::NotSeekable);
+        }
+        let base = match whence {
+            SeekWhence::Start => 0,
+            SeekWhence::Current => self.offset,
+            SeekWhence::End => self.file_inode()?.size(),
+        };
+        let new_offset = base
+            .checked_add_signed(offset as isize)
+            .ok_or(FileError::InvalidSeek)?;
+        if let OpenFileKind::Directory(dir) = &mut self.kind {
+            if new_offset == 0 {
+                dir.entries = None;
+            }
+        }
+        self.offset = new_offset;
+        Ok(new_offset)
+    }
+
+    fn truncate(&mut self) -> Result<(), FileError> {
+        let file = self.file_inode()?;
+        if !file.write(&[]) {
+            return Err(FileError::WriteFailed);
+        }
+        if let Some(id) = file.id() {
+            invalidate_cached_file_pages(id);
+        }
+        Ok(())
+    }
+}
+
+/// Per-task table mapping file descriptors to open files. Cloning the table
+/// (like when forking a task) shares the underlying `OpenFile`s, including
+/// their offsets.
+#[derive(Debug, Clone)]
+pub(crate) struct FileDescriptorTable {
+    files: BTreeMap<FileDescriptor, Arc<Mutex<OpenFile>>>,
+
+    /// File descriptors that are closed when the task execs.
+    close_on_exec: BTreeSet<FileDescriptor>,
+}
+
+impl FileDescriptorTable {
+    pub(crate) const fn new() -> Self {
+        Self {
+            files: BTreeMap::new(),
+            close_on_exec: BTreeSet::new(),
+        }
+    }
+
+    /// Adds the file to the table using the lowest available file descriptor.
+    pub(crate) fn insert(
+        &mut self,
+        file: Arc<Mutex<OpenFile>>,
+    ) -> Result<FileDescriptor, FileError> {
+        if self.files.len() >= MAX_OPEN_FILES {
+            return Err(FileError::TooManyOpenFiles);
+        }
+        let mut fd = FileDescriptor(0);
+        while self.files.contains_key(&fd) {
+            fd.0 += 1;
+        }
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    pub(crate) fn get(&self, fd: FileDescriptor) -> Option<Arc<Mutex<OpenFile>>> {
+        self.files.get(&fd).cloned()
+    }
+
+    pub(crate) fn remove(&mut self, fd: FileDescriptor) -> Option<Arc<Mutex<OpenFile>>> {
+        self.close_on_exec.remove(&fd);
+        self.files.remove(&fd)
+    }
+
+    /// Marks the file descriptor to be closed by `remove_close_on_exec`.
+    pub(crate) fn set_close_on_exec(&mut self, fd: FileDescriptor) {
+        if self.files.contains_key(&fd) {
+            self.close_on_exec.insert(fd);
+        }
+    }
+
+    /// Removes every file descriptor marked with `set_close_on_exec`, and
+    /// returns their files so the caller can drop them after releasing the
+    /// table's lock.
+    pub(crate) fn remove_close_on_exec(&mut self) -> Vec<Arc<Mutex<OpenFile>>> {
+        let close_on_exec = core::mem::take(&mut self.close_on_exec);
+        close_on_exec
+            .into_iter()
+            .filter_map(|fd| self.files.remove(&fd))
+            .collect()
+    }
+}
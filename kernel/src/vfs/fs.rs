@@ -1,21 +1,25 @@
 use core::fmt::Debug;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec::Vec;
 
+use crate::rtc;
 use crate::sync::{Mutex, MutexGuard};
 
-use super::FilePath;
+use super::{clear_page_cache, Device, FileError, FilePath};
 
 static MOUNTED_ROOT_FILE_SYSTEM: Mutex<Option<Box<dyn FileSystem + Send>>> = Mutex::new(None);
 
 pub(crate) fn mount_root_filesystem(fs: Box<dyn FileSystem + Send>) {
     MOUNTED_ROOT_FILE_SYSTEM.lock().replace(fs);
+    clear_page_cache();
 }
 
 pub(crate) fn unmount_root_filesystem() {
     MOUNTED_ROOT_FILE_SYSTEM.lock().take();
+    clear_page_cache();
 }
 
 pub(crate) fn root_filesystem_lock(
@@ -50,13 +54,136 @@ pub(crate) struct Inode {
     pub(crate) inode_type: InodeType,
 }
 
+impl Inode {
+    pub(crate) fn metadata(&mut self) -> Metadata {
+        match &mut self.inode_type {
+            InodeType::File(file) => file.metadata(),
+            InodeType::Directory(dir) => dir.metadata(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) enum InodeType {
     File(Box<dyn FileInode>),
     Directory(Box<dyn DirectoryInode>),
 }
 
+/// Everything `stat` reports about a file. The fields mean the same thing as
+/// in Linux's `struct stat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Metadata {
+    pub(crate) file_type: FileType,
+
+    /// Permission bits, like `0o755`, including the setuid, setgid, and
+    /// sticky bits.
+    pub(crate) permissions: u16,
+
+    /// Inode number, which is unique within the file's filesystem. Files
+    /// that don't live in a filesystem, like pipes, use 0.
+    pub(crate) inode: u64,
+
+    /// Size of the file in bytes.
+    pub(crate) size: u64,
+
+    /// Number of 512 byte blocks allocated for the file.
+    pub(crate) blocks: u64,
+
+    /// Number of hard links to the file.
+    pub(crate) links: u32,
+
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+
+    /// Unix times in seconds of the last access, the last change to the
+    /// file's contents, and the last change to the inode itself.
+    pub(crate) accessed: u64,
+    pub(crate) modified: u64,
+    pub(crate) changed: u64,
+}
+
+impl Metadata {
+    /// Metadata for files the kernel makes up as they are read, like the ones
+    /// in sysfs. They are owned by root, take up no space, and have always
+    /// just changed.
+    pub(crate) fn generated(file_type: FileType, permissions: u16, inode: u64, size: u64) -> Self {
+        let now = u64::from(rtc::unix_time()) / 1_000_000_000;
+        // A directory is linked from its parent and from its own `.` entry.
+        let links = if file_type == FileType::Directory {
+            2
+        } else {
+            1
+        };
+        Self {
+            file_type,
+            permissions,
+            inode,
+            size,
+            blocks: 0,
+            links,
+            uid: 0,
+            gid: 0,
+            accessed: now,
+            modified: now,
+            changed: now,
+        }
+    }
+
+    /// The file type and permissions together, like `st_mode` in Linux.
+    pub(crate) fn mode(&self) -> u32 {
+        self.file_type.mode_bits() | u32::from(self.permissions)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum FileType {
+    Regular,
+    Directory,
+    CharacterDevice,
+    Fifo,
+}
+
+impl FileType {
+    /// The `S_IF*` bits for the type in Linux's `st_mode`.
+    pub(crate) fn mode_bits(self) -> u32 {
+        match self {
+            Self::Regular => 0o100_000,
+            Self::Directory => 0o040_000,
+            Self::CharacterDevice => 0o020_000,
+            Self::Fifo => 0o010_000,
+        }
+    }
+}
+
+/// Identifies a mounted filesystem. Every filesystem gets a new ID when it is
+/// mounted, so inode numbers from different filesystems (or from a
+/// filesystem that was unmounted and mounted again) never look the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct FileSystemId(u64);
+
+impl FileSystemId {
+    pub(crate) fn new_unique() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Identifies a file across every mounted filesystem. This is what the page
+/// cache uses to tell if two open files are the same file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct InodeId {
+    pub(crate) file_system: FileSystemId,
+    pub(crate) inode: u64,
+}
+
 pub(crate) trait FileInode: Debug {
+    /// Identifies the file so its pages can be cached. Files whose contents
+    /// can change without going through `write`, like the generated files in
+    /// sysfs, return `None` and are never cached.
+    fn id(&self) -> Option<InodeId> {
+        None
+    }
+
     fn read(&mut self, buffer: &mut [u8], offset: usize) -> FileInodeReadResult;
 
     fn read_all(&mut self) -> Vec<u8> {
@@ -78,9 +205,23 @@ pub(crate) trait FileInode: Debug {
         buffer
     }
 
+    /// Size of the file in bytes.
+    fn size(&mut self) -> usize {
+        self.read_all().len()
+    }
+
+    /// Replaces the whole contents of the file with `data`.
     fn write(&mut self, _data: &[u8]) -> bool {
         false
     }
+
+    /// Writes `data` at byte `offset`, growing the file if the write ends
+    /// past the end. If `offset` is past the end, the gap is zeroed.
+    fn write_at(&mut self, _data: &[u8], _offset: usize) -> bool {
+        false
+    }
+
+    fn metadata(&mut self) -> Metadata;
 }
 
 pub(crate) enum FileInodeReadResult {
@@ -101,11 +242,19 @@ pub(crate) trait DirectoryInode: Debug {
         log::warn!("create_file: not implemented for {:?}", self);
         None
     }
+
+    fn metadata(&mut self) -> Metadata;
 }
 
 pub(crate) trait DirectoryEntry: Debug {
     fn name(&self) -> String;
     fn entry_type(&self) -> DirectoryEntryType;
+
+    /// The entry's inode number, which is the same as `Metadata::inode` for
+    /// its inode. This lets `getdents` list a directory without reading every
+    /// inode in it.
+    fn inode_number(&self) -> u64;
+
     fn get_inode(&mut self) -> Inode;
 }
 
@@ -129,3 +278,17 @@ pub(crate) fn get_path_inode(path: &FilePath) -> Result<Inode, String> {
     };
     Ok(inode)
 }
+
+/// Returns the metadata of the file at `path`, like `stat`.
+pub(crate) fn path_metadata(path: &FilePath) -> Result<Metadata, FileError> {
+    if let Some(device) = Device::from_path(path) {
+        return Ok(device.metadata());
+    }
+    match get_path_inode(path) {
+        Ok(mut inode) => Ok(inode.metadata()),
+        Err(err) => {
+            log::debug!("path_metadata: failed to find {path}: {err}");
+            Err(FileError::NotFound)
+        }
+    }
+}
@@ -1,5 +1,13 @@
+mod device;
+mod file;
 mod fs;
+mod page_cache;
 mod path;
+mod pipe;
 
+pub(crate) use device::*;
+pub(crate) use file::*;
 pub(crate) use fs::*;
+pub(crate) use page_cache::*;
 pub(crate) use path::*;
+pub(crate) use pipe::*;
@@ -0,0 +1,104 @@
+use alloc::collections::btree_map::Entry;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::memory::{self, KernPhysAddr, Page, PAGE_SIZE};
+use crate::sync::{Mutex, SpinLock};
+
+use super::{InodeId, OpenFile};
+
+/// Pages of file contents keyed by file and page index, which lets every task
+/// that maps the same part of a file (like several instances of the same
+/// executable) share one physical page. The cache holds a reference to each
+/// page (see `memory::share_physical_page`), so pages stay cached after the
+/// tasks using them exit.
+///
+/// TODO: Evict pages when we run low on memory.
+static PAGE_CACHE: SpinLock<BTreeMap<(InodeId, u64), Page<KernPhysAddr>>> =
+    SpinLock::new(BTreeMap::new());
+
+/// Returns a physical page with page `index` of the file, reading it from the
+/// file if it isn't cached yet. Anything past the end of the file is zero.
+/// The caller gets its own reference to the page, and must not write to it
+/// since other tasks may be sharing it.
+///
+/// Files that can't be cached (see `FileInode::id`) get a new copy of the page
+/// every time.
+pub(crate) fn cached_file_page(file: &Mutex<OpenFile>, index: u64) -> Option<Page<KernPhysAddr>> {
+    let id = file.lock().inode_id();
+    if let Some(id) = id {
+        if let Some(page) = PAGE_CACHE.lock().get(&(id, index)) {
+            memory::share_physical_page(*page);
+            return Some(*page);
+        }
+    }
+
+    // Reading the file can sleep, so don't hold the cache lock while we do it.
+    let page = read_file_page(file, index)?;
+    let Some(id) = id else {
+        return Some(page);
+    };
+
+    let mut cache = PAGE_CACHE.lock();
+    match cache.entry((id, index)) {
+        Entry::Occupied(entry) => {
+            // Someone else read the page while we were reading it, so use
+            // theirs and throw ours away.
+            let existing = *entry.get();
+            memory::share_physical_page(existing);
+            drop(cache);
+            memory::release_physical_page(page);
+            Some(existing)
+        }
+        Entry::Vacant(entry) => {
+            // One reference for the cache and one for the caller.
+            memory::share_physical_page(page);
+            entry.insert(page);
+            Some(page)
+        }
+    }
+}
+
+/// Reads page `index` of the file into a new physical page.
+fn read_file_page(file: &Mutex<OpenFile>, index: u64) -> Option<Page<KernPhysAddr>> {
+    let mut page = match memory::allocate_zeroed_physical_page() {
+        Ok(page) => page,
+        Err(e) => {
+            log::warn!("failed to allocate page for file page {index}: {e:?}");
+            return None;
+        }
+    };
+    let offset = index as usize * PAGE_SIZE;
+    if let Err(e) = file.lock().read_at(page.as_byte_slice(), offset) {
+        log::warn!("failed to read file page {index}: {e:?}");
+        memory::release_physical_page(page);
+        return None;
+    }
+    Some(page)
+}
+
+/// Drops every cached page of the file, like after the file is written to.
+/// Tasks that already mapped those pages keep the old contents.
+pub(crate) fn invalidate_cached_file_pages(id: InodeId) {
+    let pages = {
+        let mut cache = PAGE_CACHE.lock();
+        let keys = cache
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        keys.iter()
+            .filter_map(|key| cache.remove(key))
+            .collect::<Vec<_>>()
+    };
+    for page in pages {
+        memory::release_physical_page(page);
+    }
+}
+
+/// Drops every cached page, like when the filesystem is unmounted.
+pub(crate) fn clear_page_cache() {
+    let pages = core::mem::take(&mut *PAGE_CACHE.lock());
+    for page in pages.into_values() {
+        memory::release_physical_page(page);
+    }
+}
@@ -0,0 +1,138 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use crate::sync::{SpinLock, WaitQueue};
+
+use super::FileError;
+
+/// How many bytes can be written to a pipe before writers have to wait for a
+/// reader. This is also our `PIPE_BUF`: writes of at most this many bytes are
+/// atomic, so they are never interleaved with data from other writers.
+const PIPE_CAPACITY: usize = 4096;
+
+/// An anonymous pipe, which is a bounded buffer of bytes shared between a
+/// `PipeReader` and a `PipeWriter`. Reads sleep until there is data, and
+/// writes sleep until there is space. Once every writer is dropped, reads
+/// return 0 (end of file) after the buffer is drained. Once every reader is
+/// dropped, writes fail.
+#[derive(Debug)]
+struct Pipe {
+    state: SpinLock<PipeState>,
+
+    /// Woken up when data is written or the last writer goes away.
+    readers_wait_queue: WaitQueue,
+
+    /// Woken up when data is read or the last reader goes away.
+    writers_wait_queue: WaitQueue,
+}
+
+#[derive(Debug)]
+struct PipeState {
+    buffer: VecDeque<u8>,
+    num_readers: usize,
+    num_writers: usize,
+}
+
+/// The read end of a pipe. Dropping it closes the read end.
+#[derive(Debug)]
+pub(crate) struct PipeReader(Arc<Pipe>);
+
+/// The write end of a pipe. Dropping it closes the write end.
+#[derive(Debug)]
+pub(crate) struct PipeWriter(Arc<Pipe>);
+
+/// Creates a new pipe and returns its two ends.
+pub(crate) fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        state: SpinLock::new(PipeState {
+            buffer: VecDeque::with_capacity(PIPE_CAPACITY),
+            num_readers: 1,
+            num_writers: 1,
+        }),
+        readers_wait_queue: WaitQueue::new(),
+        writers_wait_queue: WaitQueue::new(),
+    });
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+impl PipeReader {
+    /// Reads up to `buffer.len()` bytes, sleeping until at least one byte is
+    /// available. Returns 0 if the pipe is empty and there are no writers.
+    pub(crate) fn read(&self, buffer: &mut [u8]) -> Result<usize, FileError> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let bytes_read = self
+            .0
+            .readers_wait_queue
+            .wait_until_interruptible(|| {
+                let mut state = self.0.state.lock_disable_interrupts();
+                if state.buffer.is_empty() {
+                    return (state.num_writers == 0).then_some(0);
+                }
+                let len = buffer.len().min(state.buffer.len());
+                for (dest, byte) in buffer.iter_mut().zip(state.buffer.drain(..len)) {
+                    *dest = byte;
+                }
+                Some(len)
+            })
+            .map_err(|_| FileError::Interrupted)?;
+
+        if bytes_read > 0 {
+            self.0.writers_wait_queue.wake_all();
+        }
+        Ok(bytes_read)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.state.lock_disable_interrupts().num_readers -= 1;
+        self.0.writers_wait_queue.wake_all();
+    }
+}
+
+impl PipeWriter {
+    /// Writes all of `data`, sleeping whenever the pipe is full. If a signal
+    /// interrupts the write after some data was written, returns how much was
+    /// written. Writes of up to `PIPE_CAPACITY` bytes wait until there is room
+    /// for all of `data`, and then write it at once.
+    pub(crate) fn write(&self, data: &[u8]) -> Result<usize, FileError> {
+        let atomic = data.len() <= PIPE_CAPACITY;
+        let mut written = 0;
+        while written < data.len() {
+            let result = self.0.writers_wait_queue.wait_until_interruptible(|| {
+                let mut state = self.0.state.lock_disable_interrupts();
+                if state.num_readers == 0 {
+                    return Some(Err(FileError::BrokenPipe));
+                }
+                let space = PIPE_CAPACITY - state.buffer.len();
+                if space == 0 || (atomic && space < data.len()) {
+                    return None;
+                }
+                let len = space.min(data.len() - written);
+                state.buffer.extend(&data[written..written + len]);
+                Some(Ok(len))
+            });
+
+            match result {
+                Ok(Ok(len)) => {
+                    written += len;
+                    self.0.readers_wait_queue.wake_all();
+                }
+                Ok(Err(err)) => return Err(err),
+                Err(_) if written > 0 => return Ok(written),
+                Err(_) => return Err(FileError::Interrupted),
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.state.lock_disable_interrupts().num_writers -= 1;
+        self.0.readers_wait_queue.wake_all();
+    }
+}
@@ -56,6 +56,10 @@ pub(crate) fn virtio_block_print_devices() {
     serial_println!("virtio block devices: {:#x?}", devices);
 }
 
+pub(crate) fn virtio_block_device_count() -> usize {
+    VIRTIO_BLOCK.read().len()
+}
+
 pub(crate) fn virtio_block_get_id(device_index: usize) -> OnceReceiver<VirtIOBlockResponse> {
     let devices_lock = VIRTIO_BLOCK.read();
     let mut device = devices_lock
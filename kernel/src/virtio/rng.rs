@@ -31,9 +31,16 @@ pub(crate) fn try_init_virtio_rng(device_config: VirtIODeviceConfig) {
 }
 
 pub(crate) fn request_random_numbers(num_bytes: u32) -> OnceReceiver<Box<[u8]>> {
+    try_request_random_numbers(num_bytes).expect("VirtIO RNG not initialized")
+}
+
+/// Like `request_random_numbers`, but returns `None` if there is no VirtIO RNG
+/// device. Either way, the receiver only gets the bytes the device reported
+/// writing, which can be fewer than `num_bytes`.
+pub(crate) fn try_request_random_numbers(num_bytes: u32) -> Option<OnceReceiver<Box<[u8]>>> {
     let mut lock = VIRTIO_RNG.lock_disable_interrupts();
-    let rng = lock.as_mut().expect("VirtIO RNG not initialized");
-    rng.request_random_numbers(num_bytes)
+    let rng = lock.as_mut()?;
+    Some(rng.request_random_numbers(num_bytes))
 }
 
 /// See "5.4 Entropy Device" in the VirtIO spec. The virtio entropy device
@@ -147,8 +154,8 @@ fn virtio_rng_interrupt(_vector: InterruptVector, _handler_id: InterruptHandlerI
                     addr.as_ptr::<u8>(),
                     // NOTE: Using the length from the used entry, not the buffer
                     // length, b/c the RNG device might not have written the whole
-                    // thing!
-                    used_entry.len as usize,
+                    // thing! Don't trust it to be within the buffer though.
+                    used_entry.len.min(descriptor.len) as usize,
                 )
             };
 
@@ -39,6 +39,11 @@ make -C userspace/primes clean
 make -C userspace/primes
 cp userspace/primes/primes "$mount_dir/bin/primes"
 
+make -C userspace/rust
+make -C userspace/rust install DEST="$mount_dir/bin"
+mkdir "$mount_dir/sbin"
+mv "$mount_dir/bin/init" "$mount_dir/sbin/init"
+
 # Unmount
 sudo exa --tree -lahgnimuU "$mount_dir"
 sync "$mount_dir"
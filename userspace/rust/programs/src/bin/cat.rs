@@ -0,0 +1,50 @@
+//! Prints the contents of each file given as an argument, or of stdin if
+//! there are no arguments. Reading from the console, ^D ends the input.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+
+use runtime::println;
+use runtime::syscall::{self, Errno, Fd, OpenFlags};
+
+runtime::entry!(main);
+
+fn main() -> i32 {
+    if runtime::args().len() <= 1 {
+        if let Err(err) = copy_to_stdout(Fd::STDIN) {
+            println!("cat: stdin: {err}");
+            return 1;
+        }
+        return 0;
+    }
+
+    let mut exit_code = 0;
+    for path in runtime::args().skip(1) {
+        if let Err(err) = cat(path) {
+            println!("cat: {path}: {err}");
+            exit_code = 1;
+        }
+    }
+    exit_code
+}
+
+fn cat(path: &str) -> Result<(), Errno> {
+    let fd = syscall::open(path, OpenFlags::READ_ONLY)?;
+    let result = copy_to_stdout(fd);
+    syscall::close(fd)?;
+    result
+}
+
+fn copy_to_stdout(fd: Fd) -> Result<(), Errno> {
+    let mut buffer = [0; 1024];
+    loop {
+        match syscall::read(fd, &mut buffer)? {
+            0 => return Ok(()),
+            n => runtime::print!("{}", String::from_utf8_lossy(&buffer[..n])),
+        }
+    }
+}
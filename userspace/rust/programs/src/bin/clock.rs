@@ -0,0 +1,39 @@
+//! Prints the time and checks how accurately `nanosleep` sleeps.
+
+#![no_std]
+#![no_main]
+
+use core::time::Duration;
+
+use runtime::println;
+use runtime::syscall::{self, Clock};
+
+runtime::entry!(main);
+
+fn main() -> i32 {
+    let (Ok(now), Ok(uptime)) = (
+        syscall::clock_gettime(Clock::Realtime),
+        syscall::clock_gettime(Clock::Boottime),
+    ) else {
+        println!("clock: failed to read the clocks");
+        return 1;
+    };
+    println!("Unix time: {}.{:09}", now.as_secs(), now.subsec_nanos());
+    println!("Uptime: {uptime:?}");
+
+    for micros in [100, 2_500, 75_000, 1_000_000] {
+        let requested = Duration::from_micros(micros);
+        let Ok(start) = syscall::clock_gettime(Clock::Monotonic) else {
+            return 1;
+        };
+        if let Err((errno, remaining)) = syscall::nanosleep(requested) {
+            println!("clock: nanosleep failed: {errno:?} with {remaining:?} left");
+            return 1;
+        }
+        let Ok(end) = syscall::clock_gettime(Clock::Monotonic) else {
+            return 1;
+        };
+        println!("Asked to sleep {requested:?}, slept {:?}", end - start);
+    }
+    0
+}
@@ -0,0 +1,18 @@
+//! Prints its arguments and environment.
+
+#![no_std]
+#![no_main]
+
+use runtime::println;
+
+runtime::entry!(main);
+
+fn main() {
+    println!("Hello from Rust!");
+    for (i, arg) in runtime::args().enumerate() {
+        println!("argv[{i}] = {arg:?}");
+    }
+    for (key, value) in runtime::vars() {
+        println!("env {key} = {value:?}");
+    }
+}
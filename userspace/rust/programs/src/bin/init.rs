@@ -0,0 +1,62 @@
+//! The first userspace program, which the kernel starts from `/sbin/init`
+//! when booting with a root device. Runs the program given in its arguments
+//! (passed after `--` on the kernel command line), or `/bin/sh` if there
+//! isn't one, and reaps every child, including orphans the kernel hands to
+//! us. Exits with the program's exit code once there are no children left, so
+//! the kernel falls back to its shell.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use runtime::println;
+use runtime::syscall::{self, Errno, ForkResult, Pid, WaitTarget};
+
+runtime::entry!(main);
+
+const DEFAULT_PROGRAM: &str = "/bin/sh";
+
+fn main() -> i32 {
+    let mut args: Vec<&str> = runtime::args().skip(1).collect();
+    if args.is_empty() {
+        args.push(DEFAULT_PROGRAM);
+    }
+    let path = args[0];
+    let program = match spawn(path, &args) {
+        Ok(pid) => Some(pid),
+        Err(err) => {
+            println!("init: failed to start {path}: {err}");
+            None
+        }
+    };
+
+    let mut exit_code = 0;
+    loop {
+        match syscall::waitpid(WaitTarget::AnyChild, true) {
+            Ok(Some((pid, status))) if Some(pid) == program => {
+                println!("init: {} exited with {status:?}", args[0]);
+                exit_code = status.exit_code().map_or(1, i32::from);
+            }
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(Errno::ECHILD) => return exit_code,
+            Err(err) => {
+                println!("init: waitpid failed: {err}");
+                return 1;
+            }
+        }
+    }
+}
+
+fn spawn(path: &str, args: &[&str]) -> Result<Pid, Errno> {
+    match syscall::fork()? {
+        ForkResult::Child => {
+            let err = syscall::exec(path, args, &[]);
+            println!("init: exec {path} failed: {err}");
+            syscall::exit(1);
+        }
+        ForkResult::Parent { child } => Ok(child),
+    }
+}
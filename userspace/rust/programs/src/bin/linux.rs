@@ -0,0 +1,31 @@
+//! Runs a program built for Linux, like a static musl binary, by exec'ing it
+//! with the Linux syscall ABI. The environment is passed along unchanged.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::vec::Vec;
+
+use runtime::println;
+use runtime::syscall;
+
+runtime::entry!(main);
+
+fn main() -> i32 {
+    let args: Vec<&str> = runtime::args().skip(1).collect();
+    let Some(path) = args.first() else {
+        println!("Usage: linux <program> [args...]");
+        return 1;
+    };
+    let env: Vec<_> = runtime::vars()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+    let env: Vec<&str> = env.iter().map(|var| var.as_str()).collect();
+
+    let err = syscall::exec_linux(path, &args, &env);
+    println!("linux: {path}: {err}");
+    1
+}
@@ -0,0 +1,134 @@
+//! Lists each directory given as an argument, or `/` if there are none. With
+//! `-l`, also shows each file's type and permissions, link count, owner,
+//! group, size, and when it was last modified, like `ls -l` in other systems.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use runtime::syscall::{self, Errno, Stat};
+use runtime::{eprintln, println};
+
+runtime::entry!(main);
+
+fn main() -> i32 {
+    let (flags, mut paths): (Vec<&str>, Vec<&str>) = runtime::args()
+        .skip(1)
+        .partition(|arg| arg.starts_with('-'));
+    let mut long = false;
+    for flag in flags {
+        if flag == "-l" {
+            long = true;
+        } else {
+            eprintln!("ls: unknown option {flag}");
+            return 2;
+        }
+    }
+    if paths.is_empty() {
+        paths.push("/");
+    }
+
+    let mut exit_code = 0;
+    for (i, path) in paths.iter().enumerate() {
+        if paths.len() > 1 {
+            if i > 0 {
+                println!();
+            }
+            println!("{path}:");
+        }
+        if let Err(err) = ls(path, long) {
+            eprintln!("ls: {path}: {err}");
+            exit_code = 1;
+        }
+    }
+    exit_code
+}
+
+fn ls(path: &str, long: bool) -> Result<(), Errno> {
+    let stat = syscall::stat(path)?;
+    if !stat.is_dir() {
+        print_entry(path, &stat, long);
+        return Ok(());
+    }
+
+    let mut entries = syscall::read_dir(path)?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    for entry in entries {
+        if !long {
+            println!("{}", entry.name);
+            continue;
+        }
+        let entry_path = format!("{}/{}", path.trim_end_matches('/'), entry.name);
+        match syscall::stat(&entry_path) {
+            Ok(stat) => print_entry(&entry.name, &stat, long),
+            Err(err) => eprintln!("ls: {entry_path}: {err}"),
+        }
+    }
+    Ok(())
+}
+
+fn print_entry(name: &str, stat: &Stat, long: bool) {
+    if !long {
+        println!("{name}");
+        return;
+    }
+    println!(
+        "{} {:>3} {:>4} {:>4} {:>8} {} {name}",
+        mode_string(stat),
+        stat.nlink,
+        stat.uid,
+        stat.gid,
+        stat.size,
+        format_time(stat.mtime),
+    );
+}
+
+/// Formats the file type and permissions like `drwxr-xr-x`.
+fn mode_string(stat: &Stat) -> String {
+    let mut mode = String::with_capacity(10);
+    mode.push(match stat.mode & Stat::S_IFMT {
+        Stat::S_IFDIR => 'd',
+        Stat::S_IFCHR => 'c',
+        Stat::S_IFIFO => 'p',
+        Stat::S_IFREG => '-',
+        _ => '?',
+    });
+    for (i, c) in "rwxrwxrwx".chars().enumerate() {
+        let allowed = stat.permissions() & (1 << (8 - i)) != 0;
+        mode.push(if allowed { c } else { '-' });
+    }
+    mode
+}
+
+/// Formats a time since the Unix epoch as a UTC date and time, using the
+/// civil-from-days algorithm from
+/// <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    let seconds_of_day = seconds % 86_400;
+    let days = seconds / 86_400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months start in March, so leap days come last.
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = if month < 10 {
+        (era * 400 + year_of_era, month + 3)
+    } else {
+        (era * 400 + year_of_era + 1, month - 9)
+    };
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60
+    )
+}
@@ -0,0 +1,53 @@
+//! Forks a child that writes messages into a pipe, and reads them back in
+//! the parent.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use runtime::println;
+use runtime::syscall::{self, Errno, ForkResult, WaitTarget};
+
+runtime::entry!(main);
+
+const NUM_MESSAGES: usize = 5;
+
+fn main() -> Result<(), Errno> {
+    let (reader, writer) = syscall::pipe()?;
+
+    let child = match syscall::fork()? {
+        ForkResult::Child => {
+            syscall::close(reader)?;
+            for i in 0..NUM_MESSAGES {
+                let message = alloc::format!("message {i} from the child\n");
+                syscall::write(writer, message.as_bytes())?;
+            }
+            syscall::exit(0);
+        }
+        ForkResult::Parent { child } => child,
+    };
+
+    // Close our copy of the write end so we see end of file once the child
+    // exits.
+    syscall::close(writer)?;
+    let mut buffer = [0; 64];
+    let mut total = 0;
+    loop {
+        let n = syscall::read(reader, &mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        total += n;
+        runtime::print!(
+            "{}",
+            core::str::from_utf8(&buffer[..n]).unwrap_or("<invalid UTF-8>")
+        );
+    }
+    println!("Read {total} bytes from the pipe");
+
+    if let Some((pid, status)) = syscall::waitpid(WaitTarget::Child(child), true)? {
+        println!("Child {} exited with {:?}", pid.0, status.exit_code());
+    }
+    Ok(())
+}
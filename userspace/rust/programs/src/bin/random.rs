@@ -0,0 +1,64 @@
+//! Prints random bytes from `getrandom` and from `/dev/urandom` as hex.
+
+#![no_std]
+#![no_main]
+
+use runtime::syscall::{self, GetrandomFlags, OpenFlags};
+use runtime::{print, println};
+
+runtime::entry!(main);
+
+fn main() -> i32 {
+    let mut args = runtime::args();
+    let num_bytes = match (args.next(), args.next(), args.next()) {
+        (Some(_), None, None) => 16,
+        (Some(_), Some(n), None) => {
+            let Ok(n) = n.parse::<usize>() else {
+                println!("random: {n:?} is not a number");
+                return 1;
+            };
+            n.min(256)
+        }
+        _ => {
+            println!("Usage: random [num_bytes]");
+            return 1;
+        }
+    };
+
+    let mut buffer = [0; 256];
+    let buffer = &mut buffer[..num_bytes];
+
+    match syscall::getrandom(buffer, GetrandomFlags::NONE) {
+        Ok(n) => print_hex("getrandom", &buffer[..n]),
+        Err(errno) => {
+            println!("random: getrandom failed: {errno}");
+            return 1;
+        }
+    }
+
+    let fd = match syscall::open("/dev/urandom", OpenFlags::READ_ONLY) {
+        Ok(fd) => fd,
+        Err(errno) => {
+            println!("random: failed to open /dev/urandom: {errno}");
+            return 1;
+        }
+    };
+    let result = syscall::read(fd, buffer);
+    let _ = syscall::close(fd);
+    match result {
+        Ok(n) => print_hex("/dev/urandom", &buffer[..n]),
+        Err(errno) => {
+            println!("random: failed to read /dev/urandom: {errno}");
+            return 1;
+        }
+    }
+    0
+}
+
+fn print_hex(source: &str, bytes: &[u8]) {
+    print!("{source}: ");
+    for byte in bytes {
+        print!("{byte:02x}");
+    }
+    println!();
+}
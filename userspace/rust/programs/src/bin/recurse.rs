@@ -0,0 +1,36 @@
+//! Recurses to the given depth, using about 1 KiB of stack per call. This
+//! exercises stack growth, and a big enough depth (like 10000) overflows the
+//! stack.
+
+#![no_std]
+#![no_main]
+
+use runtime::println;
+
+runtime::entry!(main);
+
+fn main() -> i32 {
+    let mut args = runtime::args();
+    let (Some(_), Some(depth), None) = (args.next(), args.next(), args.next()) else {
+        println!("Usage: recurse <depth>");
+        return 1;
+    };
+    let Ok(depth) = depth.parse::<u64>() else {
+        println!("recurse: {depth:?} is not a number");
+        return 1;
+    };
+
+    let sum = recurse(depth);
+    println!("Recursed {depth} times, sum is {sum}");
+    0
+}
+
+fn recurse(depth: u64) -> u64 {
+    // black_box keeps the compiler from optimizing away the frame or turning
+    // this into a loop.
+    let frame = core::hint::black_box([depth; 128]);
+    if depth == 0 {
+        return 0;
+    }
+    frame[usize::try_from(depth % 128).unwrap_or(0)] + core::hint::black_box(recurse(depth - 1))
+}
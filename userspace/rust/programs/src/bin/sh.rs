@@ -0,0 +1,362 @@
+//! A small shell with job control. Each line is a command, which runs
+//! `/bin/<command>` (or the path given) with the rest of the words as
+//! arguments. A trailing `&` runs the command in the background.
+//!
+//! Every job gets its own process group, and the foreground job owns the
+//! terminal, so ^C and ^Z only go to it. ^Z stops the foreground job, and the
+//! `jobs`, `fg`, and `bg` builtins list, resume, and continue jobs like in
+//! other shells. `exit` (or ^D at the prompt) quits.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use runtime::syscall::{
+    self, Errno, Fd, ForkResult, Pid, SetAttrWhen, SignalAction, SignalHandler, SignalSet, Termios,
+    WaitFlags, WaitTarget,
+};
+use runtime::{eprintln, print, println};
+
+runtime::entry!(main);
+
+/// Signals the shell ignores so ^C and ^Z don't affect it, and so it can
+/// take the terminal back from a job. Jobs go back to the default actions.
+const JOB_CONTROL_SIGNALS: [u8; 5] = [
+    syscall::SIGINT,
+    syscall::SIGQUIT,
+    syscall::SIGTSTP,
+    syscall::SIGTTIN,
+    syscall::SIGTTOU,
+];
+
+fn main() -> i32 {
+    let mut shell = match Shell::new() {
+        Ok(shell) => shell,
+        Err(err) => {
+            eprintln!("sh: failed to set up job control: {err}");
+            return 1;
+        }
+    };
+    loop {
+        shell.report_job_changes();
+        print!("$ ");
+        let Some(line) = read_line() else {
+            println!();
+            shell.hang_up_stopped_jobs();
+            return 0;
+        };
+        if let Some(exit_code) = shell.run(&line) {
+            shell.hang_up_stopped_jobs();
+            return exit_code;
+        }
+    }
+}
+
+/// Reads a line from stdin, or returns `None` at the end of input.
+fn read_line() -> Option<String> {
+    let mut line = Vec::new();
+    let mut buffer = [0; 256];
+    loop {
+        match syscall::read(Fd::STDIN, &mut buffer) {
+            Ok(0) if line.is_empty() => return None,
+            Ok(0) => break,
+            Ok(n) => {
+                line.extend_from_slice(&buffer[..n]);
+                if line.ends_with(b"\n") {
+                    break;
+                }
+            }
+            Err(Errno::EINTR) => {}
+            Err(err) => {
+                eprintln!("sh: read failed: {err}");
+                return None;
+            }
+        }
+    }
+    Some(String::from_utf8_lossy(&line).into_owned())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobState {
+    Running,
+    Stopped,
+}
+
+/// A command the shell started. Every job is a single process, which leads
+/// its own process group.
+#[derive(Debug)]
+struct Job {
+    id: usize,
+    pgid: Pid,
+    command: String,
+    state: JobState,
+}
+
+struct Shell {
+    pgid: Pid,
+
+    /// The shell's terminal settings, which we restore whenever we take the
+    /// terminal back from a job. `None` if stdin isn't a terminal, in which
+    /// case there is no job control.
+    termios: Option<Termios>,
+
+    jobs: Vec<Job>,
+}
+
+impl Shell {
+    /// Moves the shell into its own process group and puts that group in the
+    /// foreground.
+    fn new() -> Result<Self, Errno> {
+        for signal in JOB_CONTROL_SIGNALS {
+            set_signal_handler(signal, SignalHandler::Ignore)?;
+        }
+        // Session leaders (like a shell started as init) already lead their
+        // own process group and can't move.
+        match syscall::setpgid(Pid(0), Pid(0)) {
+            Ok(()) | Err(Errno::EPERM) => {}
+            Err(err) => return Err(err),
+        }
+        let pgid = syscall::getpgid(Pid(0))?;
+        let termios = syscall::tcgetattr(Fd::STDIN).ok();
+        if termios.is_some() {
+            syscall::tcsetpgrp(Fd::STDIN, pgid)?;
+        }
+        Ok(Self {
+            pgid,
+            termios,
+            jobs: Vec::new(),
+        })
+    }
+
+    /// Runs a line of input. Returns the exit code if the shell should exit.
+    fn run(&mut self, line: &str) -> Option<i32> {
+        let mut line = line.trim();
+        let background = line.ends_with('&');
+        if background {
+            line = line[..line.len() - 1].trim_end();
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let &command = words.first()?;
+
+        match command {
+            "exit" => {
+                let exit_code = words.get(1).map_or(Ok(0), |code| code.parse());
+                match exit_code {
+                    Ok(exit_code) => return Some(exit_code),
+                    Err(_) => eprintln!("sh: exit: invalid exit code"),
+                }
+            }
+            "jobs" => {
+                for job in &self.jobs {
+                    println!("[{}] {:?}\t{}", job.id, job.state, job.command);
+                }
+            }
+            "fg" => {
+                if let Some(index) = self.find_job(words.get(1).copied()) {
+                    println!("{}", self.jobs[index].command);
+                    self.continue_job(index, false);
+                }
+            }
+            "bg" => {
+                if let Some(index) = self.find_job(words.get(1).copied()) {
+                    let job = &self.jobs[index];
+                    println!("[{}] {} &", job.id, job.command);
+                    self.continue_job(index, true);
+                }
+            }
+            _ => self.start_job(line, &words, background),
+        }
+        None
+    }
+
+    /// Finds the job for `fg` or `bg`, given as `%<id>` or `<id>`. Without an
+    /// argument, picks the most recent job.
+    fn find_job(&self, arg: Option<&str>) -> Option<usize> {
+        let index = match arg {
+            None => self.jobs.len().checked_sub(1),
+            Some(arg) => arg
+                .trim_start_matches('%')
+                .parse()
+                .ok()
+                .and_then(|id| self.jobs.iter().position(|job| job.id == id)),
+        };
+        if index.is_none() {
+            eprintln!("sh: no such job");
+        }
+        index
+    }
+
+    fn start_job(&mut self, line: &str, words: &[&str], background: bool) {
+        let foreground = !background && self.termios.is_some();
+        let pid = match syscall::fork() {
+            Ok(ForkResult::Child) => run_job(words, foreground),
+            Ok(ForkResult::Parent { child }) => child,
+            Err(err) => {
+                eprintln!("sh: fork failed: {err}");
+                return;
+            }
+        };
+
+        // The child does this too, since we don't know which of us runs
+        // first. This fails if the child already called exec, but then it has
+        // already moved itself.
+        let _ = syscall::setpgid(pid, pid);
+        let id = self.jobs.last().map_or(1, |job| job.id + 1);
+        self.jobs.push(Job {
+            id,
+            pgid: pid,
+            command: String::from(line),
+            state: JobState::Running,
+        });
+        if background {
+            println!("[{id}] {}", pid.0);
+        } else {
+            self.wait_for_job(self.jobs.len() - 1);
+        }
+    }
+
+    /// Sends SIGCONT to a job, and waits for it if it goes in the foreground.
+    fn continue_job(&mut self, index: usize, background: bool) {
+        let pgid = self.jobs[index].pgid;
+        if !background {
+            self.give_terminal_to(pgid);
+        }
+        if let Err(err) = syscall::kill_process_group(pgid, syscall::SIGCONT) {
+            eprintln!("sh: failed to continue job {}: {err}", self.jobs[index].id);
+        }
+        self.jobs[index].state = JobState::Running;
+        if !background {
+            self.wait_for_job(index);
+        }
+    }
+
+    /// Waits until a foreground job exits or stops, and then takes the
+    /// terminal back.
+    fn wait_for_job(&mut self, index: usize) {
+        let pgid = self.jobs[index].pgid;
+        self.give_terminal_to(pgid);
+        loop {
+            match syscall::waitpid_flags(WaitTarget::Child(pgid), WaitFlags::UNTRACED) {
+                Ok(Some((_, status))) => {
+                    if status.stopped_signal().is_some() {
+                        let job = &mut self.jobs[index];
+                        job.state = JobState::Stopped;
+                        println!("\n[{}] Stopped\t{}", job.id, job.command);
+                    } else {
+                        match status.signal() {
+                            // The terminal already echoed ^C, so just end the
+                            // line.
+                            Some(syscall::SIGINT) => println!(),
+                            Some(signal) => println!("Killed by signal {signal}"),
+                            None => {}
+                        }
+                        self.jobs.remove(index);
+                    }
+                    break;
+                }
+                Ok(None) | Err(Errno::EINTR) => {}
+                Err(err) => {
+                    eprintln!("sh: waitpid failed: {err}");
+                    self.jobs.remove(index);
+                    break;
+                }
+            }
+        }
+        self.give_terminal_to(self.pgid);
+    }
+
+    /// Puts `pgid` in the foreground. When the shell takes the terminal
+    /// back, its settings are restored too, in case the job changed them.
+    fn give_terminal_to(&self, pgid: Pid) {
+        let Some(termios) = &self.termios else {
+            return;
+        };
+        if let Err(err) = syscall::tcsetpgrp(Fd::STDIN, pgid) {
+            eprintln!("sh: tcsetpgrp failed: {err}");
+        }
+        if pgid == self.pgid {
+            let _ = syscall::tcsetattr(Fd::STDIN, SetAttrWhen::Drain, termios);
+        }
+    }
+
+    /// Reports background jobs that exited, stopped, or continued since the
+    /// last prompt.
+    fn report_job_changes(&mut self) {
+        let flags = WaitFlags::NO_HANG | WaitFlags::UNTRACED | WaitFlags::CONTINUED;
+        while let Ok(Some((pid, status))) = syscall::waitpid_flags(WaitTarget::AnyChild, flags) {
+            let Some(index) = self.jobs.iter().position(|job| job.pgid == pid) else {
+                continue;
+            };
+            let job = &mut self.jobs[index];
+            if status.stopped_signal().is_some() {
+                job.state = JobState::Stopped;
+                println!("[{}] Stopped\t{}", job.id, job.command);
+            } else if status.continued() {
+                job.state = JobState::Running;
+            } else {
+                let result = status.signal().map_or_else(
+                    || String::from("Done"),
+                    |signal| format!("Killed by signal {signal}"),
+                );
+                println!("[{}] {result}\t{}", job.id, job.command);
+                self.jobs.remove(index);
+            }
+        }
+    }
+
+    /// Stopped jobs would never run again once the shell is gone, so hang
+    /// them up before exiting. (Linux sends SIGHUP and SIGCONT to orphaned
+    /// process groups with stopped processes itself, but we don't yet.)
+    fn hang_up_stopped_jobs(&self) {
+        for job in &self.jobs {
+            if job.state == JobState::Stopped {
+                let _ = syscall::kill_process_group(job.pgid, syscall::SIGHUP);
+                let _ = syscall::kill_process_group(job.pgid, syscall::SIGCONT);
+            }
+        }
+    }
+}
+
+/// Runs in the forked child: moves into a new process group (in the
+/// foreground if requested), restores the default signal actions, and execs
+/// the command.
+fn run_job(words: &[&str], foreground: bool) -> ! {
+    let _ = syscall::setpgid(Pid(0), Pid(0));
+    if foreground {
+        // We still ignore SIGTTOU here, so this works from the background.
+        if let Ok(pgid) = syscall::getpgid(Pid(0)) {
+            let _ = syscall::tcsetpgrp(Fd::STDIN, pgid);
+        }
+    }
+    for signal in JOB_CONTROL_SIGNALS {
+        let _ = set_signal_handler(signal, SignalHandler::Default);
+    }
+
+    let command = words[0];
+    let path = if command.contains('/') {
+        String::from(command)
+    } else {
+        format!("/bin/{command}")
+    };
+    let env: Vec<_> = runtime::vars()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+    let env: Vec<&str> = env.iter().map(|var| var.as_str()).collect();
+    let err = syscall::exec(&path, words, &env);
+    eprintln!("sh: {command}: {err}");
+    syscall::exit(127);
+}
+
+fn set_signal_handler(signal: u8, handler: SignalHandler) -> Result<(), Errno> {
+    let action = SignalAction {
+        handler,
+        flags: 0,
+        mask: SignalSet::empty(),
+    };
+    syscall::sigaction(signal, action).map(|_| ())
+}
@@ -0,0 +1,48 @@
+//! Prints the primes up to n using the sieve of Eratosthenes. This exercises
+//! the heap, since the sieve is a `Vec`.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use runtime::println;
+
+runtime::entry!(main);
+
+fn main() -> i32 {
+    let mut args = runtime::args();
+    let (Some(_), Some(n), None) = (args.next(), args.next(), args.next()) else {
+        println!("Usage: sieve <n>");
+        return 1;
+    };
+    let Ok(n) = n.parse::<usize>() else {
+        println!("sieve: {n:?} is not a number");
+        return 1;
+    };
+
+    let primes = sieve(n);
+    println!("There are {} primes up to {n}", primes.len());
+    if let Some(largest) = primes.last() {
+        println!("The largest is {largest}");
+    }
+    0
+}
+
+fn sieve(n: usize) -> Vec<usize> {
+    let mut is_prime = vec![true; n + 1];
+    let mut primes = Vec::new();
+    for i in 2..=n {
+        if !is_prime[i] {
+            continue;
+        }
+        primes.push(i);
+        for multiple in (i * i..=n).step_by(i) {
+            is_prime[multiple] = false;
+        }
+    }
+    primes
+}
@@ -0,0 +1,108 @@
+//! Starts threads that increment a shared counter. Each thread finds its ID
+//! in a thread-local block that its FS base points to. The main thread waits
+//! for them on a futex.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use runtime::println;
+use runtime::syscall::{self, Errno, WaitTarget};
+
+runtime::entry!(main);
+
+const NUM_THREADS: u64 = 4;
+const INCREMENTS_PER_THREAD: u64 = 100_000;
+const STACK_SIZE: usize = 16 * 1024;
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// How many threads are done. The main thread waits on this with a futex.
+static FINISHED: AtomicU32 = AtomicU32::new(0);
+
+/// Thread-local data. Like the x86_64 TLS ABI, the first word points to the
+/// block itself.
+#[repr(C)]
+struct ThreadBlock {
+    self_ptr: *const ThreadBlock,
+    id: u64,
+}
+
+impl ThreadBlock {
+    /// Allocates a block that lives forever, and returns its address.
+    fn leak(id: u64) -> usize {
+        let block = Box::leak(Box::new(Self {
+            self_ptr: core::ptr::null(),
+            id,
+        }));
+        block.self_ptr = core::ptr::addr_of!(*block);
+        block.self_ptr as usize
+    }
+}
+
+/// Reads the current thread's ID from its `ThreadBlock`.
+fn thread_id() -> u64 {
+    let id: u64;
+    unsafe {
+        asm!(
+            "mov {}, fs:[8]",
+            out(reg) id,
+            options(nostack, readonly, preserves_flags),
+        );
+    }
+    id
+}
+
+extern "C" fn thread_main(_arg: usize) -> ! {
+    for _ in 0..INCREMENTS_PER_THREAD {
+        COUNTER.fetch_add(1, Ordering::Relaxed);
+    }
+    println!("Thread {} done", thread_id());
+    FINISHED.fetch_add(1, Ordering::SeqCst);
+    let _ = syscall::futex_wake(&FINISHED, 1);
+    syscall::exit(0);
+}
+
+fn main() -> Result<(), Errno> {
+    let main_block = ThreadBlock::leak(0);
+    syscall::set_fs_base(main_block)?;
+    assert_eq!(syscall::fs_base()?, main_block);
+
+    let mut threads = Vec::new();
+    for id in 1..=NUM_THREADS {
+        // Threads outlive the stack's owner as far as Rust knows, so leak it.
+        let stack = Box::leak(alloc::vec![0u8; STACK_SIZE].into_boxed_slice());
+        let stack_top = stack.as_mut_ptr_range().end;
+        let pid =
+            unsafe { syscall::clone_thread(stack_top, ThreadBlock::leak(id), thread_main, 0)? };
+        threads.push(pid);
+    }
+
+    loop {
+        let finished = FINISHED.load(Ordering::SeqCst);
+        if u64::from(finished) == NUM_THREADS {
+            break;
+        }
+        match syscall::futex_wait(&FINISHED, finished, None) {
+            Ok(()) | Err(Errno::EAGAIN) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    println!(
+        "Thread {} saw the counter reach {} (expected {})",
+        thread_id(),
+        COUNTER.load(Ordering::Relaxed),
+        NUM_THREADS * INCREMENTS_PER_THREAD
+    );
+
+    for pid in threads {
+        syscall::waitpid(WaitTarget::Child(pid), true)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+
+use linked_list_allocator::LockedHeap;
+
+use crate::syscall;
+
+/// How much to grow the heap by at a time, at minimum.
+const HEAP_GROWTH: usize = 64 * 1024;
+
+/// A linked list allocator whose heap grows with `brk` whenever an
+/// allocation doesn't fit.
+struct BrkAllocator {
+    heap: LockedHeap,
+}
+
+#[global_allocator]
+static ALLOCATOR: BrkAllocator = BrkAllocator {
+    heap: LockedHeap::empty(),
+};
+
+unsafe impl GlobalAlloc for BrkAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        if let Ok(ptr) = heap.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        // Grow the heap by enough to fit the allocation even if the new
+        // memory doesn't join a free block at the end of the heap.
+        let growth = (layout.size() + layout.align()).max(HEAP_GROWTH);
+        let old_end = if heap.size() == 0 {
+            syscall::brk(0)
+        } else {
+            heap.top() as usize
+        };
+        let Some(new_end) = old_end.checked_add(growth) else {
+            return core::ptr::null_mut();
+        };
+        if syscall::brk(new_end) != new_end {
+            return core::ptr::null_mut();
+        }
+        if heap.size() == 0 {
+            heap.init(old_end as *mut u8, growth);
+        } else {
+            heap.extend(growth);
+        }
+
+        heap.allocate_first_fit(layout)
+            .map_or(core::ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            self.heap.lock().deallocate(ptr, layout);
+        }
+    }
+}
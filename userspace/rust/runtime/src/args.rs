@@ -0,0 +1,96 @@
+use core::ffi::{c_char, CStr};
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+static ARGC: AtomicUsize = AtomicUsize::new(0);
+static ARGV: AtomicPtr<*const c_char> = AtomicPtr::new(core::ptr::null_mut());
+static ENVP: AtomicPtr<*const c_char> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Records argc, argv, and envp from the initial stack. `stack` points at
+/// argc, which is followed by the NULL-terminated argv and envp arrays.
+pub(crate) unsafe fn init(stack: *const u64) {
+    let argc = *stack as usize;
+    let argv = stack.add(1).cast::<*const c_char>();
+    let envp = argv.add(argc + 1);
+    ARGC.store(argc, Ordering::Relaxed);
+    ARGV.store(argv.cast_mut(), Ordering::Relaxed);
+    ENVP.store(envp.cast_mut(), Ordering::Relaxed);
+}
+
+/// Converts a string from the initial stack. The kernel only accepts UTF-8
+/// arguments and environment variables, so invalid strings become "".
+unsafe fn stack_str(ptr: *const c_char) -> &'static str {
+    CStr::from_ptr(ptr).to_str().unwrap_or_default()
+}
+
+/// Returns an iterator over the program's arguments, starting with the
+/// program name.
+pub fn args() -> Args {
+    Args {
+        next: 0,
+        len: ARGC.load(Ordering::Relaxed),
+    }
+}
+
+/// Iterator returned by `args()`.
+#[derive(Debug, Clone)]
+pub struct Args {
+    next: usize,
+    len: usize,
+}
+
+impl Iterator for Args {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.len {
+            return None;
+        }
+        let argv = ARGV.load(Ordering::Relaxed);
+        let arg = unsafe { stack_str(*argv.add(self.next)) };
+        self.next += 1;
+        Some(arg)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.len - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Args {}
+
+/// Returns an iterator over the program's environment variables as `(key,
+/// value)` pairs. Entries without an `=` have an empty value.
+pub fn vars() -> Vars {
+    Vars {
+        next: ENVP.load(Ordering::Relaxed),
+    }
+}
+
+/// Iterator returned by `vars()`.
+#[derive(Debug, Clone)]
+pub struct Vars {
+    next: *const *const c_char,
+}
+
+impl Iterator for Vars {
+    type Item = (&'static str, &'static str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next.is_null() {
+            return None;
+        }
+        let var = unsafe { *self.next };
+        if var.is_null() {
+            return None;
+        }
+        self.next = unsafe { self.next.add(1) };
+        let var = unsafe { stack_str(var) };
+        Some(var.split_once('=').unwrap_or((var, "")))
+    }
+}
+
+/// Returns the value of the environment variable `key`, if it is set.
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find(|(k, _)| *k == key).map(|(_, v)| v)
+}
@@ -0,0 +1,56 @@
+//! A minimal runtime for Rust programs running on rust-os.
+//!
+//! Programs are `#![no_std]` and `#![no_main]` binaries that declare their
+//! entry point with `runtime::entry!`. The runtime provides `_start`, which
+//! records argv and envp from the initial stack and calls the program's main
+//! function, as well as safe wrappers around every syscall, `print!` and
+//! `println!` (and `eprint!` and `eprintln!` for stderr), a global allocator
+//! backed by `brk`, and a panic handler that prints the panic and exits.
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! use runtime::println;
+//!
+//! runtime::entry!(main);
+//!
+//! fn main() {
+//!     println!("Hello from Rust!");
+//! }
+//! ```
+
+#![no_std]
+#![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cargo_common_metadata,
+    clippy::missing_const_for_fn,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::module_name_repetitions,
+    clippy::must_use_candidate,
+    clippy::redundant_pub_crate,
+    clippy::similar_names
+)]
+
+extern crate alloc;
+
+mod allocator;
+mod args;
+mod print;
+mod start;
+pub mod syscall;
+
+pub use args::*;
+pub use print::*;
+pub use start::*;
+
+/// Exit code used when a program panics. This matches Rust's standard library.
+const PANIC_EXIT_CODE: i32 = 101;
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    eprintln!("{info}");
+    syscall::exit(PANIC_EXIT_CODE)
+}
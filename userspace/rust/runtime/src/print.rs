@@ -0,0 +1,110 @@
+use core::fmt::{self, Write};
+
+use crate::syscall::{self, Fd};
+
+/// Prints to stdout.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::print_args($crate::syscall::Fd::STDOUT, format_args!($($arg)*)));
+}
+
+/// Prints to stdout, with a newline.
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Prints to stderr.
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::print_args($crate::syscall::Fd::STDERR, format_args!($($arg)*)));
+}
+
+/// Prints to stderr, with a newline.
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn print_args(fd: Fd, args: fmt::Arguments) {
+    let mut buffer = PrintBuffer::new(fd);
+    // Writing to a PrintBuffer never fails, but formatting implementations
+    // can. There is nothing useful to do about that here.
+    let _ = buffer.write_fmt(args);
+    buffer.flush();
+}
+
+const PRINT_BUFFER_SIZE: usize = 256;
+
+/// Collects formatted output so we make one write syscall per line instead of
+/// one per formatted piece. Output is flushed at each newline, like a line
+/// buffered stdout in C.
+struct PrintBuffer {
+    fd: Fd,
+    bytes: [u8; PRINT_BUFFER_SIZE],
+    len: usize,
+}
+
+impl PrintBuffer {
+    fn new(fd: Fd) -> Self {
+        Self {
+            fd,
+            bytes: [0; PRINT_BUFFER_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Writes out the buffer. If the file descriptor isn't open (or the write
+    /// fails for some other reason), falls back to the kernel log so the
+    /// output isn't lost.
+    fn flush(&mut self) {
+        let mut data = &self.bytes[..self.len];
+        while !data.is_empty() {
+            match syscall::write(self.fd, data) {
+                Ok(n) if n > 0 => data = &data[n..],
+                _ => {
+                    // We only ever split strings at char boundaries, so this
+                    // is valid UTF-8 unless a write stopped in the middle of a
+                    // char.
+                    let s = core::str::from_utf8(data).unwrap_or_default();
+                    let _ = syscall::print(s);
+                    break;
+                }
+            }
+        }
+        self.len = 0;
+    }
+
+    fn push_str(&mut self, s: &str) {
+        let mut s = s;
+        while !s.is_empty() {
+            let space = PRINT_BUFFER_SIZE - self.len;
+            let mut len = s.len().min(space);
+            while !s.is_char_boundary(len) {
+                len -= 1;
+            }
+            if len == 0 {
+                self.flush();
+                continue;
+            }
+            self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+            self.len += len;
+            s = &s[len..];
+        }
+    }
+}
+
+impl Write for PrintBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for line in s.split_inclusive('\n') {
+            self.push_str(line);
+            if line.ends_with('\n') {
+                self.flush();
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use core::arch::global_asm;
+
+use crate::args;
+use crate::syscall;
+
+global_asm!(
+    // The kernel jumps here with rsp pointing at argc, followed by argv and
+    // envp. See "3.4 Process Initialization" in the System V AMD64 ABI. We
+    // pass that pointer to `start_rust`, clearing rbp so stack traces stop
+    // here and making sure the stack is aligned before the call.
+    ".global _start",
+    "_start:",
+    "xor rbp, rbp",
+    "mov rdi, rsp",
+    "and rsp, -16",
+    "call {start_rust}",
+    "ud2",
+    start_rust = sym start_rust,
+);
+
+extern "Rust" {
+    /// Defined by `entry!` in the program.
+    fn __runtime_main() -> i32;
+}
+
+/// Declares the program's main function. `main` can return `()`, an `i32`
+/// exit code, or a `Result` whose error is printed before exiting with 1.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[no_mangle]
+        fn __runtime_main() -> i32 {
+            let main: fn() -> _ = $main;
+            $crate::Termination::report(main())
+        }
+    };
+}
+
+unsafe extern "C" fn start_rust(stack: *const u64) -> ! {
+    args::init(stack);
+    let exit_code = __runtime_main();
+    syscall::exit(exit_code)
+}
+
+/// Return types allowed for a program's main function.
+pub trait Termination {
+    /// Converts the return value to an exit code.
+    fn report(self) -> i32;
+}
+
+impl Termination for () {
+    fn report(self) -> i32 {
+        0
+    }
+}
+
+impl Termination for i32 {
+    fn report(self) -> i32 {
+        self
+    }
+}
+
+impl<T: Termination, E: core::fmt::Debug> Termination for Result<T, E> {
+    fn report(self) -> i32 {
+        match self {
+            Ok(value) => value.report(),
+            Err(err) => {
+                crate::println!("Error: {err:?}");
+                1
+            }
+        }
+    }
+}
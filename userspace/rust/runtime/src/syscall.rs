@@ -0,0 +1,1098 @@
+//! Safe wrappers around rust-os syscalls.
+//!
+//! The syscall number goes in rdi, and the arguments go in rsi, rdx, r10, r8,
+//! r9, and rax. The result comes back in rax, where values from -4095 to -1
+//! are negated errno values. The numbers here must match `SYSCALL_HANDLERS`
+//! in the kernel.
+
+use alloc::ffi::CString;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::AtomicU32;
+use core::time::Duration;
+
+const SYS_EXIT: u64 = 0;
+const SYS_PRINT: u64 = 1;
+const SYS_OPEN: u64 = 2;
+const SYS_READ: u64 = 3;
+const SYS_WRITE: u64 = 4;
+const SYS_CLOSE: u64 = 5;
+const SYS_LSEEK: u64 = 6;
+const SYS_FORK: u64 = 7;
+const SYS_EXEC: u64 = 8;
+const SYS_WAITPID: u64 = 9;
+const SYS_BRK: u64 = 10;
+const SYS_MMAP: u64 = 11;
+const SYS_MUNMAP: u64 = 12;
+const SYS_KILL: u64 = 13;
+const SYS_SIGACTION: u64 = 14;
+const SYS_SIGPROCMASK: u64 = 15;
+const SYS_PIPE: u64 = 18;
+const SYS_CLONE: u64 = 19;
+const SYS_ARCH_PRCTL: u64 = 20;
+const SYS_FUTEX: u64 = 21;
+const SYS_CLOCK_GETTIME: u64 = 22;
+const SYS_NANOSLEEP: u64 = 23;
+const SYS_GETRANDOM: u64 = 24;
+const SYS_IOCTL: u64 = 25;
+const SYS_SETPGID: u64 = 26;
+const SYS_GETPGID: u64 = 27;
+const SYS_SETSID: u64 = 28;
+const SYS_GETSID: u64 = 29;
+const SYS_STAT: u64 = 30;
+const SYS_FSTAT: u64 = 31;
+const SYS_GETDENTS: u64 = 32;
+
+/// An error returned by a syscall.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub u16);
+
+impl Errno {
+    pub const EPERM: Self = Self(1);
+    pub const ENOENT: Self = Self(2);
+    pub const ESRCH: Self = Self(3);
+    pub const EINTR: Self = Self(4);
+    pub const EIO: Self = Self(5);
+    pub const E2BIG: Self = Self(7);
+    pub const ENOEXEC: Self = Self(8);
+    pub const EBADF: Self = Self(9);
+    pub const ECHILD: Self = Self(10);
+    pub const EAGAIN: Self = Self(11);
+    pub const ENOMEM: Self = Self(12);
+    pub const EACCES: Self = Self(13);
+    pub const EFAULT: Self = Self(14);
+    pub const ENOTDIR: Self = Self(20);
+    pub const EISDIR: Self = Self(21);
+    pub const EINVAL: Self = Self(22);
+    pub const EMFILE: Self = Self(24);
+    pub const ENOTTY: Self = Self(25);
+    pub const EFBIG: Self = Self(27);
+    pub const ENOSPC: Self = Self(28);
+    pub const ESPIPE: Self = Self(29);
+    pub const EPIPE: Self = Self(32);
+    pub const ENOSYS: Self = Self(38);
+    pub const ETIMEDOUT: Self = Self(110);
+
+    /// Returns the symbolic name of the error, like `"ENOENT"`.
+    pub fn name(self) -> Option<&'static str> {
+        let name = match self {
+            Self::EPERM => "EPERM",
+            Self::ENOENT => "ENOENT",
+            Self::ESRCH => "ESRCH",
+            Self::EINTR => "EINTR",
+            Self::EIO => "EIO",
+            Self::E2BIG => "E2BIG",
+            Self::ENOEXEC => "ENOEXEC",
+            Self::EBADF => "EBADF",
+            Self::ECHILD => "ECHILD",
+            Self::EAGAIN => "EAGAIN",
+            Self::ENOMEM => "ENOMEM",
+            Self::EACCES => "EACCES",
+            Self::EFAULT => "EFAULT",
+            Self::ENOTDIR => "ENOTDIR",
+            Self::EISDIR => "EISDIR",
+            Self::EINVAL => "EINVAL",
+            Self::EMFILE => "EMFILE",
+            Self::ENOTTY => "ENOTTY",
+            Self::EFBIG => "EFBIG",
+            Self::ENOSPC => "ENOSPC",
+            Self::ESPIPE => "ESPIPE",
+            Self::EPIPE => "EPIPE",
+            Self::ENOSYS => "ENOSYS",
+            Self::ETIMEDOUT => "ETIMEDOUT",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
+impl fmt::Debug for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "Errno({})", self.0),
+        }
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Makes a raw syscall with up to six arguments.
+///
+/// # Safety
+///
+/// The arguments must be valid for the given syscall. In particular, any
+/// pointers must point to memory the syscall is allowed to read or write.
+#[inline]
+pub unsafe fn syscall6(num: u64, args: [u64; 6]) -> u64 {
+    let ret: u64;
+    asm!(
+        "syscall",
+        in("rdi") num,
+        in("rsi") args[0],
+        in("rdx") args[1],
+        in("r10") args[2],
+        in("r8") args[3],
+        in("r9") args[4],
+        inlateout("rax") args[5] => ret,
+        // syscall stores the return address in rcx and rflags in r11.
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack),
+    );
+    ret
+}
+
+/// Converts a raw syscall return value to a `Result`.
+fn check(ret: u64) -> Result<u64, Errno> {
+    if ret > u64::MAX - 4095 {
+        Err(Errno(ret.wrapping_neg() as u16))
+    } else {
+        Ok(ret)
+    }
+}
+
+unsafe fn syscall(num: u64, args: &[u64]) -> Result<u64, Errno> {
+    let mut all_args = [0; 6];
+    all_args[..args.len()].copy_from_slice(args);
+    check(syscall6(num, all_args))
+}
+
+/// Exits the current process with the given exit code.
+pub fn exit(code: i32) -> ! {
+    #[allow(clippy::cast_sign_loss)]
+    unsafe {
+        let _ = syscall(SYS_EXIT, &[code as u64]);
+    }
+    unreachable!("exit syscall returned");
+}
+
+/// Prints a string to the kernel log.
+pub fn print(s: &str) -> Result<usize, Errno> {
+    unsafe { syscall(SYS_PRINT, &[s.as_ptr() as u64, s.len() as u64]).map(|n| n as usize) }
+}
+
+/// A file descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fd(pub u32);
+
+impl Fd {
+    pub const STDIN: Self = Self(0);
+    pub const STDOUT: Self = Self(1);
+    pub const STDERR: Self = Self(2);
+}
+
+/// Flags for `open`. Combine them with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(pub u32);
+
+impl OpenFlags {
+    pub const READ_ONLY: Self = Self(0);
+    pub const WRITE_ONLY: Self = Self(0o1);
+    pub const READ_WRITE: Self = Self(0o2);
+    pub const CREATE: Self = Self(0o100);
+    pub const TRUNCATE: Self = Self(0o1000);
+    pub const APPEND: Self = Self(0o2000);
+    pub const DIRECTORY: Self = Self(0o200_000);
+}
+
+impl core::ops::BitOr for OpenFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Opens the file at `path`.
+pub fn open(path: &str, flags: OpenFlags) -> Result<Fd, Errno> {
+    let args = [path.as_ptr() as u64, path.len() as u64, u64::from(flags.0)];
+    unsafe { syscall(SYS_OPEN, &args).map(|fd| Fd(fd as u32)) }
+}
+
+/// Reads into `buffer`, returning the number of bytes read. Returns 0 at the
+/// end of the file.
+pub fn read(fd: Fd, buffer: &mut [u8]) -> Result<usize, Errno> {
+    let args = [
+        u64::from(fd.0),
+        buffer.as_mut_ptr() as u64,
+        buffer.len() as u64,
+    ];
+    unsafe { syscall(SYS_READ, &args).map(|n| n as usize) }
+}
+
+/// Writes `data`, returning the number of bytes written.
+pub fn write(fd: Fd, data: &[u8]) -> Result<usize, Errno> {
+    let args = [u64::from(fd.0), data.as_ptr() as u64, data.len() as u64];
+    unsafe { syscall(SYS_WRITE, &args).map(|n| n as usize) }
+}
+
+/// Closes a file descriptor.
+pub fn close(fd: Fd) -> Result<(), Errno> {
+    unsafe { syscall(SYS_CLOSE, &[u64::from(fd.0)]).map(|_| ()) }
+}
+
+/// Where `lseek` measures its offset from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekWhence {
+    Start = 0,
+    Current = 1,
+    End = 2,
+}
+
+/// Moves a file's offset, returning the new offset from the start.
+pub fn lseek(fd: Fd, offset: i64, whence: SeekWhence) -> Result<u64, Errno> {
+    #[allow(clippy::cast_sign_loss)]
+    let args = [u64::from(fd.0), offset as u64, whence as u64];
+    unsafe { syscall(SYS_LSEEK, &args) }
+}
+
+/// File metadata from `stat` or `fstat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stat {
+    /// File type and permissions. See the `S_*` constants.
+    pub mode: u32,
+    pub ino: u64,
+    pub nlink: u64,
+    pub uid: u32,
+    pub gid: u32,
+    /// Size in bytes.
+    pub size: u64,
+    /// Number of 512 byte blocks allocated for the file.
+    pub blocks: u64,
+    /// Time since the Unix epoch of the last access.
+    pub atime: Duration,
+    /// Time since the Unix epoch of the last change to the contents.
+    pub mtime: Duration,
+    /// Time since the Unix epoch of the last change to the metadata.
+    pub ctime: Duration,
+}
+
+impl Stat {
+    /// Mask for the file type bits of `mode`.
+    pub const S_IFMT: u32 = 0o170_000;
+    pub const S_IFIFO: u32 = 0o010_000;
+    pub const S_IFCHR: u32 = 0o020_000;
+    pub const S_IFDIR: u32 = 0o040_000;
+    pub const S_IFREG: u32 = 0o100_000;
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & Self::S_IFMT == Self::S_IFDIR
+    }
+
+    /// The permission bits of `mode`, like `0o755`.
+    pub fn permissions(&self) -> u32 {
+        self.mode & 0o7777
+    }
+}
+
+/// The kernel's `struct stat`.
+#[repr(C)]
+#[derive(Default)]
+struct KernelStat {
+    dev: u64,
+    ino: u64,
+    nlink: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    padding: u32,
+    rdev: u64,
+    size: u64,
+    blksize: u64,
+    blocks: u64,
+    atime: Timespec,
+    mtime: Timespec,
+    ctime: Timespec,
+    reserved: [u64; 3],
+}
+
+impl From<KernelStat> for Stat {
+    fn from(stat: KernelStat) -> Self {
+        Self {
+            mode: stat.mode,
+            ino: stat.ino,
+            nlink: stat.nlink,
+            uid: stat.uid,
+            gid: stat.gid,
+            size: stat.size,
+            blocks: stat.blocks,
+            atime: stat.atime.into(),
+            mtime: stat.mtime.into(),
+            ctime: stat.ctime.into(),
+        }
+    }
+}
+
+/// Returns the metadata of the file at `path`.
+pub fn stat(path: &str) -> Result<Stat, Errno> {
+    let mut stat = KernelStat::default();
+    let args = [
+        path.as_ptr() as u64,
+        path.len() as u64,
+        core::ptr::addr_of_mut!(stat) as u64,
+    ];
+    unsafe { syscall(SYS_STAT, &args)? };
+    Ok(stat.into())
+}
+
+/// Returns the metadata of the file `fd` refers to.
+pub fn fstat(fd: Fd) -> Result<Stat, Errno> {
+    let mut stat = KernelStat::default();
+    let args = [u64::from(fd.0), core::ptr::addr_of_mut!(stat) as u64];
+    unsafe { syscall(SYS_FSTAT, &args)? };
+    Ok(stat.into())
+}
+
+/// Reads entries from the open directory `fd` into `buffer`, returning the
+/// number of bytes written, or 0 once every entry has been read.
+///
+/// The entries are the kernel's `struct linux_dirent64` records. Most
+/// programs want `read_dir` instead.
+pub fn getdents(fd: Fd, buffer: &mut [u8]) -> Result<usize, Errno> {
+    let args = [
+        u64::from(fd.0),
+        buffer.as_mut_ptr() as u64,
+        buffer.len() as u64,
+    ];
+    unsafe { syscall(SYS_GETDENTS, &args).map(|n| n as usize) }
+}
+
+/// An entry in a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub ino: u64,
+    /// One of the `DT_*` constants.
+    pub file_type: u8,
+    pub name: String,
+}
+
+impl DirEntry {
+    pub const DT_DIR: u8 = 4;
+    pub const DT_REG: u8 = 8;
+}
+
+/// Size of a `struct linux_dirent64` without the name.
+const DIRENT_HEADER_LEN: usize = 19;
+
+/// Lists the entries of the directory at `path`.
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, Errno> {
+    let fd = open(path, OpenFlags::DIRECTORY)?;
+    let result = read_dir_entries(fd);
+    close(fd)?;
+    result
+}
+
+fn read_dir_entries(fd: Fd) -> Result<Vec<DirEntry>, Errno> {
+    let mut entries = Vec::new();
+    let mut buffer = [0; 1024];
+    loop {
+        let len = getdents(fd, &mut buffer)?;
+        if len == 0 {
+            return Ok(entries);
+        }
+        let mut records = &buffer[..len];
+        while records.len() >= DIRENT_HEADER_LEN {
+            let mut ino = [0; 8];
+            ino.copy_from_slice(&records[..8]);
+            let record_len = usize::from(u16::from_ne_bytes([records[16], records[17]]));
+            if record_len < DIRENT_HEADER_LEN || record_len > records.len() {
+                return Err(Errno::EIO);
+            }
+            // The name is nul-terminated and padded out to the record length.
+            let name = &records[DIRENT_HEADER_LEN..record_len];
+            let name_len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            entries.push(DirEntry {
+                ino: u64::from_ne_bytes(ino),
+                file_type: records[18],
+                name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+            });
+            records = &records[record_len..];
+        }
+    }
+}
+
+/// A process ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pid(pub u32);
+
+/// Which side of a `fork` we are on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForkResult {
+    Parent { child: Pid },
+    Child,
+}
+
+/// Creates a copy of the current process.
+pub fn fork() -> Result<ForkResult, Errno> {
+    let pid = unsafe { syscall(SYS_FORK, &[])? };
+    if pid == 0 {
+        Ok(ForkResult::Child)
+    } else {
+        Ok(ForkResult::Parent {
+            child: Pid(pid as u32),
+        })
+    }
+}
+
+/// `exec` flag to run the new program with Linux's syscall ABI.
+const EXEC_LINUX_ABI: u64 = 1;
+
+/// Replaces the current program with the one at `path`. Only returns if
+/// there was an error.
+pub fn exec(path: &str, args: &[&str], env: &[&str]) -> Errno {
+    exec_with_flags(path, args, env, 0)
+}
+
+/// Like `exec`, but the new program makes Linux syscalls instead of ours, so
+/// it can be a static binary built for Linux (e.g. with musl).
+pub fn exec_linux(path: &str, args: &[&str], env: &[&str]) -> Errno {
+    exec_with_flags(path, args, env, EXEC_LINUX_ABI)
+}
+
+fn exec_with_flags(path: &str, args: &[&str], env: &[&str], flags: u64) -> Errno {
+    let Some(args) = c_strings(args) else {
+        return Errno::EINVAL;
+    };
+    let Some(env) = c_strings(env) else {
+        return Errno::EINVAL;
+    };
+    let argv = null_terminated_pointers(&args);
+    let envp = null_terminated_pointers(&env);
+    let syscall_args = [
+        path.as_ptr() as u64,
+        path.len() as u64,
+        argv.as_ptr() as u64,
+        envp.as_ptr() as u64,
+        flags,
+    ];
+    match unsafe { syscall(SYS_EXEC, &syscall_args) } {
+        Ok(_) => unreachable!("exec returned successfully"),
+        Err(err) => err,
+    }
+}
+
+/// Returns `None` if any string contains a nul byte.
+fn c_strings(strings: &[&str]) -> Option<Vec<CString>> {
+    strings.iter().map(|s| CString::new(*s).ok()).collect()
+}
+
+fn null_terminated_pointers(strings: &[CString]) -> Vec<u64> {
+    strings
+        .iter()
+        .map(|s| s.as_ptr() as u64)
+        .chain(core::iter::once(0))
+        .collect()
+}
+
+/// Which children `waitpid` waits for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitTarget {
+    AnyChild,
+    Child(Pid),
+}
+
+/// How a child process ended, or how it was stopped or continued, as reported
+/// by `waitpid`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitStatus(pub u32);
+
+impl WaitStatus {
+    const STOPPED: u32 = 0x7f;
+    const CONTINUED: u32 = 0xffff;
+
+    /// Returns the exit code if the child exited normally.
+    pub fn exit_code(self) -> Option<u8> {
+        let signal = self.0 & 0x7f;
+        (signal == 0).then_some((self.0 >> 8) as u8)
+    }
+
+    /// Returns the signal that killed the child, if any.
+    pub fn signal(self) -> Option<u8> {
+        let signal = self.0 & 0x7f;
+        (signal != 0 && signal != Self::STOPPED).then_some(signal as u8)
+    }
+
+    /// Returns the signal that stopped the child, if it was stopped.
+    pub fn stopped_signal(self) -> Option<u8> {
+        (self.0 & 0xff == Self::STOPPED && !self.continued()).then_some((self.0 >> 8) as u8)
+    }
+
+    /// Returns true if the child was continued by SIGCONT.
+    pub fn continued(self) -> bool {
+        self.0 == Self::CONTINUED
+    }
+}
+
+/// Flags for `waitpid_flags`. Combine them with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitFlags(pub u64);
+
+impl WaitFlags {
+    pub const NONE: Self = Self(0);
+    /// Return `Ok(None)` instead of waiting if no child has changed state.
+    pub const NO_HANG: Self = Self(0x1);
+    /// Also report children that were stopped.
+    pub const UNTRACED: Self = Self(0x2);
+    /// Also report stopped children that were continued.
+    pub const CONTINUED: Self = Self(0x8);
+}
+
+impl core::ops::BitOr for WaitFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Waits for a child to exit. If `block` is false and no child has exited
+/// yet, returns `Ok(None)` instead of waiting.
+pub fn waitpid(target: WaitTarget, block: bool) -> Result<Option<(Pid, WaitStatus)>, Errno> {
+    let flags = if block {
+        WaitFlags::NONE
+    } else {
+        WaitFlags::NO_HANG
+    };
+    waitpid_flags(target, flags)
+}
+
+/// Like `waitpid`, but with flags to also wait for children to stop or
+/// continue.
+pub fn waitpid_flags(
+    target: WaitTarget,
+    flags: WaitFlags,
+) -> Result<Option<(Pid, WaitStatus)>, Errno> {
+    let pid = match target {
+        WaitTarget::AnyChild => u64::MAX,
+        WaitTarget::Child(pid) => u64::from(pid.0),
+    };
+    let mut status = 0u32;
+    let args = [pid, core::ptr::addr_of_mut!(status) as u64, flags.0];
+    let pid = unsafe { syscall(SYS_WAITPID, &args)? };
+    Ok((pid != 0).then_some((Pid(pid as u32), WaitStatus(status))))
+}
+
+/// Sets the end of the heap to `addr` and returns the new end. Passing 0
+/// returns the current end without changing it. Like Linux, the kernel
+/// returns the old end instead of an error if it can't move it.
+pub fn brk(addr: usize) -> usize {
+    unsafe { syscall6(SYS_BRK, [addr as u64, 0, 0, 0, 0, 0]) as usize }
+}
+
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
+/// Maps memory and returns its address. Pass `None` for `fd` to make an
+/// anonymous mapping.
+///
+/// # Safety
+///
+/// With `MAP_FIXED`, this replaces any existing mapping in the range, which
+/// must not be memory Rust is using.
+pub unsafe fn mmap(
+    addr: usize,
+    len: usize,
+    prot: u64,
+    flags: u64,
+    fd: Option<Fd>,
+    offset: u64,
+) -> Result<*mut u8, Errno> {
+    let fd = fd.map_or(u64::MAX, |fd| u64::from(fd.0));
+    let args = [addr as u64, len as u64, prot, flags, fd, offset];
+    syscall(SYS_MMAP, &args).map(|addr| addr as *mut u8)
+}
+
+/// Unmaps memory.
+///
+/// # Safety
+///
+/// The range must not be memory Rust is still using.
+pub unsafe fn munmap(addr: *mut u8, len: usize) -> Result<(), Errno> {
+    syscall(SYS_MUNMAP, &[addr as u64, len as u64]).map(|_| ())
+}
+
+pub const SIGHUP: u8 = 1;
+pub const SIGINT: u8 = 2;
+pub const SIGQUIT: u8 = 3;
+pub const SIGKILL: u8 = 9;
+pub const SIGUSR1: u8 = 10;
+pub const SIGSEGV: u8 = 11;
+pub const SIGUSR2: u8 = 12;
+pub const SIGPIPE: u8 = 13;
+pub const SIGALRM: u8 = 14;
+pub const SIGTERM: u8 = 15;
+pub const SIGCHLD: u8 = 17;
+pub const SIGCONT: u8 = 18;
+pub const SIGSTOP: u8 = 19;
+pub const SIGTSTP: u8 = 20;
+pub const SIGTTIN: u8 = 21;
+pub const SIGTTOU: u8 = 22;
+
+/// Sends `signal` to a process. Signal 0 only checks that the process exists.
+pub fn kill(pid: Pid, signal: u8) -> Result<(), Errno> {
+    unsafe { syscall(SYS_KILL, &[u64::from(pid.0), u64::from(signal)]).map(|_| ()) }
+}
+
+/// Sends `signal` to every process in the process group `pgid`.
+pub fn kill_process_group(pgid: Pid, signal: u8) -> Result<(), Errno> {
+    let pid = 0u64.wrapping_sub(u64::from(pgid.0));
+    unsafe { syscall(SYS_KILL, &[pid, u64::from(signal)]).map(|_| ()) }
+}
+
+/// Moves the process `pid` into the process group `pgid`. A `pid` of 0 means
+/// the current process, and a `pgid` of 0 starts a new process group led by
+/// `pid`.
+pub fn setpgid(pid: Pid, pgid: Pid) -> Result<(), Errno> {
+    unsafe { syscall(SYS_SETPGID, &[u64::from(pid.0), u64::from(pgid.0)]).map(|_| ()) }
+}
+
+/// Returns the process group of `pid`, or of the current process if `pid` is
+/// 0.
+pub fn getpgid(pid: Pid) -> Result<Pid, Errno> {
+    unsafe { syscall(SYS_GETPGID, &[u64::from(pid.0)]).map(|pgid| Pid(pgid as u32)) }
+}
+
+/// Starts a new session and process group led by the current process, and
+/// returns the session ID. The new session has no controlling terminal.
+pub fn setsid() -> Result<Pid, Errno> {
+    unsafe { syscall(SYS_SETSID, &[]).map(|sid| Pid(sid as u32)) }
+}
+
+/// Returns the session of `pid`, or of the current process if `pid` is 0.
+pub fn getsid(pid: Pid) -> Result<Pid, Errno> {
+    unsafe { syscall(SYS_GETSID, &[u64::from(pid.0)]).map(|sid| Pid(sid as u32)) }
+}
+
+/// A set of signals, where bit `n - 1` is signal `n`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(transparent)]
+pub struct SignalSet(pub u64);
+
+impl SignalSet {
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    #[must_use]
+    pub fn with(self, signal: u8) -> Self {
+        Self(self.0 | 1 << (signal - 1))
+    }
+
+    pub fn contains(self, signal: u8) -> bool {
+        self.0 & 1 << (signal - 1) != 0
+    }
+}
+
+pub const SA_RESTART: u64 = 0x1000_0000;
+pub const SA_NODEFER: u64 = 0x4000_0000;
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+
+/// What to do when a signal arrives.
+#[derive(Debug, Clone, Copy)]
+pub enum SignalHandler {
+    Default,
+    Ignore,
+    Handler(extern "C" fn(i32)),
+}
+
+/// A signal action for `sigaction`.
+#[derive(Debug, Clone, Copy)]
+pub struct SignalAction {
+    pub handler: SignalHandler,
+    pub flags: u64,
+    pub mask: SignalSet,
+}
+
+/// The kernel's `struct sigaction` layout. The kernel supplies its own
+/// restorer trampoline, so we never set one.
+#[repr(C)]
+#[derive(Default)]
+struct RawSignalAction {
+    handler: u64,
+    flags: u64,
+    restorer: u64,
+    mask: u64,
+}
+
+const SIG_DFL: u64 = 0;
+const SIG_IGN: u64 = 1;
+
+impl From<SignalAction> for RawSignalAction {
+    fn from(action: SignalAction) -> Self {
+        let handler = match action.handler {
+            SignalHandler::Default => SIG_DFL,
+            SignalHandler::Ignore => SIG_IGN,
+            SignalHandler::Handler(handler) => handler as usize as u64,
+        };
+        Self {
+            handler,
+            flags: action.flags,
+            restorer: 0,
+            mask: action.mask.0,
+        }
+    }
+}
+
+impl From<RawSignalAction> for SignalAction {
+    fn from(action: RawSignalAction) -> Self {
+        let handler = match action.handler {
+            SIG_DFL => SignalHandler::Default,
+            SIG_IGN => SignalHandler::Ignore,
+            handler => SignalHandler::Handler(unsafe {
+                core::mem::transmute::<usize, extern "C" fn(i32)>(handler as usize)
+            }),
+        };
+        Self {
+            handler,
+            flags: action.flags,
+            mask: SignalSet(action.mask),
+        }
+    }
+}
+
+/// Sets the action for `signal`, returning the previous action.
+pub fn sigaction(signal: u8, action: SignalAction) -> Result<SignalAction, Errno> {
+    let new = RawSignalAction::from(action);
+    let mut old = RawSignalAction::default();
+    let args = [
+        u64::from(signal),
+        core::ptr::addr_of!(new) as u64,
+        core::ptr::addr_of_mut!(old) as u64,
+    ];
+    unsafe { syscall(SYS_SIGACTION, &args)? };
+    Ok(SignalAction::from(old))
+}
+
+/// How `sigprocmask` changes the blocked signal mask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigprocmaskHow {
+    Block = 0,
+    Unblock = 1,
+    SetMask = 2,
+}
+
+/// Changes the set of blocked signals, returning the previous set.
+pub fn sigprocmask(how: SigprocmaskHow, set: SignalSet) -> Result<SignalSet, Errno> {
+    let mut old = SignalSet::empty();
+    let args = [
+        how as u64,
+        core::ptr::addr_of!(set) as u64,
+        core::ptr::addr_of_mut!(old) as u64,
+    ];
+    unsafe { syscall(SYS_SIGPROCMASK, &args)? };
+    Ok(old)
+}
+
+/// Creates a pipe, returning its read end and write end.
+pub fn pipe() -> Result<(Fd, Fd), Errno> {
+    let mut fds = [0u32; 2];
+    unsafe { syscall(SYS_PIPE, &[fds.as_mut_ptr() as u64])? };
+    Ok((Fd(fds[0]), Fd(fds[1])))
+}
+
+/// Starts a new thread that shares this process's memory.
+///
+/// The thread calls `entry(arg)` on the stack that ends at `stack_top`, with
+/// its FS base set to `fs_base`. `entry` must call `exit` instead of
+/// returning. Returns the thread's ID, which can be passed to `waitpid`.
+///
+/// # Safety
+///
+/// The stack must be writable memory that nothing else uses until the thread
+/// exits.
+pub unsafe fn clone_thread(
+    stack_top: *mut u8,
+    fs_base: usize,
+    entry: extern "C" fn(usize) -> !,
+    arg: usize,
+) -> Result<Pid, Errno> {
+    let ret: u64;
+    asm!(
+        "syscall",
+        "test rax, rax",
+        "jnz 2f",
+        // We are the new thread, on the new stack. The parent's registers
+        // were copied, so entry and arg are still in r13 and r12.
+        "and rsp, -16",
+        "mov rdi, r12",
+        "call r13",
+        "ud2",
+        "2:",
+        in("rdi") SYS_CLONE,
+        in("rsi") stack_top as u64,
+        in("rdx") fs_base as u64,
+        in("r12") arg,
+        in("r13") entry,
+        inlateout("rax") 0u64 => ret,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack),
+    );
+    check(ret).map(|pid| Pid(pid as u32))
+}
+
+const ARCH_SET_FS: u64 = 0x1002;
+const ARCH_GET_FS: u64 = 0x1003;
+
+/// Sets the FS base register, which thread-local storage is addressed from.
+pub fn set_fs_base(addr: usize) -> Result<(), Errno> {
+    unsafe { syscall(SYS_ARCH_PRCTL, &[ARCH_SET_FS, addr as u64]).map(|_| ()) }
+}
+
+/// Returns the FS base register.
+pub fn fs_base() -> Result<usize, Errno> {
+    let mut addr = 0u64;
+    let args = [ARCH_GET_FS, core::ptr::addr_of_mut!(addr) as u64];
+    unsafe { syscall(SYS_ARCH_PRCTL, &args)? };
+    Ok(addr as usize)
+}
+
+const FUTEX_WAIT: u64 = 0;
+const FUTEX_WAKE: u64 = 1;
+
+/// The kernel's `struct timespec`.
+#[repr(C)]
+#[derive(Default)]
+struct Timespec {
+    seconds: u64,
+    nanoseconds: u64,
+}
+
+impl From<Duration> for Timespec {
+    fn from(duration: Duration) -> Self {
+        Self {
+            seconds: duration.as_secs(),
+            nanoseconds: u64::from(duration.subsec_nanos()),
+        }
+    }
+}
+
+impl From<Timespec> for Duration {
+    fn from(timespec: Timespec) -> Self {
+        Self::new(timespec.seconds, timespec.nanoseconds as u32)
+    }
+}
+
+/// Sleeps until `futex_wake` is called on `word`, as long as `word` still
+/// contains `expected`. Fails with `EAGAIN` if it doesn't, and with
+/// `ETIMEDOUT` if `timeout` passes first.
+pub fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) -> Result<(), Errno> {
+    let timeout = timeout.map(Timespec::from);
+    let timeout_ptr = timeout
+        .as_ref()
+        .map_or(0, |timeout| core::ptr::from_ref(timeout) as u64);
+    let args = [
+        word.as_ptr() as u64,
+        FUTEX_WAIT,
+        u64::from(expected),
+        timeout_ptr,
+    ];
+    unsafe { syscall(SYS_FUTEX, &args).map(|_| ()) }
+}
+
+/// Wakes up at most `count` tasks waiting on `word`, and returns how many
+/// were woken up.
+pub fn futex_wake(word: &AtomicU32, count: u32) -> Result<u32, Errno> {
+    let args = [word.as_ptr() as u64, FUTEX_WAKE, u64::from(count)];
+    unsafe { syscall(SYS_FUTEX, &args).map(|n| n as u32) }
+}
+
+/// Clocks that can be read with `clock_gettime`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// Wall clock time since the Unix epoch.
+    Realtime = 0,
+    /// Time since boot, which never goes backwards. Use this to measure how
+    /// long something takes.
+    Monotonic = 1,
+    /// Time since boot, including time spent suspended.
+    Boottime = 7,
+}
+
+/// Returns the current time of `clock`, with nanosecond resolution.
+pub fn clock_gettime(clock: Clock) -> Result<Duration, Errno> {
+    let mut timespec = Timespec::default();
+    let args = [clock as u64, core::ptr::addr_of_mut!(timespec) as u64];
+    unsafe { syscall(SYS_CLOCK_GETTIME, &args)? };
+    Ok(timespec.into())
+}
+
+/// Sleeps for `duration`. If a signal interrupts the sleep, fails with
+/// `EINTR` and returns the time that was left.
+pub fn nanosleep(duration: Duration) -> Result<(), (Errno, Duration)> {
+    let request = Timespec::from(duration);
+    let mut remaining = Timespec::default();
+    let args = [
+        core::ptr::from_ref(&request) as u64,
+        core::ptr::addr_of_mut!(remaining) as u64,
+    ];
+    unsafe { syscall(SYS_NANOSLEEP, &args) }
+        .map(|_| ())
+        .map_err(|errno| (errno, remaining.into()))
+}
+
+/// Flags for `getrandom`. Combine them with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GetrandomFlags(pub u32);
+
+impl GetrandomFlags {
+    pub const NONE: Self = Self(0);
+    /// Fail with `EAGAIN` instead of waiting for the entropy pool to be
+    /// seeded.
+    pub const NONBLOCK: Self = Self(0x1);
+    pub const RANDOM: Self = Self(0x2);
+    /// Don't wait for the entropy pool to be seeded, even if the bytes might
+    /// be predictable.
+    pub const INSECURE: Self = Self(0x4);
+}
+
+impl core::ops::BitOr for GetrandomFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Fills `buffer` with random bytes from the kernel, returning how many were
+/// written. Large requests may be cut short.
+pub fn getrandom(buffer: &mut [u8], flags: GetrandomFlags) -> Result<usize, Errno> {
+    let args = [
+        buffer.as_mut_ptr() as u64,
+        buffer.len() as u64,
+        u64::from(flags.0),
+    ];
+    unsafe { syscall(SYS_GETRANDOM, &args).map(|n| n as usize) }
+}
+
+const TCGETS: u64 = 0x5401;
+const TCSETS: u64 = 0x5402;
+const TCSETSW: u64 = 0x5403;
+const TCSETSF: u64 = 0x5404;
+const TIOCGPGRP: u64 = 0x540f;
+const TIOCSPGRP: u64 = 0x5410;
+
+/// Terminal settings. This is the kernel's `struct termios`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; 19],
+}
+
+impl Termios {
+    // `iflag` bits
+    pub const ICRNL: u32 = 0o400;
+    pub const IXON: u32 = 0o2000;
+
+    // `oflag` bits
+    pub const OPOST: u32 = 0o1;
+
+    // `lflag` bits
+    pub const ISIG: u32 = 0o1;
+    pub const ICANON: u32 = 0o2;
+    pub const ECHO: u32 = 0o10;
+    pub const IEXTEN: u32 = 0o100_000;
+
+    // Indexes into `cc`
+    pub const VTIME: usize = 5;
+    pub const VMIN: usize = 6;
+
+    /// Switches to raw mode, like `cfmakeraw`: no line editing, echo,
+    /// signals, or newline translation, and reads return as soon as a byte is
+    /// available.
+    pub fn make_raw(&mut self) {
+        self.iflag &= !(Self::ICRNL | Self::IXON);
+        self.oflag &= !Self::OPOST;
+        self.lflag &= !(Self::ISIG | Self::ICANON | Self::ECHO | Self::IEXTEN);
+        self.cc[Self::VMIN] = 1;
+        self.cc[Self::VTIME] = 0;
+    }
+}
+
+/// When `tcsetattr` changes the settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetAttrWhen {
+    /// Right away.
+    Now,
+    /// After pending output has been written.
+    Drain,
+    /// After pending output has been written, and unread input is discarded.
+    Flush,
+}
+
+/// Returns the settings of the terminal `fd` refers to. Fails with `ENOTTY`
+/// if it isn't a terminal.
+pub fn tcgetattr(fd: Fd) -> Result<Termios, Errno> {
+    let mut termios = Termios::default();
+    let args = [
+        u64::from(fd.0),
+        TCGETS,
+        core::ptr::addr_of_mut!(termios) as u64,
+    ];
+    unsafe { syscall(SYS_IOCTL, &args)? };
+    Ok(termios)
+}
+
+/// Changes the settings of the terminal `fd` refers to.
+pub fn tcsetattr(fd: Fd, when: SetAttrWhen, termios: &Termios) -> Result<(), Errno> {
+    let request = match when {
+        SetAttrWhen::Now => TCSETS,
+        SetAttrWhen::Drain => TCSETSW,
+        SetAttrWhen::Flush => TCSETSF,
+    };
+    let args = [
+        u64::from(fd.0),
+        request,
+        core::ptr::from_ref(termios) as u64,
+    ];
+    unsafe { syscall(SYS_IOCTL, &args).map(|_| ()) }
+}
+
+/// Returns the foreground process group of the terminal `fd` refers to.
+pub fn tcgetpgrp(fd: Fd) -> Result<Pid, Errno> {
+    let mut pgid = 0u32;
+    let args = [
+        u64::from(fd.0),
+        TIOCGPGRP,
+        core::ptr::addr_of_mut!(pgid) as u64,
+    ];
+    unsafe { syscall(SYS_IOCTL, &args)? };
+    Ok(Pid(pgid))
+}
+
+/// Puts the process group `pgid` in the foreground of the terminal `fd`
+/// refers to, so it can read input and gets signals from ^C and ^Z.
+///
+/// Calling this from the background sends SIGTTOU, which stops the caller unless it
+/// blocks or ignores the signal.
+pub fn tcsetpgrp(fd: Fd, pgid: Pid) -> Result<(), Errno> {
+    let args = [
+        u64::from(fd.0),
+        TIOCSPGRP,
+        core::ptr::addr_of!(pgid.0) as u64,
+    ];
+    unsafe { syscall(SYS_IOCTL, &args).map(|_| ()) }
+}
+
+/// Returns true if `fd` refers to a terminal.
+pub fn isatty(fd: Fd) -> bool {
+    tcgetattr(fd).is_ok()
+}